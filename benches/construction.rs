@@ -0,0 +1,50 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use merkle_tree::{ArenaMerkleTree, BatchDigest, FlatMerkleTree, MerkleTree, TreeConfig};
+
+const LEAF_COUNTS: [usize; 4] = [1_000, 100_000, 1_000_000, 10_000_000];
+
+fn leaves(leaf_count: usize) -> Vec<[u8; 32]> {
+    (0..leaf_count as u64)
+        .map(|i| {
+            let mut leaf = [0; 32];
+            leaf[..8].copy_from_slice(&i.to_le_bytes());
+            leaf
+        })
+        .collect()
+}
+
+fn bench_digest<D: BatchDigest>(c: &mut Criterion, name: &str) {
+    let mut group = c.benchmark_group(format!("from_vec/{}", name));
+    group.sample_size(10);
+
+    for &leaf_count in &LEAF_COUNTS {
+        let data = leaves(leaf_count);
+
+        group.bench_with_input(BenchmarkId::new("boxed", leaf_count), &data, |b, data| {
+            b.iter(|| MerkleTree::<_, D>::from_vec_with_digest(data.clone()).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("arena", leaf_count), &data, |b, data| {
+            b.iter(|| ArenaMerkleTree::<_, D>::from_vec_with_digest(data.clone()).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("fixed", leaf_count), &data, |b, data| {
+            b.iter(|| MerkleTree::<_, D>::from_fixed_vec_with_digest(data.clone()).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("flat", leaf_count), &data, |b, data| {
+            b.iter(|| FlatMerkleTree::<D>::from_vec_with_config_and_digest(data.clone(), TreeConfig::default()).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+// Run with and without `--features rayon` to compare sequential and parallel construction.
+fn construction(c: &mut Criterion) {
+    bench_digest::<blake2::Blake2b>(c, "blake2b");
+    bench_digest::<sha2::Sha256>(c, "sha256");
+    bench_digest::<sha3::Keccak256>(c, "keccak256");
+    #[cfg(feature = "blake3")]
+    bench_digest::<merkle_tree::Blake3>(c, "blake3");
+}
+
+criterion_group!(benches, construction);
+criterion_main!(benches);
@@ -0,0 +1,58 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use merkle_tree::{Digest, FlatMerkleTree, MerkleTree, TreeConfig};
+
+const LEAF_COUNTS: [usize; 4] = [1_000, 100_000, 1_000_000, 10_000_000];
+
+fn leaves(leaf_count: usize) -> Vec<[u8; 32]> {
+    (0..leaf_count as u64)
+        .map(|i| {
+            let mut leaf = [0; 32];
+            leaf[..8].copy_from_slice(&i.to_le_bytes());
+            leaf
+        })
+        .collect()
+}
+
+fn bench_digest<D: Digest>(c: &mut Criterion, name: &str) {
+    let mut group = c.benchmark_group(format!("proofs/{}", name));
+
+    // Each tree is only built once and dropped before the next one, as building the larger
+    // ones takes far longer, and far more memory, than the proofs being measured.
+    for &leaf_count in &LEAF_COUNTS {
+        let data = leaves(leaf_count);
+        let index = leaf_count / 3;
+        let flat = FlatMerkleTree::<D>::from_vec_with_config_and_digest(data.clone(), TreeConfig::default()).unwrap();
+        let boxed = MerkleTree::<_, D>::from_vec_with_digest(data).unwrap();
+
+        group.bench_with_input(BenchmarkId::new("generate/boxed", leaf_count), &index, |b, &index| {
+            b.iter(|| boxed.proof(index).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("generate/flat", leaf_count), &index, |b, &index| {
+            b.iter(|| flat.proof(index).unwrap())
+        });
+
+        // A batch of a thousand consecutive leaves, as an airdrop script would request them.
+        let batch: Vec<usize> = (index..leaf_count.min(index + 1000)).collect();
+        group.bench_with_input(BenchmarkId::new("generate/batch", leaf_count), &batch, |b, batch| {
+            b.iter(|| boxed.proofs(batch).unwrap())
+        });
+
+        let proof = boxed.proof(index).unwrap();
+        let leaf = boxed.get_leaf(index).unwrap().data;
+
+        group.bench_with_input(BenchmarkId::new("verify", leaf_count), &proof, |b, proof| {
+            b.iter(|| proof.verify(boxed.root_hash(), &leaf).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+fn proofs(c: &mut Criterion) {
+    bench_digest::<blake2::Blake2b>(c, "blake2b");
+    bench_digest::<sha2::Sha256>(c, "sha256");
+    bench_digest::<sha3::Keccak256>(c, "keccak256");
+}
+
+criterion_group!(benches, proofs);
+criterion_main!(benches);
@@ -0,0 +1,125 @@
+//! Python bindings, built with PyO3 into the `merkle_tree` extension module.
+//!
+//! Trees are hashed with Blake2b. Leaves, hashes and encoded proofs are `bytes`, proofs being
+//! encoded in the format of `Proof::to_bytes`, so they can be stored and checked later.
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use merkle::{MerkleError, MerkleTree, Padding, Prefixes, Proof, TreeConfig};
+
+fn value_error(error: MerkleError) -> PyErr {
+    PyValueError::new_err(error.to_string())
+}
+
+fn config(padding: &str, prefixed: bool) -> PyResult<TreeConfig> {
+    let padding = match padding {
+        "duplicate-last" => Padding::DuplicateLast,
+        "promote" => Padding::Promote,
+        "empty-hash" => Padding::EmptyHash,
+        _ => return Err(PyValueError::new_err("padding must be one of 'duplicate-last', 'promote' or 'empty-hash'")),
+    };
+    let prefixes = if prefixed { Some(Prefixes::RFC6962) } else { None };
+
+    Ok(TreeConfig { prefixes, ..TreeConfig::new(padding) })
+}
+
+/// Merkle tree over a list of `bytes` leaves.
+#[pyclass(name = "MerkleTree")]
+struct PyMerkleTree {
+    tree: MerkleTree<Vec<u8>>,
+}
+
+#[pymethods]
+impl PyMerkleTree {
+    /// The padding is one of `"duplicate-last"`, `"promote"` or `"empty-hash"`, and
+    /// `prefixed` adds RFC 6962 leaf and node prefixes.
+    #[new]
+    #[pyo3(signature = (leaves, padding = "duplicate-last", prefixed = false))]
+    fn new(leaves: Vec<Vec<u8>>, padding: &str, prefixed: bool) -> PyResult<PyMerkleTree> {
+        let tree = MerkleTree::from_vec_with_config(leaves, config(padding, prefixed)?).map_err(value_error)?;
+        Ok(PyMerkleTree { tree })
+    }
+
+    fn root_hash<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, self.tree.root_hash())
+    }
+
+    fn root_hash_hex(&self) -> String {
+        self.tree.root_hash_hex()
+    }
+
+    #[getter]
+    fn leaf_count(&self) -> usize {
+        self.tree.leaf_count()
+    }
+
+    fn push(&mut self, leaf: Vec<u8>) -> PyResult<usize> {
+        self.tree.push(leaf).map_err(value_error)
+    }
+
+    fn update(&mut self, index: usize, leaf: Vec<u8>) -> PyResult<()> {
+        self.tree.update(index, leaf).map_err(value_error)
+    }
+
+    fn proof(&self, index: usize) -> PyResult<PyProof> {
+        Ok(PyProof { proof: self.tree.proof(index).map_err(value_error)? })
+    }
+
+    fn __len__(&self) -> usize {
+        self.tree.leaf_count()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("MerkleTree(root_hash='{}', leaf_count={})", self.tree.root_hash_hex(), self.tree.leaf_count())
+    }
+}
+
+/// Inclusion proof of a single leaf.
+#[pyclass(name = "Proof")]
+struct PyProof {
+    proof: Proof,
+}
+
+#[pymethods]
+impl PyProof {
+    #[staticmethod]
+    fn from_bytes(bytes: &[u8]) -> PyResult<PyProof> {
+        Ok(PyProof { proof: Proof::from_bytes(bytes).map_err(value_error)? })
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.proof.to_bytes())
+    }
+
+    #[getter]
+    fn leaf_index(&self) -> usize {
+        self.proof.leaf_index
+    }
+
+    /// Whether `leaf` is included in the tree with root `root_hash`, built with the given
+    /// padding and prefixes. Proofs generated with another config do not verify.
+    #[pyo3(signature = (root_hash, leaf, padding = "duplicate-last", prefixed = false))]
+    fn verify(&self, root_hash: &[u8], leaf: &[u8], padding: &str, prefixed: bool) -> PyResult<bool> {
+        Ok(self.proof.verify_with_config(root_hash, leaf, config(padding, prefixed)?).is_ok())
+    }
+}
+
+/// Whether `proof`, encoded with `Proof.to_bytes`, proves that `leaf` is included in the tree
+/// with root `root_hash`, built with the given padding and prefixes. Proofs that cannot be
+/// decoded or were generated with another config do not verify.
+#[pyfunction]
+#[pyo3(signature = (proof, root_hash, leaf, padding = "duplicate-last", prefixed = false))]
+fn verify_proof(proof: &[u8], root_hash: &[u8], leaf: &[u8], padding: &str, prefixed: bool) -> PyResult<bool> {
+    let config = config(padding, prefixed)?;
+    Ok(Proof::from_bytes(proof).and_then(|proof: Proof| proof.verify_with_config(root_hash, leaf, config)).is_ok())
+}
+
+#[pymodule]
+fn merkle_tree(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyMerkleTree>()?;
+    m.add_class::<PyProof>()?;
+    m.add_function(wrap_pyfunction!(verify_proof, m)?)?;
+    Ok(())
+}
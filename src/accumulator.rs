@@ -0,0 +1,321 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use blake2::Blake2b;
+use digest::Digest;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::error::MerkleError;
+use crate::{calculate_hash, hash_pair, Hash};
+
+/// Dynamic accumulator for stateless clients: a forest of perfect binary trees, one per bit set
+/// in the number of leaves added, of which only the roots are kept. Every element added gets a
+/// [`Witness`] of its inclusion, and every addition or deletion returns an
+/// [`AccumulatorUpdate`] that holders of witnesses apply to keep them valid, without asking
+/// anyone for a new proof.
+///
+/// Deleting an element replaces its leaf with an all-zero hash, so the trees never shrink and
+/// leaves keep their index. The updates must be applied to witnesses in the order they were
+/// returned.
+pub struct MerkleAccumulator<D: Digest = Blake2b> {
+    /// `roots[h]` holds the root of the tree of height `h`, if there is one.
+    roots: Vec<Option<Hash<D>>>,
+    leaf_count: usize,
+}
+
+impl MerkleAccumulator {
+    /// Creates an empty MerkleAccumulator hashing with Blake2b.
+    pub fn new() -> MerkleAccumulator {
+        MerkleAccumulator::with_digest()
+    }
+}
+
+impl Default for MerkleAccumulator {
+    fn default() -> Self {
+        MerkleAccumulator::new()
+    }
+}
+
+impl<D> MerkleAccumulator<D> where D: Digest {
+    /// Creates an empty MerkleAccumulator hashing with the digest `D`.
+    pub fn with_digest() -> MerkleAccumulator<D> {
+        MerkleAccumulator { roots: Vec::new(), leaf_count: 0 }
+    }
+
+    /// Number of leaves added, including those deleted since.
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaf_count == 0
+    }
+
+    /// Roots of the trees, from the highest (leftmost) to the lowest.
+    pub fn roots(&self) -> Vec<&[u8]> {
+        self.roots.iter().rev().flatten().map(|root| root.as_slice()).collect()
+    }
+
+    /// Adds an element, merging the trees of equal height, and returns its witness along with
+    /// the update to apply to the witnesses of the elements already added.
+    pub fn add(&mut self, data: &[u8]) -> (Witness<D>, AccumulatorUpdate<D>) {
+        let leaf_index = self.leaf_count;
+        let leaf_hash = calculate_hash::<D>(data);
+        let mut hash = leaf_hash.clone();
+        let mut merged = Vec::new();
+
+        while let Some(root) = self.roots.get_mut(merged.len()).and_then(Option::take) {
+            hash = hash_pair::<D>(None, &root, &hash);
+            merged.push(root);
+        }
+
+        if self.roots.len() == merged.len() {
+            self.roots.push(None);
+        }
+
+        self.roots[merged.len()] = Some(hash);
+        self.leaf_count += 1;
+
+        (Witness { leaf_index, siblings: merged.clone() }, AccumulatorUpdate::Added { leaf_hash, merged })
+    }
+
+    /// Checks that `data` is the element at `witness.leaf_index` and was not deleted. Fails with
+    /// `MalformedProof` if the witness cannot belong to a tree of the accumulator, which happens
+    /// when updates were missed.
+    pub fn verify(&self, witness: &Witness<D>, data: &[u8]) -> Result<(), MerkleError> {
+        let root = self.root_from(witness, &calculate_hash::<D>(data))?;
+
+        if self.roots[witness.siblings.len()].as_ref() == Some(&root) {
+            Ok(())
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+
+    /// Deletes the element `data` proven by `witness`, and returns the update to apply to the
+    /// witnesses of the other elements. The accumulator is left unchanged when the witness does
+    /// not verify.
+    pub fn delete(&mut self, witness: &Witness<D>, data: &[u8]) -> Result<AccumulatorUpdate<D>, MerkleError> {
+        self.verify(witness, data)?;
+
+        let mut hash = Hash::<D>::default();
+        let mut path = Vec::with_capacity(witness.siblings.len());
+
+        for (height, sibling) in witness.siblings.iter().enumerate() {
+            let parent = witness.parent(height, &hash, sibling);
+            path.push(core::mem::replace(&mut hash, parent));
+        }
+
+        self.roots[path.len()] = Some(hash);
+
+        Ok(AccumulatorUpdate::Deleted { leaf_index: witness.leaf_index, path })
+    }
+
+    /// Hashes the leaf up the siblings of `witness`, after checking that the tree of the height
+    /// the witness implies holds its leaf.
+    fn root_from(&self, witness: &Witness<D>, leaf_hash: &Hash<D>) -> Result<Hash<D>, MerkleError> {
+        if witness.leaf_index >= self.leaf_count {
+            return Err(MerkleError::IndexOutOfBounds { index: witness.leaf_index, leaf_count: self.leaf_count });
+        }
+
+        // A witness cannot have more siblings than the leaf count has bits, and the tree of
+        // height `h` starts after the leaves of the higher trees.
+        let height = witness.siblings.len();
+
+        if height >= usize::BITS as usize
+            || (self.leaf_count >> height) & 1 == 0
+            || witness.leaf_index >> height != (self.leaf_count >> height) - 1 {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        Ok(witness.siblings.iter().enumerate()
+            .fold(leaf_hash.clone(), |hash, (height, sibling)| witness.parent(height, &hash, sibling)))
+    }
+}
+
+impl<D> fmt::Debug for MerkleAccumulator<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MerkleAccumulator")
+            .field("roots", &self.roots())
+            .field("leaf_count", &self.leaf_count)
+            .finish()
+    }
+}
+
+/// Proof of inclusion of an element of a [`MerkleAccumulator`]: the siblings of its leaf up to
+/// the root of its tree, lowest first. The number of siblings is the height of the tree.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct Witness<D: Digest = Blake2b> {
+    pub leaf_index: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::list"))]
+    pub siblings: Vec<Hash<D>>,
+}
+
+impl<D> Witness<D> where D: Digest {
+    /// Brings the witness up to date with an addition or deletion. Witnesses of elements in
+    /// trees untouched by the change are left as they are.
+    pub fn update(&mut self, update: &AccumulatorUpdate<D>) {
+        let height = self.siblings.len();
+
+        match update {
+            // The tree of the witness is merged, as the left child, with the tree holding the
+            // new leaf and the lower trees, and then as a right child with the higher trees.
+            AccumulatorUpdate::Added { leaf_hash, merged } if height < merged.len() => {
+                let carry = merged[..height].iter().fold(leaf_hash.clone(), |hash, root| hash_pair::<D>(None, root, &hash));
+                self.siblings.push(carry);
+                self.siblings.extend(merged[height + 1..].iter().cloned());
+            }
+            // The paths of two leaves of the same tree meet at the highest bit their indices
+            // differ in, where the node of the deleted path is the sibling of the witness.
+            AccumulatorUpdate::Deleted { leaf_index, path } if height == path.len() && self.leaf_index != *leaf_index
+                && self.leaf_index >> height == leaf_index >> height => {
+                let meet = (usize::BITS - 1 - (self.leaf_index ^ leaf_index).leading_zeros()) as usize;
+                self.siblings[meet] = path[meet].clone();
+            }
+            _ => {}
+        }
+    }
+
+    /// Hash of the node at `height + 1` on the path of the leaf, from the node at `height` and
+    /// its sibling.
+    fn parent(&self, height: usize, hash: &Hash<D>, sibling: &Hash<D>) -> Hash<D> {
+        if (self.leaf_index >> height) & 1 == 1 {
+            hash_pair::<D>(None, sibling, hash)
+        } else {
+            hash_pair::<D>(None, hash, sibling)
+        }
+    }
+}
+
+/// Change of a [`MerkleAccumulator`], from which witnesses are updated.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub enum AccumulatorUpdate<D: Digest = Blake2b> {
+    /// An element was added, merging the roots of the `merged.len()` lowest trees, lowest first,
+    /// with its leaf.
+    Added {
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash"))]
+        leaf_hash: Hash<D>,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::list"))]
+        merged: Vec<Hash<D>>,
+    },
+    /// The element at `leaf_index` was deleted, changing the hashes of the nodes on its path,
+    /// from the leaf up to the children of the root, to `path`.
+    Deleted {
+        leaf_index: usize,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::list"))]
+        path: Vec<Hash<D>>,
+    },
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for Witness<D> where D: Digest {
+    fn clone(&self) -> Self {
+        Witness { leaf_index: self.leaf_index, siblings: self.siblings.clone() }
+    }
+}
+
+impl<D> PartialEq for Witness<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf_index == other.leaf_index && self.siblings == other.siblings
+    }
+}
+
+impl<D> fmt::Debug for Witness<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Witness")
+            .field("leaf_index", &self.leaf_index)
+            .field("siblings", &self.siblings)
+            .finish()
+    }
+}
+
+impl<D> Clone for AccumulatorUpdate<D> where D: Digest {
+    fn clone(&self) -> Self {
+        match self {
+            AccumulatorUpdate::Added { leaf_hash, merged } =>
+                AccumulatorUpdate::Added { leaf_hash: leaf_hash.clone(), merged: merged.clone() },
+            AccumulatorUpdate::Deleted { leaf_index, path } =>
+                AccumulatorUpdate::Deleted { leaf_index: *leaf_index, path: path.clone() },
+        }
+    }
+}
+
+impl<D> PartialEq for AccumulatorUpdate<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (AccumulatorUpdate::Added { leaf_hash, merged }, AccumulatorUpdate::Added { leaf_hash: other_hash, merged: other_merged }) =>
+                leaf_hash == other_hash && merged == other_merged,
+            (AccumulatorUpdate::Deleted { leaf_index, path }, AccumulatorUpdate::Deleted { leaf_index: other_index, path: other_path }) =>
+                leaf_index == other_index && path == other_path,
+            _ => false,
+        }
+    }
+}
+
+impl<D> fmt::Debug for AccumulatorUpdate<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccumulatorUpdate::Added { leaf_hash, merged } =>
+                f.debug_struct("Added").field("leaf_hash", leaf_hash).field("merged", merged).finish(),
+            AccumulatorUpdate::Deleted { leaf_index, path } =>
+                f.debug_struct("Deleted").field("leaf_index", leaf_index).field("path", path).finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::{String, ToString};
+
+    #[test]
+    fn it_keeps_witnesses_valid_across_changes() {
+        let mut accumulator = MerkleAccumulator::new();
+        let mut elements: Vec<(String, Witness)> = Vec::new();
+        let mut deleted = Vec::new();
+
+        for i in 0..23 {
+            let data = i.to_string();
+            let (witness, update) = accumulator.add(data.as_bytes());
+            elements.iter_mut().chain(&mut deleted).for_each(|(_, witness)| witness.update(&update));
+            elements.push((data, witness));
+
+            // Delete every third element, at different heights of the forest.
+            if i % 3 == 2 {
+                let (data, witness) = elements.remove(elements.len() / 2);
+                let update = accumulator.delete(&witness, data.as_bytes()).unwrap();
+                elements.iter_mut().chain(&mut deleted).for_each(|(_, witness)| witness.update(&update));
+                deleted.push((data, witness));
+            }
+
+            for (data, witness) in &elements {
+                assert_eq!(accumulator.verify(witness, data.as_bytes()), Ok(()));
+            }
+        }
+
+        assert_eq!(accumulator.roots().len(), 23usize.count_ones() as usize);
+
+        for (data, witness) in &deleted {
+            assert_eq!(accumulator.delete(witness, data.as_bytes()), Err(MerkleError::RootMismatch));
+        }
+    }
+
+    #[test]
+    fn it_rejects_witnesses_outside_their_tree() {
+        let mut accumulator = MerkleAccumulator::new();
+        let (stale, _) = accumulator.add(b"a");
+        let (witness, _) = accumulator.add(b"b");
+
+        assert_eq!(accumulator.verify(&witness, b"b"), Ok(()));
+        assert_eq!(accumulator.verify(&stale, b"a"), Err(MerkleError::MalformedProof));
+        assert_eq!(accumulator.verify(&Witness { leaf_index: 2, siblings: Vec::new() }, b"c"),
+            Err(MerkleError::IndexOutOfBounds { index: 2, leaf_count: 2 }));
+
+        let oversized = Witness { leaf_index: 0, siblings: vec![Hash::<Blake2b>::default(); usize::BITS as usize + 1] };
+        assert_eq!(accumulator.verify(&oversized, b"a"), Err(MerkleError::MalformedProof));
+        assert_eq!(accumulator.delete(&oversized, b"a"), Err(MerkleError::MalformedProof));
+    }
+}
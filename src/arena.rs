@@ -0,0 +1,401 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter;
+
+use blake2::Blake2b;
+use digest::Digest;
+use digest::generic_array::typenum::U64;
+use digest::generic_array::{ArrayLength, GenericArray};
+
+use crate::{apply_duplicate_policy, calculate_hash, check_leaf, hash_children, leaf_hash, map_hashes, to_hex};
+use crate::config::{LeafMode, Padding, TreeConfig};
+use crate::error::MerkleError;
+use crate::leaf::{LeafBytes, LeafEncode};
+use crate::metrics;
+use crate::proof::{Proof, ProofStep, Side};
+
+/// Position of a node in the arena of an [`ArenaMerkleTree`]. Leaves come first, so the leaf
+/// at index `i` is at `ArenaIndex::new(i)`, followed by the levels above them from the bottom up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ArenaIndex(usize);
+
+impl ArenaIndex {
+    pub fn new(index: usize) -> ArenaIndex {
+        ArenaIndex(index)
+    }
+
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// A node of an [`ArenaMerkleTree`], linked to its children by their position in the arena.
+pub enum ArenaNode<N: ArrayLength<u8> = U64> {
+    Node { hash: GenericArray<u8, N>, left: ArenaIndex, right: ArenaIndex },
+    Leaf { hash: GenericArray<u8, N> },
+    /// Stand-in sibling for a node left unpaired on its level.
+    Padding(GenericArray<u8, N>),
+}
+
+impl<N> ArenaNode<N> where N: ArrayLength<u8> {
+    pub fn hash(&self) -> &GenericArray<u8, N> {
+        match self {
+            ArenaNode::Node { hash, .. } | ArenaNode::Leaf { hash } | ArenaNode::Padding(hash) => hash,
+        }
+    }
+
+    /// Positions of the left and right children, or `None` for leaves and padding.
+    pub fn children(&self) -> Option<(ArenaIndex, ArenaIndex)> {
+        match self {
+            ArenaNode::Node { left, right, .. } => Some((*left, *right)),
+            _ => None,
+        }
+    }
+}
+
+// Implemented by hand so that `N` is not required to implement these traits.
+impl<N> Clone for ArenaNode<N> where N: ArrayLength<u8> {
+    fn clone(&self) -> Self {
+        match self {
+            ArenaNode::Node { hash, left, right } => ArenaNode::Node { hash: hash.clone(), left: *left, right: *right },
+            ArenaNode::Leaf { hash } => ArenaNode::Leaf { hash: hash.clone() },
+            ArenaNode::Padding(hash) => ArenaNode::Padding(hash.clone()),
+        }
+    }
+}
+
+impl<N> PartialEq for ArenaNode<N> where N: ArrayLength<u8> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ArenaNode::Node { hash: a, left: al, right: ar }, ArenaNode::Node { hash: b, left: bl, right: br }) =>
+                a == b && al == bl && ar == br,
+            (ArenaNode::Leaf { hash: a }, ArenaNode::Leaf { hash: b }) => a == b,
+            (ArenaNode::Padding(a), ArenaNode::Padding(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl<N> fmt::Debug for ArenaNode<N> where N: ArrayLength<u8> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArenaNode::Node { hash, left, right } => f.debug_struct("Node")
+                .field("hash", &to_hex(hash))
+                .field("left", left)
+                .field("right", right)
+                .finish(),
+            ArenaNode::Leaf { hash } => f.debug_struct("Leaf").field("hash", &to_hex(hash)).finish(),
+            ArenaNode::Padding(hash) => f.debug_tuple("Padding").field(&to_hex(hash)).finish(),
+        }
+    }
+}
+
+/// A Merkle tree whose nodes are all stored in a single `Vec`, linked by their [`ArenaIndex`]
+/// rather than by pointers, so that building a tree of millions of nodes makes a handful of
+/// allocations instead of one per node. The tree has the same root and proofs as a
+/// [`MerkleTree`](crate::MerkleTree) built from the same data and config, and keeps the data,
+/// but cannot share nodes with snapshots.
+pub struct ArenaMerkleTree<T, D: Digest = Blake2b> {
+    data: Vec<T>,
+    nodes: Vec<ArenaNode<D::OutputSize>>,
+    config: TreeConfig,
+}
+
+impl<T> ArenaMerkleTree<T> where T: LeafEncode {
+    /// Creates an ArenaMerkleTree from a vector, hashing with Blake2b and the default config.
+    pub fn from_vec(data: Vec<T>) -> Result<ArenaMerkleTree<T>, MerkleError> {
+        ArenaMerkleTree::from_vec_with_digest(data)
+    }
+
+    /// Creates an ArenaMerkleTree from a vector, hashing with Blake2b.
+    pub fn from_vec_with_config(data: Vec<T>, config: TreeConfig) -> Result<ArenaMerkleTree<T>, MerkleError> {
+        ArenaMerkleTree::from_vec_with_config_and_digest(data, config)
+    }
+}
+
+impl<T, D> ArenaMerkleTree<T, D> where T: LeafEncode, D: Digest {
+    /// Creates an ArenaMerkleTree from a vector, hashing with the digest `D` and the default config.
+    pub fn from_vec_with_digest(data: Vec<T>) -> Result<ArenaMerkleTree<T, D>, MerkleError> {
+        ArenaMerkleTree::from_vec_with_config_and_digest(data, TreeConfig::default())
+    }
+
+    /// Creates an ArenaMerkleTree from a vector, hashing with the digest `D`.
+    pub fn from_vec_with_config_and_digest(data: Vec<T>, config: TreeConfig) -> Result<ArenaMerkleTree<T, D>, MerkleError> {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        for (index, leaf) in data.iter().enumerate() {
+            check_leaf::<D>(config.leaf_mode, index, &leaf.encode_leaf())?;
+        }
+
+        let slices: Vec<LeafBytes> = data.iter().map(LeafEncode::encode_leaf).collect();
+        let hashes = map_hashes(&slices, |d| leaf_hash::<D>(config.prefixes, config.leaf_mode, d));
+        if config.leaf_mode == LeafMode::Hashed {
+            metrics::record(|metrics| metrics.hashes_computed(hashes.len()));
+        }
+        let leaves = apply_duplicate_policy(data.into_iter().zip(hashes).collect(), config.duplicates, |(_, hash)| hash)?;
+        let (data, hashes): (Vec<T>, Vec<_>) = leaves.into_iter().unzip();
+
+        // Every level holds at most half of the nodes below it, plus one padding node.
+        let leaf_count = hashes.len();
+        let mut nodes = Vec::with_capacity(2 * leaf_count + 2 * leaf_count.next_power_of_two().trailing_zeros() as usize);
+        nodes.extend(hashes.into_iter().map(|hash| ArenaNode::Leaf { hash }));
+
+        let mut level: Vec<ArenaIndex> = (0..leaf_count).map(ArenaIndex).collect();
+        let mut height = 0;
+
+        while level.len() > 1 {
+            let pairs: Vec<&[ArenaIndex]> = level.chunks(2).collect();
+            let hashes = map_hashes(&pairs, |pair| {
+                let left = nodes[pair[0].0].hash();
+                match (pair.get(1), config.padding) {
+                    (Some(right), _) => hash_children::<D>(config.prefixes, config.sort_pairs, left, nodes[right.0].hash()),
+                    (None, Padding::Promote) => left.clone(),
+                    (None, Padding::DuplicateLast) => hash_children::<D>(config.prefixes, config.sort_pairs, left, left),
+                    (None, Padding::EmptyHash) => hash_children::<D>(config.prefixes, config.sort_pairs, left, &calculate_hash::<D>(&[])),
+                }
+            });
+
+            let len = level.len();
+
+            // Parent `i` only depends on children `2i` and `2i + 1`, so it can be written in place.
+            for (i, hash) in hashes.into_iter().enumerate() {
+                let left = level[2 * i];
+                let right = match (level.get(2 * i + 1), config.padding) {
+                    (Some(&right), _) => right,
+                    (None, Padding::Promote) => {
+                        level[i] = left;
+                        continue;
+                    }
+                    (None, Padding::DuplicateLast) => {
+                        let hash = nodes[left.0].hash().clone();
+                        push(&mut nodes, ArenaNode::Padding(hash))
+                    }
+                    (None, Padding::EmptyHash) => push(&mut nodes, ArenaNode::Padding(calculate_hash::<D>(&[]))),
+                };
+
+                level[i] = push(&mut nodes, ArenaNode::Node { hash, left, right });
+            }
+
+            level.truncate(len.div_ceil(2));
+            height += 1;
+
+            let hashed = if config.padding == Padding::Promote { len / 2 } else { level.len() };
+            metrics::record(|metrics| {
+                metrics.hashes_computed(hashed);
+                metrics.level_built(height, level.len());
+            });
+        }
+
+        Ok(ArenaMerkleTree { data, nodes, config })
+    }
+}
+
+impl<T, D> ArenaMerkleTree<T, D> where D: Digest {
+    pub fn root_hash(&self) -> &[u8] {
+        self.nodes[self.root().0].hash()
+    }
+
+    /// Returns the root hash as a lowercase hex string.
+    pub fn root_hash_hex(&self) -> String {
+        to_hex(self.root_hash())
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn config(&self) -> TreeConfig {
+        self.config
+    }
+
+    /// Number of nodes in the arena, including the leaves and padding.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Position of the root, the last node built.
+    pub fn root(&self) -> ArenaIndex {
+        ArenaIndex(self.nodes.len() - 1)
+    }
+
+    /// Returns the node at `index`, or `None` if the arena has no such node.
+    pub fn node(&self, index: ArenaIndex) -> Option<&ArenaNode<D::OutputSize>> {
+        self.nodes.get(index.0)
+    }
+
+    /// Returns the data of the leaf at `index`, or `None` if there is no such leaf.
+    pub fn get_leaf(&self, index: usize) -> Option<&T> {
+        self.data.get(index)
+    }
+
+    /// Returns the data of the leaves, in order.
+    pub fn data(&self) -> &[T] {
+        &self.data
+    }
+
+    /// Visits the nodes depth first from the root, left children before right ones, along with
+    /// their position in the arena. Nodes promoted without a sibling are visited once.
+    pub fn iter(&self) -> impl Iterator<Item = (ArenaIndex, &ArenaNode<D::OutputSize>)> {
+        let mut stack = vec![self.root()];
+
+        iter::from_fn(move || {
+            let index = stack.pop()?;
+            let node = &self.nodes[index.0];
+
+            if let Some((left, right)) = node.children() {
+                stack.push(right);
+                stack.push(left);
+            }
+
+            Some((index, node))
+        })
+    }
+
+    /// Generates an inclusion proof for the leaf at `leaf_index`, identical to the one
+    /// a MerkleTree built from the same data and config would produce.
+    pub fn proof(&self, leaf_index: usize) -> Result<Proof<D>, MerkleError> {
+        let leaf_count = self.leaf_count();
+
+        if leaf_index >= leaf_count {
+            return Err(MerkleError::IndexOutOfBounds { index: leaf_index, leaf_count });
+        }
+
+        // From the leaves up, whether the path goes through a right child at each level,
+        // or `None` when the node on the path was promoted without a sibling.
+        let mut moves = Vec::new();
+        let (mut index, mut count) = (leaf_index, leaf_count);
+
+        while count > 1 {
+            if self.config.padding == Padding::Promote && index == count - 1 && count & 1 == 1 {
+                moves.push(None);
+            } else {
+                moves.push(Some(index & 1 == 1));
+            }
+
+            index /= 2;
+            count = count.div_ceil(2);
+        }
+
+        let mut siblings = Vec::with_capacity(moves.len());
+        let mut node = &self.nodes[self.root().0];
+
+        for is_right in moves.into_iter().rev().flatten() {
+            if let Some((left, right)) = node.children() {
+                if is_right {
+                    siblings.push(ProofStep::new(Side::Left, self.nodes[left.0].hash().clone()));
+                    node = &self.nodes[right.0];
+                } else {
+                    siblings.push(ProofStep::new(Side::Right, self.nodes[right.0].hash().clone()));
+                    node = &self.nodes[left.0];
+                }
+            }
+        }
+
+        siblings.reverse();
+        metrics::record(|metrics| metrics.proof_generated(siblings.len()));
+
+        let mut proof = Proof::new(leaf_index, leaf_count, self.config.padding, siblings);
+        proof.prefixes = self.config.prefixes;
+        proof.sort_pairs = self.config.sort_pairs;
+        proof.leaf_mode = self.config.leaf_mode;
+
+        Ok(proof)
+    }
+}
+
+/// Appends `node` to the arena, returning its position.
+fn push<N>(nodes: &mut Vec<ArenaNode<N>>, node: ArenaNode<N>) -> ArenaIndex where N: ArrayLength<u8> {
+    nodes.push(node);
+    ArenaIndex(nodes.len() - 1)
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<T, D> Clone for ArenaMerkleTree<T, D> where T: Clone, D: Digest {
+    fn clone(&self) -> Self {
+        ArenaMerkleTree {
+            data: self.data.clone(),
+            nodes: self.nodes.clone(),
+            config: self.config,
+        }
+    }
+}
+
+impl<T, D> PartialEq for ArenaMerkleTree<T, D> where T: PartialEq, D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.config == other.config && self.data == other.data && self.nodes == other.nodes
+    }
+}
+
+impl<T, D> fmt::Debug for ArenaMerkleTree<T, D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ArenaMerkleTree")
+            .field("root_hash", &self.root_hash_hex())
+            .field("config", &self.config)
+            .field("leaf_count", &self.leaf_count())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DuplicatePolicy, MerkleTree};
+
+    fn leaves(len: usize) -> Vec<String> {
+        (0..len).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn it_matches_boxed_tree_roots_and_proofs() {
+        for &padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            for len in 1..=17 {
+                let config = TreeConfig::new(padding);
+                let arena = ArenaMerkleTree::from_vec_with_config(leaves(len), config).unwrap();
+                let boxed = MerkleTree::from_vec_with_config(leaves(len), config).unwrap();
+
+                assert_eq!(arena.root_hash(), boxed.root_hash());
+
+                for index in 0..len {
+                    assert_eq!(arena.proof(index).unwrap(), boxed.proof(index).unwrap());
+                }
+            }
+        }
+
+        assert_eq!(ArenaMerkleTree::<String>::from_vec(vec![]), Err(MerkleError::EmptyInput));
+    }
+
+    #[test]
+    fn it_traverses_nodes_with_their_indices() {
+        let merkle_tree = ArenaMerkleTree::from_vec_with_config(leaves(5), TreeConfig::RFC6962).unwrap();
+        let visited: Vec<ArenaIndex> = merkle_tree.iter().map(|(index, _)| index).collect();
+
+        // Leaves, then the 2 nodes of the first level, the node of the second and the root.
+        assert_eq!(merkle_tree.node_count(), 9);
+        assert_eq!(visited.len(), 9);
+        assert_eq!(visited[0], merkle_tree.root());
+
+        let leaves: Vec<usize> = merkle_tree.iter()
+            .filter(|(_, node)| matches!(node, ArenaNode::Leaf { .. }))
+            .map(|(index, _)| index.index())
+            .collect();
+        assert_eq!(leaves, vec![0, 1, 2, 3, 4]);
+        assert_eq!(merkle_tree.get_leaf(4), Some(&"4".to_string()));
+
+        let (left, right) = merkle_tree.node(merkle_tree.root()).unwrap().children().unwrap();
+        assert_eq!(right, ArenaIndex::new(4));
+        assert_eq!(merkle_tree.node(left).unwrap().children(), Some((ArenaIndex::new(5), ArenaIndex::new(6))));
+    }
+
+    #[test]
+    fn it_keeps_the_data_of_deduplicated_leaves() {
+        let deduplicate = TreeConfig { duplicates: DuplicatePolicy::Deduplicate, ..TreeConfig::default() };
+        let arena = ArenaMerkleTree::from_vec_with_config(vec!["a", "b", "a", "c"], deduplicate).unwrap();
+
+        assert_eq!(arena.get_leaf(2), Some(&"c"));
+        assert_eq!(arena.proof(2).unwrap().verify(arena.root_hash(), b"c"), Ok(()));
+    }
+}
@@ -0,0 +1,277 @@
+//! Content-addressed persistence of trees in a key-value store provided by the application.
+//!
+//! Every node of a [`MerkleTree`] is put in a [`Backend`] under its hash, so identical subtrees
+//! are only stored once, and a tree is loaded back from its root hash alone. Implementing
+//! [`Backend`] over sled, RocksDB or LMDB only takes mapping `get` and `put` to the database,
+//! using [`BackendNode::to_bytes`] and [`BackendNode::from_bytes`] for the values.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use core::fmt;
+
+use digest::Digest;
+use digest::generic_array::{ArrayLength, GenericArray};
+
+use crate::{check_leaf, hash_children, leaf_hash};
+use crate::config::TreeConfig;
+use crate::leaf::LeafEncode;
+use crate::tree::{MerkleLeaf, MerkleNode, MerkleTree, Node};
+
+/// Node of a tree as put in a [`Backend`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum BackendNode {
+    /// Data of a leaf.
+    Leaf(Vec<u8>),
+    /// Hashes of the children of an inner node. The right child is padding, which is not put
+    /// in the backend, when `padded` is set.
+    Inner { left: Vec<u8>, right: Vec<u8>, padded: bool },
+}
+
+const LEAF: u8 = 0;
+const INNER: u8 = 1;
+const PADDED: u8 = 2;
+
+impl BackendNode {
+    /// Encodes the node as a tag byte followed by the leaf data or both child hashes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        match self {
+            BackendNode::Leaf(data) => {
+                bytes.push(LEAF);
+                bytes.extend_from_slice(data);
+            }
+            BackendNode::Inner { left, right, padded } => {
+                bytes.push(if *padded { PADDED } else { INNER });
+                bytes.extend_from_slice(left);
+                bytes.extend_from_slice(right);
+            }
+        }
+
+        bytes
+    }
+
+    /// Decodes a node encoded by `to_bytes`, or returns `None` if the encoding is invalid.
+    pub fn from_bytes(bytes: &[u8]) -> Option<BackendNode> {
+        let (tag, rest) = bytes.split_first()?;
+
+        match *tag {
+            LEAF => Some(BackendNode::Leaf(rest.to_vec())),
+            INNER | PADDED if rest.len() % 2 == 0 => {
+                let (left, right) = rest.split_at(rest.len() / 2);
+                Some(BackendNode::Inner { left: left.to_vec(), right: right.to_vec(), padded: *tag == PADDED })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Key-value store holding the nodes of trees, keyed by node hash.
+pub trait Backend {
+    type Error;
+
+    fn get(&self, hash: &[u8]) -> Result<Option<BackendNode>, Self::Error>;
+
+    fn put(&mut self, hash: &[u8], node: BackendNode) -> Result<(), Self::Error>;
+}
+
+/// Error loading a tree from a [`Backend`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum BackendError<E> {
+    /// The backend itself failed.
+    Backend(E),
+    /// No node was stored under this hash.
+    MissingNode(Vec<u8>),
+    /// The node stored under this hash does not hash to it.
+    HashMismatch(Vec<u8>),
+}
+
+impl<E> fmt::Display for BackendError<E> where E: fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackendError::Backend(e) => write!(f, "backend error: {}", e),
+            BackendError::MissingNode(hash) => write!(f, "node {} missing from backend", crate::to_hex(hash)),
+            BackendError::HashMismatch(hash) => write!(f, "node stored under {} does not match its hash", crate::to_hex(hash)),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E> std::error::Error for BackendError<E> where E: fmt::Debug + fmt::Display {}
+
+/// [`Backend`] keeping nodes in memory.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct MemoryBackend {
+    nodes: BTreeMap<Vec<u8>, BackendNode>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+
+    /// Number of distinct nodes stored.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+impl Backend for MemoryBackend {
+    type Error = Infallible;
+
+    fn get(&self, hash: &[u8]) -> Result<Option<BackendNode>, Infallible> {
+        Ok(self.nodes.get(hash).cloned())
+    }
+
+    fn put(&mut self, hash: &[u8], node: BackendNode) -> Result<(), Infallible> {
+        self.nodes.insert(hash.to_vec(), node);
+        Ok(())
+    }
+}
+
+type TreeNode<D> = Node<Vec<u8>, <D as Digest>::OutputSize>;
+
+impl<T, D> MerkleTree<T, D> where T: LeafEncode, D: Digest {
+    /// Puts every node of the tree in `backend`, keyed by its hash.
+    pub fn save<B: Backend>(&self, backend: &mut B) -> Result<(), B::Error> {
+        save_node(self.root(), backend)
+    }
+}
+
+impl<D> MerkleTree<Vec<u8>, D> where D: Digest {
+    /// Loads the tree with the given root hash from `backend`. The tree must have been saved
+    /// with the same config, which is not stored. Every node is checked against its hash.
+    pub fn load<B: Backend>(backend: &B, root_hash: &[u8], config: TreeConfig) -> Result<MerkleTree<Vec<u8>, D>, BackendError<B::Error>> {
+        let root = load_node::<B, D>(backend, root_hash, config)?;
+        Ok(MerkleTree::with_root(root, config))
+    }
+}
+
+fn save_node<T, N, B>(node: &Node<T, N>, backend: &mut B) -> Result<(), B::Error>
+    where T: LeafEncode, N: ArrayLength<u8>, B: Backend {
+    match node {
+        Node::Node(n) => {
+            let padded = matches!(*n.right, Node::Padding(_));
+            backend.put(&n.hash, BackendNode::Inner { left: n.left.hash().to_vec(), right: n.right.hash().to_vec(), padded })?;
+
+            save_node(&n.left, backend)?;
+            if !padded {
+                save_node(&n.right, backend)?;
+            }
+
+            Ok(())
+        }
+        Node::Leaf(l) => backend.put(&l.hash, BackendNode::Leaf(l.data.encode_leaf().into_owned())),
+        Node::Padding(_) => Ok(()),
+    }
+}
+
+fn load_node<B, D>(backend: &B, hash: &[u8], config: TreeConfig) -> Result<TreeNode<D>, BackendError<B::Error>>
+    where B: Backend, D: Digest {
+    let node = backend.get(hash).map_err(BackendError::Backend)?.ok_or_else(|| BackendError::MissingNode(hash.to_vec()))?;
+    let mismatch = || BackendError::HashMismatch(hash.to_vec());
+
+    match node {
+        BackendNode::Leaf(data) => {
+            // The index is unknown here, and only needed to report the length error.
+            if check_leaf::<D>(config.leaf_mode, 0, &data).is_err() {
+                return Err(mismatch());
+            }
+
+            let leaf_hash = leaf_hash::<D>(config.prefixes, config.leaf_mode, &data);
+
+            if leaf_hash.as_slice() != hash {
+                return Err(mismatch());
+            }
+
+            Ok(Node::Leaf(MerkleLeaf { hash: leaf_hash, data }))
+        }
+        BackendNode::Inner { left, right, padded } => {
+            let node_hash = hash_children::<D>(config.prefixes, config.sort_pairs, &left, &right);
+
+            if node_hash.as_slice() != hash || left.len() != node_hash.len() || right.len() != node_hash.len() {
+                return Err(mismatch());
+            }
+
+            let left = load_node::<B, D>(backend, &left, config)?;
+            let right = if padded {
+                Node::Padding(GenericArray::clone_from_slice(&right))
+            } else {
+                load_node::<B, D>(backend, &right, config)?
+            };
+
+            Ok(Node::Node(MerkleNode { hash: node_hash, left: Arc::new(left), right: Arc::new(right) }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Padding;
+
+    fn data(len: usize) -> Vec<Vec<u8>> {
+        (0..len).map(|i| i.to_string().into_bytes()).collect()
+    }
+
+    #[test]
+    fn it_saves_and_loads_trees() {
+        for padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            let config = TreeConfig::new(*padding);
+            let merkle_tree = MerkleTree::from_vec_with_config(data(11), config).unwrap();
+            let mut backend = MemoryBackend::new();
+
+            merkle_tree.save(&mut backend).unwrap();
+            let mut loaded = MerkleTree::load(&backend, merkle_tree.root_hash(), config).unwrap();
+
+            assert_eq!(loaded, merkle_tree);
+            assert_eq!(loaded.leaf_count(), 11);
+
+            // The loaded tree keeps working like the original one.
+            loaded.push(b"11".to_vec()).unwrap();
+            assert_eq!(loaded.root_hash(), MerkleTree::from_vec_with_config(data(12), config).unwrap().root_hash());
+        }
+    }
+
+    #[test]
+    fn it_deduplicates_identical_subtrees() {
+        let merkle_tree = MerkleTree::from_vec(vec![b"a".to_vec(), b"b".to_vec(), b"a".to_vec(), b"b".to_vec()]).unwrap();
+        let mut backend = MemoryBackend::new();
+        merkle_tree.save(&mut backend).unwrap();
+
+        // The root, the shared inner node and two leaves.
+        assert_eq!(backend.len(), 4);
+    }
+
+    #[test]
+    fn it_detects_missing_and_corrupted_nodes() {
+        let merkle_tree = MerkleTree::from_vec(data(4)).unwrap();
+        let mut backend = MemoryBackend::new();
+        merkle_tree.save(&mut backend).unwrap();
+
+        let leaf_hash = merkle_tree.get_leaf(3).unwrap().hash.to_vec();
+        backend.put(&leaf_hash, BackendNode::Leaf(b"tampered".to_vec())).unwrap();
+        assert_eq!(MerkleTree::<Vec<u8>>::load(&backend, merkle_tree.root_hash(), TreeConfig::default()), Err(BackendError::HashMismatch(leaf_hash)));
+
+        let unknown = vec![0; 64];
+        assert_eq!(MerkleTree::<Vec<u8>>::load(&backend, &unknown, TreeConfig::default()), Err(BackendError::MissingNode(unknown)));
+    }
+
+    #[test]
+    fn it_encodes_nodes_as_bytes() {
+        let inner = BackendNode::Inner { left: vec![1; 32], right: vec![2; 32], padded: true };
+        let leaf = BackendNode::Leaf(b"data".to_vec());
+
+        assert_eq!(BackendNode::from_bytes(&inner.to_bytes()), Some(inner));
+        assert_eq!(BackendNode::from_bytes(&leaf.to_bytes()), Some(leaf));
+        assert_eq!(BackendNode::from_bytes(&[]), None);
+        assert_eq!(BackendNode::from_bytes(&[INNER, 0, 0, 0]), None);
+        assert_eq!(BackendNode::from_bytes(&[3]), None);
+    }
+}
@@ -0,0 +1,438 @@
+//! Hashing of many leaves of the same length at once, for trees of fixed-size leaves such as
+//! 32-byte keys or 64-byte records.
+//!
+//! [`BatchDigest`] hashes a batch of inputs one at a time by default. SHA-256 and BLAKE2b hash
+//! inputs of the same length 8 and 4 at a time on x86-64 processors with AVX2, one input per
+//! SIMD lane, which is detected at runtime with the `std` feature.
+
+use alloc::vec::Vec;
+
+use blake2::{Blake2b, Blake2s};
+use digest::generic_array::GenericArray;
+use sha2::{Sha224, Sha256, Sha384, Sha512};
+use sha3::{Keccak256, Keccak512, Sha3_256, Sha3_512};
+
+use crate::{calculate_prefixed_hash, map_hashes, Digest, Hash};
+use crate::bitcoin::Sha256d;
+use crate::keyed::{KeyedBlake2b, MacKey};
+
+/// Digests able to hash several inputs in a single call, to build trees of fixed-size leaves with
+/// `MerkleTree::from_fixed_vec_with_config_and_digest`.
+pub trait BatchDigest: Digest + Sized {
+    /// Number of inputs of the same length hashed together on this processor, 1 without a fast
+    /// path.
+    fn lanes() -> usize {
+        1
+    }
+
+    /// Hashes `prefix ‖ input` for every input, in order. Inputs of the same length are hashed
+    /// `lanes()` at a time.
+    fn digest_batch(prefix: Option<u8>, inputs: &[&[u8]]) -> Vec<Hash<Self>> {
+        inputs.iter().map(|input| calculate_prefixed_hash::<Self>(prefix, input)).collect()
+    }
+}
+
+impl BatchDigest for Sha256 {
+    fn lanes() -> usize {
+        #[cfg(all(feature = "std", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return avx2::SHA256_LANES;
+            }
+        }
+
+        1
+    }
+
+    fn digest_batch(prefix: Option<u8>, inputs: &[&[u8]]) -> Vec<Hash<Self>> {
+        #[cfg(all(feature = "std", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("avx2") {
+                // SAFETY: AVX2 was detected on this processor.
+                return unsafe { avx2::sha256(prefix, inputs) };
+            }
+        }
+
+        inputs.iter().map(|input| calculate_prefixed_hash::<Sha256>(prefix, input)).collect()
+    }
+}
+
+impl BatchDigest for Blake2b {
+    fn lanes() -> usize {
+        #[cfg(all(feature = "std", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return avx2::BLAKE2B_LANES;
+            }
+        }
+
+        1
+    }
+
+    fn digest_batch(prefix: Option<u8>, inputs: &[&[u8]]) -> Vec<Hash<Self>> {
+        #[cfg(all(feature = "std", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("avx2") {
+                // SAFETY: AVX2 was detected on this processor.
+                return unsafe { avx2::blake2b(prefix, inputs) };
+            }
+        }
+
+        inputs.iter().map(|input| calculate_prefixed_hash::<Blake2b>(prefix, input)).collect()
+    }
+}
+
+/// Hashes the inputs with SHA-256, then their 32-byte hashes, all of the same length, again.
+impl BatchDigest for Sha256d {
+    fn lanes() -> usize {
+        Sha256::lanes()
+    }
+
+    fn digest_batch(prefix: Option<u8>, inputs: &[&[u8]]) -> Vec<Hash<Self>> {
+        let first = Sha256::digest_batch(prefix, inputs);
+        let first: Vec<&[u8]> = first.iter().map(|hash| &hash[..]).collect();
+
+        Sha256::digest_batch(None, &first).into_iter().map(|hash| GenericArray::clone_from_slice(&hash)).collect()
+    }
+}
+
+impl BatchDigest for Blake2s {}
+impl BatchDigest for Sha224 {}
+impl BatchDigest for Sha384 {}
+impl BatchDigest for Sha512 {}
+impl BatchDigest for Keccak256 {}
+impl BatchDigest for Keccak512 {}
+impl BatchDigest for Sha3_256 {}
+impl BatchDigest for Sha3_512 {}
+impl<K> BatchDigest for KeyedBlake2b<K> where K: MacKey {}
+#[cfg(feature = "blake3")]
+impl BatchDigest for crate::blake3::Blake3 {}
+
+/// Hashes the leaves holding `data` with `prefix`, `D::lanes()` leaves per call, spreading the
+/// batches across threads when the `rayon` feature is enabled.
+pub(crate) fn hash_leaves<D: BatchDigest>(prefix: Option<u8>, data: &[&[u8]]) -> Vec<Hash<D>> {
+    // Batches are large enough to fill the lanes many times and to be worth a thread.
+    let batches: Vec<&[&[u8]]> = data.chunks(1024).collect();
+
+    map_hashes(&batches, |batch| D::digest_batch(prefix, batch)).into_iter().flatten().collect()
+}
+
+#[cfg(all(feature = "std", target_arch = "x86_64"))]
+mod avx2 {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::arch::x86_64::*;
+
+    use digest::generic_array::GenericArray;
+
+    use super::{calculate_prefixed_hash, Blake2b, Hash, Sha256};
+
+    pub(super) const SHA256_LANES: usize = 8;
+    pub(super) const BLAKE2B_LANES: usize = 4;
+
+    const SHA256_K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+    ];
+
+    const SHA256_H: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+    const BLAKE2B_IV: [u64; 8] = [
+        0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+        0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+    ];
+
+    const BLAKE2B_SIGMA: [[usize; 16]; 10] = [
+        [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+        [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+        [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+        [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+        [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+        [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+        [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+        [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+        [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+        [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+    ];
+
+    /// Inputs of the same length, as groups of `lanes` inputs, with the inputs left over, or
+    /// groups of inputs of different lengths, hashed one at a time by `single`.
+    fn for_each_group<'a, H, F, S>(inputs: &[&'a [u8]], lanes: usize, mut group: F, single: S) -> Vec<H>
+        where F: FnMut(&[&'a [u8]], &mut Vec<H>), S: Fn(&[u8]) -> H {
+        let mut hashes = Vec::with_capacity(inputs.len());
+
+        for chunk in inputs.chunks(lanes) {
+            if chunk.len() == lanes && chunk.iter().all(|input| input.len() == chunk[0].len()) {
+                group(chunk, &mut hashes);
+            } else {
+                hashes.extend(chunk.iter().map(|input| single(input)));
+            }
+        }
+
+        hashes
+    }
+
+    /// `prefix ‖ input`, followed by the padding of SHA-256 up to a multiple of 64 bytes.
+    fn sha256_padded(prefix: Option<u8>, input: &[u8]) -> Vec<u8> {
+        let mut message = Vec::with_capacity(input.len() + 73);
+        message.extend(prefix);
+        message.extend_from_slice(input);
+
+        let bits = (message.len() as u64) * 8;
+        message.push(0x80);
+        message.resize(message.len() + (64 + 56 - message.len() % 64) % 64, 0);
+        message.extend_from_slice(&bits.to_be_bytes());
+
+        message
+    }
+
+    /// Hashes every input with SHA-256, 8 inputs of the same length at a time.
+    ///
+    /// # Safety
+    ///
+    /// The processor must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn sha256(prefix: Option<u8>, inputs: &[&[u8]]) -> Vec<Hash<Sha256>> {
+        for_each_group(inputs, SHA256_LANES, |group, hashes| {
+            let messages: Vec<Vec<u8>> = group.iter().map(|input| sha256_padded(prefix, input)).collect();
+            // SAFETY: the caller checked that AVX2 is supported.
+            let states = unsafe { sha256_blocks(&messages) };
+
+            hashes.extend((0..SHA256_LANES).map(|lane| {
+                let mut hash = GenericArray::default();
+                for (word, state) in hash.chunks_mut(4).zip(&states) {
+                    word.copy_from_slice(&state[lane].to_be_bytes());
+                }
+                hash
+            }));
+        }, |input| calculate_prefixed_hash::<Sha256>(prefix, input))
+    }
+
+    /// State words of SHA-256 after compressing 8 padded messages of the same length, by word
+    /// then by lane.
+    #[target_feature(enable = "avx2")]
+    unsafe fn sha256_blocks(messages: &[Vec<u8>]) -> [[u32; 8]; 8] {
+        macro_rules! rotr {
+            ($x:expr, $n:literal) => { _mm256_or_si256(_mm256_srli_epi32($x, $n), _mm256_slli_epi32($x, 32 - $n)) };
+        }
+
+        let mut state = [_mm256_setzero_si256(); 8];
+        for (word, &h) in state.iter_mut().zip(&SHA256_H) {
+            *word = _mm256_set1_epi32(h as i32);
+        }
+
+        let word = |lane: usize, offset: usize| {
+            let bytes = &messages[lane][offset..offset + 4];
+            u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i32
+        };
+
+        for block in (0..messages[0].len()).step_by(64) {
+            let mut w = [_mm256_setzero_si256(); 64];
+
+            for (t, w) in w.iter_mut().enumerate().take(16) {
+                let offset = block + 4 * t;
+                *w = _mm256_setr_epi32(
+                    word(0, offset), word(1, offset), word(2, offset), word(3, offset),
+                    word(4, offset), word(5, offset), word(6, offset), word(7, offset),
+                );
+            }
+
+            for t in 16..64 {
+                let s0 = _mm256_xor_si256(_mm256_xor_si256(rotr!(w[t - 15], 7), rotr!(w[t - 15], 18)), _mm256_srli_epi32(w[t - 15], 3));
+                let s1 = _mm256_xor_si256(_mm256_xor_si256(rotr!(w[t - 2], 17), rotr!(w[t - 2], 19)), _mm256_srli_epi32(w[t - 2], 10));
+                w[t] = _mm256_add_epi32(_mm256_add_epi32(s1, w[t - 7]), _mm256_add_epi32(s0, w[t - 16]));
+            }
+
+            let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+
+            for (t, &k) in SHA256_K.iter().enumerate() {
+                let s1 = _mm256_xor_si256(_mm256_xor_si256(rotr!(e, 6), rotr!(e, 11)), rotr!(e, 25));
+                let ch = _mm256_xor_si256(_mm256_and_si256(e, f), _mm256_andnot_si256(e, g));
+                let t1 = _mm256_add_epi32(
+                    _mm256_add_epi32(_mm256_add_epi32(h, s1), _mm256_add_epi32(ch, _mm256_set1_epi32(k as i32))),
+                    w[t],
+                );
+                let s0 = _mm256_xor_si256(_mm256_xor_si256(rotr!(a, 2), rotr!(a, 13)), rotr!(a, 22));
+                let maj = _mm256_xor_si256(_mm256_xor_si256(_mm256_and_si256(a, b), _mm256_and_si256(a, c)), _mm256_and_si256(b, c));
+                let t2 = _mm256_add_epi32(s0, maj);
+
+                h = g;
+                g = f;
+                f = e;
+                e = _mm256_add_epi32(d, t1);
+                d = c;
+                c = b;
+                b = a;
+                a = _mm256_add_epi32(t1, t2);
+            }
+
+            for (word, value) in state.iter_mut().zip(&[a, b, c, d, e, f, g, h]) {
+                *word = _mm256_add_epi32(*word, *value);
+            }
+        }
+
+        let mut words = [[0; 8]; 8];
+        for (lanes, word) in words.iter_mut().zip(&state) {
+            // SAFETY: `lanes` holds 8 32-bit integers, the size of a 256-bit vector.
+            unsafe { _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, *word) };
+        }
+
+        words
+    }
+
+    /// Hashes every input with BLAKE2b, 4 inputs of the same length at a time.
+    ///
+    /// # Safety
+    ///
+    /// The processor must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn blake2b(prefix: Option<u8>, inputs: &[&[u8]]) -> Vec<Hash<Blake2b>> {
+        for_each_group(inputs, BLAKE2B_LANES, |group, hashes| {
+            let messages: Vec<Vec<u8>> = group.iter().map(|input| prefix.into_iter().chain(input.iter().copied()).collect()).collect();
+            // SAFETY: the caller checked that AVX2 is supported.
+            let states = unsafe { blake2b_blocks(&messages) };
+
+            hashes.extend((0..BLAKE2B_LANES).map(|lane| {
+                let mut hash = GenericArray::default();
+                for (word, state) in hash.chunks_mut(8).zip(&states) {
+                    word.copy_from_slice(&state[lane].to_le_bytes());
+                }
+                hash
+            }));
+        }, |input| calculate_prefixed_hash::<Blake2b>(prefix, input))
+    }
+
+    /// State words of BLAKE2b with 64-byte output after compressing 4 messages of the same
+    /// length, by word then by lane.
+    #[target_feature(enable = "avx2")]
+    unsafe fn blake2b_blocks(messages: &[Vec<u8>]) -> [[u64; 4]; 8] {
+        macro_rules! rotr {
+            ($x:expr, $n:literal) => { _mm256_or_si256(_mm256_srli_epi64($x, $n), _mm256_slli_epi64($x, 64 - $n)) };
+        }
+
+        macro_rules! g {
+            ($v:ident, $a:literal, $b:literal, $c:literal, $d:literal, $x:expr, $y:expr) => {
+                $v[$a] = _mm256_add_epi64(_mm256_add_epi64($v[$a], $v[$b]), $x);
+                $v[$d] = rotr!(_mm256_xor_si256($v[$d], $v[$a]), 32);
+                $v[$c] = _mm256_add_epi64($v[$c], $v[$d]);
+                $v[$b] = rotr!(_mm256_xor_si256($v[$b], $v[$c]), 24);
+                $v[$a] = _mm256_add_epi64(_mm256_add_epi64($v[$a], $v[$b]), $y);
+                $v[$d] = rotr!(_mm256_xor_si256($v[$d], $v[$a]), 16);
+                $v[$c] = _mm256_add_epi64($v[$c], $v[$d]);
+                $v[$b] = rotr!(_mm256_xor_si256($v[$b], $v[$c]), 63);
+            };
+        }
+
+        let len = messages[0].len();
+        let mut state = [_mm256_setzero_si256(); 8];
+        for (word, &iv) in state.iter_mut().zip(&BLAKE2B_IV) {
+            *word = _mm256_set1_epi64x(iv as i64);
+        }
+        // Parameter block: 64-byte output, no key, fanout and depth of 1.
+        state[0] = _mm256_xor_si256(state[0], _mm256_set1_epi64x(0x0101_0040));
+
+        // An empty message is still compressed once, as a block of zeros.
+        let blocks = len.div_ceil(128).max(1);
+        let mut block = vec![[0u8; 128]; messages.len()];
+
+        for index in 0..blocks {
+            let start = index * 128;
+            let end = len.min(start + 128);
+
+            for (block, message) in block.iter_mut().zip(messages) {
+                *block = [0; 128];
+                block[..end - start].copy_from_slice(&message[start..end]);
+            }
+
+            let word = |lane: usize, word: usize| {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(&block[lane][8 * word..8 * word + 8]);
+                u64::from_le_bytes(bytes) as i64
+            };
+
+            let mut m = [_mm256_setzero_si256(); 16];
+            for (i, m) in m.iter_mut().enumerate() {
+                *m = _mm256_set_epi64x(word(3, i), word(2, i), word(1, i), word(0, i));
+            }
+
+            let mut v = [_mm256_setzero_si256(); 16];
+            v[..8].copy_from_slice(&state);
+            for (word, &iv) in v[8..].iter_mut().zip(&BLAKE2B_IV) {
+                *word = _mm256_set1_epi64x(iv as i64);
+            }
+            v[12] = _mm256_xor_si256(v[12], _mm256_set1_epi64x(end as i64));
+            if index == blocks - 1 {
+                v[14] = _mm256_xor_si256(v[14], _mm256_set1_epi64x(-1));
+            }
+
+            for round in 0..12 {
+                let s = &BLAKE2B_SIGMA[round % 10];
+
+                g!(v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+                g!(v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+                g!(v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+                g!(v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+                g!(v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+                g!(v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+                g!(v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+                g!(v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+            }
+
+            for (i, word) in state.iter_mut().enumerate() {
+                *word = _mm256_xor_si256(*word, _mm256_xor_si256(v[i], v[i + 8]));
+            }
+        }
+
+        let mut words = [[0; 4]; 8];
+        for (lanes, word) in words.iter_mut().zip(&state) {
+            // SAFETY: `lanes` holds 4 64-bit integers, the size of a 256-bit vector.
+            unsafe { _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, *word) };
+        }
+
+        words
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inputs of every length up to three blocks, in batches of 9 of the same length, so that
+    /// each batch fills the lanes and leaves one over, then all the lengths mixed.
+    fn check_batches<D: BatchDigest>() {
+        for prefix in &[None, Some(0x00)] {
+            let inputs: Vec<Vec<u8>> = (0..=260).flat_map(|len| (0..9u8).map(move |i| vec![i.wrapping_mul(31) ^ len as u8; len])).collect();
+            let inputs: Vec<&[u8]> = inputs.iter().map(Vec::as_slice).collect();
+            let expected: Vec<Hash<D>> = inputs.iter().map(|input| calculate_prefixed_hash::<D>(*prefix, input)).collect();
+
+            for batch in inputs.chunks(9).zip(expected.chunks(9)) {
+                assert_eq!(D::digest_batch(*prefix, batch.0), batch.1);
+            }
+            assert_eq!(D::digest_batch(*prefix, &inputs[4..]), &expected[4..]);
+        }
+    }
+
+    #[test]
+    fn it_hashes_batches_like_single_inputs() {
+        check_batches::<Sha256>();
+        check_batches::<Blake2b>();
+        check_batches::<Sha256d>();
+        check_batches::<Keccak256>();
+    }
+
+    #[cfg(all(feature = "std", target_arch = "x86_64"))]
+    #[test]
+    fn it_uses_simd_lanes_when_available() {
+        let lanes = if is_x86_feature_detected!("avx2") { (8, 4) } else { (1, 1) };
+
+        assert_eq!((Sha256::lanes(), Blake2b::lanes()), lanes);
+        assert_eq!(Sha256d::lanes(), Sha256::lanes());
+        assert_eq!(Sha512::lanes(), 1);
+    }
+}
@@ -0,0 +1,220 @@
+//! `merkle`: computes the Merkle roots of files and directories, and generates and verifies
+//! inclusion proofs of the chunks of a file or of the files of a directory.
+//!
+//! Files are split in chunks of `--chunk-size` bytes, one leaf per chunk. Directories have one
+//! leaf per file below them, in the byte order of their relative paths, holding the path, a
+//! zero byte and the root of the file. Trees are hashed with Blake2b and the default config.
+//! Roots and proofs are written in hex, proofs in the format of `Proof::to_bytes`.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process;
+
+use blake2::Blake2b;
+use clap::{value_parser, Arg, ArgMatches, Command};
+use merkle_tree::{Digest, MerkleTree, Proof, TreeConfig};
+
+const DEFAULT_CHUNK_SIZE: usize = 1 << 20;
+
+fn main() {
+    let path = || Arg::new("path").required(true).help("File or directory");
+    let selector = || Arg::new("selector").required(true).help("Chunk index for a file, relative path of a file for a directory");
+    let chunk_size = Arg::new("chunk-size")
+        .long("chunk-size")
+        .global(true)
+        .value_parser(value_parser!(usize))
+        .default_value("1048576")
+        .help("Size of the chunks files are split in, in bytes");
+
+    let matches = Command::new("merkle")
+        .about("Merkle roots and inclusion proofs of files and directories")
+        .subcommand_required(true)
+        .arg(chunk_size)
+        .subcommand(Command::new("root").about("Prints the root of a file or directory").arg(path()))
+        .subcommand(Command::new("proof").about("Prints the proof of a chunk of a file or of a file of a directory").arg(path()).arg(selector()))
+        .subcommand(
+            Command::new("verify")
+                .about("Verifies the proof read from stdin against a root")
+                .arg(Arg::new("root").required(true).help("Root, in hex"))
+                .arg(path())
+                .arg(selector()),
+        )
+        .get_matches();
+
+    if let Err(error) = run(&matches) {
+        eprintln!("merkle: {}", error);
+        process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> io::Result<()> {
+    let chunk_size = *matches.get_one::<usize>("chunk-size").unwrap_or(&DEFAULT_CHUNK_SIZE);
+    let (command, args) = matches.subcommand().expect("a subcommand is required");
+    let path = Path::new(args.get_one::<String>("path").expect("path is required"));
+
+    match command {
+        "root" => println!("{}", encode_hex(&root(path, chunk_size)?)),
+        "proof" => {
+            let proof = proof(path, args.get_one::<String>("selector").expect("selector is required"), chunk_size)?;
+            println!("{}", encode_hex(&proof.to_bytes()));
+        }
+        "verify" => {
+            let root = decode_hex(args.get_one::<String>("root").expect("root is required"))?;
+            let selector = args.get_one::<String>("selector").expect("selector is required");
+
+            let mut encoded = String::new();
+            io::stdin().read_to_string(&mut encoded)?;
+            let proof = Proof::<Blake2b>::from_bytes(&decode_hex(encoded.trim())?).map_err(invalid_input)?;
+
+            let leaf = leaf_data(path, selector, chunk_size, proof.leaf_index)?;
+            proof.verify_with_config(&root, &leaf, TreeConfig::default()).map_err(invalid_input)?;
+            println!("ok");
+        }
+        _ => unreachable!("unknown subcommand {}", command),
+    }
+
+    Ok(())
+}
+
+fn root(path: &Path, chunk_size: usize) -> io::Result<Vec<u8>> {
+    if path.is_dir() {
+        Ok(directory_tree(path, chunk_size)?.root_hash().to_vec())
+    } else {
+        file_root(path, chunk_size)
+    }
+}
+
+/// Proof of the leaf selected by `selector`: a chunk index for a file, a relative path for a
+/// directory.
+fn proof(path: &Path, selector: &str, chunk_size: usize) -> io::Result<Proof<Blake2b>> {
+    let proof = if path.is_dir() {
+        let index = files(path)?.iter().position(|file| file == selector).ok_or_else(|| invalid_input("no such file in the directory"))?;
+        directory_tree(path, chunk_size)?.proof(index)
+    } else {
+        let index = selector.parse().map_err(|_| invalid_input("chunk index must be a number"))?;
+        MerkleTree::from_reader(BufReader::new(File::open(path)?), chunk_size)?.proof(index)
+    };
+
+    proof.map_err(invalid_input)
+}
+
+fn directory_tree(path: &Path, chunk_size: usize) -> io::Result<MerkleTree<Vec<u8>>> {
+    let leaves = files(path)?
+        .into_iter()
+        .map(|relative| Ok(directory_leaf(&relative, &file_root(&path.join(&relative), chunk_size)?)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    MerkleTree::from_vec(leaves).map_err(|_| invalid_input("directory has no files"))
+}
+
+/// Root of the chunks of a file, or the hash of nothing for an empty file.
+fn file_root(path: &Path, chunk_size: usize) -> io::Result<Vec<u8>> {
+    if fs::metadata(path)?.len() == 0 {
+        return Ok(Blake2b::digest(&[]).to_vec());
+    }
+
+    let tree = MerkleTree::from_reader(BufReader::new(File::open(path)?), chunk_size)?;
+    Ok(tree.root_hash().to_vec())
+}
+
+fn directory_leaf(relative: &str, root: &[u8]) -> Vec<u8> {
+    [relative.as_bytes(), &[0], root].concat()
+}
+
+/// Relative paths of the files below `directory`, with `/` separators, in byte order.
+/// Symbolic links are not followed.
+fn files(directory: &Path) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut pending = vec![String::new()];
+
+    while let Some(relative) = pending.pop() {
+        for entry in fs::read_dir(directory.join(&relative))? {
+            let entry = entry?;
+            let name = entry.file_name().into_string().map_err(|_| invalid_input("file name is not UTF-8"))?;
+            let path = if relative.is_empty() { name } else { format!("{}/{}", relative, name) };
+            let file_type = entry.file_type()?;
+
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file() {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Data of the leaf selected by `selector`, which must be the leaf the proof is for.
+fn leaf_data(path: &Path, selector: &str, chunk_size: usize, proven_index: usize) -> io::Result<Vec<u8>> {
+    if path.is_dir() {
+        let relative = selector.trim_start_matches('/');
+        return Ok(directory_leaf(relative, &file_root(&path.join(relative), chunk_size)?));
+    }
+
+    let index: usize = selector.parse().map_err(|_| invalid_input("chunk index must be a number"))?;
+
+    if index != proven_index {
+        return Err(invalid_input(format!("proof is for chunk {}, not {}", proven_index, index)));
+    }
+
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start((index as u64) * chunk_size as u64))?;
+    read_chunk(&mut file, chunk_size)
+}
+
+/// Reads up to `chunk_size` bytes, fewer only at the end of the stream.
+fn read_chunk<R: Read>(reader: &mut R, chunk_size: usize) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(chunk_size);
+    reader.by_ref().take(chunk_size as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> io::Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(invalid_input("invalid hex"));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid_input("invalid hex")))
+        .collect()
+}
+
+fn invalid_input<E>(error: E) -> io::Error where E: Into<Box<dyn std::error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::InvalidInput, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_lists_files_in_path_order() {
+        let directory = std::env::temp_dir().join(format!("merkle-cli-files-{}", process::id()));
+        fs::create_dir_all(directory.join("b/c")).unwrap();
+        fs::write(directory.join("b/c/d"), b"d").unwrap();
+        fs::write(directory.join("b.txt"), b"").unwrap();
+        fs::write(directory.join("a"), b"a").unwrap();
+
+        let files = files(&directory);
+        let index = proof(&directory, "b/c/d", 4).map(|proof| proof.leaf_index);
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(files.unwrap(), vec!["a", "b.txt", "b/c/d"]);
+        assert_eq!(index.unwrap(), 2);
+    }
+
+    #[test]
+    fn it_decodes_the_hex_it_encodes() {
+        assert_eq!(decode_hex(&encode_hex(&[0, 15, 255])).unwrap(), vec![0, 15, 255]);
+        assert!(decode_hex("abc").is_err());
+        assert!(decode_hex("zz").is_err());
+    }
+}
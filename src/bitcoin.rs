@@ -0,0 +1,170 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use digest::{FixedOutput, Input, Reset};
+use digest::generic_array::GenericArray;
+use digest::generic_array::typenum::U32;
+use sha2::{Digest, Sha256};
+
+use crate::{from_hex, to_hex};
+use crate::config::{Padding, TreeConfig};
+use crate::error::MerkleError;
+use crate::proof::Proof;
+use crate::tree::{MerkleLeaf, MerkleTree, Node};
+
+/// Double SHA-256, `SHA256(SHA256(data))`, the hash Bitcoin uses for transactions and blocks.
+#[derive(Clone, Default)]
+pub struct Sha256d(Sha256);
+
+impl Input for Sha256d {
+    fn input<B: AsRef<[u8]>>(&mut self, data: B) {
+        Input::input(&mut self.0, data);
+    }
+}
+
+impl FixedOutput for Sha256d {
+    type OutputSize = U32;
+
+    fn fixed_result(self) -> GenericArray<u8, U32> {
+        Sha256::digest(&self.0.fixed_result())
+    }
+}
+
+impl Reset for Sha256d {
+    fn reset(&mut self) {
+        Reset::reset(&mut self.0);
+    }
+}
+
+/// A transaction id or block Merkle root. Bitcoin hashes these bytes as they are, but
+/// displays them byte-reversed, so hex strings are read and written in reverse order.
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+pub struct BitcoinHash(pub [u8; 32]);
+
+impl BitcoinHash {
+    /// Parses a hash as displayed by block explorers and `bitcoin-cli`.
+    pub fn from_hex(hex: &str) -> Result<BitcoinHash, MerkleError> {
+        let bytes = from_hex(hex)?;
+
+        if bytes.len() != 32 {
+            return Err(MerkleError::InvalidHex);
+        }
+
+        let mut hash = [0; 32];
+        for (byte, reversed) in hash.iter_mut().zip(bytes.iter().rev()) {
+            *byte = *reversed;
+        }
+
+        Ok(BitcoinHash(hash))
+    }
+
+    pub fn to_hex(&self) -> String {
+        let mut reversed = self.0;
+        reversed.reverse();
+        to_hex(&reversed)
+    }
+}
+
+impl fmt::Display for BitcoinHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::Debug for BitcoinHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BitcoinHash({})", self)
+    }
+}
+
+impl MerkleTree<BitcoinHash, Sha256d> {
+    /// Creates a MerkleTree whose root is the Merkle root of a Bitcoin block with these
+    /// transaction ids, in block order. Txids are used as leaf hashes without hashing them
+    /// again, and the last hash of a level with an odd number of hashes is paired with itself.
+    pub fn from_txids(txids: Vec<BitcoinHash>) -> Result<MerkleTree<BitcoinHash, Sha256d>, MerkleError> {
+        let leaves = txids.into_iter()
+            .map(|txid| Node::Leaf(MerkleLeaf { hash: GenericArray::clone_from_slice(&txid.0), data: txid }))
+            .collect();
+
+        MerkleTree::from_leaves(leaves, TreeConfig::new(Padding::DuplicateLast))
+    }
+
+    /// Block Merkle root, as it appears in the block header.
+    pub fn merkle_root(&self) -> BitcoinHash {
+        let mut root = [0; 32];
+        root.copy_from_slice(self.root_hash());
+        BitcoinHash(root)
+    }
+}
+
+impl Proof<Sha256d> {
+    /// Checks that the transaction `txid` is included in the block with Merkle root `merkle_root`.
+    pub fn verify_txid(&self, merkle_root: &BitcoinHash, txid: &BitcoinHash) -> Result<(), MerkleError> {
+        self.verify_hash_with_config(&merkle_root.0, &txid.0, TreeConfig::new(Padding::DuplicateLast))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn txids(hexes: &[&str]) -> Vec<BitcoinHash> {
+        hexes.iter().map(|hex| BitcoinHash::from_hex(hex).unwrap()).collect()
+    }
+
+    // Block 100000.
+    const BLOCK_100000_TXIDS: [&str; 4] = [
+        "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+        "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+        "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+        "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+    ];
+    const BLOCK_100000_ROOT: &str = "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766";
+
+    #[test]
+    fn it_matches_mainnet_block_roots() {
+        let merkle_tree = MerkleTree::from_txids(txids(&BLOCK_100000_TXIDS)).unwrap();
+        assert_eq!(merkle_tree.merkle_root().to_hex(), BLOCK_100000_ROOT);
+
+        // Block 170, holding the first transaction between two people.
+        let merkle_tree = MerkleTree::from_txids(txids(&[
+            "b1fea52486ce0c62bb442b530a3f0132b826c74e473d1f2c220bfa78111c5082",
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+        ])).unwrap();
+        assert_eq!(merkle_tree.merkle_root().to_hex(), "7dac2c5666815c17a3b36427de37bb9d2e2c5ccec3f8633eb91a4205cb4c10ff");
+    }
+
+    #[test]
+    fn it_uses_the_coinbase_txid_as_root_of_single_transaction_blocks() {
+        // Genesis block.
+        let coinbase = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        let merkle_tree = MerkleTree::from_txids(txids(&[coinbase])).unwrap();
+
+        assert_eq!(merkle_tree.merkle_root().to_hex(), coinbase);
+    }
+
+    #[test]
+    fn it_duplicates_the_last_hash_of_odd_levels() {
+        let ids = txids(&BLOCK_100000_TXIDS[..3]);
+        let merkle_tree = MerkleTree::from_txids(ids.clone()).unwrap();
+
+        let pair = |left: &[u8], right: &[u8]| Sha256d::digest(&[left, right].concat());
+        let expected = pair(&pair(&ids[0].0, &ids[1].0), &pair(&ids[2].0, &ids[2].0));
+
+        assert_eq!(merkle_tree.root_hash(), expected.as_slice());
+    }
+
+    #[test]
+    fn it_verifies_txid_proofs() {
+        let ids = txids(&BLOCK_100000_TXIDS);
+        let merkle_tree = MerkleTree::from_txids(ids.clone()).unwrap();
+        let root = BitcoinHash::from_hex(BLOCK_100000_ROOT).unwrap();
+
+        for (index, txid) in ids.iter().enumerate() {
+            assert_eq!(merkle_tree.proof(index).unwrap().verify_txid(&root, txid), Ok(()));
+        }
+
+        assert_eq!(merkle_tree.proof(0).unwrap().verify_txid(&root, &ids[1]), Err(MerkleError::RootMismatch));
+    }
+}
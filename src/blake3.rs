@@ -0,0 +1,76 @@
+use digest::{FixedOutput, Input, Reset};
+use digest::generic_array::GenericArray;
+use digest::generic_array::typenum::U32;
+
+/// Inputs from this length on are hashed on several threads with the `rayon` feature, below
+/// which the BLAKE3 authors found that splitting the work does not pay off.
+#[cfg(feature = "rayon")]
+const PARALLEL_THRESHOLD: usize = 128 * 1024;
+
+/// BLAKE3 with a 32-byte output, as a digest usable with every tree of the crate.
+///
+/// BLAKE3 hashes its input as a tree of 1 KiB chunks itself, so large leaves, such as the
+/// chunks of `MerkleTree::from_reader`, are hashed several chunks at a time with the SIMD
+/// instructions of the CPU, and on several threads with the `rayon` feature.
+#[derive(Clone, Default)]
+pub struct Blake3(::blake3::Hasher);
+
+impl Input for Blake3 {
+    fn input<B: AsRef<[u8]>>(&mut self, data: B) {
+        let data = data.as_ref();
+
+        #[cfg(feature = "rayon")]
+        {
+            if data.len() >= PARALLEL_THRESHOLD {
+                self.0.update_rayon(data);
+                return;
+            }
+        }
+
+        self.0.update(data);
+    }
+}
+
+impl FixedOutput for Blake3 {
+    type OutputSize = U32;
+
+    fn fixed_result(self) -> GenericArray<u8, U32> {
+        GenericArray::clone_from_slice(self.0.finalize().as_bytes())
+    }
+}
+
+impl Reset for Blake3 {
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use digest::Digest;
+    use crate::{to_hex, MerkleTree};
+
+    #[test]
+    fn it_matches_the_reference_implementation() {
+        assert_eq!(to_hex(&Blake3::digest(b"abc")), "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+
+        let large: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let mut hasher = Blake3::new();
+        Digest::input(&mut hasher, &large[..1000]);
+        Digest::input(&mut hasher, &large[1000..]);
+        assert_eq!(hasher.result().as_slice(), ::blake3::hash(&large).as_bytes());
+    }
+
+    #[test]
+    fn it_builds_trees() {
+        let merkle_tree = MerkleTree::<_, Blake3>::from_vec_with_digest(vec!["a", "b"]).unwrap();
+
+        let mut root = ::blake3::Hasher::new();
+        root.update(::blake3::hash(b"a").as_bytes());
+        root.update(::blake3::hash(b"b").as_bytes());
+
+        assert_eq!(merkle_tree.root_hash(), root.finalize().as_bytes());
+        assert_eq!(merkle_tree.proof(1).unwrap().verify(merkle_tree.root_hash(), b"b"), Ok(()));
+    }
+}
@@ -0,0 +1,70 @@
+use blake2::Blake2b;
+use digest::Digest;
+
+use crate::config::TreeConfig;
+use crate::error::MerkleError;
+use crate::tree::MerkleTree;
+
+impl<'a> MerkleTree<&'a [u8], Blake2b> {
+    /// Splits `buffer` in chunks of `chunk_size` bytes, the last one possibly shorter, and
+    /// builds a tree with one leaf per chunk, hashing with Blake2b and the default config.
+    pub fn from_chunks(buffer: &'a [u8], chunk_size: usize) -> Result<MerkleTree<&'a [u8]>, MerkleError> {
+        MerkleTree::from_chunks_with_config_and_digest(buffer, chunk_size, TreeConfig::default())
+    }
+
+    /// Splits `buffer` in chunks of `chunk_size` bytes, hashing with Blake2b.
+    pub fn from_chunks_with_config(buffer: &'a [u8], chunk_size: usize, config: TreeConfig) -> Result<MerkleTree<&'a [u8]>, MerkleError> {
+        MerkleTree::from_chunks_with_config_and_digest(buffer, chunk_size, config)
+    }
+}
+
+impl<'a, D> MerkleTree<&'a [u8], D> where D: Digest {
+    /// Splits `buffer` in chunks of `chunk_size` bytes, hashing with the digest `D`. The leaves
+    /// borrow their chunks from `buffer` instead of copying them, so the tree cannot outlive it.
+    /// Any `T: LeafEncode` works the same way, such as `Cow<[u8]>` for trees mixing borrowed
+    /// and owned leaves. Fails with `EmptyInput` when `buffer` is empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn from_chunks_with_config_and_digest(buffer: &'a [u8], chunk_size: usize, config: TreeConfig) -> Result<MerkleTree<&'a [u8], D>, MerkleError> {
+        MerkleTree::from_vec_with_config_and_digest(buffer.chunks(chunk_size).collect(), config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+    use crate::{Padding, Prefixes};
+
+    #[test]
+    fn it_borrows_chunks_from_the_buffer() {
+        let buffer: Vec<u8> = (0..=255).collect();
+        let merkle_tree = MerkleTree::from_chunks(&buffer, 10).unwrap();
+
+        assert_eq!(merkle_tree.leaf_count(), 26);
+        assert_eq!(merkle_tree.get_leaf(25).unwrap().data, &buffer[250..]);
+        assert!(core::ptr::eq(merkle_tree.get_leaf(1).unwrap().data.as_ptr(), &buffer[10]));
+
+        let config = TreeConfig { prefixes: Some(Prefixes::RFC6962), ..TreeConfig::new(Padding::Promote) };
+        let expected = MerkleTree::from_vec_with_config(buffer.chunks(10).map(<[u8]>::to_vec).collect(), config).unwrap();
+        assert_eq!(MerkleTree::from_chunks_with_config(&buffer, 10, config).unwrap().root_hash(), expected.root_hash());
+    }
+
+    #[test]
+    fn it_mixes_borrowed_and_owned_leaves() {
+        let buffer = b"abc".to_vec();
+        let leaves = vec![Cow::Borrowed(&buffer[..1]), Cow::Owned(b"x".to_vec()), Cow::Borrowed(&buffer[2..])];
+
+        let merkle_tree = MerkleTree::from_vec(leaves).unwrap();
+
+        assert_eq!(merkle_tree.root_hash(), MerkleTree::from_vec(vec!["a", "x", "c"]).unwrap().root_hash());
+    }
+
+    #[test]
+    fn it_fails_on_empty_buffers() {
+        assert_eq!(MerkleTree::from_chunks(&[], 4), Err(MerkleError::EmptyInput));
+    }
+}
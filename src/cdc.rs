@@ -0,0 +1,184 @@
+//! Content-defined chunking with FastCDC, which cuts a stream where the bytes before the cut
+//! match a pattern rather than every `n` bytes.
+//!
+//! Cut points only depend on the bytes around them, so inserting or removing bytes in a file
+//! moves the cuts next to the edit and leaves the chunks elsewhere unchanged, and the trees of
+//! two versions of a file share all but a few leaves. A gear hash rolls over the bytes after the
+//! minimum chunk size, and a cut is made where its top bits are zero, with more bits required
+//! before the average size than after it so that chunk sizes stay close to the average.
+
+/// Parameters of FastCDC chunking.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct FastCdc {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+    /// Bits of the gear hash that must be zero for a cut before the average size.
+    mask_small: u64,
+    /// Bits of the gear hash that must be zero for a cut after the average size.
+    mask_large: u64,
+}
+
+impl FastCdc {
+    /// Creates chunking parameters for chunks of `min_size` to `max_size` bytes, averaging
+    /// about `avg_size` bytes. Only the last chunk of a stream can be shorter than `min_size`.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless `0 < min_size <= avg_size <= max_size` and `avg_size` is at least 4.
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> FastCdc {
+        assert!(min_size > 0 && min_size <= avg_size && avg_size <= max_size, "chunk sizes must satisfy 0 < min <= avg <= max");
+        assert!(avg_size >= 4, "average chunk size must be at least 4 bytes");
+
+        let bits = avg_size.ilog2();
+
+        FastCdc {
+            min_size,
+            avg_size,
+            max_size,
+            mask_small: top_bits(bits + 2),
+            mask_large: top_bits(bits - 2),
+        }
+    }
+
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    pub fn avg_size(&self) -> usize {
+        self.avg_size
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Length of the first chunk of `data`, all of it if no cut is found, as at the end of a
+    /// stream.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        let (mut scanned, mut hash) = (0, 0);
+        self.find_cut(data, &mut scanned, &mut hash).unwrap_or(data.len())
+    }
+
+    /// Splits `data` into chunks.
+    pub fn chunks<'a>(&self, mut data: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let cdc = *self;
+
+        core::iter::from_fn(move || {
+            if data.is_empty() {
+                return None;
+            }
+
+            let (chunk, rest) = data.split_at(cdc.cut(data));
+            data = rest;
+            Some(chunk)
+        })
+    }
+
+    /// Looks for the end of the chunk starting at the start of `data`, resuming from the first
+    /// `scanned` bytes with their gear hash `hash`, so that a chunk arriving in pieces is only
+    /// scanned once. Returns `None` when more data is needed, leaving `scanned` and `hash` to
+    /// resume from.
+    pub(crate) fn find_cut(&self, data: &[u8], scanned: &mut usize, hash: &mut u64) -> Option<usize> {
+        let end = data.len().min(self.max_size);
+        let mut position = (*scanned).max(self.min_size);
+
+        while position < end {
+            *hash = (*hash << 1).wrapping_add(GEAR[data[position] as usize]);
+            let mask = if position < self.avg_size { self.mask_small } else { self.mask_large };
+            position += 1;
+
+            if *hash & mask == 0 {
+                return Some(position);
+            }
+        }
+
+        if data.len() >= self.max_size {
+            return Some(self.max_size);
+        }
+
+        *scanned = position;
+        None
+    }
+}
+
+impl Default for FastCdc {
+    /// Chunks of 2 KiB to 64 KiB, averaging 8 KiB.
+    fn default() -> Self {
+        FastCdc::new(2 * 1024, 8 * 1024, 64 * 1024)
+    }
+}
+
+/// Mask of the `bits` most significant bits, which the gear hash mixes the most bytes into.
+fn top_bits(bits: u32) -> u64 {
+    !(u64::MAX >> bits)
+}
+
+/// Random value of every byte for the gear hash, from a fixed seed so that cut points never
+/// change.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x6d65_726b_6c65_6364;
+    let mut index = 0;
+
+    // SplitMix64.
+    while index < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut value = state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[index] = value ^ (value >> 31);
+        index += 1;
+    }
+
+    table
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// Pseudo-random bytes, so that chunks have the sizes of real data.
+    pub(crate) fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+
+        (0..len).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn it_cuts_chunks_within_bounds() {
+        let cdc = FastCdc::new(256, 1024, 4096);
+        let data = noise(200_000, 1);
+        let chunks: Vec<&[u8]> = cdc.chunks(&data).collect();
+
+        assert_eq!(chunks.concat(), data);
+        assert!(chunks[..chunks.len() - 1].iter().all(|chunk| chunk.len() >= 256 && chunk.len() <= 4096));
+
+        let average = data.len() / chunks.len();
+        assert!(average > 512 && average < 2048, "average chunk size {}", average);
+
+        // Data without any cut point is cut at the maximum size.
+        assert_eq!(cdc.cut(&[0; 10_000]), 4096);
+        assert_eq!(cdc.cut(&data[..100]), 100);
+    }
+
+    #[test]
+    fn it_only_moves_cuts_next_to_edits() {
+        let cdc = FastCdc::new(256, 1024, 4096);
+        let data = noise(100_000, 2);
+        let mut edited = data.clone();
+        edited.splice(50_000..50_000, b"inserted".iter().copied());
+
+        let chunks: Vec<&[u8]> = cdc.chunks(&data).collect();
+        let edited_chunks: Vec<&[u8]> = cdc.chunks(&edited).collect();
+        let changed = edited_chunks.iter().filter(|chunk| !chunks.contains(chunk)).count();
+
+        assert!(changed <= 2, "{} of {} chunks changed", changed, chunks.len());
+    }
+}
@@ -0,0 +1,235 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use blake2::Blake2b;
+use digest::Digest;
+
+use crate::config::TreeConfig;
+use crate::error::MerkleError;
+use crate::proof::{path_sides, Proof};
+
+/// Checks the chunks of a stream one at a time against the root of the tree built from it
+/// by `MerkleTree::from_reader`, so chunks can be accepted in any order as they arrive.
+/// Keeps track of which chunks have been verified, to resume interrupted transfers.
+pub struct ChunkVerifier<D: Digest = Blake2b> {
+    root_hash: Vec<u8>,
+    chunk_size: usize,
+    total_len: u64,
+    config: TreeConfig,
+    verified: Vec<bool>,
+    digest: PhantomData<D>,
+}
+
+impl ChunkVerifier<Blake2b> {
+    /// Verifier for a stream of `total_len` bytes split into chunks of `chunk_size` bytes,
+    /// hashed with Blake2b and the default config.
+    pub fn new(root_hash: &[u8], chunk_size: usize, total_len: u64) -> Result<ChunkVerifier, MerkleError> {
+        ChunkVerifier::with_config_and_digest(root_hash, chunk_size, total_len, TreeConfig::default())
+    }
+
+    pub fn with_config(root_hash: &[u8], chunk_size: usize, total_len: u64, config: TreeConfig) -> Result<ChunkVerifier, MerkleError> {
+        ChunkVerifier::with_config_and_digest(root_hash, chunk_size, total_len, config)
+    }
+}
+
+impl<D> ChunkVerifier<D> where D: Digest {
+    /// Fails with `EmptyInput` when the stream is empty, as no tree can be built from it, and
+    /// with `UnsupportedSortedPairs` if `config` hashes sorted pairs, whose proofs do not bind
+    /// the position of their chunk.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn with_config_and_digest(root_hash: &[u8], chunk_size: usize, total_len: u64, config: TreeConfig) -> Result<ChunkVerifier<D>, MerkleError> {
+        assert!(chunk_size > 0, "chunk size must not be zero");
+
+        if total_len == 0 {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        if config.sort_pairs {
+            return Err(MerkleError::UnsupportedSortedPairs);
+        }
+
+        let chunk_count = total_len.div_ceil(chunk_size as u64) as usize;
+
+        Ok(ChunkVerifier {
+            root_hash: root_hash.to_vec(),
+            chunk_size,
+            total_len,
+            config,
+            verified: vec![false; chunk_count],
+            digest: PhantomData,
+        })
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.verified.len()
+    }
+
+    /// Byte length the chunk at `index` must have: `chunk_size` for all but the last chunk.
+    pub fn chunk_len(&self, index: usize) -> Result<usize, MerkleError> {
+        let chunk_count = self.chunk_count();
+
+        if index >= chunk_count {
+            return Err(MerkleError::IndexOutOfBounds { index, leaf_count: chunk_count });
+        }
+
+        let offset = index as u64 * self.chunk_size as u64;
+        Ok((self.total_len - offset).min(self.chunk_size as u64) as usize)
+    }
+
+    /// Checks that `bytes` is the chunk at `index` of the stream and marks it as verified.
+    /// The proof must be for that exact position in a tree of `chunk_count()` leaves, which is
+    /// checked from the sides of its siblings, and must have been generated with the verifier's
+    /// config.
+    pub fn verify_chunk(&mut self, index: usize, bytes: &[u8], proof: &Proof<D>) -> Result<(), MerkleError> {
+        let expected = self.chunk_len(index)?;
+
+        if bytes.len() != expected {
+            return Err(MerkleError::InvalidChunkLength { index, len: bytes.len(), expected });
+        }
+
+        if proof.leaf_index != index || proof.leaf_count != self.chunk_count() || proof.check_config(self.config).is_err() {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let sides = path_sides(index, self.chunk_count(), self.config.padding);
+
+        if !proof.siblings.iter().map(|step| step.side).eq(sides) {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        proof.verify(&self.root_hash, bytes)?;
+        self.verified[index] = true;
+        Ok(())
+    }
+
+    pub fn is_verified(&self, index: usize) -> bool {
+        self.verified.get(index).cloned().unwrap_or(false)
+    }
+
+    pub fn verified_count(&self) -> usize {
+        self.verified.iter().filter(|verified| **verified).count()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.verified.iter().all(|verified| *verified)
+    }
+
+    /// Indices of the chunks that have not been verified yet, in ascending order.
+    pub fn missing(&self) -> impl Iterator<Item=usize> + '_ {
+        self.verified.iter().enumerate().filter(|(_, verified)| !**verified).map(|(index, _)| index)
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for ChunkVerifier<D> where D: Digest {
+    fn clone(&self) -> Self {
+        ChunkVerifier {
+            root_hash: self.root_hash.clone(),
+            chunk_size: self.chunk_size,
+            total_len: self.total_len,
+            config: self.config,
+            verified: self.verified.clone(),
+            digest: PhantomData,
+        }
+    }
+}
+
+impl<D> fmt::Debug for ChunkVerifier<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ChunkVerifier")
+            .field("root_hash", &self.root_hash)
+            .field("chunk_size", &self.chunk_size)
+            .field("total_len", &self.total_len)
+            .field("config", &self.config)
+            .field("verified", &self.verified)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MerkleTree, Padding};
+
+    const CHUNK_SIZE: usize = 16;
+
+    fn data() -> Vec<u8> {
+        (0..200).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn it_verifies_chunks_out_of_order() {
+        let data = data();
+
+        for padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            let config = TreeConfig::new(*padding);
+            let merkle_tree = MerkleTree::from_vec_with_config(data.chunks(CHUNK_SIZE).collect(), config).unwrap();
+            let mut verifier = ChunkVerifier::with_config(merkle_tree.root_hash(), CHUNK_SIZE, data.len() as u64, config).unwrap();
+
+            assert_eq!(verifier.chunk_count(), 13);
+
+            for index in (0..13).rev() {
+                let chunk = data.chunks(CHUNK_SIZE).nth(index).unwrap();
+                assert_eq!(verifier.verify_chunk(index, chunk, &merkle_tree.proof(index).unwrap()), Ok(()));
+            }
+
+            assert!(verifier.is_complete());
+        }
+    }
+
+    #[test]
+    fn it_tracks_missing_chunks() {
+        let data = data();
+        let merkle_tree = MerkleTree::from_vec(data.chunks(CHUNK_SIZE).collect()).unwrap();
+        let mut verifier = ChunkVerifier::new(merkle_tree.root_hash(), CHUNK_SIZE, data.len() as u64).unwrap();
+
+        for index in &[0, 3, 12] {
+            let chunk = data.chunks(CHUNK_SIZE).nth(*index).unwrap();
+            verifier.verify_chunk(*index, chunk, &merkle_tree.proof(*index).unwrap()).unwrap();
+        }
+
+        assert_eq!(verifier.verified_count(), 3);
+        assert!(verifier.is_verified(3));
+        assert!(!verifier.is_complete());
+        assert_eq!(verifier.missing().collect::<Vec<_>>(), vec![1, 2, 4, 5, 6, 7, 8, 9, 10, 11]);
+    }
+
+    #[test]
+    fn it_rejects_bad_chunks() {
+        let data = data();
+        let merkle_tree = MerkleTree::from_vec(data.chunks(CHUNK_SIZE).collect()).unwrap();
+        let mut verifier = ChunkVerifier::new(merkle_tree.root_hash(), CHUNK_SIZE, data.len() as u64).unwrap();
+        let proof = merkle_tree.proof(1).unwrap();
+
+        assert_eq!(verifier.verify_chunk(1, &data[16..32], &merkle_tree.proof(2).unwrap()), Err(MerkleError::MalformedProof));
+        assert_eq!(verifier.verify_chunk(1, &data[16..31], &proof), Err(MerkleError::InvalidChunkLength { index: 1, len: 15, expected: 16 }));
+        assert_eq!(verifier.verify_chunk(1, &data[32..48], &proof), Err(MerkleError::RootMismatch));
+        assert_eq!(verifier.verify_chunk(13, &data[..8], &proof), Err(MerkleError::IndexOutOfBounds { index: 13, leaf_count: 13 }));
+
+        // A valid proof for another chunk must not be accepted at a different position.
+        let mut moved = merkle_tree.proof(2).unwrap();
+        moved.leaf_index = 1;
+        assert_eq!(verifier.verify_chunk(1, &data[32..48], &moved), Err(MerkleError::MalformedProof));
+
+        // Sorted pairs would let the proof of a chunk pass at any index with its sides rewritten.
+        let mut moved = merkle_tree.proof(2).unwrap();
+        moved.leaf_index = 1;
+        moved.sort_pairs = true;
+        moved.siblings.iter_mut().zip(path_sides(1, 13, Padding::DuplicateLast)).for_each(|(step, side)| step.side = side);
+        assert_eq!(verifier.verify_chunk(1, &data[32..48], &moved), Err(MerkleError::MalformedProof));
+
+        let mut resized = proof.clone();
+        resized.leaf_count = 14;
+        assert_eq!(verifier.verify_chunk(1, &data[16..32], &resized), Err(MerkleError::MalformedProof));
+
+        let sorted = TreeConfig { sort_pairs: true, ..TreeConfig::default() };
+        assert_eq!(ChunkVerifier::with_config(merkle_tree.root_hash(), CHUNK_SIZE, data.len() as u64, sorted).err(), Some(MerkleError::UnsupportedSortedPairs));
+
+        assert_eq!(verifier.verified_count(), 0);
+    }
+}
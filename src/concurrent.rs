@@ -0,0 +1,204 @@
+use std::fmt;
+use std::sync::{Mutex, PoisonError, RwLock};
+
+use blake2::Blake2b;
+use digest::Digest;
+
+use crate::config::TreeConfig;
+use crate::error::MerkleError;
+use crate::leaf::LeafEncode;
+use crate::proof::Proof;
+use crate::snapshot::MerkleSnapshot;
+use crate::tree::MerkleTree;
+
+/// A MerkleTree that threads can append to and query concurrently through a shared reference.
+/// Appends are serialized by a lock on the tree, and each one publishes a snapshot of the tree
+/// it produced. Queries only lock the published snapshot for as long as it takes to clone it,
+/// and are then answered from that clone, so they neither wait for appends nor hold them up
+/// while hashing.
+pub struct ConcurrentMerkleTree<T, D: Digest = Blake2b> {
+    tree: Mutex<MerkleTree<T, D>>,
+    published: RwLock<MerkleSnapshot<T, D>>,
+}
+
+impl<T> ConcurrentMerkleTree<T, Blake2b> where T: LeafEncode + Clone {
+    /// Creates a ConcurrentMerkleTree from a vector, hashing with Blake2b and the default config.
+    pub fn from_vec(data: Vec<T>) -> Result<ConcurrentMerkleTree<T>, MerkleError> {
+        ConcurrentMerkleTree::from_vec_with_config_and_digest(data, TreeConfig::default())
+    }
+
+    /// Creates a ConcurrentMerkleTree from a vector, hashing with Blake2b.
+    pub fn from_vec_with_config(data: Vec<T>, config: TreeConfig) -> Result<ConcurrentMerkleTree<T>, MerkleError> {
+        ConcurrentMerkleTree::from_vec_with_config_and_digest(data, config)
+    }
+}
+
+impl<T, D> ConcurrentMerkleTree<T, D> where T: LeafEncode + Clone, D: Digest {
+    /// Creates a ConcurrentMerkleTree from a vector, hashing with the digest `D`.
+    pub fn from_vec_with_config_and_digest(data: Vec<T>, config: TreeConfig) -> Result<ConcurrentMerkleTree<T, D>, MerkleError> {
+        Ok(ConcurrentMerkleTree::new(MerkleTree::from_vec_with_config_and_digest(data, config)?))
+    }
+
+    pub fn new(tree: MerkleTree<T, D>) -> ConcurrentMerkleTree<T, D> {
+        ConcurrentMerkleTree {
+            published: RwLock::new(tree.snapshot()),
+            tree: Mutex::new(tree),
+        }
+    }
+
+    /// Appends a leaf and returns its index, as `MerkleTree::push` does. Nothing is published
+    /// when the push fails.
+    pub fn push(&self, data: T) -> Result<usize, MerkleError> {
+        let mut tree = self.lock_tree();
+        let index = tree.push(data)?;
+        self.publish(&tree);
+
+        Ok(index)
+    }
+
+    /// Appends every item as a new leaf, publishing a single snapshot once all are appended,
+    /// and returns the index of the first one, as `MerkleTree::try_extend` does. Stops at the
+    /// first item that fails, with its error, after publishing the leaves appended before it.
+    pub fn extend<I: IntoIterator<Item = T>>(&self, iter: I) -> Result<usize, MerkleError> {
+        let mut tree = self.lock_tree();
+        let pushed = tree.try_extend(iter);
+        self.publish(&tree);
+
+        pushed
+    }
+
+    /// The tree as of the latest append. Roots and proofs taken from the same snapshot are
+    /// consistent with each other, whatever is appended in the meantime.
+    pub fn snapshot(&self) -> MerkleSnapshot<T, D> {
+        self.published.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    pub fn root_hash(&self) -> Vec<u8> {
+        self.snapshot().root_hash().to_vec()
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.snapshot().leaf_count()
+    }
+
+    /// Generates an inclusion proof for the leaf at `leaf_index` of the latest snapshot. To
+    /// verify it against a root, take both from the same `snapshot`.
+    pub fn proof(&self, leaf_index: usize) -> Result<Proof<D>, MerkleError> {
+        self.snapshot().proof(leaf_index)
+    }
+
+    /// Takes the tree back, with every leaf appended so far.
+    pub fn into_inner(self) -> MerkleTree<T, D> {
+        self.tree.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Pushes fail before they modify the tree rather than panic, so a poisoned lock still
+    // guards a sound tree and is taken over.
+    fn lock_tree(&self) -> std::sync::MutexGuard<'_, MerkleTree<T, D>> {
+        self.tree.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Called with the tree locked, so that snapshots are published in the order of the appends.
+    fn publish(&self, tree: &MerkleTree<T, D>) {
+        let snapshot = tree.snapshot();
+        *self.published.write().unwrap_or_else(PoisonError::into_inner) = snapshot;
+    }
+}
+
+impl<T, D> fmt::Debug for ConcurrentMerkleTree<T, D> where T: LeafEncode + Clone, D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConcurrentMerkleTree")
+            .field("root_hash", &self.snapshot().root_hash_hex())
+            .field("leaf_count", &self.leaf_count())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::DuplicatePolicy;
+
+    #[test]
+    fn it_appends_from_many_threads() {
+        let concurrent = Arc::new(ConcurrentMerkleTree::from_vec(vec!["start".to_string()]).unwrap());
+
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let concurrent = concurrent.clone();
+                thread::spawn(move || {
+                    for i in 0..50 {
+                        let index = concurrent.push(format!("{}-{}", writer, i)).unwrap();
+                        assert!(concurrent.snapshot().leaf_count() > index);
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(concurrent.leaf_count(), 201);
+
+        let tree = Arc::try_unwrap(concurrent).unwrap().into_inner();
+        let data: Vec<String> = tree.leaves().map(|leaf| leaf.data.clone()).collect();
+        assert_eq!(tree.root_hash(), MerkleTree::from_vec(data.clone()).unwrap().root_hash());
+
+        for writer in 0..4 {
+            let own: Vec<String> = data.iter().filter(|d| d.starts_with(&format!("{}-", writer))).cloned().collect();
+            assert_eq!(own, (0..50).map(|i| format!("{}-{}", writer, i)).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn it_serves_consistent_proofs_while_appending() {
+        let concurrent = Arc::new(ConcurrentMerkleTree::from_vec(vec!["0".to_string()]).unwrap());
+
+        let reader = {
+            let concurrent = concurrent.clone();
+            thread::spawn(move || {
+                for _ in 0..200 {
+                    let snapshot = concurrent.snapshot();
+                    let index = snapshot.leaf_count() - 1;
+                    let proof = snapshot.proof(index).unwrap();
+                    assert!(proof.verify(snapshot.root_hash(), index.to_string().as_bytes()).is_ok());
+                }
+            })
+        };
+
+        for i in 1..200 {
+            concurrent.push(i.to_string()).unwrap();
+        }
+
+        reader.join().unwrap();
+        assert_eq!(concurrent.extend(vec!["200".to_string(), "201".to_string()]), Ok(200));
+        assert_eq!(concurrent.root_hash(), MerkleTree::from_vec((0..202).map(|i| i.to_string()).collect()).unwrap().root_hash());
+    }
+
+    #[test]
+    fn it_reports_failed_pushes() {
+        let config = TreeConfig { leaf_mode: crate::LeafMode::Prehashed, ..TreeConfig::default() };
+        let concurrent = ConcurrentMerkleTree::from_vec_with_config(vec![vec![0; 64]], config).unwrap();
+
+        assert_eq!(concurrent.push(vec![0; 3]), Err(MerkleError::InvalidLeafLength { index: 1, len: 3, expected: 64 }));
+        assert_eq!(concurrent.extend(vec![vec![1; 64], vec![0; 3]]), Err(MerkleError::InvalidLeafLength { index: 2, len: 3, expected: 64 }));
+        assert_eq!(concurrent.leaf_count(), 2);
+        assert_eq!(concurrent.push(vec![2; 64]), Ok(2));
+        assert_eq!(concurrent.proof(1).unwrap().verify(&concurrent.root_hash(), &[1; 64]), Ok(()));
+    }
+
+    #[test]
+    fn it_applies_the_duplicate_policy() {
+        let config = TreeConfig { duplicates: DuplicatePolicy::Reject, ..TreeConfig::default() };
+        let concurrent = ConcurrentMerkleTree::from_vec_with_config(vec!["a", "b"], config).unwrap();
+
+        assert_eq!(concurrent.push("a"), Err(MerkleError::DuplicateLeaf { index: 2, first: 0 }));
+        assert_eq!(concurrent.extend(vec!["c", "b"]), Err(MerkleError::DuplicateLeaf { index: 3, first: 1 }));
+        assert_eq!(concurrent.extend(vec!["d"]), Ok(3));
+        assert_eq!(concurrent.leaf_count(), 4);
+    }
+}
@@ -0,0 +1,115 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Strategy used when a level of the tree has an odd number of nodes,
+/// leaving the last node without a sibling.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Padding {
+    /// Pairs the unpaired node with a copy of its own hash.
+    #[default]
+    DuplicateLast,
+    /// Moves the unpaired node up to the next level unchanged.
+    Promote,
+    /// Pairs the unpaired node with the hash of an empty input.
+    EmptyHash,
+}
+
+/// Bytes prepended to leaf and inner node hash inputs, so that a leaf can never be
+/// passed off as an inner node (second-preimage attack).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Prefixes {
+    pub leaf: u8,
+    pub node: u8,
+}
+
+impl Prefixes {
+    /// Prefixes used by RFC 6962 (Certificate Transparency).
+    pub const RFC6962: Prefixes = Prefixes { leaf: 0x00, node: 0x01 };
+}
+
+/// How the data of a leaf is turned into its hash.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LeafMode {
+    /// Hashes the data, with the leaf prefix if any.
+    #[default]
+    Hashed,
+    /// Uses the data as the leaf hash, for protocols whose leaves are already hashes. The data
+    /// of every leaf must be exactly as long as a hash.
+    Prehashed,
+}
+
+/// What building a MerkleTree does with a leaf whose hash is the hash of an earlier leaf.
+///
+/// Verifiers that identify leaves by their hash alone, rather than by their position, accept
+/// the proof of one copy of a duplicated leaf for any other copy, which protocols counting or
+/// spending leaves must rule out.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DuplicatePolicy {
+    /// Keeps every leaf.
+    #[default]
+    Allow,
+    /// Fails with `DuplicateLeaf`.
+    Reject,
+    /// Keeps the first leaf with each hash and drops the others.
+    Deduplicate,
+}
+
+/// Options controlling how a MerkleTree is built.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TreeConfig {
+    pub padding: Padding,
+    /// Domain separation prefixes. Hashes are computed without prefixes when `None`.
+    pub prefixes: Option<Prefixes>,
+    /// Hashes the smaller of two siblings first instead of the left one, so that proofs can be
+    /// checked without knowing on which side each sibling sits. The root then no longer commits
+    /// to the order of the leaves.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sort_pairs: bool,
+    /// How the data of the leaves is turned into leaf hashes.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub leaf_mode: LeafMode,
+    /// What building a tree from a vector and `MerkleTree::push` do with duplicate leaves.
+    /// `IncrementalTree` and `StoredMerkleTree` keep no index of their leaves and only accept
+    /// `DuplicatePolicy::Allow`. It does not change how leaves are hashed, and is not recorded by
+    /// the binary encodings of trees and roots, which decode with `DuplicatePolicy::Allow`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub duplicates: DuplicatePolicy,
+}
+
+impl TreeConfig {
+    /// Config reproducing RFC 6962's Merkle Tree Hash when used with SHA-256:
+    /// unpaired nodes are promoted, which splits every subtree at the largest power of two
+    /// smaller than its leaf count, and leaves and nodes are prefixed with 0x00 and 0x01.
+    pub const RFC6962: TreeConfig = TreeConfig {
+        padding: Padding::Promote,
+        prefixes: Some(Prefixes::RFC6962),
+        sort_pairs: false,
+        leaf_mode: LeafMode::Hashed,
+        duplicates: DuplicatePolicy::Allow,
+    };
+
+    /// Config matching OpenZeppelin's `MerkleProof` when used with Keccak-256: siblings are
+    /// hashed in ascending order, without prefixes, and unpaired nodes are promoted.
+    pub const OPENZEPPELIN: TreeConfig = TreeConfig {
+        padding: Padding::Promote,
+        prefixes: None,
+        sort_pairs: true,
+        leaf_mode: LeafMode::Hashed,
+        duplicates: DuplicatePolicy::Allow,
+    };
+
+    pub fn new(padding: Padding) -> TreeConfig {
+        TreeConfig {
+            padding,
+            prefixes: None,
+            sort_pairs: false,
+            leaf_mode: LeafMode::Hashed,
+            duplicates: DuplicatePolicy::Allow,
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use blake2::Blake2b;
+use digest::Digest;
+use digest::generic_array::GenericArray;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::config::Prefixes;
+use crate::error::MerkleError;
+use crate::{hash_children, Hash};
+
+/// Proof that the tree of the first `old_size` leaves is a prefix of the tree of the first
+/// `new_size` leaves, as defined by RFC 6962.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct ConsistencyProof<D: Digest = Blake2b> {
+    pub old_size: usize,
+    pub new_size: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::list"))]
+    pub hashes: Vec<Hash<D>>,
+    /// Domain separation prefixes of the tree the proof was generated from.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub prefixes: Option<Prefixes>,
+    /// Whether the tree the proof was generated from hashes siblings in ascending order.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sort_pairs: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    digest: PhantomData<D>,
+}
+
+impl<D> ConsistencyProof<D> where D: Digest {
+    pub fn new(old_size: usize, new_size: usize, hashes: Vec<Hash<D>>) -> ConsistencyProof<D> {
+        ConsistencyProof {
+            old_size,
+            new_size,
+            hashes,
+            prefixes: None,
+            sort_pairs: false,
+            digest: PhantomData,
+        }
+    }
+
+    /// Checks that `old_root` and `new_root` are the roots of trees of `old_size` and `new_size` leaves,
+    /// the first being a prefix of the second.
+    pub fn verify(&self, old_root: &[u8], new_root: &[u8]) -> Result<(), MerkleError> {
+        let (old_size, new_size) = (self.old_size, self.new_size);
+
+        if old_size == 0 || old_size > new_size {
+            return Err(MerkleError::InvalidSizes { old_size, new_size });
+        }
+
+        if old_size == new_size {
+            return if !self.hashes.is_empty() {
+                Err(MerkleError::MalformedProof)
+            } else if old_root == new_root {
+                Ok(())
+            } else {
+                Err(MerkleError::RootMismatch)
+            };
+        }
+
+        // When the old tree is a complete subtree of the new one, its root is the starting point.
+        let mut hashes = self.hashes.iter();
+        let first: Hash<D> = if old_size.is_power_of_two() {
+            GenericArray::from_exact_iter(old_root.iter().cloned()).ok_or(MerkleError::RootMismatch)?
+        } else {
+            hashes.next().ok_or(MerkleError::MalformedProof)?.clone()
+        };
+
+        let (mut old_index, mut new_index) = (old_size - 1, new_size - 1);
+
+        while old_index & 1 == 1 {
+            old_index >>= 1;
+            new_index >>= 1;
+        }
+
+        let (mut old_hash, mut new_hash) = (first.clone(), first);
+
+        for hash in hashes {
+            if new_index == 0 {
+                return Err(MerkleError::MalformedProof);
+            }
+
+            if old_index & 1 == 1 || old_index == new_index {
+                old_hash = hash_children::<D>(self.prefixes, self.sort_pairs, hash, &old_hash);
+                new_hash = hash_children::<D>(self.prefixes, self.sort_pairs, hash, &new_hash);
+
+                while old_index & 1 == 0 && old_index != 0 {
+                    old_index >>= 1;
+                    new_index >>= 1;
+                }
+            } else {
+                new_hash = hash_children::<D>(self.prefixes, self.sort_pairs, &new_hash, hash);
+            }
+
+            old_index >>= 1;
+            new_index >>= 1;
+        }
+
+        if new_index != 0 {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        if old_hash.as_slice() == old_root && new_hash.as_slice() == new_root {
+            Ok(())
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for ConsistencyProof<D> where D: Digest {
+    fn clone(&self) -> Self {
+        ConsistencyProof {
+            old_size: self.old_size,
+            new_size: self.new_size,
+            hashes: self.hashes.clone(),
+            prefixes: self.prefixes,
+            sort_pairs: self.sort_pairs,
+            digest: PhantomData,
+        }
+    }
+}
+
+impl<D> PartialEq for ConsistencyProof<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.old_size == other.old_size
+            && self.new_size == other.new_size
+            && self.hashes == other.hashes
+            && self.prefixes == other.prefixes
+            && self.sort_pairs == other.sort_pairs
+    }
+}
+
+impl<D> fmt::Debug for ConsistencyProof<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConsistencyProof")
+            .field("old_size", &self.old_size)
+            .field("new_size", &self.new_size)
+            .field("hashes", &self.hashes)
+            .field("prefixes", &self.prefixes)
+            .field("sort_pairs", &self.sort_pairs)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{MerkleError, MerkleTree, Padding, TreeConfig};
+
+    fn leaves(len: usize) -> Vec<String> {
+        (0..len).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn it_verifies_consistency_between_all_sizes() {
+        let data = leaves(13);
+        let merkle_tree = MerkleTree::from_vec_rfc6962(data.clone()).unwrap();
+
+        for new_size in 1..=data.len() {
+            let new_root = MerkleTree::from_vec_rfc6962(data[..new_size].to_vec()).unwrap();
+
+            for old_size in 1..=new_size {
+                let old_root = MerkleTree::from_vec_rfc6962(data[..old_size].to_vec()).unwrap();
+                let proof = merkle_tree.consistency_proof(old_size, new_size).unwrap();
+
+                assert!(proof.verify(old_root.root_hash(), new_root.root_hash()).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn it_matches_rfc6962_proof_lengths() {
+        let merkle_tree = MerkleTree::from_vec_rfc6962(leaves(8)).unwrap();
+
+        assert_eq!(merkle_tree.consistency_proof(1, 1).unwrap().hashes.len(), 0);
+        assert_eq!(merkle_tree.consistency_proof(1, 8).unwrap().hashes.len(), 3);
+        assert_eq!(merkle_tree.consistency_proof(6, 8).unwrap().hashes.len(), 3);
+        assert_eq!(merkle_tree.consistency_proof(2, 5).unwrap().hashes.len(), 2);
+        assert_eq!(merkle_tree.consistency_proof(4, 8).unwrap().hashes.len(), 1);
+    }
+
+    #[test]
+    fn it_rejects_inconsistent_roots() {
+        let merkle_tree = MerkleTree::from_vec_rfc6962(leaves(7)).unwrap();
+        let old_root = MerkleTree::from_vec_rfc6962(leaves(3)).unwrap();
+        let mut forked = leaves(7);
+        forked[1] = "forked".to_string();
+        let forked_tree = MerkleTree::from_vec_rfc6962(forked).unwrap();
+
+        let proof = merkle_tree.consistency_proof(3, 7).unwrap();
+
+        assert_eq!(proof.verify(old_root.root_hash(), forked_tree.root_hash()), Err(MerkleError::RootMismatch));
+        assert_eq!(proof.verify(forked_tree.root_hash(), merkle_tree.root_hash()), Err(MerkleError::RootMismatch));
+    }
+
+    #[test]
+    fn it_rejects_malformed_proofs() {
+        let merkle_tree = MerkleTree::from_vec_rfc6962(leaves(7)).unwrap();
+        let old_root = MerkleTree::from_vec_rfc6962(leaves(3)).unwrap();
+
+        let mut proof = merkle_tree.consistency_proof(3, 7).unwrap();
+        proof.hashes.push(proof.hashes[0]);
+        assert_eq!(proof.verify(old_root.root_hash(), merkle_tree.root_hash()), Err(MerkleError::MalformedProof));
+
+        let mut proof = merkle_tree.consistency_proof(3, 7).unwrap();
+        proof.hashes.pop();
+        assert_eq!(proof.verify(old_root.root_hash(), merkle_tree.root_hash()), Err(MerkleError::MalformedProof));
+    }
+
+    #[test]
+    fn it_fails_on_invalid_sizes() {
+        let merkle_tree = MerkleTree::from_vec_rfc6962(leaves(4)).unwrap();
+
+        assert_eq!(merkle_tree.consistency_proof(0, 4), Err(MerkleError::InvalidSizes { old_size: 0, new_size: 4 }));
+        assert_eq!(merkle_tree.consistency_proof(3, 2), Err(MerkleError::InvalidSizes { old_size: 3, new_size: 2 }));
+        assert_eq!(merkle_tree.consistency_proof(2, 5), Err(MerkleError::InvalidSizes { old_size: 2, new_size: 5 }));
+    }
+
+    #[test]
+    fn it_requires_promote_padding() {
+        let merkle_tree = MerkleTree::from_vec(leaves(4)).unwrap();
+        let promoted_tree = MerkleTree::from_vec_with_config(leaves(4), TreeConfig::new(Padding::Promote)).unwrap();
+
+        assert_eq!(merkle_tree.consistency_proof(1, 4), Err(MerkleError::UnsupportedPadding(Padding::DuplicateLast)));
+        assert!(promoted_tree.consistency_proof(1, 4).is_ok());
+    }
+}
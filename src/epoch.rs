@@ -0,0 +1,277 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use blake2::Blake2b;
+use digest::Digest;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{check_leaf, leaf_hash};
+use crate::config::TreeConfig;
+use crate::error::MerkleError;
+use crate::leaf::LeafEncode;
+use crate::proof::Proof;
+use crate::tree::MerkleTree;
+
+/// A chain of MerkleTrees, one per epoch, as kept by audit logs closing a tree every hour or
+/// every so many entries. The tree of every epoch after the first starts with the root of the
+/// previous epoch as its leaf 0, so the root of the latest epoch commits to every entry ever
+/// logged, and a leaf of any epoch can be proven against the root of any later one.
+pub struct EpochChain<D: Digest = Blake2b> {
+    epochs: Vec<MerkleTree<Vec<u8>, D>>,
+    pending: Vec<Vec<u8>>,
+    config: TreeConfig,
+}
+
+impl EpochChain<Blake2b> {
+    /// Creates an EpochChain with no epoch, hashing with Blake2b and the default config.
+    pub fn new() -> EpochChain {
+        EpochChain::with_config_and_digest(TreeConfig::default())
+    }
+
+    /// Creates an EpochChain with no epoch, hashing with Blake2b.
+    pub fn with_config(config: TreeConfig) -> EpochChain {
+        EpochChain::with_config_and_digest(config)
+    }
+}
+
+impl Default for EpochChain<Blake2b> {
+    fn default() -> Self {
+        EpochChain::new()
+    }
+}
+
+impl<D> EpochChain<D> where D: Digest {
+    /// Creates an EpochChain with no epoch, hashing with the digest `D` and the default config.
+    pub fn with_digest() -> EpochChain<D> {
+        EpochChain::with_config_and_digest(TreeConfig::default())
+    }
+
+    /// Creates an EpochChain with no epoch, hashing with the digest `D`.
+    pub fn with_config_and_digest(config: TreeConfig) -> EpochChain<D> {
+        EpochChain { epochs: Vec::new(), pending: Vec::new(), config }
+    }
+
+    /// Adds a leaf to the current epoch and returns its index in the tree of the epoch, which
+    /// starts at 1 from the second epoch on. With `LeafMode::Prehashed`, fails with
+    /// `InvalidLeafLength` unless `data` is one hash long.
+    pub fn push<T: LeafEncode + ?Sized>(&mut self, data: &T) -> Result<usize, MerkleError> {
+        let index = self.pending.len() + if self.epochs.is_empty() { 0 } else { 1 };
+        let data = data.encode_leaf().into_owned();
+        check_leaf::<D>(self.config.leaf_mode, index, &data)?;
+        self.pending.push(data);
+
+        Ok(index)
+    }
+
+    /// Builds the tree of the current epoch from the root of the previous one and the leaves
+    /// pushed since, and returns its root. Fails with `EmptyInput` if no leaf was pushed since
+    /// the last epoch was closed, and with `DuplicateLeaf` under `DuplicatePolicy::Reject`, in
+    /// which case the leaves pushed stay in the current epoch.
+    pub fn close_epoch(&mut self) -> Result<&[u8], MerkleError> {
+        if self.pending.is_empty() {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        let mut leaves = Vec::with_capacity(self.pending.len() + 1);
+        leaves.extend(self.epochs.last().map(|previous| previous.root_hash().to_vec()));
+        leaves.extend(self.pending.iter().cloned());
+
+        let tree = MerkleTree::from_vec_with_config_and_digest(leaves, self.config)?;
+        self.pending.clear();
+        self.epochs.push(tree);
+
+        Ok(self.epochs.last().expect("an epoch was just closed").root_hash())
+    }
+
+    /// Number of epochs closed.
+    pub fn epoch_count(&self) -> usize {
+        self.epochs.len()
+    }
+
+    /// Number of leaves pushed since the last epoch was closed.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn config(&self) -> TreeConfig {
+        self.config
+    }
+
+    /// The tree of a closed epoch, whose leaf 0 is the root of the previous epoch unless it is
+    /// the first.
+    pub fn epoch(&self, epoch: usize) -> Option<&MerkleTree<Vec<u8>, D>> {
+        self.epochs.get(epoch)
+    }
+
+    /// The root of a closed epoch.
+    pub fn root(&self, epoch: usize) -> Option<&[u8]> {
+        self.epoch(epoch).map(MerkleTree::root_hash)
+    }
+
+    /// Generates a proof of the leaf at `leaf_index` of `epoch` against the root of the latest
+    /// epoch closed.
+    pub fn proof(&self, epoch: usize, leaf_index: usize) -> Result<EpochProof<D>, MerkleError> {
+        let to_epoch = self.epochs.len().saturating_sub(1).max(epoch);
+        self.proof_to(epoch, leaf_index, to_epoch)
+    }
+
+    /// Generates a proof of the leaf at `leaf_index` of `epoch` against the root of `to_epoch`,
+    /// for auditors that only hold an older root. Fails with `UnknownEpoch` if either epoch is
+    /// not closed yet.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `to_epoch` is before `epoch`.
+    pub fn proof_to(&self, epoch: usize, leaf_index: usize, to_epoch: usize) -> Result<EpochProof<D>, MerkleError> {
+        assert!(epoch <= to_epoch, "a leaf can only be proven against the root of a later epoch");
+
+        let epoch_count = self.epochs.len();
+
+        if to_epoch >= epoch_count {
+            return Err(MerkleError::UnknownEpoch { epoch: to_epoch, epoch_count });
+        }
+
+        let leaf = self.epochs[epoch].proof(leaf_index)?;
+        let links = self.epochs[epoch + 1..=to_epoch].iter()
+            .map(|tree| tree.proof(0))
+            .collect::<Result<Vec<_>, MerkleError>>()?;
+
+        Ok(EpochProof { epoch, leaf, links })
+    }
+}
+
+impl<D> fmt::Debug for EpochChain<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EpochChain")
+            .field("epochs", &self.epochs.len())
+            .field("latest", &self.epochs.last().map(MerkleTree::root_hash_hex))
+            .field("pending", &self.pending.len())
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// Proof of a leaf of an [`EpochChain`] against the root of the same or a later epoch: the
+/// proof of the leaf in the tree of its epoch, followed by a proof of the root of every epoch as
+/// leaf 0 of the next one.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct EpochProof<D: Digest = Blake2b> {
+    /// Epoch holding the leaf.
+    pub epoch: usize,
+    pub leaf: Proof<D>,
+    pub links: Vec<Proof<D>>,
+}
+
+impl<D> EpochProof<D> where D: Digest {
+    /// Epoch whose root the proof verifies against.
+    pub fn to_epoch(&self) -> usize {
+        self.epoch + self.links.len()
+    }
+
+    /// Checks that `leaf_data` is included in the epoch `epoch`, and that the epochs up to
+    /// `to_epoch()` chained their roots, with the given root hash as the root of `to_epoch()`.
+    ///
+    /// Fails with `MalformedProof` if a link does not prove leaf 0. Under sorted pairs, the
+    /// position of a leaf is not bound by its proof, so a link only shows that the previous root
+    /// is somewhere in the next epoch.
+    pub fn verify(&self, root_hash: &[u8], leaf_data: &[u8]) -> Result<(), MerkleError> {
+        check_leaf::<D>(self.leaf.leaf_mode, self.leaf.leaf_index, leaf_data)?;
+        let mut root = self.leaf.root_from(&leaf_hash::<D>(self.leaf.prefixes, self.leaf.leaf_mode, leaf_data))?;
+
+        for link in &self.links {
+            if link.leaf_index != 0 {
+                return Err(MerkleError::MalformedProof);
+            }
+
+            root = link.root_from(&leaf_hash::<D>(link.prefixes, link.leaf_mode, &root))?;
+        }
+
+        if root.as_slice() == root_hash {
+            Ok(())
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+
+    /// Same as `verify`, for a chain built with `config`. Fails with `ConfigMismatch` unless the
+    /// proof of the leaf and every link were generated with `config`.
+    pub fn verify_with_config(&self, root_hash: &[u8], leaf_data: &[u8], config: TreeConfig) -> Result<(), MerkleError> {
+        self.leaf.check_config(config)?;
+        self.links.iter().try_for_each(|link| link.check_config(config))?;
+        self.verify(root_hash, leaf_data)
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for EpochProof<D> where D: Digest {
+    fn clone(&self) -> Self {
+        EpochProof { epoch: self.epoch, leaf: self.leaf.clone(), links: self.links.clone() }
+    }
+}
+
+impl<D> PartialEq for EpochProof<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.epoch == other.epoch && self.leaf == other.leaf && self.links == other.links
+    }
+}
+
+impl<D> fmt::Debug for EpochProof<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EpochProof")
+            .field("epoch", &self.epoch)
+            .field("leaf", &self.leaf)
+            .field("links", &self.links)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_proves_leaves_across_epochs() {
+        for config in &[TreeConfig::default(), TreeConfig::RFC6962] {
+            let mut chain = EpochChain::with_config(*config);
+
+            for epoch in &[&["a", "b", "c"][..], &["d", "e"], &["f"]] {
+                let first = chain.epoch_count().min(1);
+                for (offset, data) in epoch.iter().enumerate() {
+                    assert_eq!(chain.push(data), Ok(first + offset));
+                }
+                chain.close_epoch().unwrap();
+            }
+
+            assert_eq!(chain.epoch(2).unwrap().leaves().next().unwrap().data, chain.root(1).unwrap());
+
+            let proof = chain.proof(0, 1).unwrap();
+            assert_eq!((proof.epoch, proof.to_epoch()), (0, 2));
+            assert_eq!(proof.verify(chain.root(2).unwrap(), b"b"), Ok(()));
+            assert_eq!(proof.verify(chain.root(2).unwrap(), b"c"), Err(MerkleError::RootMismatch));
+            assert_eq!(proof.verify(chain.root(1).unwrap(), b"b"), Err(MerkleError::RootMismatch));
+
+            let proof = chain.proof_to(0, 1, 1).unwrap();
+            assert_eq!(proof.verify(chain.root(1).unwrap(), b"b"), Ok(()));
+            assert_eq!(chain.proof_to(2, 1, 2).unwrap().verify(chain.root(2).unwrap(), b"f"), Ok(()));
+
+            let mut proof = chain.proof(1, 1).unwrap();
+            proof.links[0].leaf_index = 1;
+            assert_eq!(proof.verify(chain.root(2).unwrap(), b"d"), Err(MerkleError::MalformedProof));
+        }
+    }
+
+    #[test]
+    fn it_rejects_empty_and_unknown_epochs() {
+        let mut chain = EpochChain::new();
+        assert_eq!(chain.close_epoch(), Err(MerkleError::EmptyInput));
+        assert_eq!(chain.proof(0, 0), Err(MerkleError::UnknownEpoch { epoch: 0, epoch_count: 0 }));
+
+        chain.push("a").unwrap();
+        chain.close_epoch().unwrap();
+        assert_eq!(chain.close_epoch(), Err(MerkleError::EmptyInput));
+        assert_eq!(chain.proof_to(0, 0, 1), Err(MerkleError::UnknownEpoch { epoch: 1, epoch_count: 1 }));
+        assert_eq!(chain.proof(0, 1), Err(MerkleError::IndexOutOfBounds { index: 1, leaf_count: 1 }));
+    }
+}
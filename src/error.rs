@@ -0,0 +1,133 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::config::{DuplicatePolicy, Padding};
+use crate::proof::Side;
+
+/// Errors returned by tree construction, proof generation and verification.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum MerkleError {
+    /// A tree was requested from an empty set of data.
+    EmptyInput,
+    /// A leaf index was not smaller than the number of leaves in the tree.
+    IndexOutOfBounds { index: usize, leaf_count: usize },
+    /// A proof, or a tree rebuilt from its leaves, did not hash up to the expected root.
+    RootMismatch,
+    /// A hash could not be decoded from its hex representation.
+    InvalidHex,
+    /// Tree sizes for a consistency proof were not `0 < old_size <= new_size <= leaf_count`.
+    InvalidSizes { old_size: usize, new_size: usize },
+    /// The operation is not defined for trees built with this padding strategy.
+    UnsupportedPadding(Padding),
+    /// A proof had too many or too few hashes.
+    MalformedProof,
+    /// The hashes needed for the leaf at `index` were discarded by pruning.
+    Pruned { index: usize },
+    /// A chunk did not have the length implied by its index, the chunk size and the stream length.
+    InvalidChunkLength { index: usize, len: usize, expected: usize },
+    /// The data of a prehashed leaf was not exactly as long as a hash.
+    InvalidLeafLength { index: usize, len: usize, expected: usize },
+    /// A proof was encoded with a version of the binary format this crate does not know.
+    UnsupportedVersion(u8),
+    /// A tree version was not smaller than the number of versions recorded.
+    UnknownVersion { version: usize, version_count: usize },
+    /// A suspended incremental tree did not hold one frontier hash per bit set in its leaf count,
+    /// or could not be decoded.
+    InvalidState,
+    /// A signed checkpoint did not carry a valid signature of its message.
+    InvalidSignature,
+    /// A public key could not be decoded.
+    InvalidKey,
+    /// A signed note or the tree head it holds could not be parsed.
+    InvalidNote,
+    /// A tile path could not be parsed, tile data did not hold one hash per node of the tile, or
+    /// a tree did not hold every node of a tile.
+    InvalidTile,
+    /// Bytes did not hold a flat tree encoded with `FlatMerkleTree::to_bytes`, or its nodes did
+    /// not hash to their parents.
+    InvalidFlatTree,
+    /// The hash of the node at `index` on the level `height` levels above the leaves could not
+    /// be found or fetched.
+    MissingNode { height: usize, index: usize },
+    /// A leaf was appended to a fixed-depth tree holding all the leaves it can.
+    TreeFull { capacity: usize },
+    /// The hash of the node reached from the root through the children on `path` did not match
+    /// its data or children.
+    CorruptNode { path: Vec<Side> },
+    /// A subtree of `leaf_count` leaves did not cover the leaves of a single node when placed at
+    /// leaf `position`.
+    MisalignedSubtree { position: usize, leaf_count: usize },
+    /// Trees built with different configs were combined, or a proof was checked against the
+    /// config of another tree.
+    ConfigMismatch,
+    /// The operation is not defined for trees built with this duplicate policy.
+    UnsupportedDuplicatePolicy(DuplicatePolicy),
+    /// The operation is not defined for trees hashing pairs in ascending order.
+    UnsupportedSortedPairs,
+    /// The leaf at `index` had the same hash as the leaf at `first`, in a tree built with
+    /// `DuplicatePolicy::Reject`.
+    DuplicateLeaf { index: usize, first: usize },
+    /// An epoch was not smaller than the number of epochs closed.
+    UnknownEpoch { epoch: usize, epoch_count: usize },
+    /// A leaf to delete or prove was not in the forest.
+    UnknownLeaf,
+    /// A salted tree was given a number of salts other than its number of leaves.
+    SaltCountMismatch { leaf_count: usize, salt_count: usize },
+    /// More chunks were merkleized than the limit of their SSZ type allows.
+    LimitExceeded { len: usize, limit: usize },
+    /// The nodes `index` and `index + 1` on level `height` had the same hash, which a partial
+    /// tree padded with `Padding::DuplicateLast` cannot tell apart from padding.
+    EqualSiblings { height: usize, index: usize },
+}
+
+impl fmt::Display for MerkleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MerkleError::EmptyInput => write!(f, "cannot build a merkle tree from empty input"),
+            MerkleError::IndexOutOfBounds { index, leaf_count } =>
+                write!(f, "leaf index {} out of bounds for tree with {} leaves", index, leaf_count),
+            MerkleError::RootMismatch => write!(f, "proof does not match the expected root hash"),
+            MerkleError::InvalidHex => write!(f, "invalid hex encoded hash"),
+            MerkleError::InvalidSizes { old_size, new_size } =>
+                write!(f, "invalid tree sizes {} and {}", old_size, new_size),
+            MerkleError::UnsupportedPadding(padding) => write!(f, "operation not supported with {:?} padding", padding),
+            MerkleError::MalformedProof => write!(f, "malformed proof"),
+            MerkleError::Pruned { index } => write!(f, "leaf {} has been pruned", index),
+            MerkleError::InvalidChunkLength { index, len, expected } =>
+                write!(f, "chunk {} has {} bytes, expected {}", index, len, expected),
+            MerkleError::InvalidLeafLength { index, len, expected } =>
+                write!(f, "prehashed leaf {} has {} bytes, expected {}", index, len, expected),
+            MerkleError::UnsupportedVersion(version) => write!(f, "unsupported proof encoding version {}", version),
+            MerkleError::UnknownVersion { version, version_count } =>
+                write!(f, "unknown tree version {}, only {} versions are recorded", version, version_count),
+            MerkleError::InvalidState => write!(f, "invalid incremental tree state"),
+            MerkleError::InvalidSignature => write!(f, "invalid checkpoint signature"),
+            MerkleError::InvalidKey => write!(f, "invalid public key"),
+            MerkleError::InvalidNote => write!(f, "malformed signed note"),
+            MerkleError::InvalidTile => write!(f, "invalid tile"),
+            MerkleError::InvalidFlatTree => write!(f, "invalid flat tree encoding"),
+            MerkleError::MissingNode { height, index } => write!(f, "node {} at height {} is missing", index, height),
+            MerkleError::TreeFull { capacity } => write!(f, "tree is full with {} leaves", capacity),
+            MerkleError::CorruptNode { path } => {
+                write!(f, "corrupt node at root")?;
+                path.iter().try_for_each(|side| f.write_str(if *side == Side::Left { "/left" } else { "/right" }))
+            }
+            MerkleError::MisalignedSubtree { position, leaf_count } =>
+                write!(f, "subtree with {} leaves cannot be placed at leaf {}", leaf_count, position),
+            MerkleError::ConfigMismatch => write!(f, "trees or proofs were built with different configs"),
+            MerkleError::UnsupportedDuplicatePolicy(policy) => write!(f, "operation not supported with the {:?} duplicate policy", policy),
+            MerkleError::UnsupportedSortedPairs => write!(f, "operation not supported with sorted pairs"),
+            MerkleError::DuplicateLeaf { index, first } => write!(f, "leaf {} duplicates leaf {}", index, first),
+            MerkleError::UnknownEpoch { epoch, epoch_count } =>
+                write!(f, "unknown epoch {}, only {} epochs are closed", epoch, epoch_count),
+            MerkleError::UnknownLeaf => write!(f, "leaf is not in the forest"),
+            MerkleError::SaltCountMismatch { leaf_count, salt_count } =>
+                write!(f, "{} salts given for {} leaves", salt_count, leaf_count),
+            MerkleError::LimitExceeded { len, limit } => write!(f, "{} chunks exceed the limit of {}", len, limit),
+            MerkleError::EqualSiblings { height, index } => write!(f, "nodes {} and {} on level {} have the same hash", index, index + 1, height),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MerkleError {}
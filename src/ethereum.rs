@@ -0,0 +1,91 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use digest::Digest;
+use sha3::Keccak256;
+
+use crate::to_hex;
+use crate::config::TreeConfig;
+use crate::error::MerkleError;
+use crate::leaf::LeafEncode;
+use crate::proof::Proof;
+use crate::tree::MerkleTree;
+
+impl<T> MerkleTree<T, Keccak256> where T: LeafEncode {
+    /// Creates a MerkleTree whose proofs are accepted by OpenZeppelin's `MerkleProof.verify`,
+    /// with each leaf being the Keccak-256 hash of the data.
+    pub fn from_vec_openzeppelin(data: Vec<T>) -> Result<MerkleTree<T, Keccak256>, MerkleError> {
+        MerkleTree::from_vec_with_config_and_digest(data, TreeConfig::OPENZEPPELIN)
+    }
+
+    /// Root hash as a 0x-prefixed `bytes32` literal.
+    pub fn root_hash_bytes32(&self) -> String {
+        bytes32(self.root_hash())
+    }
+}
+
+impl<D> Proof<D> where D: Digest {
+    /// Sibling hashes as 0x-prefixed hex strings, in the order expected by the
+    /// `bytes32[] proof` argument of OpenZeppelin's `MerkleProof.verify`.
+    pub fn to_bytes32_array(&self) -> Vec<String> {
+        self.siblings.iter().map(|step| bytes32(&step.hash)).collect()
+    }
+}
+
+fn bytes32(hash: &[u8]) -> String {
+    format!("0x{}", to_hex(hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MerkleError;
+
+    /// Port of `MerkleProof.processProof` from OpenZeppelin Contracts.
+    fn process_proof(proof: &[String], leaf: &[u8]) -> String {
+        let decode = |hex: &str| crate::from_hex(&hex[2..]).unwrap();
+
+        let hash = proof.iter().fold(Keccak256::digest(leaf).to_vec(), |hash, sibling| {
+            let sibling = decode(sibling);
+            let (a, b) = if hash < sibling { (hash, sibling) } else { (sibling, hash) };
+            Keccak256::digest(&[a, b].concat()).to_vec()
+        });
+
+        bytes32(&hash)
+    }
+
+    #[test]
+    fn it_hashes_with_keccak_256() {
+        let merkle_tree = MerkleTree::from_vec_openzeppelin(vec![""]).unwrap();
+
+        assert_eq!(merkle_tree.root_hash_bytes32(), "0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470");
+    }
+
+    #[test]
+    fn it_exports_proofs_accepted_by_openzeppelin() {
+        let data: Vec<String> = (0..11).map(|i| format!("address-{}", i)).collect();
+        let merkle_tree = MerkleTree::from_vec_openzeppelin(data.clone()).unwrap();
+
+        for (index, leaf) in data.iter().enumerate() {
+            let proof = merkle_tree.proof(index).unwrap();
+
+            assert_eq!(process_proof(&proof.to_bytes32_array(), leaf.as_bytes()), merkle_tree.root_hash_bytes32());
+            assert!(proof.verify(merkle_tree.root_hash(), leaf.as_bytes()).is_ok());
+        }
+    }
+
+    #[test]
+    fn it_ignores_sides_of_sorted_pairs() {
+        let merkle_tree = MerkleTree::from_vec_openzeppelin(vec!["a", "b", "c", "d"]).unwrap();
+        let mut proof = merkle_tree.proof(2).unwrap();
+
+        for sibling in &mut proof.siblings {
+            sibling.side = crate::Side::Left;
+        }
+
+        assert!(proof.verify(merkle_tree.root_hash(), b"c").is_ok());
+
+        proof.sort_pairs = false;
+        assert_eq!(proof.verify(merkle_tree.root_hash(), b"c"), Err(MerkleError::MalformedProof));
+    }
+}
@@ -0,0 +1,294 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use blake2::Blake2b;
+use digest::Digest;
+
+use crate::{check_leaf, hash_children, leaf_hash, to_hex, Hash};
+use crate::config::TreeConfig;
+use crate::error::MerkleError;
+use crate::proof::{Proof, ProofStep, Side};
+
+/// Merkle tree of exactly `2^depth` leaves, every one of which holds a default leaf until it is
+/// set, as in the deposit trees and commitment accumulators of depth 32 of blockchains.
+///
+/// Subtrees holding only default leaves are never stored: their hashes only depend on their
+/// height, so they are computed once when the tree is created, which takes `depth` hashes, and a
+/// leaf is set by rehashing the `depth` nodes on its path.
+pub struct FixedDepthMerkleTree<D: Digest = Blake2b> {
+    depth: usize,
+    config: TreeConfig,
+    /// Hashes of the nodes with a leaf set below them, by height above the leaves and index
+    /// within their level.
+    nodes: BTreeMap<(usize, usize), Hash<D>>,
+    /// `defaults[h]` is the hash of a subtree of height `h` holding only default leaves.
+    defaults: Vec<Hash<D>>,
+}
+
+impl FixedDepthMerkleTree {
+    /// Creates a FixedDepthMerkleTree of `2^depth` default leaves, hashing with Blake2b and the
+    /// default config.
+    pub fn new(depth: usize, default_leaf: &[u8]) -> Result<FixedDepthMerkleTree, MerkleError> {
+        FixedDepthMerkleTree::with_config_and_digest(depth, default_leaf, TreeConfig::default())
+    }
+
+    pub fn with_config(depth: usize, default_leaf: &[u8], config: TreeConfig) -> Result<FixedDepthMerkleTree, MerkleError> {
+        FixedDepthMerkleTree::with_config_and_digest(depth, default_leaf, config)
+    }
+}
+
+impl<D> FixedDepthMerkleTree<D> where D: Digest {
+    /// Creates a FixedDepthMerkleTree of `2^depth` default leaves, hashing with the digest `D`
+    /// and the default config.
+    pub fn with_digest(depth: usize, default_leaf: &[u8]) -> Result<FixedDepthMerkleTree<D>, MerkleError> {
+        FixedDepthMerkleTree::with_config_and_digest(depth, default_leaf, TreeConfig::default())
+    }
+
+    /// Creates a FixedDepthMerkleTree of `2^depth` default leaves, hashing with the digest `D`.
+    /// The padding of `config` is ignored, as every level is full. With `LeafMode::Prehashed`,
+    /// fails with `InvalidLeafLength` unless the default leaf is one hash long.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are not fewer than `usize::BITS` levels of leaves.
+    pub fn with_config_and_digest(depth: usize, default_leaf: &[u8], config: TreeConfig) -> Result<FixedDepthMerkleTree<D>, MerkleError> {
+        assert!(depth < usize::BITS as usize, "depth {} is too large", depth);
+        check_leaf::<D>(config.leaf_mode, 0, default_leaf)?;
+
+        let mut defaults = Vec::with_capacity(depth + 1);
+        defaults.push(leaf_hash::<D>(config.prefixes, config.leaf_mode, default_leaf));
+
+        for height in 0..depth {
+            let default = &defaults[height];
+            let parent = hash_children::<D>(config.prefixes, config.sort_pairs, default, default);
+            defaults.push(parent);
+        }
+
+        Ok(FixedDepthMerkleTree { depth, config, nodes: BTreeMap::new(), defaults })
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Number of leaves of the tree, set or not.
+    pub fn capacity(&self) -> usize {
+        1 << self.depth
+    }
+
+    pub fn config(&self) -> TreeConfig {
+        self.config
+    }
+
+    pub fn root_hash(&self) -> &[u8] {
+        self.node(self.depth, 0)
+    }
+
+    /// Root hash as a lowercase hexadecimal string.
+    pub fn root_hash_hex(&self) -> String {
+        to_hex(self.root_hash())
+    }
+
+    /// Sets the leaf at `index` to `data`, rehashing the nodes on its path. Setting a leaf back
+    /// to the default leaf stops storing the subtrees left with default leaves only.
+    pub fn set(&mut self, index: usize, data: &[u8]) -> Result<(), MerkleError> {
+        if index >= self.capacity() {
+            return Err(MerkleError::IndexOutOfBounds { index, leaf_count: self.capacity() });
+        }
+
+        check_leaf::<D>(self.config.leaf_mode, index, data)?;
+
+        let mut hash = leaf_hash::<D>(self.config.prefixes, self.config.leaf_mode, data);
+        let mut index = index;
+
+        for height in 0..=self.depth {
+            self.store(height, index, hash.clone());
+
+            if height == self.depth {
+                break;
+            }
+
+            let sibling = self.node(height, index ^ 1);
+            hash = if index & 1 == 1 {
+                hash_children::<D>(self.config.prefixes, self.config.sort_pairs, sibling, &hash)
+            } else {
+                hash_children::<D>(self.config.prefixes, self.config.sort_pairs, &hash, sibling)
+            };
+            index >>= 1;
+        }
+
+        Ok(())
+    }
+
+    /// Sets every leaf of `entries` like `set`, a later entry for an index replacing an earlier
+    /// one, but rehashes every node above the entries once, computing the nodes shared by the
+    /// paths of several leaves for all of them rather than once per leaf. Nothing is set unless
+    /// every entry is valid.
+    pub fn set_batch<I, B>(&mut self, entries: I) -> Result<(), MerkleError>
+        where I: IntoIterator<Item = (usize, B)>, B: AsRef<[u8]> {
+        let mut leaves = BTreeMap::new();
+
+        for (index, data) in entries {
+            if index >= self.capacity() {
+                return Err(MerkleError::IndexOutOfBounds { index, leaf_count: self.capacity() });
+            }
+
+            check_leaf::<D>(self.config.leaf_mode, index, data.as_ref())?;
+            leaves.insert(index, leaf_hash::<D>(self.config.prefixes, self.config.leaf_mode, data.as_ref()));
+        }
+
+        let mut dirty = BTreeSet::new();
+
+        for (index, hash) in leaves {
+            self.store(0, index, hash);
+            dirty.insert(index);
+        }
+
+        for height in 0..self.depth {
+            let parents: BTreeSet<usize> = dirty.iter().map(|index| index >> 1).collect();
+
+            for &parent in &parents {
+                let left = self.node(height, parent << 1);
+                let right = self.node(height, parent << 1 | 1);
+                let hash = hash_children::<D>(self.config.prefixes, self.config.sort_pairs, left, right);
+                self.store(height + 1, parent, hash);
+            }
+
+            dirty = parents;
+        }
+
+        Ok(())
+    }
+
+    /// Generates an inclusion proof for the leaf at `index`, set or not.
+    pub fn proof(&self, index: usize) -> Result<Proof<D>, MerkleError> {
+        if index >= self.capacity() {
+            return Err(MerkleError::IndexOutOfBounds { index, leaf_count: self.capacity() });
+        }
+
+        let siblings = (0..self.depth)
+            .map(|height| {
+                let position = index >> height;
+                let side = if position & 1 == 1 { Side::Left } else { Side::Right };
+                ProofStep::new(side, self.node(height, position ^ 1).clone())
+            })
+            .collect();
+
+        let mut proof = Proof::new(index, self.capacity(), self.config.padding, siblings);
+        proof.prefixes = self.config.prefixes;
+        proof.sort_pairs = self.config.sort_pairs;
+        proof.leaf_mode = self.config.leaf_mode;
+
+        Ok(proof)
+    }
+
+    fn node(&self, height: usize, index: usize) -> &Hash<D> {
+        self.nodes.get(&(height, index)).unwrap_or(&self.defaults[height])
+    }
+
+    /// Stores the hash of a node, unless it is the hash of a subtree of default leaves.
+    fn store(&mut self, height: usize, index: usize, hash: Hash<D>) {
+        if hash == self.defaults[height] {
+            self.nodes.remove(&(height, index));
+        } else {
+            self.nodes.insert((height, index), hash);
+        }
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for FixedDepthMerkleTree<D> where D: Digest {
+    fn clone(&self) -> Self {
+        FixedDepthMerkleTree {
+            depth: self.depth,
+            config: self.config,
+            nodes: self.nodes.clone(),
+            defaults: self.defaults.clone(),
+        }
+    }
+}
+
+impl<D> fmt::Debug for FixedDepthMerkleTree<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FixedDepthMerkleTree")
+            .field("root_hash", &self.root_hash_hex())
+            .field("depth", &self.depth)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{LeafMode, Prefixes};
+    use crate::MerkleTree;
+
+    #[test]
+    fn it_matches_full_trees_of_default_leaves() {
+        let config = TreeConfig { prefixes: Some(Prefixes::RFC6962), ..TreeConfig::default() };
+        let mut merkle_tree = FixedDepthMerkleTree::with_config(3, b"", config).unwrap();
+        let mut data = vec!["".to_string(); 8];
+
+        assert_eq!(merkle_tree.root_hash(), MerkleTree::from_vec_with_config(data.clone(), config).unwrap().root_hash());
+
+        for &index in &[5, 0, 7] {
+            data[index] = index.to_string();
+            merkle_tree.set(index, data[index].as_bytes()).unwrap();
+
+            let full = MerkleTree::from_vec_with_config(data.clone(), config).unwrap();
+            assert_eq!(merkle_tree.root_hash(), full.root_hash());
+            assert_eq!(merkle_tree.proof(index).unwrap(), full.proof(index).unwrap());
+        }
+
+        assert_eq!(merkle_tree.set(8, b"x"), Err(MerkleError::IndexOutOfBounds { index: 8, leaf_count: 8 }));
+    }
+
+    #[test]
+    fn it_creates_deep_trees_instantly_and_stores_set_paths_only() {
+        let mut merkle_tree = FixedDepthMerkleTree::<sha2::Sha256>::with_digest(32, &[0; 32]).unwrap();
+        let empty_root = merkle_tree.root_hash().to_vec();
+
+        merkle_tree.set(3_000_000_000, b"deposit").unwrap();
+        assert_eq!(merkle_tree.nodes.len(), 33);
+
+        for &index in &[3_000_000_000, 3_000_000_001, 0] {
+            let proof = merkle_tree.proof(index).unwrap();
+            let leaf: &[u8] = if index == 3_000_000_000 { b"deposit" } else { &[0; 32] };
+
+            assert_eq!(proof.siblings.len(), 32);
+            assert_eq!(proof.verify(merkle_tree.root_hash(), leaf), Ok(()));
+        }
+
+        merkle_tree.set(3_000_000_000, &[0; 32]).unwrap();
+        assert!(merkle_tree.nodes.is_empty());
+        assert_eq!(merkle_tree.root_hash(), empty_root.as_slice());
+    }
+
+    #[test]
+    fn it_sets_batches_like_single_leaves() {
+        let mut batched = FixedDepthMerkleTree::new(10, b"").unwrap();
+        let mut single = batched.clone();
+        let entries = vec![(700, "a"), (3, "b"), (2, "c"), (700, "d"), (1023, "e"), (3, "")];
+
+        batched.set_batch(entries.clone()).unwrap();
+        for (index, data) in entries {
+            single.set(index, data.as_bytes()).unwrap();
+        }
+
+        assert_eq!(batched.root_hash(), single.root_hash());
+        assert_eq!(batched.nodes, single.nodes);
+
+        assert_eq!(batched.set_batch(vec![(0, "x"), (1024, "y")]), Err(MerkleError::IndexOutOfBounds { index: 1024, leaf_count: 1024 }));
+        assert_eq!(batched.root_hash(), single.root_hash());
+    }
+
+    #[test]
+    fn it_checks_default_leaves_of_prehashed_trees() {
+        let config = TreeConfig { leaf_mode: LeafMode::Prehashed, ..TreeConfig::default() };
+
+        assert!(FixedDepthMerkleTree::with_config(4, &[0; 64], config).is_ok());
+        assert_eq!(FixedDepthMerkleTree::with_config(4, b"", config).err(), Some(MerkleError::InvalidLeafLength { index: 0, len: 0, expected: 64 }));
+    }
+}
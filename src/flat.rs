@@ -0,0 +1,458 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use blake2::Blake2b;
+use digest::Digest;
+use digest::generic_array::GenericArray;
+
+use crate::{apply_duplicate_policy, calculate_hash, check_leaf, hash_children, leaf_hash, map_hashes, to_hex, Hash};
+use crate::config::{DuplicatePolicy, LeafMode, Padding, Prefixes, TreeConfig};
+use crate::error::MerkleError;
+use crate::leaf::{LeafBytes, LeafEncode};
+use crate::proof::{Proof, ProofStep, Side};
+
+const MAGIC: &[u8; 4] = b"MKLF";
+const VERSION: u8 = 1;
+pub(crate) const HEADER_LEN: usize = 18;
+
+const HAS_PREFIXES: u8 = 0b001;
+const SORT_PAIRS: u8 = 0b010;
+const PREHASHED: u8 = 0b100;
+
+/// A Merkle tree stored as an implicit binary heap in a single contiguous `Vec` of hashes.
+/// The root is at index 0 and the children of node `i` are at `2i + 1` and `2i + 2`, so any
+/// node is reached in O(1) without following pointers. The tree has the same root and proofs
+/// as a [`MerkleTree`](crate::MerkleTree) built from the same data and config, but only keeps
+/// the hashes, not the data.
+pub struct FlatMerkleTree<D: Digest = Blake2b> {
+    /// Heap of `2^(depth + 1) - 1` hashes. Slots past the last node of a level are unused.
+    nodes: Vec<Hash<D>>,
+    config: TreeConfig,
+    leaf_count: usize,
+    depth: usize,
+    digest: PhantomData<D>,
+}
+
+impl FlatMerkleTree {
+    /// Creates a FlatMerkleTree from a vector, hashing with Blake2b and the default config.
+    pub fn from_vec<T>(data: Vec<T>) -> Result<FlatMerkleTree, MerkleError> where T: LeafEncode {
+        FlatMerkleTree::from_vec_with_digest(data)
+    }
+
+    /// Creates a FlatMerkleTree from a vector, hashing with Blake2b.
+    pub fn from_vec_with_config<T>(data: Vec<T>, config: TreeConfig) -> Result<FlatMerkleTree, MerkleError>
+        where T: LeafEncode {
+        FlatMerkleTree::from_vec_with_config_and_digest(data, config)
+    }
+}
+
+impl<D> FlatMerkleTree<D> where D: Digest {
+    /// Creates a FlatMerkleTree from a vector, hashing with the digest `D` and the default config.
+    pub fn from_vec_with_digest<T>(data: Vec<T>) -> Result<FlatMerkleTree<D>, MerkleError> where T: LeafEncode {
+        FlatMerkleTree::from_vec_with_config_and_digest(data, TreeConfig::default())
+    }
+
+    /// Creates a FlatMerkleTree from a vector, hashing with the digest `D`.
+    pub fn from_vec_with_config_and_digest<T>(data: Vec<T>, config: TreeConfig) -> Result<FlatMerkleTree<D>, MerkleError>
+        where T: LeafEncode {
+        if data.is_empty() {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        for (index, leaf) in data.iter().enumerate() {
+            check_leaf::<D>(config.leaf_mode, index, &leaf.encode_leaf())?;
+        }
+
+        let slices: Vec<LeafBytes> = data.iter().map(LeafEncode::encode_leaf).collect();
+        let leaves = map_hashes(&slices, |d| leaf_hash::<D>(config.prefixes, config.leaf_mode, d));
+        let leaves = apply_duplicate_policy(leaves, config.duplicates, |hash| hash)?;
+
+        let leaf_count = leaves.len();
+        let depth = leaf_count.next_power_of_two().trailing_zeros() as usize;
+        let mut nodes = vec![GenericArray::default(); (2 << depth) - 1];
+        let offset = (1 << depth) - 1;
+        nodes[offset..offset + leaf_count].clone_from_slice(&leaves);
+
+        let mut count = leaf_count;
+
+        for height in 1..=depth {
+            let children = &nodes[(1 << (depth - height + 1)) - 1..];
+            let parents: Vec<usize> = (0..count.div_ceil(2)).collect();
+            let hashes = map_hashes(&parents, |&i| {
+                let right = if 2 * i + 1 < count { Some(&children[2 * i + 1]) } else { None };
+                FlatMerkleTree::<D>::parent_hash(config, &children[2 * i], right)
+            });
+
+            let offset = (1 << (depth - height)) - 1;
+            nodes[offset..offset + hashes.len()].clone_from_slice(&hashes);
+            count = hashes.len();
+        }
+
+        Ok(FlatMerkleTree {
+            nodes,
+            config,
+            leaf_count,
+            depth,
+            digest: PhantomData,
+        })
+    }
+
+    /// Hash of the parent of `left` and `right`, padding a lone child as configured.
+    pub(crate) fn parent_hash(config: TreeConfig, left: &Hash<D>, right: Option<&Hash<D>>) -> Hash<D> {
+        match (right, config.padding) {
+            (Some(right), _) => hash_children::<D>(config.prefixes, config.sort_pairs, left, right),
+            (None, Padding::Promote) => left.clone(),
+            (None, Padding::DuplicateLast) => hash_children::<D>(config.prefixes, config.sort_pairs, left, left),
+            (None, Padding::EmptyHash) => hash_children::<D>(config.prefixes, config.sort_pairs, left, &calculate_hash::<D>(&[])),
+        }
+    }
+
+    pub fn root_hash(&self) -> &[u8] {
+        &self.nodes[0]
+    }
+
+    /// Returns the root hash as a lowercase hex string.
+    pub fn root_hash_hex(&self) -> String {
+        to_hex(self.root_hash())
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    pub fn config(&self) -> TreeConfig {
+        self.config
+    }
+
+    /// Every slot of the heap, in order, including the unused ones.
+    #[cfg(all(feature = "mmap", unix))]
+    pub(crate) fn nodes(&self) -> &[Hash<D>] {
+        &self.nodes
+    }
+
+    /// Encodes the tree as a header followed by every slot of its heap, the format
+    /// `MappedMerkleTree` maps from a file.
+    ///
+    /// The header holds a magic number, a version byte, the hash length, the padding, a flags
+    /// byte, the prefixes and the leaf count as a big-endian `u64`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.nodes.len() * D::output_size());
+        bytes.extend_from_slice(&self.header());
+
+        for node in &self.nodes {
+            bytes.extend_from_slice(node);
+        }
+
+        bytes
+    }
+
+    /// Decodes a tree encoded with `to_bytes`. Fails with `InvalidFlatTree` unless every node
+    /// is the hash of its children and the unused slots are zero, so that `to_bytes` gives back
+    /// the same bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<FlatMerkleTree<D>, MerkleError> {
+        let (config, leaf_count, depth) = decode_header::<D>(bytes)?;
+        let nodes: Vec<Hash<D>> = bytes[HEADER_LEN..].chunks(D::output_size()).map(GenericArray::clone_from_slice).collect();
+
+        let mut count = leaf_count;
+
+        for height in 0..=depth {
+            let offset = (1 << (depth - height)) - 1;
+            let level = &nodes[offset..2 * offset + 1];
+
+            if height > 0 {
+                let children = &nodes[2 * offset + 1..];
+                let child_count = count;
+                count = count.div_ceil(2);
+
+                for (i, node) in level[..count].iter().enumerate() {
+                    let right = if 2 * i + 1 < child_count { Some(&children[2 * i + 1]) } else { None };
+
+                    if *node != FlatMerkleTree::<D>::parent_hash(config, &children[2 * i], right) {
+                        return Err(MerkleError::InvalidFlatTree);
+                    }
+                }
+            }
+
+            if level[count..].iter().any(|node| node.iter().any(|&byte| byte != 0)) {
+                return Err(MerkleError::InvalidFlatTree);
+            }
+        }
+
+        Ok(FlatMerkleTree { nodes, config, leaf_count, depth, digest: PhantomData })
+    }
+
+    /// Header of the encoding of `to_bytes`.
+    pub(crate) fn header(&self) -> [u8; HEADER_LEN] {
+        let mut flags = 0;
+
+        if self.config.prefixes.is_some() {
+            flags |= HAS_PREFIXES;
+        }
+
+        if self.config.sort_pairs {
+            flags |= SORT_PAIRS;
+        }
+
+        if self.config.leaf_mode == LeafMode::Prehashed {
+            flags |= PREHASHED;
+        }
+
+        let prefixes = self.config.prefixes.unwrap_or(Prefixes { leaf: 0, node: 0 });
+        let padding = match self.config.padding {
+            Padding::DuplicateLast => 0,
+            Padding::Promote => 1,
+            Padding::EmptyHash => 2,
+        };
+
+        let mut header = [0; HEADER_LEN];
+        header[..4].copy_from_slice(MAGIC);
+        header[4..10].copy_from_slice(&[VERSION, D::output_size() as u8, padding, flags, prefixes.leaf, prefixes.node]);
+        header[10..].copy_from_slice(&(self.leaf_count as u64).to_be_bytes());
+        header
+    }
+
+    /// Number of levels above the leaves.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Number of nodes on the level `height` above the leaves.
+    fn level_len(&self, height: usize) -> usize {
+        (0..height).fold(self.leaf_count, |count, _| count.div_ceil(2))
+    }
+
+    /// Returns the hash of the `index`-th node of the level `height` above the leaves,
+    /// or `None` when the level has no such node. A node promoted without a sibling has
+    /// the same hash as its parent.
+    pub fn node(&self, height: usize, index: usize) -> Option<&[u8]> {
+        if height > self.depth || index >= self.level_len(height) {
+            return None;
+        }
+
+        Some(&self.nodes[(1 << (self.depth - height)) - 1 + index])
+    }
+
+    /// Generates an inclusion proof for the leaf at `leaf_index`, identical to the one
+    /// a MerkleTree built from the same data and config would produce.
+    pub fn proof(&self, leaf_index: usize) -> Result<Proof<D>, MerkleError> {
+        heap_proof(self.config, self.leaf_count, self.depth, leaf_index, |slot| self.nodes[slot].clone())
+    }
+}
+
+/// Config, leaf count and depth of the tree encoded in `bytes` by `FlatMerkleTree::to_bytes`,
+/// checking that `bytes` holds the whole heap of such a tree.
+pub(crate) fn decode_header<D: Digest>(bytes: &[u8]) -> Result<(TreeConfig, usize, usize), MerkleError> {
+    if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+        return Err(MerkleError::InvalidFlatTree);
+    }
+
+    if bytes[4] != VERSION {
+        return Err(MerkleError::UnsupportedVersion(bytes[4]));
+    }
+
+    let flags = bytes[7];
+
+    if bytes[5] as usize != D::output_size() || flags & !(HAS_PREFIXES | SORT_PAIRS | PREHASHED) != 0 {
+        return Err(MerkleError::InvalidFlatTree);
+    }
+
+    let padding = match bytes[6] {
+        0 => Padding::DuplicateLast,
+        1 => Padding::Promote,
+        2 => Padding::EmptyHash,
+        _ => return Err(MerkleError::InvalidFlatTree),
+    };
+    let prefixes = match (flags & HAS_PREFIXES != 0, bytes[8], bytes[9]) {
+        (true, leaf, node) => Some(Prefixes { leaf, node }),
+        (false, 0, 0) => None,
+        (false, _, _) => return Err(MerkleError::InvalidFlatTree),
+    };
+    let config = TreeConfig {
+        padding,
+        prefixes,
+        sort_pairs: flags & SORT_PAIRS != 0,
+        leaf_mode: if flags & PREHASHED != 0 { LeafMode::Prehashed } else { LeafMode::Hashed },
+        duplicates: DuplicatePolicy::Allow,
+    };
+
+    let mut leaf_count = [0; 8];
+    leaf_count.copy_from_slice(&bytes[10..HEADER_LEN]);
+    let leaf_count = u64::from_be_bytes(leaf_count);
+    let len = bytes.len() - HEADER_LEN;
+
+    // Bounding the leaf count by the number of hashes first keeps the heap size from overflowing.
+    if leaf_count == 0 || leaf_count > (len / D::output_size()) as u64 {
+        return Err(MerkleError::InvalidFlatTree);
+    }
+
+    let leaf_count = leaf_count as usize;
+    let depth = leaf_count.next_power_of_two().trailing_zeros() as usize;
+
+    if len != ((2 << depth) - 1) * D::output_size() {
+        return Err(MerkleError::InvalidFlatTree);
+    }
+
+    Ok((config, leaf_count, depth))
+}
+
+/// Generates the proof for `leaf_index` in a heap of `depth` levels laid out like the nodes of
+/// a FlatMerkleTree, reading the node in each slot with `node`.
+pub(crate) fn heap_proof<D, F>(config: TreeConfig, leaf_count: usize, depth: usize, leaf_index: usize, node: F) -> Result<Proof<D>, MerkleError>
+    where D: Digest, F: Fn(usize) -> Hash<D> {
+    if leaf_index >= leaf_count {
+        return Err(MerkleError::IndexOutOfBounds { index: leaf_index, leaf_count });
+    }
+
+    let mut siblings = Vec::with_capacity(depth);
+    let (mut index, mut count) = (leaf_index, leaf_count);
+
+    for height in 0..depth {
+        let level = (1 << (depth - height)) - 1;
+        let sibling = index ^ 1;
+
+        if sibling < count {
+            let side = if index & 1 == 1 { Side::Left } else { Side::Right };
+            siblings.push(ProofStep::new(side, node(level + sibling)));
+        } else {
+            match config.padding {
+                Padding::Promote => {}
+                Padding::DuplicateLast => siblings.push(ProofStep::new(Side::Right, node(level + index))),
+                Padding::EmptyHash => siblings.push(ProofStep::new(Side::Right, calculate_hash::<D>(&[]))),
+            }
+        }
+
+        index /= 2;
+        count = count.div_ceil(2);
+    }
+
+    let mut proof = Proof::new(leaf_index, leaf_count, config.padding, siblings);
+    proof.prefixes = config.prefixes;
+    proof.sort_pairs = config.sort_pairs;
+    proof.leaf_mode = config.leaf_mode;
+
+    Ok(proof)
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for FlatMerkleTree<D> where D: Digest {
+    fn clone(&self) -> Self {
+        FlatMerkleTree {
+            nodes: self.nodes.clone(),
+            config: self.config,
+            leaf_count: self.leaf_count,
+            depth: self.depth,
+            digest: PhantomData,
+        }
+    }
+}
+
+impl<D> PartialEq for FlatMerkleTree<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.root_hash() == other.root_hash()
+    }
+}
+
+impl<D> fmt::Debug for FlatMerkleTree<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FlatMerkleTree")
+            .field("root_hash", &self.root_hash_hex())
+            .field("config", &self.config)
+            .field("leaf_count", &self.leaf_count)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MerkleTree;
+
+    fn leaves(len: usize) -> Vec<String> {
+        (0..len).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn it_matches_boxed_tree_roots_and_proofs() {
+        for &padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            for len in 1..=17 {
+                let config = TreeConfig::new(padding);
+                let flat = FlatMerkleTree::from_vec_with_config(leaves(len), config).unwrap();
+                let boxed = MerkleTree::from_vec_with_config(leaves(len), config).unwrap();
+
+                assert_eq!(flat.root_hash(), boxed.root_hash());
+
+                for index in 0..len {
+                    assert_eq!(flat.proof(index).unwrap(), boxed.proof(index).unwrap());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_matches_rfc6962_roots() {
+        let flat = FlatMerkleTree::<sha2::Sha256>::from_vec_with_config_and_digest(leaves(7), TreeConfig::RFC6962).unwrap();
+        let boxed = MerkleTree::from_vec_rfc6962(leaves(7)).unwrap();
+
+        assert_eq!(flat.root_hash(), boxed.root_hash());
+        assert!(flat.proof(6).unwrap().verify(boxed.root_hash(), "6".as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn it_gives_random_access_to_nodes() {
+        let flat = FlatMerkleTree::from_vec(vec!["a", "b", "c"]).unwrap();
+        let boxed = MerkleTree::from_vec(vec!["a", "b", "c"]).unwrap();
+
+        assert_eq!(flat.depth(), 2);
+        assert_eq!(flat.node(0, 2), Some(&calculate_hash::<Blake2b>("c".as_bytes())[..]));
+        assert_eq!(flat.node(2, 0), Some(boxed.root_hash()));
+        assert_eq!(flat.node(0, 3), None);
+        assert_eq!(flat.node(1, 2), None);
+        assert_eq!(flat.node(3, 0), None);
+    }
+
+    #[test]
+    fn it_rejects_empty_input_and_out_of_bounds_proofs() {
+        assert_eq!(FlatMerkleTree::from_vec(Vec::<&str>::new()), Err(MerkleError::EmptyInput));
+
+        let flat = FlatMerkleTree::from_vec(vec!["a", "b"]).unwrap();
+
+        assert_eq!(flat.proof(2), Err(MerkleError::IndexOutOfBounds { index: 2, leaf_count: 2 }));
+    }
+
+    #[test]
+    fn it_decodes_its_own_bytes() {
+        for &padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            for len in 1..=5 {
+                let config = TreeConfig { prefixes: Some(Prefixes::RFC6962), ..TreeConfig::new(padding) };
+                let flat = FlatMerkleTree::from_vec_with_config(leaves(len), config).unwrap();
+                let decoded = FlatMerkleTree::from_bytes(&flat.to_bytes()).unwrap();
+
+                assert_eq!(decoded.config(), config);
+                assert_eq!(decoded.to_bytes(), flat.to_bytes());
+                assert_eq!(decoded.proof(len - 1).unwrap(), flat.proof(len - 1).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn it_rejects_invalid_bytes() {
+        let bytes = FlatMerkleTree::from_vec(leaves(3)).unwrap().to_bytes();
+        let changed = |index: usize| {
+            let mut bytes = bytes.clone();
+            bytes[index] ^= 1;
+            FlatMerkleTree::<Blake2b>::from_bytes(&bytes)
+        };
+
+        assert_eq!(FlatMerkleTree::<Blake2b>::from_bytes(&bytes[..bytes.len() - 1]), Err(MerkleError::InvalidFlatTree));
+        assert_eq!(FlatMerkleTree::<sha2::Sha256>::from_bytes(&bytes), Err(MerkleError::InvalidFlatTree));
+        assert_eq!(changed(4), Err(MerkleError::UnsupportedVersion(0)));
+        // Leaf count, the root, a leaf, and the unused slot after the last leaf.
+        assert_eq!(changed(HEADER_LEN - 1), Err(MerkleError::InvalidFlatTree));
+        assert_eq!(changed(HEADER_LEN), Err(MerkleError::InvalidFlatTree));
+        assert_eq!(changed(HEADER_LEN + 3 * 64), Err(MerkleError::InvalidFlatTree));
+        assert_eq!(changed(bytes.len() - 1), Err(MerkleError::InvalidFlatTree));
+    }
+}
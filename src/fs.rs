@@ -0,0 +1,333 @@
+//! Merkle DAGs of filesystem trees, for backup verification and content-addressed sync.
+//!
+//! The hash of a file is the root of the tree over its chunks, an empty file having a single
+//! empty chunk. The hash of a directory is the root of the tree with one leaf per entry, in the
+//! byte order of their names, each leaf holding a kind byte (`f`, `d` or `l`), the name, a zero
+//! byte and the hash of the entry; an empty directory has a single empty leaf. The hash of a
+//! symbolic link is the root of the tree with its target as the only leaf. Entries of other
+//! kinds, like sockets, are left out. Hashes only depend on names and contents, so copies of a
+//! directory have the same root wherever they are.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::marker::PhantomData;
+use std::path::Path;
+
+use blake2::Blake2b;
+use digest::Digest;
+
+use crate::Hash;
+use crate::cdc::FastCdc;
+use crate::config::{LeafMode, TreeConfig};
+use crate::reader::Chunking;
+use crate::tree::MerkleTree;
+
+/// Hashes files and directories, splitting files in chunks of `chunk_size` bytes, or in
+/// content-defined chunks with FastCDC.
+pub struct FsHasher<D: Digest = Blake2b> {
+    chunking: Chunking,
+    config: TreeConfig,
+    digest: PhantomData<D>,
+}
+
+/// Hashed file, directory or symbolic link.
+pub struct FsEntry<D: Digest = Blake2b> {
+    /// File name of the entry, empty for a path without one, like `/`.
+    pub name: String,
+    pub hash: Hash<D>,
+    pub kind: FsKind<D>,
+}
+
+pub enum FsKind<D: Digest = Blake2b> {
+    File { len: u64 },
+    /// The entries of the directory, in the byte order of their names.
+    Directory(Vec<FsEntry<D>>),
+    Symlink { target: String },
+}
+
+impl FsHasher<Blake2b> {
+    /// Creates a hasher hashing with Blake2b and the default config.
+    pub fn new(chunk_size: usize) -> FsHasher {
+        FsHasher::with_config_and_digest(chunk_size, TreeConfig::default())
+    }
+
+    /// Creates a hasher hashing with Blake2b.
+    pub fn with_config(chunk_size: usize, config: TreeConfig) -> FsHasher {
+        FsHasher::with_config_and_digest(chunk_size, config)
+    }
+
+    /// Creates a hasher splitting files in chunks cut by `cdc`, hashing with Blake2b and the
+    /// default config.
+    pub fn content_defined(cdc: FastCdc) -> FsHasher {
+        FsHasher::content_defined_with_config_and_digest(cdc, TreeConfig::default())
+    }
+}
+
+impl<D> FsHasher<D> where D: Digest {
+    /// Creates a hasher hashing with the digest `D`. Leaves are always hashed, whatever the
+    /// leaf mode of `config`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn with_config_and_digest(chunk_size: usize, config: TreeConfig) -> FsHasher<D> {
+        assert!(chunk_size > 0, "chunk size must not be zero");
+        FsHasher::with_chunking(Chunking::Fixed(chunk_size), config)
+    }
+
+    /// Creates a hasher splitting files in chunks cut by `cdc`, hashing with the digest `D`, so
+    /// that an edit to a file only changes the leaves of the chunks around it, as deduplicating
+    /// backups need.
+    pub fn content_defined_with_config_and_digest(cdc: FastCdc, config: TreeConfig) -> FsHasher<D> {
+        FsHasher::with_chunking(Chunking::ContentDefined(cdc), config)
+    }
+
+    fn with_chunking(chunking: Chunking, config: TreeConfig) -> FsHasher<D> {
+        FsHasher {
+            chunking,
+            config: TreeConfig { leaf_mode: LeafMode::Hashed, ..config },
+            digest: PhantomData,
+        }
+    }
+
+    /// Hashes the file, directory or symbolic link at `path`, without following symbolic links.
+    /// Fails with `InvalidData` on names and link targets that are not UTF-8, and with
+    /// `InvalidInput` if `path` is of another kind.
+    pub fn hash_path<P: AsRef<Path>>(&self, path: P) -> io::Result<FsEntry<D>> {
+        let path = path.as_ref();
+        let name = match path.file_name() {
+            Some(name) => name.to_str().ok_or_else(not_utf8)?.to_string(),
+            None => String::new(),
+        };
+
+        self.hash_entry(path, name)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file, directory or symbolic link"))
+    }
+
+    fn hash_entry(&self, path: &Path, name: String) -> io::Result<Option<FsEntry<D>>> {
+        let file_type = fs::symlink_metadata(path)?.file_type();
+
+        let (hash, kind) = if file_type.is_dir() {
+            let mut entries = Vec::new();
+
+            for dir_entry in fs::read_dir(path)? {
+                let dir_entry = dir_entry?;
+                let name = dir_entry.file_name().into_string().map_err(|_| not_utf8())?;
+
+                if let Some(entry) = self.hash_entry(&dir_entry.path(), name)? {
+                    entries.push(entry);
+                }
+            }
+
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+            let mut leaves: Vec<Vec<u8>> = entries.iter().map(FsEntry::leaf).collect();
+
+            if leaves.is_empty() {
+                leaves.push(Vec::new());
+            }
+
+            (self.root(leaves), FsKind::Directory(entries))
+        } else if file_type.is_file() {
+            let file = File::open(path)?;
+            let len = file.metadata()?.len();
+
+            let hash = if len == 0 {
+                self.root(vec![Vec::new()])
+            } else {
+                let tree = MerkleTree::<_, D>::from_reader_with_chunking(BufReader::new(file), self.chunking, self.config)?;
+                tree.root.hash().clone()
+            };
+
+            (hash, FsKind::File { len })
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(path)?.into_os_string().into_string().map_err(|_| not_utf8())?;
+            (self.root(vec![target.clone().into_bytes()]), FsKind::Symlink { target })
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some(FsEntry { name, hash, kind }))
+    }
+
+    fn root(&self, leaves: Vec<Vec<u8>>) -> Hash<D> {
+        let tree = MerkleTree::<_, D>::from_vec_with_config_and_digest(leaves, self.config).expect("there is at least one leaf");
+        tree.root.hash().clone()
+    }
+}
+
+impl<D> FsEntry<D> where D: Digest {
+    /// Leaf of the entry in the tree of its directory.
+    pub fn leaf(&self) -> Vec<u8> {
+        let kind = match self.kind {
+            FsKind::File { .. } => b'f',
+            FsKind::Directory(_) => b'd',
+            FsKind::Symlink { .. } => b'l',
+        };
+
+        [&[kind][..], self.name.as_bytes(), &[0], &self.hash].concat()
+    }
+
+    /// Returns the entry at `path` below this one, a sequence of names separated by `/`.
+    pub fn find(&self, path: &str) -> Option<&FsEntry<D>> {
+        path.split('/').filter(|name| !name.is_empty()).try_fold(self, |entry, name| match &entry.kind {
+            FsKind::Directory(entries) => entries.iter().find(|entry| entry.name == name),
+            _ => None,
+        })
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for FsEntry<D> where D: Digest {
+    fn clone(&self) -> Self {
+        FsEntry { name: self.name.clone(), hash: self.hash.clone(), kind: self.kind.clone() }
+    }
+}
+
+impl<D> Clone for FsKind<D> where D: Digest {
+    fn clone(&self) -> Self {
+        match self {
+            FsKind::File { len } => FsKind::File { len: *len },
+            FsKind::Directory(entries) => FsKind::Directory(entries.clone()),
+            FsKind::Symlink { target } => FsKind::Symlink { target: target.clone() },
+        }
+    }
+}
+
+impl<D> PartialEq for FsEntry<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name &&
+            self.hash == other.hash &&
+            self.kind == other.kind
+    }
+}
+
+impl<D> PartialEq for FsKind<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (FsKind::File { len: a }, FsKind::File { len: b }) => a == b,
+            (FsKind::Directory(a), FsKind::Directory(b)) => a == b,
+            (FsKind::Symlink { target: a }, FsKind::Symlink { target: b }) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl<D> fmt::Debug for FsEntry<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FsEntry")
+            .field("name", &self.name)
+            .field("hash", &crate::to_hex(&self.hash))
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+impl<D> fmt::Debug for FsKind<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsKind::File { len } => f.debug_struct("File").field("len", len).finish(),
+            FsKind::Directory(entries) => f.debug_tuple("Directory").field(entries).finish(),
+            FsKind::Symlink { target } => f.debug_struct("Symlink").field("target", target).finish(),
+        }
+    }
+}
+
+fn not_utf8() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "name is not UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> TestDir {
+            let path = std::env::temp_dir().join(format!("merkle-tree-fs-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(path.join("docs/empty")).unwrap();
+            fs::write(path.join("docs/readme"), b"read me").unwrap();
+            fs::write(path.join("data"), vec![7; 1000]).unwrap();
+            fs::write(path.join("nothing"), b"").unwrap();
+            TestDir(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn it_hashes_copies_of_a_directory_alike() {
+        let (a, b) = (TestDir::new("copy-a"), TestDir::new("copy-b"));
+        let hasher = FsHasher::new(64);
+
+        let root = hasher.hash_path(&a.0).unwrap();
+        assert_eq!(root.hash, hasher.hash_path(&b.0).unwrap().hash);
+
+        let names: Vec<&str> = match &root.kind {
+            FsKind::Directory(entries) => entries.iter().map(|entry| entry.name.as_str()).collect(),
+            _ => panic!("root is a directory"),
+        };
+        assert_eq!(names, vec!["data", "docs", "nothing"]);
+
+        let data = root.find("data").unwrap();
+        let contents = vec![7; 1000];
+        let expected = MerkleTree::from_vec(contents.chunks(64).collect()).unwrap();
+        assert_eq!(&data.hash[..], expected.root_hash());
+        assert_eq!(data.kind, FsKind::File { len: 1000 });
+        assert_eq!(root.find("docs/empty").map(|entry| entry.kind.clone()), Some(FsKind::Directory(vec![])));
+        assert_eq!(root.find("docs/missing"), None);
+    }
+
+    #[test]
+    fn it_changes_the_root_with_contents_and_names() {
+        let dir = TestDir::new("changes");
+        let hasher = FsHasher::new(64);
+        let original = hasher.hash_path(&dir.0).unwrap();
+
+        fs::write(dir.0.join("docs/readme"), b"read me!").unwrap();
+        let edited = hasher.hash_path(&dir.0).unwrap();
+        assert_ne!(edited.hash, original.hash);
+        assert_ne!(edited.find("docs").unwrap().hash, original.find("docs").unwrap().hash);
+        assert_eq!(edited.find("data"), original.find("data"));
+
+        fs::rename(dir.0.join("data"), dir.0.join("moved")).unwrap();
+        assert_ne!(hasher.hash_path(&dir.0).unwrap().hash, edited.hash);
+    }
+
+    #[test]
+    fn it_hashes_files_in_content_defined_chunks() {
+        let dir = TestDir::new("cdc");
+        let cdc = FastCdc::new(64, 256, 1024);
+        let contents = crate::cdc::tests::noise(10_000, 4);
+        fs::write(dir.0.join("data"), &contents).unwrap();
+
+        let root = FsHasher::content_defined(cdc).hash_path(&dir.0).unwrap();
+        let expected = MerkleTree::from_vec(cdc.chunks(&contents).collect()).unwrap();
+
+        assert_eq!(&root.find("data").unwrap().hash[..], expected.root_hash());
+        assert_ne!(root.hash, FsHasher::new(256).hash_path(&dir.0).unwrap().hash);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_hashes_symbolic_links_by_target() {
+        let dir = TestDir::new("links");
+        std::os::unix::fs::symlink("docs/readme", dir.0.join("link")).unwrap();
+
+        let root = FsHasher::new(64).hash_path(&dir.0).unwrap();
+        let link = root.find("link").unwrap();
+
+        assert_eq!(link.kind, FsKind::Symlink { target: "docs/readme".to_string() });
+        assert_eq!(&link.hash[..], MerkleTree::from_vec(vec!["docs/readme"]).unwrap().root_hash());
+        assert_eq!(link.leaf(), [&b"llink\0"[..], &link.hash].concat());
+    }
+}
@@ -0,0 +1,145 @@
+//! Entry points for fuzzing the decoding of untrusted bytes, for the cargo-fuzz targets in
+//! `fuzz/` or any other fuzzer.
+//!
+//! Every function accepts any input. They only panic to report a bug, when a value decoded from
+//! the input does not encode back to it, which would mean that two encodings decode to the same
+//! value, or when decoding or using a decoded value panics on its own.
+
+use alloc::vec;
+
+use blake2::Blake2b;
+use digest::Digest;
+use sha2::Sha256;
+
+use crate::flat::FlatMerkleTree;
+use crate::incremental::{IncrementalState, IncrementalTree};
+use crate::multiproof::MultiProof;
+use crate::proof::Proof;
+
+/// Decodes `data` as every encoding of proofs, for digests of 64 and 32 bytes, and verifies the
+/// proofs decoded against an arbitrary root.
+pub fn decode_proof_bytes(data: &[u8]) {
+    decode_proofs::<Blake2b>(data);
+    decode_proofs::<Sha256>(data);
+}
+
+/// Decodes `data` as a flat tree in the format of `FlatMerkleTree::to_bytes`, for digests of 64
+/// and 32 bytes, and proves the first and last leaves of the trees decoded.
+pub fn decode_tree_bytes(data: &[u8]) {
+    decode_tree::<Blake2b>(data);
+    decode_tree::<Sha256>(data);
+}
+
+/// Decodes `data` as a suspended incremental tree, for digests of 64 and 32 bytes, and resumes
+/// the trees decoded to compute their root and append a leaf.
+pub fn decode_incremental_state_bytes(data: &[u8]) {
+    decode_incremental_state::<Blake2b>(data);
+    decode_incremental_state::<Sha256>(data);
+}
+
+fn decode_proofs<D: Digest>(data: &[u8]) {
+    let root = vec![0; D::output_size()];
+
+    if let Ok(proof) = Proof::<D>::from_bytes(data) {
+        assert_eq!(proof.to_bytes(), data, "proof does not encode back to its bytes");
+        let _ = proof.verify(&root, b"");
+    }
+
+    if let Ok(proof) = MultiProof::<D>::from_bytes(data) {
+        assert_eq!(proof.to_bytes(), data, "multiproof does not encode back to its bytes");
+        let _ = proof.verify(&root, &vec![&b""[..]; proof.leaf_indices.len()]);
+    }
+
+    if let Ok(proof) = MultiProof::<D>::from_compressed_bytes(data) {
+        assert_eq!(proof.to_compressed_bytes(), data, "compressed multiproof does not encode back to its bytes");
+        let _ = proof.verify(&root, &vec![&b""[..]; proof.leaf_indices.len()]);
+    }
+}
+
+fn decode_tree<D: Digest>(data: &[u8]) {
+    if let Ok(merkle_tree) = FlatMerkleTree::<D>::from_bytes(data) {
+        assert_eq!(merkle_tree.to_bytes(), data, "flat tree does not encode back to its bytes");
+
+        for &index in &[0, merkle_tree.leaf_count() - 1] {
+            let proof = merkle_tree.proof(index).expect("leaf of a decoded tree cannot be proven");
+            let leaf = merkle_tree.node(0, index).expect("leaf of a decoded tree is missing");
+            assert_eq!(proof.verify_hash_with_config(merkle_tree.root_hash(), leaf, merkle_tree.config()), Ok(()), "proof of a decoded tree does not verify");
+        }
+    }
+}
+
+fn decode_incremental_state<D: Digest>(data: &[u8]) {
+    if let Ok(state) = IncrementalState::<D>::from_bytes(data) {
+        assert_eq!(state.to_bytes(), data, "incremental state does not encode back to its bytes");
+
+        if let Ok(mut merkle_tree) = IncrementalTree::resume(state) {
+            let _ = merkle_tree.root_hash();
+            let _ = merkle_tree.push(&[0; 64][..D::output_size()]);
+            let _ = merkle_tree.root_hash();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MerkleTree, Padding, Prefixes, TreeConfig};
+
+    /// Every truncation of `bytes` and every change of one of its bytes.
+    fn mutations(bytes: &[u8]) -> impl Iterator<Item = alloc::vec::Vec<u8>> + '_ {
+        let truncations = (0..bytes.len()).map(move |len| bytes[..len].to_vec());
+        let changes = (0..bytes.len()).flat_map(move |index| {
+            [0x01, 0x80, 0xff].iter().map(move |value| {
+                let mut changed = bytes.to_vec();
+                changed[index] ^= value;
+                changed
+            })
+        });
+
+        truncations.chain(changes)
+    }
+
+    #[test]
+    fn it_decodes_mutated_proofs_without_panicking() {
+        let merkle_tree = MerkleTree::<_, Sha256>::from_vec_with_digest(vec!["a", "b", "c", "d", "e"]).unwrap();
+
+        for bytes in &[
+            merkle_tree.proof(4).unwrap().to_bytes(),
+            merkle_tree.multi_proof(&[1, 4]).unwrap().to_bytes(),
+            merkle_tree.multi_proof(&[1, 4]).unwrap().to_compressed_bytes(),
+        ] {
+            mutations(bytes).for_each(|bytes| decode_proof_bytes(&bytes));
+        }
+    }
+
+    #[test]
+    fn it_decodes_crafted_proofs_without_panicking() {
+        // A compressed multiproof of 32-byte hashes over u64::MAX leaves, whose second index
+        // would overflow: the gaps u64::MAX - 1 and 1 are each below the leaf count.
+        let header = [2, 3, 0, 32, 0];
+        let leaf_count = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        let first_gap = [0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        let bytes = [&header[..], &leaf_count, &[2], &first_gap, &[1]].concat();
+
+        decode_proof_bytes(&bytes);
+    }
+
+    #[test]
+    fn it_decodes_mutated_trees_without_panicking() {
+        let config = TreeConfig { prefixes: Some(Prefixes::RFC6962), ..TreeConfig::new(Padding::Promote) };
+        let merkle_tree = FlatMerkleTree::<Sha256>::from_vec_with_config_and_digest(vec!["a", "b", "c"], config).unwrap();
+        decode_tree_bytes(&merkle_tree.to_bytes());
+
+        mutations(&merkle_tree.to_bytes()).for_each(|bytes| decode_tree_bytes(&bytes));
+    }
+
+    #[test]
+    fn it_decodes_mutated_incremental_states_without_panicking() {
+        let mut merkle_tree = IncrementalTree::<Sha256>::with_config_and_digest(TreeConfig::default());
+        for leaf in &["a", "b", "c"] {
+            merkle_tree.push(leaf.as_bytes()).unwrap();
+        }
+
+        mutations(&merkle_tree.suspend().to_bytes()).for_each(|bytes| decode_incremental_state_bytes(&bytes));
+    }
+}
@@ -0,0 +1,315 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use blake2::Blake2b;
+use digest::Digest;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::config::{DuplicatePolicy, Padding, TreeConfig};
+use crate::error::MerkleError;
+use crate::{calculate_hash, check_leaf, hash_children, leaf_hash, Hash};
+
+/// Computes the root of the MerkleTree that would be built from a sequence of leaves while they
+/// are pushed, keeping only the frontier: the hash of one perfect subtree per bit set in the leaf
+/// count. It takes a logarithmic amount of memory and cannot generate proofs.
+///
+/// A tree can be suspended into an [`IncrementalState`], stored, and resumed later to carry on
+/// with the next leaves, so that hashing a long upload survives a restart of the process.
+pub struct IncrementalTree<D: Digest = Blake2b> {
+    config: TreeConfig,
+    leaf_count: usize,
+    /// Hash of the perfect subtree of height `h` at index `h`, for every bit `h` set in the
+    /// leaf count.
+    frontier: Vec<Option<Hash<D>>>,
+}
+
+/// Name of [`IncrementalTree`] for pipelines that only need the root of a stream of leaves: push
+/// every leaf, then `finish` to get the root the MerkleTree of those leaves would have, without
+/// ever holding more than one hash per level.
+pub type RootHasher<D = Blake2b> = IncrementalTree<D>;
+
+impl IncrementalTree {
+    /// Creates an empty IncrementalTree hashing with Blake2b and the default config.
+    pub fn new() -> IncrementalTree {
+        IncrementalTree::with_config_and_digest(TreeConfig::default())
+    }
+
+    /// Creates an empty IncrementalTree hashing with Blake2b.
+    pub fn with_config(config: TreeConfig) -> IncrementalTree {
+        IncrementalTree::with_config_and_digest(config)
+    }
+}
+
+impl Default for IncrementalTree {
+    fn default() -> Self {
+        IncrementalTree::new()
+    }
+}
+
+impl<D> IncrementalTree<D> where D: Digest {
+    /// Creates an empty IncrementalTree hashing with the digest `D`.
+    pub fn with_config_and_digest(config: TreeConfig) -> IncrementalTree<D> {
+        IncrementalTree { config, leaf_count: 0, frontier: Vec::new() }
+    }
+
+    /// Resumes a tree suspended by `suspend`. Fails with `InvalidState` when the state does not
+    /// hold one frontier hash per bit set in its leaf count.
+    pub fn resume(state: IncrementalState<D>) -> Result<IncrementalTree<D>, MerkleError> {
+        if state.frontier.len() != state.leaf_count.count_ones() as usize {
+            return Err(MerkleError::InvalidState);
+        }
+
+        let mut hashes = state.frontier.into_iter();
+        let mut frontier = vec![None; (usize::BITS - state.leaf_count.leading_zeros()) as usize];
+
+        for height in (0..frontier.len()).rev() {
+            if (state.leaf_count >> height) & 1 == 1 {
+                frontier[height] = hashes.next();
+            }
+        }
+
+        Ok(IncrementalTree { config: state.config, leaf_count: state.leaf_count, frontier })
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaf_count == 0
+    }
+
+    pub fn config(&self) -> TreeConfig {
+        self.config
+    }
+
+    /// Appends a leaf and returns its index, merging the perfect subtrees it completes. Fails
+    /// with `TreeFull` if the tree holds `usize::MAX` leaves, as a resumed one can, and with
+    /// `UnsupportedDuplicatePolicy` unless the config allows duplicates, as the frontier keeps
+    /// nothing to recognize an earlier leaf by.
+    pub fn push(&mut self, data: &[u8]) -> Result<usize, MerkleError> {
+        if self.config.duplicates != DuplicatePolicy::Allow {
+            return Err(MerkleError::UnsupportedDuplicatePolicy(self.config.duplicates));
+        }
+
+        if self.leaf_count == usize::MAX {
+            return Err(MerkleError::TreeFull { capacity: usize::MAX });
+        }
+
+        check_leaf::<D>(self.config.leaf_mode, self.leaf_count, data)?;
+
+        let mut hash = leaf_hash::<D>(self.config.prefixes, self.config.leaf_mode, data);
+        let mut height = 0;
+
+        while (self.leaf_count >> height) & 1 == 1 {
+            let left = self.frontier[height].take().expect("frontier holds a hash per bit of the leaf count");
+            hash = hash_children::<D>(self.config.prefixes, self.config.sort_pairs, &left, &hash);
+            height += 1;
+        }
+
+        if self.frontier.len() == height {
+            self.frontier.push(None);
+        }
+
+        self.frontier[height] = Some(hash);
+        self.leaf_count += 1;
+
+        Ok(self.leaf_count - 1)
+    }
+
+    /// Root hash of the MerkleTree built from the leaves pushed so far with the same config,
+    /// which allows duplicates. Fails with `EmptyInput` before the first leaf.
+    pub fn root_hash(&self) -> Result<Hash<D>, MerkleError> {
+        if self.leaf_count == 0 {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        // Last node of the current level, when it does not cover a perfect subtree.
+        let mut last: Option<Hash<D>> = None;
+        let mut height = 0;
+
+        while height < usize::BITS as usize && self.leaf_count > 1 << height {
+            last = match (&self.frontier[height], last) {
+                (Some(left), Some(right)) => Some(hash_children::<D>(self.config.prefixes, self.config.sort_pairs, left, &right)),
+                (Some(lone), None) => Some(self.pad(lone)),
+                (None, Some(lone)) => Some(self.pad(&lone)),
+                (None, None) => None,
+            };
+            height += 1;
+        }
+
+        Ok(last.unwrap_or_else(|| self.frontier[height].clone().expect("a perfect tree is its only subtree")))
+    }
+
+    /// Consumes the tree and returns its root hash, like `root_hash`.
+    pub fn finish(self) -> Result<Hash<D>, MerkleError> {
+        self.root_hash()
+    }
+
+    /// Hash of the parent of a node without a sibling.
+    fn pad(&self, lone: &Hash<D>) -> Hash<D> {
+        let config = self.config;
+
+        match config.padding {
+            Padding::Promote => lone.clone(),
+            Padding::DuplicateLast => hash_children::<D>(config.prefixes, config.sort_pairs, lone, lone),
+            Padding::EmptyHash => hash_children::<D>(config.prefixes, config.sort_pairs, lone, &calculate_hash::<D>(&[])),
+        }
+    }
+
+    /// Turns the tree into the state `resume` carries on from.
+    pub fn suspend(self) -> IncrementalState<D> {
+        IncrementalState::new(self.config, self.leaf_count, self.frontier.into_iter().rev().flatten().collect())
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for IncrementalTree<D> where D: Digest {
+    fn clone(&self) -> Self {
+        IncrementalTree { config: self.config, leaf_count: self.leaf_count, frontier: self.frontier.clone() }
+    }
+}
+
+impl<D> fmt::Debug for IncrementalTree<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IncrementalTree")
+            .field("config", &self.config)
+            .field("leaf_count", &self.leaf_count)
+            .finish()
+    }
+}
+
+/// State of a suspended [`IncrementalTree`]. `frontier` holds the hashes of the perfect subtrees
+/// covering the leaves pushed so far, from the highest (leftmost) to the lowest. It can be
+/// stored with serde or with `to_bytes`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct IncrementalState<D: Digest = Blake2b> {
+    pub config: TreeConfig,
+    pub leaf_count: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::list"))]
+    pub frontier: Vec<Hash<D>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    digest: PhantomData<D>,
+}
+
+impl<D> IncrementalState<D> where D: Digest {
+    pub fn new(config: TreeConfig, leaf_count: usize, frontier: Vec<Hash<D>>) -> IncrementalState<D> {
+        IncrementalState { config, leaf_count, frontier, digest: PhantomData }
+    }
+}
+
+impl<D> Clone for IncrementalState<D> where D: Digest {
+    fn clone(&self) -> Self {
+        IncrementalState::new(self.config, self.leaf_count, self.frontier.clone())
+    }
+}
+
+impl<D> PartialEq for IncrementalState<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.config == other.config
+            && self.leaf_count == other.leaf_count
+            && self.frontier == other.frontier
+    }
+}
+
+impl<D> fmt::Debug for IncrementalState<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IncrementalState")
+            .field("config", &self.config)
+            .field("leaf_count", &self.leaf_count)
+            .field("frontier", &self.frontier)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MerkleTree, Prefixes};
+
+    fn leaves(len: usize) -> Vec<String> {
+        (0..len).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn it_matches_the_root_of_trees_built_at_once() {
+        for &padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            for &config in &[TreeConfig::new(padding), TreeConfig { prefixes: Some(Prefixes::RFC6962), sort_pairs: true, ..TreeConfig::new(padding) }] {
+                let mut incremental = IncrementalTree::with_config(config);
+
+                for len in 1..=33 {
+                    assert_eq!(incremental.push((len - 1).to_string().as_bytes()), Ok(len - 1));
+
+                    let merkle_tree = MerkleTree::from_vec_with_config(leaves(len), config).unwrap();
+                    assert_eq!(incremental.root_hash().unwrap().as_slice(), merkle_tree.root_hash());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_hashes_streams_of_leaves() {
+        let mut hasher = RootHasher::<sha2::Sha256>::with_config_and_digest(TreeConfig::RFC6962);
+
+        for leaf in leaves(1000) {
+            hasher.push(leaf.as_bytes()).unwrap();
+        }
+
+        let merkle_tree = MerkleTree::from_vec_rfc6962(leaves(1000)).unwrap();
+        assert_eq!(hasher.finish().unwrap().as_slice(), merkle_tree.root_hash());
+        assert_eq!(RootHasher::new().finish(), Err(MerkleError::EmptyInput));
+    }
+
+    #[test]
+    fn it_resumes_where_it_was_suspended() {
+        let mut incremental = IncrementalTree::new();
+
+        for leaf in &leaves(11) {
+            incremental.push(leaf.as_bytes()).unwrap();
+        }
+
+        let state = incremental.suspend();
+        assert_eq!(state.frontier.len(), 3);
+
+        let mut resumed = IncrementalTree::resume(state.clone()).unwrap();
+        assert_eq!(resumed.clone().suspend(), state);
+
+        for leaf in &leaves(20)[11..] {
+            resumed.push(leaf.as_bytes()).unwrap();
+        }
+
+        assert_eq!(resumed.root_hash().unwrap().as_slice(), MerkleTree::from_vec(leaves(20)).unwrap().root_hash());
+    }
+
+    #[test]
+    fn it_rejects_invalid_states_and_empty_trees() {
+        assert_eq!(IncrementalTree::new().root_hash(), Err(MerkleError::EmptyInput));
+
+        let mut state = IncrementalTree::new().suspend();
+        state.leaf_count = 3;
+        assert_eq!(IncrementalTree::resume(state).unwrap_err(), MerkleError::InvalidState);
+    }
+
+    #[test]
+    fn it_handles_resumed_trees_of_any_size() {
+        let hash = calculate_hash::<Blake2b>(b"a");
+        let state = IncrementalState::<Blake2b>::new(TreeConfig::default(), usize::MAX, vec![hash; usize::BITS as usize]);
+        let mut merkle_tree = IncrementalTree::resume(state).unwrap();
+
+        assert!(merkle_tree.root_hash().is_ok());
+        assert_eq!(merkle_tree.push(b"b"), Err(MerkleError::TreeFull { capacity: usize::MAX }));
+    }
+
+    #[test]
+    fn it_only_allows_duplicates() {
+        for &duplicates in &[DuplicatePolicy::Reject, DuplicatePolicy::Deduplicate] {
+            let mut incremental = IncrementalTree::with_config(TreeConfig { duplicates, ..TreeConfig::default() });
+
+            assert_eq!(incremental.push(b"a"), Err(MerkleError::UnsupportedDuplicatePolicy(duplicates)));
+        }
+    }
+}
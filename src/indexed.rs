@@ -0,0 +1,180 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use blake2::Blake2b;
+use digest::Digest;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{map_hashes, Hash};
+use crate::config::{LeafMode, Prefixes, TreeConfig};
+use crate::error::MerkleError;
+use crate::leaf::{LeafBytes, LeafEncode};
+use crate::proof::Proof;
+use crate::tree::{MerkleLeaf, MerkleTree, Node};
+
+/// A MerkleTree whose leaf hashes mix the index of each leaf in with its data, so that the proof
+/// of a leaf only verifies at its own index, even against another leaf holding the same data.
+///
+/// `Proof::verify_with_config` rejects proofs of trees hashing pairs in order whose siblings are
+/// not on the sides of the path of their `leaf_index`, while `Proof::verify` lets the proof claim
+/// sorted pairs and skip that check. Trees hashing sorted pairs ignore the sides, so there a
+/// proof verifies at the index of any leaf holding the same data, as it does for verifiers that
+/// only check the root reached. Binding the index into the leaf hash covers every case, at the
+/// cost of roots that differ from those of a plain MerkleTree of the same data.
+pub struct IndexedMerkleTree<T, D: Digest = Blake2b> {
+    tree: MerkleTree<T, D>,
+}
+
+impl<T> IndexedMerkleTree<T, Blake2b> where T: LeafEncode {
+    /// Creates an IndexedMerkleTree from a vector, hashing with Blake2b and the default config.
+    pub fn from_vec(data: Vec<T>) -> Result<IndexedMerkleTree<T>, MerkleError> {
+        IndexedMerkleTree::from_vec_with_config_and_digest(data, TreeConfig::default())
+    }
+
+    pub fn from_vec_with_config(data: Vec<T>, config: TreeConfig) -> Result<IndexedMerkleTree<T>, MerkleError> {
+        IndexedMerkleTree::from_vec_with_config_and_digest(data, config)
+    }
+}
+
+impl<T, D> IndexedMerkleTree<T, D> where T: LeafEncode, D: Digest {
+    /// Creates an IndexedMerkleTree from a vector, hashing with the digest `D`. The leaf mode of
+    /// `config` is ignored, as the index and data are always hashed together.
+    pub fn from_vec_with_config_and_digest(data: Vec<T>, config: TreeConfig) -> Result<IndexedMerkleTree<T, D>, MerkleError> {
+        let config = TreeConfig { leaf_mode: LeafMode::Hashed, ..config };
+        let hashes = {
+            let leaves: Vec<(usize, LeafBytes)> = data.iter().map(LeafEncode::encode_leaf).enumerate().collect();
+            map_hashes(&leaves, |(index, data)| indexed_leaf_hash::<D>(config.prefixes, *index, data))
+        };
+        let leaves = data.into_iter().zip(hashes).map(|(data, hash)| Node::Leaf(MerkleLeaf { hash, data }));
+
+        Ok(IndexedMerkleTree { tree: MerkleTree::from_leaves(leaves.collect(), config)? })
+    }
+
+    /// The underlying tree, whose proofs verify against the index-bound leaf hashes only.
+    pub fn tree(&self) -> &MerkleTree<T, D> {
+        &self.tree
+    }
+
+    pub fn root_hash(&self) -> &[u8] {
+        self.tree.root_hash()
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.tree.leaf_count()
+    }
+
+    /// Generates an inclusion proof for the leaf at `leaf_index`, which only verifies at that
+    /// index.
+    pub fn proof(&self, leaf_index: usize) -> Result<IndexedProof<D>, MerkleError> {
+        Ok(IndexedProof { proof: self.tree.proof(leaf_index)? })
+    }
+}
+
+/// Inclusion proof for a leaf of an IndexedMerkleTree, bound to `proof.leaf_index`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct IndexedProof<D: Digest = Blake2b> {
+    pub proof: Proof<D>,
+}
+
+impl<D> IndexedProof<D> where D: Digest {
+    pub fn leaf_index(&self) -> usize {
+        self.proof.leaf_index
+    }
+
+    /// Checks that `leaf_data` is the leaf at `leaf_index()` of the tree with the given root hash.
+    /// A proof whose index was changed reaches another root, whatever the sides of its siblings.
+    pub fn verify(&self, root_hash: &[u8], leaf_data: &[u8]) -> Result<(), MerkleError> {
+        self.proof.verify_hash(root_hash, &indexed_leaf_hash::<D>(self.proof.prefixes, self.proof.leaf_index, leaf_data))
+    }
+
+    /// Same as `verify`, for a tree built with `config`, whose leaf mode is ignored as it is when
+    /// building. Fails with `ConfigMismatch` unless the proof was generated with `config`.
+    pub fn verify_with_config(&self, root_hash: &[u8], leaf_data: &[u8], config: TreeConfig) -> Result<(), MerkleError> {
+        self.proof.check_config(TreeConfig { leaf_mode: LeafMode::Hashed, ..config })?;
+        self.verify(root_hash, leaf_data)
+    }
+}
+
+/// Hashes the index, as 8 big-endian bytes, followed by the data, after the leaf prefix if any.
+fn indexed_leaf_hash<D: Digest>(prefixes: Option<Prefixes>, index: usize, data: &[u8]) -> Hash<D> {
+    let mut hasher = D::new();
+
+    if let Some(prefixes) = prefixes {
+        hasher.input([prefixes.leaf]);
+    }
+
+    hasher.input((index as u64).to_be_bytes());
+    hasher.input(data);
+    hasher.result()
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<T, D> Clone for IndexedMerkleTree<T, D> where T: Clone, D: Digest {
+    fn clone(&self) -> Self {
+        IndexedMerkleTree { tree: self.tree.clone() }
+    }
+}
+
+impl<T, D> fmt::Debug for IndexedMerkleTree<T, D> where T: fmt::Debug, D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IndexedMerkleTree").field("tree", &self.tree).finish()
+    }
+}
+
+impl<D> Clone for IndexedProof<D> where D: Digest {
+    fn clone(&self) -> Self {
+        IndexedProof { proof: self.proof.clone() }
+    }
+}
+
+impl<D> PartialEq for IndexedProof<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.proof == other.proof
+    }
+}
+
+impl<D> fmt::Debug for IndexedProof<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IndexedProof").field("proof", &self.proof).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Padding;
+
+    #[test]
+    fn it_verifies_proofs_at_their_index() {
+        for &padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            let data: Vec<String> = (0..11).map(|i| i.to_string()).collect();
+            let merkle_tree = IndexedMerkleTree::from_vec_with_config(data.clone(), TreeConfig::new(padding)).unwrap();
+
+            for (index, leaf) in data.iter().enumerate() {
+                let proof = merkle_tree.proof(index).unwrap();
+                assert_eq!(proof.leaf_index(), index);
+                assert_eq!(proof.verify(merkle_tree.root_hash(), leaf.as_bytes()), Ok(()));
+            }
+
+            assert_ne!(merkle_tree.root_hash(), MerkleTree::from_vec_with_config(data, TreeConfig::new(padding)).unwrap().root_hash());
+        }
+    }
+
+    #[test]
+    fn it_rejects_proofs_replayed_at_another_index() {
+        // Sorted pairs ignore the sides of the siblings, so the index of a plain proof is free.
+        let data = vec!["a", "b", "a", "b"];
+        let plain = MerkleTree::<_, sha3::Keccak256>::from_vec_with_config_and_digest(data.clone(), TreeConfig::OPENZEPPELIN).unwrap();
+        let mut replayed = plain.proof(0).unwrap();
+        replayed.leaf_index = 2;
+        assert_eq!(replayed.verify(plain.root_hash(), b"a"), Ok(()));
+
+        let indexed = IndexedMerkleTree::<_, sha3::Keccak256>::from_vec_with_config_and_digest(data, TreeConfig::OPENZEPPELIN).unwrap();
+        let mut replayed = indexed.proof(0).unwrap();
+        replayed.proof.leaf_index = 2;
+        assert_eq!(replayed.verify(indexed.root_hash(), b"a"), Err(MerkleError::RootMismatch));
+        assert_eq!(indexed.proof(2).unwrap().verify(indexed.root_hash(), b"a"), Ok(()));
+    }
+}
@@ -0,0 +1,355 @@
+//! IPFS UnixFS DAGs of files, with content identifiers (CIDs) compatible with IPFS.
+//!
+//! Files are split in fixed-size chunks, which become the leaves of the DAG, either as raw
+//! blocks or wrapped in UnixFS nodes. Leaves are linked by dag-pb nodes of at most
+//! `max_links` children, in a balanced layout, as `ipfs add` does. The root CID of a file is
+//! then the one `ipfs add --chunker=size-<chunk_size> [--raw-leaves] [--cid-version=1]` prints
+//! for it. Blocks are hashed with SHA-256.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use digest::Digest;
+use sha2::Sha256;
+
+/// Multicodec of raw blocks.
+const RAW: u64 = 0x55;
+/// Multicodec of dag-pb blocks.
+const DAG_PB: u64 = 0x70;
+/// Multihash code of SHA-256.
+const SHA2_256: u64 = 0x12;
+
+/// UnixFS type of file nodes.
+const UNIXFS_FILE: u64 = 2;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CidVersion {
+    /// Base58 CIDs starting with `Qm`, only available for dag-pb blocks.
+    V0,
+    V1,
+}
+
+/// Content identifier of a block: its codec and the SHA-256 multihash of its bytes.
+/// Displayed as IPFS does, in base58 for version 0 and in base32 for version 1.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Cid {
+    version: CidVersion,
+    codec: u64,
+    digest: [u8; 32],
+}
+
+impl Cid {
+    fn of(version: CidVersion, codec: u64, block: &[u8]) -> Cid {
+        let mut digest = [0; 32];
+        digest.copy_from_slice(&Sha256::digest(block));
+
+        Cid { version, codec, digest }
+    }
+
+    pub fn version(&self) -> CidVersion {
+        self.version
+    }
+
+    /// Multicodec of the block, `0x55` for raw blocks and `0x70` for dag-pb ones.
+    pub fn codec(&self) -> u64 {
+        self.codec
+    }
+
+    /// SHA-256 hash of the block.
+    pub fn digest(&self) -> &[u8; 32] {
+        &self.digest
+    }
+
+    /// Binary form of the CID, as dag-pb links hold it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(36);
+
+        if self.version == CidVersion::V1 {
+            write_varint(1, &mut bytes);
+            write_varint(self.codec, &mut bytes);
+        }
+
+        write_varint(SHA2_256, &mut bytes);
+        write_varint(self.digest.len() as u64, &mut bytes);
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.version {
+            CidVersion::V0 => f.write_str(&base58(&self.to_bytes())),
+            CidVersion::V1 => write!(f, "b{}", base32(&self.to_bytes())),
+        }
+    }
+}
+
+/// How files are turned into DAGs. The default matches `ipfs add --cid-version=1`, which
+/// implies raw leaves.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct DagConfig {
+    /// Size of the chunks, in bytes.
+    pub chunk_size: usize,
+    /// Whether chunks are stored as raw blocks, instead of being wrapped in UnixFS nodes.
+    pub raw_leaves: bool,
+    /// Maximum number of children of a node.
+    pub max_links: usize,
+    /// Version of the CIDs of dag-pb blocks. Raw blocks always have version 1 CIDs, so the
+    /// root of a file of a single chunk stored as a raw leaf does too.
+    pub cid_version: CidVersion,
+}
+
+impl DagConfig {
+    /// Config of `ipfs add` by default: chunks of 256 KiB in UnixFS leaves, version 0 CIDs.
+    pub const LEGACY: DagConfig = DagConfig {
+        chunk_size: 262_144,
+        raw_leaves: false,
+        max_links: 174,
+        cid_version: CidVersion::V0,
+    };
+}
+
+impl Default for DagConfig {
+    fn default() -> DagConfig {
+        DagConfig { raw_leaves: true, cid_version: CidVersion::V1, ..DagConfig::LEGACY }
+    }
+}
+
+/// Encoded block of a DAG.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct DagBlock {
+    pub cid: Cid,
+    pub data: Vec<u8>,
+}
+
+/// DAG of a file, holding every block, leaves first and the root last.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct UnixFsDag {
+    pub blocks: Vec<DagBlock>,
+}
+
+/// Node of the level being built: its CID, the size of the file data below it, and the
+/// cumulative size of its blocks, as recorded in the link to it.
+struct Link {
+    cid: Cid,
+    file_size: u64,
+    tsize: u64,
+}
+
+impl UnixFsDag {
+    /// Builds the DAG of a file.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chunk size is zero or the config allows fewer than 2 links per node.
+    pub fn build(data: &[u8], config: DagConfig) -> UnixFsDag {
+        assert!(config.chunk_size > 0, "chunk size must not be zero");
+        assert!(config.max_links > 1, "nodes must be allowed at least 2 links");
+
+        let mut blocks = Vec::new();
+        let chunks: Vec<&[u8]> = if data.is_empty() { vec![data] } else { data.chunks(config.chunk_size).collect() };
+
+        let mut level: Vec<Link> = chunks
+            .into_iter()
+            .map(|chunk| {
+                let block = if config.raw_leaves {
+                    DagBlock { cid: Cid::of(CidVersion::V1, RAW, chunk), data: chunk.to_vec() }
+                } else {
+                    let data = unixfs_file(Some(chunk), chunk.len() as u64, &[]);
+                    let node = dag_pb_node(&[], &data);
+                    DagBlock { cid: Cid::of(config.cid_version, DAG_PB, &node), data: node }
+                };
+
+                let link = Link { cid: block.cid.clone(), file_size: chunk.len() as u64, tsize: block.data.len() as u64 };
+                blocks.push(block);
+                link
+            })
+            .collect();
+
+        while level.len() > 1 {
+            level = level
+                .chunks(config.max_links)
+                .map(|children| {
+                    let block_sizes: Vec<u64> = children.iter().map(|child| child.file_size).collect();
+                    let file_size = block_sizes.iter().sum();
+                    let data = unixfs_file(None, file_size, &block_sizes);
+                    let node = dag_pb_node(children, &data);
+
+                    let link = Link {
+                        cid: Cid::of(config.cid_version, DAG_PB, &node),
+                        file_size,
+                        tsize: node.len() as u64 + children.iter().map(|child| child.tsize).sum::<u64>(),
+                    };
+                    blocks.push(DagBlock { cid: link.cid.clone(), data: node });
+                    link
+                })
+                .collect();
+        }
+
+        UnixFsDag { blocks }
+    }
+
+    pub fn root(&self) -> &Cid {
+        &self.blocks.last().expect("a DAG has at least one block").cid
+    }
+}
+
+/// CID of the root of the DAG of a file.
+pub fn file_cid(data: &[u8], config: DagConfig) -> Cid {
+    UnixFsDag::build(data, config).root().clone()
+}
+
+/// UnixFS `Data` message of a file node.
+fn unixfs_file(data: Option<&[u8]>, file_size: u64, block_sizes: &[u64]) -> Vec<u8> {
+    let mut message = Vec::new();
+    write_varint_field(1, UNIXFS_FILE, &mut message);
+
+    if let Some(data) = data.filter(|data| !data.is_empty()) {
+        write_bytes_field(2, data, &mut message);
+    }
+
+    write_varint_field(3, file_size, &mut message);
+
+    for &size in block_sizes {
+        write_varint_field(4, size, &mut message);
+    }
+
+    message
+}
+
+/// dag-pb `PBNode` message, which encodes its links before its data.
+fn dag_pb_node(links: &[Link], data: &[u8]) -> Vec<u8> {
+    let mut node = Vec::new();
+
+    for link in links {
+        let mut message = Vec::new();
+        write_bytes_field(1, &link.cid.to_bytes(), &mut message);
+        write_bytes_field(2, &[], &mut message);
+        write_varint_field(3, link.tsize, &mut message);
+        write_bytes_field(2, &message, &mut node);
+    }
+
+    write_bytes_field(1, data, &mut node);
+    node
+}
+
+fn write_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    out.push(value as u8);
+}
+
+fn write_varint_field(field: u64, value: u64, out: &mut Vec<u8>) {
+    write_varint(field << 3, out);
+    write_varint(value, out);
+}
+
+fn write_bytes_field(field: u64, bytes: &[u8], out: &mut Vec<u8>) {
+    write_varint(field << 3 | 2, out);
+    write_varint(bytes.len() as u64, out);
+    out.extend_from_slice(bytes);
+}
+
+/// Lowercase RFC 4648 base32, without padding.
+fn base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+
+    for &byte in bytes {
+        buffer = buffer << 8 | byte as u32;
+        bits += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+
+    if bits > 0 {
+        encoded.push(ALPHABET[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+
+    encoded
+}
+
+/// Bitcoin base58, with a leading `1` per leading zero byte.
+fn base58(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    let mut digits: Vec<u8> = Vec::new();
+
+    for &byte in bytes {
+        let mut carry = byte as u32;
+
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    let zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+    core::iter::repeat_n('1', zeros).chain(digits.iter().rev().map(|&digit| ALPHABET[digit as usize] as char)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_matches_ipfs_cids_of_single_chunk_files() {
+        assert_eq!(file_cid(b"", DagConfig::default()).to_string(), "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku");
+        assert_eq!(file_cid(b"", DagConfig::LEGACY).to_string(), "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH");
+        assert_eq!(file_cid(b"hello world\n", DagConfig::LEGACY).to_string(), "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o");
+    }
+
+    #[test]
+    fn it_links_chunks_in_a_balanced_layout() {
+        let data: Vec<u8> = (0..100u8).collect();
+        let config = DagConfig { chunk_size: 10, max_links: 3, ..DagConfig::default() };
+        let dag = UnixFsDag::build(&data, config);
+
+        // 10 raw leaves, 4 nodes over them, 2 above those and the root.
+        assert_eq!(dag.blocks.len(), 17);
+        assert_eq!(dag.blocks[..10].iter().map(|block| block.cid.codec()).collect::<Vec<_>>(), vec![RAW; 10]);
+        assert_eq!(dag.blocks[0].data, &data[..10]);
+        assert_eq!(dag.root(), &dag.blocks[16].cid);
+        assert_eq!(dag.root().codec(), DAG_PB);
+
+        for block in &dag.blocks {
+            assert_eq!(&block.cid, &Cid::of(block.cid.version(), block.cid.codec(), &block.data));
+        }
+
+        // The root links to its children by CID, and records the file sizes below them.
+        let root = &dag.blocks[16].data;
+        assert!(contains(root, &dag.blocks[14].cid.to_bytes()));
+        assert!(contains(root, &dag.blocks[15].cid.to_bytes()));
+        assert!(contains(root, &unixfs_file(None, 100, &[90, 10])));
+        assert_ne!(file_cid(&data, DagConfig { max_links: 4, ..config }), *dag.root());
+    }
+
+    #[test]
+    fn it_encodes_cids_in_their_multibase() {
+        let cid = Cid::of(CidVersion::V1, RAW, b"");
+
+        assert_eq!(&cid.to_bytes()[..4], &[0x01, 0x55, 0x12, 0x20]);
+        assert_eq!(cid.digest()[..], Sha256::digest(b"")[..]);
+        assert_eq!(base32(b"foobar"), "mzxw6ytboi");
+        assert_eq!(base58(b"\0\0hello"), "11Cn8eVZg");
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|window| window == needle)
+    }
+}
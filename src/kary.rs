@@ -0,0 +1,477 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use blake2::Blake2b;
+use digest::Digest;
+use digest::generic_array::GenericArray;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{apply_duplicate_policy, calculate_hash, check_leaf, leaf_hash, map_hashes, to_hex, Hash};
+use crate::config::{LeafMode, Padding, Prefixes, TreeConfig};
+use crate::error::MerkleError;
+use crate::leaf::{LeafBytes, LeafEncode};
+
+/// A Merkle tree whose inner nodes have up to `arity` children instead of two. A wider tree is
+/// shallower, so proofs have fewer levels, but every level carries `arity - 1` sibling hashes.
+///
+/// An inner node hashes the node prefix, if any, followed by the hashes of its children. The last
+/// node of a level may be left with fewer children, which are padded as the config says: with
+/// `DuplicateLast` the last child is repeated and with `EmptyHash` the hash of an empty input is
+/// appended until there are `arity` of them, while `Promote` hashes the children there are and
+/// moves a lone child up unchanged. With `sort_pairs`, children are hashed in ascending order.
+/// A tree with an arity of 2 has the same root as a [`MerkleTree`](crate::MerkleTree) built from
+/// the same data and config.
+pub struct KaryMerkleTree<D: Digest = Blake2b> {
+    /// `levels[0]` holds the leaf hashes and the last level holds the root alone.
+    levels: Vec<Vec<Hash<D>>>,
+    arity: usize,
+    config: TreeConfig,
+    digest: PhantomData<D>,
+}
+
+impl KaryMerkleTree {
+    /// Creates a KaryMerkleTree from a vector, hashing with Blake2b and the default config.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `arity` is less than 2.
+    pub fn from_vec<T>(data: Vec<T>, arity: usize) -> Result<KaryMerkleTree, MerkleError> where T: LeafEncode {
+        KaryMerkleTree::from_vec_with_digest(data, arity)
+    }
+
+    /// Creates a KaryMerkleTree from a vector, hashing with Blake2b.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `arity` is less than 2.
+    pub fn from_vec_with_config<T>(data: Vec<T>, arity: usize, config: TreeConfig) -> Result<KaryMerkleTree, MerkleError>
+        where T: LeafEncode {
+        KaryMerkleTree::from_vec_with_config_and_digest(data, arity, config)
+    }
+}
+
+impl<D> KaryMerkleTree<D> where D: Digest {
+    /// Creates a KaryMerkleTree from a vector, hashing with the digest `D` and the default config.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `arity` is less than 2.
+    pub fn from_vec_with_digest<T>(data: Vec<T>, arity: usize) -> Result<KaryMerkleTree<D>, MerkleError> where T: LeafEncode {
+        KaryMerkleTree::from_vec_with_config_and_digest(data, arity, TreeConfig::default())
+    }
+
+    /// Creates a KaryMerkleTree from a vector, hashing with the digest `D`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `arity` is less than 2.
+    pub fn from_vec_with_config_and_digest<T>(data: Vec<T>, arity: usize, config: TreeConfig) -> Result<KaryMerkleTree<D>, MerkleError>
+        where T: LeafEncode {
+        assert!(arity >= 2, "the arity of a tree must be at least 2");
+
+        if data.is_empty() {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        for (index, leaf) in data.iter().enumerate() {
+            check_leaf::<D>(config.leaf_mode, index, &leaf.encode_leaf())?;
+        }
+
+        let slices: Vec<LeafBytes> = data.iter().map(LeafEncode::encode_leaf).collect();
+        let leaves = map_hashes(&slices, |d| leaf_hash::<D>(config.prefixes, config.leaf_mode, d));
+        let mut levels = vec![apply_duplicate_policy(leaves, config.duplicates, |hash| hash)?];
+
+        while levels[levels.len() - 1].len() > 1 {
+            let level = &levels[levels.len() - 1];
+            let groups: Vec<&[Hash<D>]> = level.chunks(arity).collect();
+            let parents = map_hashes(&groups, |children| match children {
+                [child] if config.padding == Padding::Promote => child.clone(),
+                _ => hash_children::<D>(config.prefixes, config.sort_pairs, &padded::<D>(children, arity, config.padding)),
+            });
+
+            levels.push(parents);
+        }
+
+        Ok(KaryMerkleTree { levels, arity, config, digest: PhantomData })
+    }
+
+    pub fn root_hash(&self) -> &[u8] {
+        &self.levels[self.levels.len() - 1][0]
+    }
+
+    /// Returns the root hash as a lowercase hex string.
+    pub fn root_hash_hex(&self) -> String {
+        to_hex(self.root_hash())
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// Maximum number of children of an inner node.
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Number of levels above the leaves.
+    pub fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    pub fn config(&self) -> TreeConfig {
+        self.config
+    }
+
+    /// Generates an inclusion proof for the leaf at `leaf_index`, with the siblings of the node on
+    /// its path at every level, padding included.
+    pub fn proof(&self, leaf_index: usize) -> Result<KaryProof<D>, MerkleError> {
+        if leaf_index >= self.leaf_count() {
+            return Err(MerkleError::IndexOutOfBounds { index: leaf_index, leaf_count: self.leaf_count() });
+        }
+
+        let mut steps = Vec::with_capacity(self.depth());
+        let mut index = leaf_index;
+
+        for level in &self.levels[..self.depth()] {
+            let start = index - index % self.arity;
+            let children = &level[start..level.len().min(start + self.arity)];
+
+            if children.len() > 1 || self.config.padding != Padding::Promote {
+                let position = index - start;
+                let mut siblings = padded::<D>(children, self.arity, self.config.padding);
+                siblings.remove(position);
+
+                steps.push(KaryStep::new(position, siblings));
+            }
+
+            index /= self.arity;
+        }
+
+        let mut proof = KaryProof::new(leaf_index, self.arity, steps);
+        proof.prefixes = self.config.prefixes;
+        proof.sort_pairs = self.config.sort_pairs;
+        proof.leaf_mode = self.config.leaf_mode;
+
+        Ok(proof)
+    }
+}
+
+/// The children of an inner node, padded up to `arity` unless `padding` is `Promote`.
+fn padded<D: Digest>(children: &[Hash<D>], arity: usize, padding: Padding) -> Vec<Hash<D>> {
+    let mut padded = children.to_vec();
+
+    match padding {
+        Padding::Promote => {}
+        Padding::DuplicateLast => padded.resize(arity, children[children.len() - 1].clone()),
+        Padding::EmptyHash => padded.resize(arity, calculate_hash::<D>(&[])),
+    }
+
+    padded
+}
+
+/// Hashes the children of an inner node, in ascending order rather than left to right when
+/// `sorted` is set.
+fn hash_children<D: Digest>(prefixes: Option<Prefixes>, sorted: bool, children: &[Hash<D>]) -> Hash<D> {
+    let mut children: Vec<&Hash<D>> = children.iter().collect();
+
+    if sorted {
+        children.sort();
+    }
+
+    let mut hasher = D::new();
+
+    if let Some(prefixes) = prefixes {
+        hasher.input([prefixes.node]);
+    }
+
+    for child in children {
+        hasher.input(child);
+    }
+
+    hasher.result()
+}
+
+/// Level of a [`KaryProof`]: the position of the node on the path among its parent's children,
+/// and the hashes of the other children, left to right.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct KaryStep<D: Digest = Blake2b> {
+    pub position: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::list"))]
+    pub siblings: Vec<Hash<D>>,
+}
+
+impl<D> KaryStep<D> where D: Digest {
+    pub fn new(position: usize, siblings: Vec<Hash<D>>) -> KaryStep<D> {
+        KaryStep { position, siblings }
+    }
+}
+
+/// Inclusion proof for a single leaf of a [`KaryMerkleTree`]. `steps` holds the levels on the
+/// path from the leaf up to the root, except the ones a lone node was promoted through.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct KaryProof<D: Digest = Blake2b> {
+    pub leaf_index: usize,
+    /// Arity of the tree the proof was generated from.
+    pub arity: usize,
+    pub steps: Vec<KaryStep<D>>,
+    /// Domain separation prefixes of the tree the proof was generated from.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub prefixes: Option<Prefixes>,
+    /// Whether the tree the proof was generated from hashes children in ascending order.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sort_pairs: bool,
+    /// How the tree the proof was generated from turns leaf data into leaf hashes.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub leaf_mode: LeafMode,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    digest: PhantomData<D>,
+}
+
+impl<D> KaryProof<D> where D: Digest {
+    pub fn new(leaf_index: usize, arity: usize, steps: Vec<KaryStep<D>>) -> KaryProof<D> {
+        KaryProof {
+            leaf_index,
+            arity,
+            steps,
+            prefixes: None,
+            sort_pairs: false,
+            leaf_mode: LeafMode::Hashed,
+            digest: PhantomData,
+        }
+    }
+
+    /// Checks that `leaf_data` is included in the tree with the given root hash.
+    pub fn verify(&self, root_hash: &[u8], leaf_data: &[u8]) -> Result<(), MerkleError> {
+        check_leaf::<D>(self.leaf_mode, self.leaf_index, leaf_data)?;
+        self.verify_hash(root_hash, &leaf_hash::<D>(self.prefixes, self.leaf_mode, leaf_data))
+    }
+
+    /// Checks that the leaf with hash `leaf_hash` is included in the tree with the given root hash.
+    ///
+    /// Fails with `MalformedProof` when a level holds more than `arity` children, or when the
+    /// positions on the path do not belong to leaf `leaf_index`, unless children are sorted.
+    pub fn verify_hash(&self, root_hash: &[u8], leaf_hash: &[u8]) -> Result<(), MerkleError> {
+        let mut hash = GenericArray::from_exact_iter(leaf_hash.iter().cloned()).ok_or(MerkleError::MalformedProof)?;
+
+        if self.arity < 2 || (!self.sort_pairs && !self.has_consistent_positions()) {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        for step in &self.steps {
+            if step.siblings.len() >= self.arity || step.position > step.siblings.len() {
+                return Err(MerkleError::MalformedProof);
+            }
+
+            let mut children = step.siblings.clone();
+            children.insert(step.position, hash);
+            hash = hash_children::<D>(self.prefixes, self.sort_pairs, &children);
+        }
+
+        if hash.as_slice() == root_hash {
+            Ok(())
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+
+    /// Whether the positions on the path are the digits of `leaf_index` in base `arity`, from the
+    /// lowest one up. Levels the leaf was promoted through have no step, which is only possible
+    /// for a 0.
+    fn has_consistent_positions(&self) -> bool {
+        let mut index = self.leaf_index;
+
+        for step in &self.steps {
+            while index % self.arity != step.position {
+                if !index.is_multiple_of(self.arity) {
+                    return false;
+                }
+
+                index /= self.arity;
+
+                if index == 0 {
+                    return false;
+                }
+            }
+
+            index /= self.arity;
+        }
+
+        index == 0
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for KaryMerkleTree<D> where D: Digest {
+    fn clone(&self) -> Self {
+        KaryMerkleTree {
+            levels: self.levels.clone(),
+            arity: self.arity,
+            config: self.config,
+            digest: PhantomData,
+        }
+    }
+}
+
+impl<D> fmt::Debug for KaryMerkleTree<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KaryMerkleTree")
+            .field("root_hash", &self.root_hash_hex())
+            .field("arity", &self.arity)
+            .field("config", &self.config)
+            .field("leaf_count", &self.leaf_count())
+            .finish()
+    }
+}
+
+impl<D> Clone for KaryStep<D> where D: Digest {
+    fn clone(&self) -> Self {
+        KaryStep::new(self.position, self.siblings.clone())
+    }
+}
+
+impl<D> PartialEq for KaryStep<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.position == other.position
+            && self.siblings == other.siblings
+    }
+}
+
+impl<D> fmt::Debug for KaryStep<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KaryStep")
+            .field("position", &self.position)
+            .field("siblings", &self.siblings)
+            .finish()
+    }
+}
+
+impl<D> Clone for KaryProof<D> where D: Digest {
+    fn clone(&self) -> Self {
+        KaryProof {
+            leaf_index: self.leaf_index,
+            arity: self.arity,
+            steps: self.steps.clone(),
+            prefixes: self.prefixes,
+            sort_pairs: self.sort_pairs,
+            leaf_mode: self.leaf_mode,
+            digest: PhantomData,
+        }
+    }
+}
+
+impl<D> PartialEq for KaryProof<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf_index == other.leaf_index
+            && self.arity == other.arity
+            && self.steps == other.steps
+            && self.prefixes == other.prefixes
+            && self.sort_pairs == other.sort_pairs
+            && self.leaf_mode == other.leaf_mode
+    }
+}
+
+impl<D> fmt::Debug for KaryProof<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KaryProof")
+            .field("leaf_index", &self.leaf_index)
+            .field("arity", &self.arity)
+            .field("steps", &self.steps)
+            .field("prefixes", &self.prefixes)
+            .field("sort_pairs", &self.sort_pairs)
+            .field("leaf_mode", &self.leaf_mode)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MerkleTree;
+
+    const PADDINGS: [Padding; 3] = [Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash];
+
+    fn leaves(len: usize) -> Vec<String> {
+        (0..len).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn it_matches_binary_trees_with_an_arity_of_2() {
+        for &padding in &PADDINGS {
+            for &config in &[TreeConfig::new(padding), TreeConfig { prefixes: Some(Prefixes::RFC6962), sort_pairs: true, ..TreeConfig::new(padding) }] {
+                for len in 1..=17 {
+                    let kary = KaryMerkleTree::from_vec_with_config(leaves(len), 2, config).unwrap();
+                    let binary = MerkleTree::from_vec_with_config(leaves(len), config).unwrap();
+
+                    assert_eq!(kary.root_hash(), binary.root_hash());
+
+                    for index in 0..len {
+                        let siblings: Vec<_> = kary.proof(index).unwrap().steps.into_iter().flat_map(|step| step.siblings).collect();
+                        let expected: Vec<_> = binary.proof(index).unwrap().siblings.into_iter().map(|step| step.hash).collect();
+
+                        assert_eq!(siblings, expected);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_verifies_proofs_for_any_arity_leaf_count_and_padding() {
+        for &padding in &PADDINGS {
+            for arity in 2..=5 {
+                for len in 1..=30 {
+                    let merkle_tree = KaryMerkleTree::from_vec_with_config(leaves(len), arity, TreeConfig::new(padding)).unwrap();
+
+                    for (index, leaf) in leaves(len).iter().enumerate() {
+                        let proof = merkle_tree.proof(index).unwrap();
+
+                        assert!(proof.steps.len() <= merkle_tree.depth());
+                        assert_eq!(proof.verify(merkle_tree.root_hash(), leaf.as_bytes()), Ok(()));
+                        assert_eq!(proof.verify(merkle_tree.root_hash(), b"other"), Err(MerkleError::RootMismatch));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_shortens_proofs_with_wider_nodes() {
+        let merkle_tree = KaryMerkleTree::from_vec(leaves(256), 16).unwrap();
+        let proof = merkle_tree.proof(200).unwrap();
+
+        assert_eq!(merkle_tree.depth(), 2);
+        assert_eq!(proof.steps, vec![
+            KaryStep::new(8, proof.steps[0].siblings.clone()),
+            KaryStep::new(12, proof.steps[1].siblings.clone()),
+        ]);
+        assert!(proof.steps.iter().all(|step| step.siblings.len() == 15));
+    }
+
+    #[test]
+    fn it_rejects_malformed_proofs() {
+        let merkle_tree = KaryMerkleTree::from_vec(leaves(20), 4).unwrap();
+        let proof = merkle_tree.proof(6).unwrap();
+
+        let mut moved = proof.clone();
+        moved.steps[0].position = 1;
+        assert_eq!(moved.verify(merkle_tree.root_hash(), b"6"), Err(MerkleError::MalformedProof));
+
+        let mut widened = proof.clone();
+        widened.steps[1].siblings.push(GenericArray::default());
+        assert_eq!(widened.verify(merkle_tree.root_hash(), b"6"), Err(MerkleError::MalformedProof));
+
+        let mut reindexed = proof.clone();
+        reindexed.leaf_index = 70;
+        assert_eq!(reindexed.verify(merkle_tree.root_hash(), b"6"), Err(MerkleError::MalformedProof));
+
+        assert_eq!(merkle_tree.proof(20), Err(MerkleError::IndexOutOfBounds { index: 20, leaf_count: 20 }));
+        assert_eq!(KaryMerkleTree::from_vec(Vec::<String>::new(), 4).unwrap_err(), MerkleError::EmptyInput);
+    }
+
+}
@@ -0,0 +1,119 @@
+use core::marker::PhantomData;
+
+use blake2::Blake2b;
+use blake2::crypto_mac::Mac;
+use digest::{FixedOutput, Input, Reset};
+use digest::generic_array::GenericArray;
+use digest::generic_array::typenum::U64;
+
+/// Secret key of a [`KeyedBlake2b`]. As digests are created without arguments, the key is
+/// looked up through the type, typically from a static initialised when the application starts.
+pub trait MacKey {
+    /// Key of at most 64 bytes.
+    fn key() -> &'static [u8];
+}
+
+/// Blake2b in keyed mode, so every leaf and node hash is a MAC under the key of `K`. The root
+/// of a tree hashed with it cannot be computed, or forged, without the key, while trees can be
+/// built, proven and verified like with any other digest, e.g. through
+/// `MerkleTree::<_, KeyedBlake2b<K>>::from_vec_with_digest`.
+///
+/// Comparing a root against an expected one is not done in constant time, so roots checked
+/// against untrusted input can leak timing information about the expected MAC.
+pub struct KeyedBlake2b<K: MacKey> {
+    mac: Blake2b,
+    key: PhantomData<K>,
+}
+
+impl<K> Default for KeyedBlake2b<K> where K: MacKey {
+    /// # Panics
+    ///
+    /// Panics if the key is longer than 64 bytes.
+    fn default() -> Self {
+        let mac = Blake2b::new_varkey(K::key()).expect("MAC key must be at most 64 bytes");
+        KeyedBlake2b { mac, key: PhantomData }
+    }
+}
+
+// Implemented by hand so that `K` is not required to implement `Clone`.
+impl<K> Clone for KeyedBlake2b<K> where K: MacKey {
+    fn clone(&self) -> Self {
+        KeyedBlake2b { mac: self.mac.clone(), key: PhantomData }
+    }
+}
+
+impl<K> Input for KeyedBlake2b<K> where K: MacKey {
+    fn input<B: AsRef<[u8]>>(&mut self, data: B) {
+        Input::input(&mut self.mac, data);
+    }
+}
+
+impl<K> FixedOutput for KeyedBlake2b<K> where K: MacKey {
+    type OutputSize = U64;
+
+    fn fixed_result(self) -> GenericArray<u8, U64> {
+        FixedOutput::fixed_result(self.mac)
+    }
+}
+
+impl<K> Reset for KeyedBlake2b<K> where K: MacKey {
+    /// Resets to the keyed state, ready to hash the next input under the same key.
+    fn reset(&mut self) {
+        Reset::reset(&mut self.mac);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Digest, MerkleError, MerkleTree};
+
+    struct TestKey;
+
+    impl MacKey for TestKey {
+        fn key() -> &'static [u8] {
+            b"secret key"
+        }
+    }
+
+    struct OtherKey;
+
+    impl MacKey for OtherKey {
+        fn key() -> &'static [u8] {
+            b"another key"
+        }
+    }
+
+    #[test]
+    fn it_matches_blake2b_keyed_hashing() {
+        let mut mac = Blake2b::new_varkey(b"secret key").unwrap();
+        Mac::input(&mut mac, b"data");
+
+        assert_eq!(KeyedBlake2b::<TestKey>::digest(b"data"), Mac::result(mac).code());
+        assert_ne!(KeyedBlake2b::<TestKey>::digest(b"data"), Blake2b::digest(b"data"));
+    }
+
+    #[test]
+    fn it_builds_trees_whose_roots_depend_on_the_key() {
+        let data = vec!["a", "b", "c"];
+        let keyed = MerkleTree::<_, KeyedBlake2b<TestKey>>::from_vec_with_digest(data.clone()).unwrap();
+        let other = MerkleTree::<_, KeyedBlake2b<OtherKey>>::from_vec_with_digest(data.clone()).unwrap();
+
+        assert_ne!(keyed.root_hash(), other.root_hash());
+        assert_ne!(keyed.root_hash(), MerkleTree::from_vec(data).unwrap().root_hash());
+
+        let proof = keyed.proof(2).unwrap();
+        assert!(proof.verify(keyed.root_hash(), b"c").is_ok());
+        assert_eq!(proof.verify(other.root_hash(), b"c"), Err(MerkleError::RootMismatch));
+    }
+
+    #[test]
+    fn it_resets_to_the_keyed_state() {
+        let mut hasher = KeyedBlake2b::<TestKey>::new();
+        Input::input(&mut hasher, b"discarded");
+        Reset::reset(&mut hasher);
+        Input::input(&mut hasher, b"data");
+
+        assert_eq!(hasher.fixed_result(), KeyedBlake2b::<TestKey>::digest(b"data"));
+    }
+}
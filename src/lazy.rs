@@ -0,0 +1,196 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use blake2::Blake2b;
+use digest::Digest;
+
+use crate::check_leaf;
+use crate::config::TreeConfig;
+use crate::error::MerkleError;
+use crate::leaf::LeafEncode;
+use crate::proof::Proof;
+use crate::tree::MerkleTree;
+
+/// A MerkleTree that only records the data of the leaves pushed, and hashes them when its root
+/// or a proof is first requested, for leaves arriving by the thousand before anything is
+/// verified. The leaves waiting to be hashed are hashed together, across threads with the
+/// `rayon` feature, and the tree is built again from all its leaves when at least as many are
+/// waiting as were already hashed.
+pub struct LazyMerkleTree<T, D: Digest = Blake2b> {
+    tree: Option<MerkleTree<T, D>>,
+    pending: Vec<T>,
+    config: TreeConfig,
+}
+
+impl<T> LazyMerkleTree<T> where T: LeafEncode + Clone {
+    /// Creates an empty LazyMerkleTree, hashing with Blake2b and the default config.
+    pub fn new() -> LazyMerkleTree<T> {
+        LazyMerkleTree::with_config_and_digest(TreeConfig::default())
+    }
+
+    /// Creates an empty LazyMerkleTree, hashing with Blake2b.
+    pub fn with_config(config: TreeConfig) -> LazyMerkleTree<T> {
+        LazyMerkleTree::with_config_and_digest(config)
+    }
+}
+
+impl<T> Default for LazyMerkleTree<T> where T: LeafEncode + Clone {
+    fn default() -> Self {
+        LazyMerkleTree::new()
+    }
+}
+
+impl<T, D> LazyMerkleTree<T, D> where T: LeafEncode + Clone, D: Digest {
+    /// Creates an empty LazyMerkleTree, hashing with the digest `D` and the default config.
+    pub fn with_digest() -> LazyMerkleTree<T, D> {
+        LazyMerkleTree::with_config_and_digest(TreeConfig::default())
+    }
+
+    /// Creates an empty LazyMerkleTree, hashing with the digest `D`.
+    pub fn with_config_and_digest(config: TreeConfig) -> LazyMerkleTree<T, D> {
+        LazyMerkleTree { tree: None, pending: Vec::new(), config }
+    }
+
+    /// Records a leaf without hashing it and returns its index. With `LeafMode::Prehashed`,
+    /// fails with `InvalidLeafLength` unless `data` is one hash long.
+    pub fn push(&mut self, data: T) -> Result<usize, MerkleError> {
+        let index = self.leaf_count();
+        check_leaf::<D>(self.config.leaf_mode, index, &data.encode_leaf())?;
+        self.pending.push(data);
+
+        Ok(index)
+    }
+
+    /// Number of leaves, hashed or not.
+    pub fn leaf_count(&self) -> usize {
+        self.tree.as_ref().map_or(0, MerkleTree::leaf_count) + self.pending.len()
+    }
+
+    /// Number of leaves recorded since the last time the tree was hashed.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn config(&self) -> TreeConfig {
+        self.config
+    }
+
+    /// Hashes the leaves waiting to be hashed, and returns the tree of every leaf. Fails with
+    /// `EmptyInput` if no leaf was pushed.
+    ///
+    /// The duplicate policy of the config is applied to the leaves as they are hashed. With
+    /// `DuplicatePolicy::Reject`, fails with `DuplicateLeaf` and drops every leaf that was
+    /// waiting, leaving the tree as it was before they were pushed. With
+    /// `DuplicatePolicy::Deduplicate`, the leaves pushed after a dropped duplicate end up one
+    /// position before the index `push` returned.
+    pub fn tree(&mut self) -> Result<&MerkleTree<T, D>, MerkleError> {
+        if !self.pending.is_empty() {
+            let leaves = MerkleTree::<T, D>::hash_leaves(core::mem::take(&mut self.pending), self.config);
+
+            match &mut self.tree {
+                Some(tree) => tree.append_leaves(leaves)?,
+                None => self.tree = Some(MerkleTree::from_leaves(leaves, self.config)?),
+            }
+        }
+
+        self.tree.as_ref().ok_or(MerkleError::EmptyInput)
+    }
+
+    /// Hashes the leaves waiting to be hashed and returns the root hash.
+    pub fn root_hash(&mut self) -> Result<&[u8], MerkleError> {
+        Ok(self.tree()?.root_hash())
+    }
+
+    /// Hashes the leaves waiting to be hashed and generates an inclusion proof for the leaf at
+    /// `leaf_index`.
+    pub fn proof(&mut self, leaf_index: usize) -> Result<Proof<D>, MerkleError> {
+        let leaf_count = self.leaf_count();
+
+        if leaf_index >= leaf_count {
+            return Err(MerkleError::IndexOutOfBounds { index: leaf_index, leaf_count });
+        }
+
+        self.tree()?.proof(leaf_index)
+    }
+
+    /// Hashes the leaves waiting to be hashed and takes the tree of every leaf.
+    pub fn into_tree(mut self) -> Result<MerkleTree<T, D>, MerkleError> {
+        self.tree()?;
+        self.tree.ok_or(MerkleError::EmptyInput)
+    }
+}
+
+impl<T, D> Extend<T> for LazyMerkleTree<T, D> where T: LeafEncode + Clone, D: Digest {
+    /// # Panics
+    ///
+    /// Panics if the tree uses `LeafMode::Prehashed` and an item is not one hash long.
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for data in iter {
+            self.push(data).unwrap_or_else(|e| panic!("{}", e));
+        }
+    }
+}
+
+impl<T, D> fmt::Debug for LazyMerkleTree<T, D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LazyMerkleTree")
+            .field("hashed", &self.tree.as_ref().map(|tree| tree.root_hash_hex()))
+            .field("pending", &self.pending.len())
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DuplicatePolicy, LeafMode, Padding};
+
+    #[test]
+    fn it_matches_trees_built_at_once() {
+        for config in &[TreeConfig::default(), TreeConfig::RFC6962, TreeConfig::new(Padding::EmptyHash)] {
+            let data: Vec<String> = (0..40).map(|i| i.to_string()).collect();
+            let mut lazy = LazyMerkleTree::with_config(*config);
+
+            // Batches smaller and larger than the leaves already hashed.
+            for batch in data.chunks(13).chain(data.chunks(3)) {
+                lazy.extend(batch.iter().cloned());
+                assert_eq!(lazy.pending(), batch.len());
+
+                let hashed = lazy.leaf_count();
+                let expected = MerkleTree::from_vec_with_config(lazy.tree().unwrap().leaves().map(|leaf| leaf.data.clone()).collect(), *config).unwrap();
+                assert_eq!(lazy.pending(), 0);
+                assert_eq!(lazy.leaf_count(), hashed);
+                assert_eq!(lazy.root_hash().unwrap(), expected.root_hash());
+                assert_eq!(lazy.proof(hashed - 1).unwrap(), expected.proof(hashed - 1).unwrap());
+            }
+
+            assert_eq!(lazy.into_tree().unwrap().leaf_count(), 80);
+        }
+    }
+
+    #[test]
+    fn it_rejects_batches_holding_duplicates() {
+        let config = TreeConfig { duplicates: DuplicatePolicy::Reject, ..TreeConfig::default() };
+        let mut lazy = LazyMerkleTree::with_config(config);
+
+        lazy.extend(vec!["a", "b"]);
+        let root = lazy.root_hash().unwrap().to_vec();
+
+        lazy.extend(vec!["c", "a"]);
+        assert_eq!(lazy.root_hash(), Err(MerkleError::DuplicateLeaf { index: 3, first: 0 }));
+        assert_eq!((lazy.leaf_count(), lazy.root_hash().unwrap()), (2, &root[..]));
+    }
+
+    #[test]
+    fn it_checks_leaves_without_hashing_them() {
+        let config = TreeConfig { leaf_mode: LeafMode::Prehashed, ..TreeConfig::default() };
+        let mut lazy = LazyMerkleTree::<Vec<u8>>::with_config(config);
+
+        assert_eq!(lazy.root_hash(), Err(MerkleError::EmptyInput));
+        assert_eq!(lazy.push(vec![0; 3]), Err(MerkleError::InvalidLeafLength { index: 0, len: 3, expected: 64 }));
+        assert_eq!(lazy.push(vec![7; 64]), Ok(0));
+        assert_eq!(lazy.proof(1), Err(MerkleError::IndexOutOfBounds { index: 1, leaf_count: 1 }));
+        assert_eq!(lazy.root_hash().unwrap(), &[7; 64][..]);
+    }
+}
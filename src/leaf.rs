@@ -0,0 +1,98 @@
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+
+/// Bytes of an encoded leaf, borrowed from the leaf when it already is a byte string.
+pub type LeafBytes<'a> = Cow<'a, [u8]>;
+
+/// Canonical encoding of leaf data, which trees hash to compute leaf hashes and which proofs are
+/// verified against.
+///
+/// Every byte-like type (`&[u8]`, `Vec<u8>`, `[u8; N]`, `str`, `String`, ...) is encoded as its
+/// bytes, as anything implementing `AsRef<[u8]>`. Other types implement it with their own
+/// encoding, such as bincode or SSZ, by hand or with [`impl_leaf_encode!`](crate::impl_leaf_encode).
+pub trait LeafEncode {
+    fn encode_leaf(&self) -> LeafBytes<'_>;
+}
+
+impl<T> LeafEncode for T where T: AsRef<[u8]> + ?Sized {
+    fn encode_leaf(&self) -> LeafBytes<'_> {
+        Cow::Borrowed(self.as_ref())
+    }
+}
+
+/// Concatenates the encodings of `fields`, each one prefixed with its length as a big-endian
+/// `u64` so that bytes cannot move from one field to the next.
+pub fn encode_fields(fields: &[&dyn LeafEncode]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    for field in fields {
+        let encoded = field.encode_leaf();
+        bytes.extend_from_slice(&(encoded.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(&encoded);
+    }
+
+    bytes
+}
+
+/// Implements [`LeafEncode`](crate::LeafEncode) for a struct, either from some of its fields,
+/// encoded with [`encode_fields`](crate::encode_fields) in the order given, as in
+/// `impl_leaf_encode!(Account { name, key })`, or with a closure returning the encoding as a
+/// `Vec<u8>`, as in `impl_leaf_encode!(Transfer, |transfer| bincode::serialize(transfer).unwrap())`.
+/// Fields must implement `LeafEncode` themselves, so integers are better stored as byte arrays
+/// or encoded with the closure form.
+#[macro_export]
+macro_rules! impl_leaf_encode {
+    ($ty:ty { $($field:ident),+ $(,)? }) => {
+        impl $crate::LeafEncode for $ty {
+            fn encode_leaf(&self) -> $crate::LeafBytes<'_> {
+                $crate::LeafBytes::Owned($crate::encode_fields(&[$(&self.$field),+]))
+            }
+        }
+    };
+    ($ty:ty, |$value:ident| $encode:expr) => {
+        impl $crate::LeafEncode for $ty {
+            fn encode_leaf(&self) -> $crate::LeafBytes<'_> {
+                let $value = self;
+                $crate::LeafBytes::Owned($encode)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MerkleTree;
+
+    struct Entry {
+        key: String,
+        value: Vec<u8>,
+    }
+
+    impl_leaf_encode!(Entry { key, value });
+
+    struct Counter(u32);
+
+    impl_leaf_encode!(Counter, |counter| counter.0.to_be_bytes().to_vec());
+
+    #[test]
+    fn it_encodes_byte_like_types_as_their_bytes() {
+        assert_eq!("abc".encode_leaf(), LeafBytes::Borrowed(b"abc"));
+        assert_eq!(vec![1, 2].encode_leaf().as_ref(), &[1, 2]);
+        assert!(matches!([7u8; 4].encode_leaf(), LeafBytes::Borrowed(_)));
+        assert_eq!(Counter(258).encode_leaf().as_ref(), &[0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn it_hashes_structs_with_their_encoding() {
+        let entries = vec![Entry { key: "a".into(), value: vec![1] }, Entry { key: "ab".into(), value: vec![] }];
+        let encoded: Vec<Vec<u8>> = entries.iter().map(|entry| entry.encode_leaf().into_owned()).collect();
+
+        assert_eq!(encoded[0], [&1u64.to_be_bytes()[..], b"a", &1u64.to_be_bytes(), &[1]].concat());
+        assert_ne!(encoded[0], encoded[1]);
+
+        let merkle_tree = MerkleTree::from_vec(entries).unwrap();
+        assert_eq!(merkle_tree.root_hash(), MerkleTree::from_vec(encoded.clone()).unwrap().root_hash());
+        assert_eq!(merkle_tree.proof(1).unwrap().verify(merkle_tree.root_hash(), &encoded[1]), Ok(()));
+    }
+}
@@ -1,11 +1,311 @@
-use tree::{MerkleLeaf, MerkleNode, MerkleTree, Node};
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
+extern crate alloc;
+
+pub use digest::Digest;
+pub use digest::generic_array;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use digest::generic_array::GenericArray;
+
+/// Records a field of the current tracing span with the `tracing` feature, and expands to
+/// nothing without it.
+macro_rules! trace_record {
+    ($field:literal, $value:expr) => {
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record($field, $value);
+    };
+}
+
+pub use accumulator::{AccumulatorUpdate, MerkleAccumulator, Witness};
+pub use arena::{ArenaIndex, ArenaMerkleTree, ArenaNode};
+pub use backend::{Backend, BackendError, BackendNode, MemoryBackend};
+pub use batch::BatchDigest;
+#[cfg(feature = "blake3")]
+pub use crate::blake3::Blake3;
+pub use bitcoin::{BitcoinHash, Sha256d};
+pub use cdc::FastCdc;
+pub use chunk_verifier::ChunkVerifier;
+#[cfg(feature = "std")]
+pub use concurrent::ConcurrentMerkleTree;
+pub use config::{DuplicatePolicy, LeafMode, Padding, Prefixes, TreeConfig};
+pub use consistency::ConsistencyProof;
+pub use epoch::{EpochChain, EpochProof};
+pub use error::MerkleError;
+pub use fixed::FixedDepthMerkleTree;
+pub use flat::FlatMerkleTree;
+#[cfg(feature = "std")]
+pub use fs::{FsEntry, FsHasher, FsKind};
+#[cfg(feature = "fuzz")]
+pub use fuzz::{decode_incremental_state_bytes, decode_proof_bytes, decode_tree_bytes};
+pub use incremental::{IncrementalState, IncrementalTree, RootHasher};
+pub use indexed::{IndexedMerkleTree, IndexedProof};
+pub use ipfs::{file_cid, Cid, CidVersion, DagBlock, DagConfig, UnixFsDag};
+pub use lazy::LazyMerkleTree;
+pub use leaf::{encode_fields, LeafBytes, LeafEncode};
+pub use kary::{KaryMerkleTree, KaryProof, KaryStep};
+pub use keyed::{KeyedBlake2b, MacKey};
+pub use map::{MapProof, MerkleMap};
+pub use metrics::{set_metrics, Metrics};
+#[cfg(all(feature = "mmap", unix))]
+pub use mmap::MappedMerkleTree;
+pub use mmr::{MerkleMountainRange, MmrProof};
+pub use mpt::MerklePatriciaTrie;
+pub use multiproof::MultiProof;
+#[cfg(feature = "signing")]
+pub use note::{Note, NoteCheckpoint, NoteSignature, NoteSigner, NoteVerifier};
+pub use partial::PartialMerkleTree;
+#[cfg(feature = "poseidon")]
+pub use poseidon::{poseidon_hash, FieldElement, MerkleWitness, PoseidonCombiner, PoseidonMerkleTree, PoseidonProof};
+pub use proof::{verify_path, Proof, ProofStep, Side};
+pub use proof_builder::ProofBuilder;
+pub use proof_verifier::ProofVerifier;
+pub use range::RangeProof;
+#[cfg(feature = "std")]
+pub use reader::{Chunk, ChunkTreeBuilder};
+pub use root_log::{Checkpoint, CheckpointSigner, CheckpointVerifier, MacSigner, RootLog, SignedCheckpoint};
+pub use salted::{Salt, SaltedMerkleTree, SaltedProof};
+#[cfg(feature = "signing")]
+pub use signing::{Ed25519Signer, Ed25519Verifier};
+pub use snapshot::MerkleSnapshot;
+pub use solana::{SolanaChangeLog, SolanaMerkleTree};
+pub use sorted::{AbsenceProof, Neighbor, SortedMerkleTree};
+#[cfg(feature = "ssz")]
+pub use ssz::{ssz_basic_list_root, ssz_branch, ssz_list_root, ssz_merkleize, ssz_mix_in_length, ssz_pack, verify_ssz_branch, SszChunk};
+#[cfg(feature = "proptest")]
+pub use strategies::{arb_leaves, arb_proof, arb_tree};
+pub use sparse::{SparseMerkleTree, SparseProof};
+#[cfg(feature = "std")]
+pub use storage::{FileStore, NodeStore, StoredMerkleTree, StoredRoot};
+pub use sync::{NodeHash, NodeId, NodesResponse, Reconciler, SyncRequest, SyncResponse};
+pub use testvectors::{check_test_vectors, TestVector, VectorDigest, TEST_VECTORS};
+pub use tendermint::{tendermint_root, TendermintProof};
+#[cfg(feature = "std")]
+pub use tiles::{Tile, TileReader, TiledLog};
+pub use utreexo::{UtreexoForest, UtreexoProof, UtreexoStump};
+pub use tree::{Comparison, DiffEntry, MerkleLeaf, MerkleNode, MerkleTree, Node, NodeCombiner, TreeStats};
+pub use versioned::VersionedMerkleTree;
+#[cfg(feature = "wasm")]
+pub use wasm::{WasmConfig, WasmMerkleTree};
+
+mod accumulator;
+mod arena;
+mod backend;
+mod batch;
+mod borrowed;
+mod bitcoin;
+#[cfg(feature = "blake3")]
+mod blake3;
+mod cdc;
+mod chunk_verifier;
+#[cfg(feature = "std")]
+mod concurrent;
+mod config;
+mod consistency;
+mod epoch;
+mod error;
+mod ethereum;
+mod fixed;
+mod flat;
+#[cfg(feature = "std")]
+mod fs;
+#[cfg(feature = "fuzz")]
+mod fuzz;
+mod incremental;
+mod indexed;
+mod ipfs;
+mod kary;
+mod keyed;
+mod lazy;
+mod leaf;
+mod map;
+mod metrics;
+#[cfg(all(feature = "mmap", unix))]
+mod mmap;
+mod mmr;
+mod mpt;
+mod multiproof;
+#[cfg(feature = "signing")]
+mod note;
+mod partial;
+#[cfg(feature = "poseidon")]
+mod poseidon;
+mod proof;
+mod proof_builder;
+mod proof_verifier;
+mod range;
+#[cfg(feature = "std")]
+mod reader;
+mod root_log;
+mod salted;
+#[cfg(feature = "serde")]
+mod serde_hash;
+#[cfg(feature = "signing")]
+mod signing;
+mod snapshot;
+mod solana;
+mod sorted;
+mod sparse;
+#[cfg(feature = "ssz")]
+mod ssz;
+#[cfg(feature = "proptest")]
+mod strategies;
+#[cfg(feature = "std")]
+mod storage;
+mod sync;
+mod tendermint;
+mod testvectors;
+#[cfg(feature = "std")]
+mod tiles;
 mod tree;
+mod utreexo;
+mod versioned;
+#[cfg(feature = "wasm")]
+mod wasm;
+mod wire;
+
+/// Fixed-size output of the digest `D`, stored inline without a heap allocation.
+pub type Hash<D> = GenericArray<u8, <D as Digest>::OutputSize>;
+
+fn calculate_hash<D: Digest>(data: &[u8]) -> Hash<D> {
+    let mut hasher = D::new();
+    hasher.input(data);
+    hasher.result()
+}
+
+fn calculate_prefixed_hash<D: Digest>(prefix: Option<u8>, data: &[u8]) -> Hash<D> {
+    let mut hasher = D::new();
 
-fn calculate_hash(data: &[u8]) -> Vec<u8> {
-    use blake2::{Blake2b, Digest};
+    if let Some(prefix) = prefix {
+        hasher.input([prefix]);
+    }
 
-    let mut hasher = Blake2b::new();
     hasher.input(data);
-    hasher.result().to_vec()
+    hasher.result()
+}
+
+fn hash_leaf<D: Digest>(prefixes: Option<Prefixes>, data: &[u8]) -> Hash<D> {
+    calculate_prefixed_hash::<D>(prefixes.map(|p| p.leaf), data)
+}
+
+/// Checks that the data of the leaf at `index` can be turned into a hash with `leaf_mode`.
+fn check_leaf<D: Digest>(leaf_mode: LeafMode, index: usize, data: &[u8]) -> Result<(), MerkleError> {
+    match leaf_mode {
+        LeafMode::Prehashed if data.len() != D::output_size() =>
+            Err(MerkleError::InvalidLeafLength { index, len: data.len(), expected: D::output_size() }),
+        _ => Ok(()),
+    }
+}
+
+/// Hash of the leaf holding `data`, which must have been accepted by `check_leaf`.
+fn leaf_hash<D: Digest>(prefixes: Option<Prefixes>, leaf_mode: LeafMode, data: &[u8]) -> Hash<D> {
+    match leaf_mode {
+        LeafMode::Hashed => hash_leaf::<D>(prefixes, data),
+        LeafMode::Prehashed => GenericArray::clone_from_slice(data),
+    }
+}
+
+/// Applies `policy` to `leaves` in order, `hash` giving the hash of a leaf: fails with
+/// `DuplicateLeaf` under `DuplicatePolicy::Reject` and drops every copy after the first under
+/// `DuplicatePolicy::Deduplicate`, as `MerkleTree::from_vec` does.
+fn apply_duplicate_policy<L, H, F>(leaves: Vec<L>, policy: DuplicatePolicy, hash: F) -> Result<Vec<L>, MerkleError>
+    where H: Ord + Clone, F: Fn(&L) -> &H {
+    if policy == DuplicatePolicy::Allow {
+        return Ok(leaves);
+    }
+
+    let mut firsts = BTreeMap::new();
+    let mut kept = Vec::with_capacity(leaves.len());
+
+    for leaf in leaves {
+        match firsts.get(hash(&leaf)).copied() {
+            Some(first) if policy == DuplicatePolicy::Reject => return Err(MerkleError::DuplicateLeaf { index: kept.len(), first }),
+            Some(_) => {}
+            None => {
+                firsts.insert(hash(&leaf).clone(), kept.len());
+                kept.push(leaf);
+            }
+        }
+    }
+
+    Ok(kept)
+}
+
+/// Hashes two child hashes together, without going through a heap allocated buffer.
+fn hash_pair<D: Digest>(prefixes: Option<Prefixes>, left: &[u8], right: &[u8]) -> Hash<D> {
+    let mut hasher = D::new();
+
+    if let Some(prefixes) = prefixes {
+        hasher.input([prefixes.node]);
+    }
+
+    hasher.input(left);
+    hasher.input(right);
+    hasher.result()
+}
+
+/// Hashes two sibling hashes, in ascending order rather than left to right when `sorted` is set.
+fn hash_children<D: Digest>(prefixes: Option<Prefixes>, sorted: bool, left: &[u8], right: &[u8]) -> Hash<D> {
+    if sorted && right < left {
+        hash_pair::<D>(prefixes, right, left)
+    } else {
+        hash_pair::<D>(prefixes, left, right)
+    }
+}
+
+/// Hashes every input, spreading the work across threads when the `rayon` feature is enabled.
+#[cfg(feature = "rayon")]
+fn map_hashes<I, H, F>(inputs: &[I], hash_fn: F) -> Vec<H> where I: Sync, H: Send, F: Fn(&I) -> H + Sync + Send {
+    use rayon::prelude::*;
+
+    // Hashing a single input is cheap, so work is only split into reasonably sized batches.
+    inputs.par_iter().with_min_len(1024).map(hash_fn).collect()
+}
+
+#[cfg(not(feature = "rayon"))]
+fn map_hashes<I, H, F>(inputs: &[I], hash_fn: F) -> Vec<H> where F: Fn(&I) -> H {
+    inputs.iter().map(hash_fn).collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    use core::fmt::Write;
+
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, MerkleError> {
+    let digits = hex.chars()
+        .map(|c| c.to_digit(16).map(|d| d as u8).ok_or(MerkleError::InvalidHex))
+        .collect::<Result<Vec<u8>, MerkleError>>()?;
+
+    if digits.len() & 1 == 1 {
+        return Err(MerkleError::InvalidHex);
+    }
+
+    Ok(digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_encodes_hex() {
+        assert_eq!(to_hex(&[]), "");
+        assert_eq!(to_hex(&[0x00, 0x0f, 0xa5, 0xff]), "000fa5ff");
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn it_decodes_hex() {
+        assert_eq!(from_hex("000fA5ff"), Ok(vec![0x00, 0x0f, 0xa5, 0xff]));
+        assert_eq!(from_hex("abc"), Err(MerkleError::InvalidHex));
+        assert_eq!(from_hex("zz"), Err(MerkleError::InvalidHex));
+        assert_eq!(from_hex("+1"), Err(MerkleError::InvalidHex));
+    }
 }
@@ -0,0 +1,240 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use blake2::Blake2b;
+use digest::Digest;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::config::{LeafMode, TreeConfig};
+use crate::error::MerkleError;
+use crate::proof::Proof;
+use crate::tree::{MerkleLeaf, MerkleTree, Node};
+use crate::{calculate_hash, hash_leaf, Hash};
+
+/// Authenticated dictionary: a MerkleTree over key-value pairs, ordered by the hash of their
+/// key so that the root only depends on the entries, not on the order they were given in.
+/// Every leaf commits to the key and the value together, so a proof for an entry fails for the
+/// same value under another key as well as for another value.
+///
+/// A leaf holds the length of the key as a big-endian `u64`, the key and the value. The
+/// `leaf_mode` of the config is ignored, since entries are always hashed.
+pub struct MerkleMap<K, V, D: Digest = Blake2b> {
+    tree: MerkleTree<(K, V), D>,
+    /// Hash of the key of every leaf, in leaf order.
+    keys: Vec<Hash<D>>,
+}
+
+impl<K, V> MerkleMap<K, V> where K: AsRef<[u8]>, V: AsRef<[u8]> {
+    /// Creates a MerkleMap from a vector of entries, hashing with Blake2b and the default config.
+    pub fn from_vec(entries: Vec<(K, V)>) -> Result<MerkleMap<K, V>, MerkleError> {
+        MerkleMap::from_vec_with_config_and_digest(entries, TreeConfig::default())
+    }
+
+    /// Creates a MerkleMap from a vector of entries, hashing with Blake2b.
+    pub fn from_vec_with_config(entries: Vec<(K, V)>, config: TreeConfig) -> Result<MerkleMap<K, V>, MerkleError> {
+        MerkleMap::from_vec_with_config_and_digest(entries, config)
+    }
+}
+
+impl<K, V, D> MerkleMap<K, V, D> where K: AsRef<[u8]>, V: AsRef<[u8]>, D: Digest {
+    /// Creates a MerkleMap from a vector of entries, hashing with the digest `D`. When several
+    /// entries have the same key, the last one is kept.
+    pub fn from_vec_with_config_and_digest(entries: Vec<(K, V)>, config: TreeConfig) -> Result<MerkleMap<K, V, D>, MerkleError> {
+        let config = TreeConfig { leaf_mode: LeafMode::Hashed, ..config };
+
+        let mut entries: Vec<(Hash<D>, (K, V))> = entries.into_iter()
+            .map(|entry| (calculate_hash::<D>(entry.0.as_ref()), entry))
+            .collect();
+
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries.dedup_by(|later, earlier| {
+            if later.0 == earlier.0 {
+                core::mem::swap(later, earlier);
+                true
+            } else {
+                false
+            }
+        });
+
+        let (keys, leaves) = entries.into_iter()
+            .map(|(key_hash, (key, value))| {
+                let hash = hash_leaf::<D>(config.prefixes, &entry_leaf(key.as_ref(), value.as_ref()));
+                (key_hash, Node::Leaf(MerkleLeaf { hash, data: (key, value) }))
+            })
+            .unzip();
+
+        Ok(MerkleMap { tree: MerkleTree::from_leaves(leaves, config)?, keys })
+    }
+
+    pub fn root_hash(&self) -> &[u8] {
+        self.tree.root_hash()
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Always `false`, since maps cannot be built without entries.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns the value stored under `key`, if any.
+    pub fn get(&self, key: &[u8]) -> Option<&V> {
+        self.position(key).map(|index| &self.entry(index).1)
+    }
+
+    /// Iterates over the entries in leaf order, which is the order of their key hashes.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        (0..self.len()).map(move |index| {
+            let (key, value) = self.entry(index);
+            (key, value)
+        })
+    }
+
+    /// Generates a proof of the entry stored under `key`, or `None` if there is no such entry.
+    pub fn proof(&self, key: &[u8]) -> Option<MapProof<D>> {
+        let index = self.position(key)?;
+        let proof = self.tree.proof(index).expect("index is smaller than the leaf count");
+
+        Some(MapProof { proof })
+    }
+
+    fn position(&self, key: &[u8]) -> Option<usize> {
+        let index = self.keys.binary_search(&calculate_hash::<D>(key)).ok()?;
+
+        if self.entry(index).0.as_ref() == key {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    fn entry(&self, index: usize) -> &(K, V) {
+        &self.tree.get_leaf(index).expect("index is smaller than the leaf count").data
+    }
+}
+
+/// Data of the leaf holding the entry `key`, `value`.
+fn entry_leaf(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut leaf = Vec::with_capacity(8 + key.len() + value.len());
+    leaf.extend_from_slice(&(key.len() as u64).to_be_bytes());
+    leaf.extend_from_slice(key);
+    leaf.extend_from_slice(value);
+    leaf
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<K, V, D> Clone for MerkleMap<K, V, D> where K: Clone, V: Clone, D: Digest {
+    fn clone(&self) -> Self {
+        MerkleMap { tree: self.tree.clone(), keys: self.keys.clone() }
+    }
+}
+
+impl<K, V, D> fmt::Debug for MerkleMap<K, V, D> where K: fmt::Debug, V: fmt::Debug, D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MerkleMap").field("tree", &self.tree).finish()
+    }
+}
+
+/// Proof that a key is mapped to a value in a [`MerkleMap`].
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct MapProof<D: Digest = Blake2b> {
+    /// Inclusion proof of the leaf holding the entry.
+    pub proof: Proof<D>,
+}
+
+impl<D> MapProof<D> where D: Digest {
+    /// Checks that `key` is mapped to `value` in the map with the given root hash.
+    pub fn verify(&self, root_hash: &[u8], key: &[u8], value: &[u8]) -> Result<(), MerkleError> {
+        if self.proof.leaf_mode != LeafMode::Hashed {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        self.proof.verify(root_hash, &entry_leaf(key, value))
+    }
+
+    /// Same as `verify`, for a map built with `config`, whose leaf mode is ignored as it is when
+    /// building. Fails with `ConfigMismatch` unless the proof was generated with `config`.
+    pub fn verify_with_config(&self, root_hash: &[u8], key: &[u8], value: &[u8], config: TreeConfig) -> Result<(), MerkleError> {
+        self.proof.check_config(TreeConfig { leaf_mode: LeafMode::Hashed, ..config })?;
+        self.verify(root_hash, key, value)
+    }
+}
+
+impl<D> Clone for MapProof<D> where D: Digest {
+    fn clone(&self) -> Self {
+        MapProof { proof: self.proof.clone() }
+    }
+}
+
+impl<D> PartialEq for MapProof<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.proof == other.proof
+    }
+}
+
+impl<D> fmt::Debug for MapProof<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MapProof").field("proof", &self.proof).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Prefixes;
+
+    fn entries() -> Vec<(String, String)> {
+        (0..7).map(|i| (format!("key {}", i), format!("value {}", i))).collect()
+    }
+
+    #[test]
+    fn it_does_not_depend_on_the_order_of_entries() {
+        let mut reversed = entries();
+        reversed.reverse();
+
+        let map = MerkleMap::from_vec(entries()).unwrap();
+
+        assert_eq!(map.root_hash(), MerkleMap::from_vec(reversed).unwrap().root_hash());
+        assert_eq!(map.len(), 7);
+        assert_eq!(map.get(b"key 3").map(String::as_str), Some("value 3"));
+        assert_eq!(map.get(b"key 7"), None);
+
+        let mut keys: Vec<_> = map.iter().map(|(key, _)| key.clone()).collect();
+        keys.sort();
+        assert_eq!(keys, entries().into_iter().map(|(key, _)| key).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn it_binds_proofs_to_both_key_and_value() {
+        let config = TreeConfig { prefixes: Some(Prefixes::RFC6962), ..TreeConfig::default() };
+        let map = MerkleMap::from_vec_with_config(entries(), config).unwrap();
+
+        for (key, value) in entries() {
+            let proof = map.proof(key.as_bytes()).unwrap();
+
+            assert_eq!(proof.verify(map.root_hash(), key.as_bytes(), value.as_bytes()), Ok(()));
+            assert_eq!(proof.verify(map.root_hash(), key.as_bytes(), b"other"), Err(MerkleError::RootMismatch));
+            assert_eq!(proof.verify(map.root_hash(), b"other", value.as_bytes()), Err(MerkleError::RootMismatch));
+        }
+
+        // Moving bytes between the key and the value changes the leaf.
+        let proof = map.proof(b"key 1").unwrap();
+        assert_eq!(proof.verify(map.root_hash(), b"key ", b"1value 1"), Err(MerkleError::RootMismatch));
+        assert_eq!(map.proof(b"key 7"), None);
+    }
+
+    #[test]
+    fn it_keeps_the_last_entry_of_a_key() {
+        let map = MerkleMap::from_vec(vec![("a", "1"), ("b", "2"), ("a", "3")]).unwrap();
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(b"a"), Some(&"3"));
+        assert_eq!(map.root_hash(), MerkleMap::from_vec(vec![("b", "2"), ("a", "3")]).unwrap().root_hash());
+        assert_eq!(MerkleMap::from_vec(Vec::<(&str, &str)>::new()).unwrap_err(), MerkleError::EmptyInput);
+    }
+}
@@ -0,0 +1,105 @@
+//! Hooks counting the work done by trees, such as the hashes computed, the levels built and the
+//! sizes of the proofs generated, for applications exporting them to Prometheus or any other
+//! metrics system without this crate depending on one.
+//!
+//! A single [`Metrics`] implementation is installed for the whole process with [`set_metrics`],
+//! as loggers are with the `log` crate. It is called on the thread that did the work once the
+//! work is done, and recording without one only costs an atomic load.
+
+use alloc::boxed::Box;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// Receives the counts of the work done by trees. Every method does nothing by default.
+pub trait Metrics: Sync {
+    /// `count` hashes of leaves or nodes were computed.
+    fn hashes_computed(&self, count: usize) {
+        let _ = count;
+    }
+
+    /// A level of `nodes` nodes was built, `height` levels above the leaves.
+    fn level_built(&self, height: usize, nodes: usize) {
+        let _ = (height, nodes);
+    }
+
+    /// A proof holding `hashes` hashes was generated.
+    fn proof_generated(&self, hashes: usize) {
+        let _ = hashes;
+    }
+}
+
+static METRICS: AtomicPtr<&'static dyn Metrics> = AtomicPtr::new(ptr::null_mut());
+
+/// Installs the Metrics called for the rest of the process. Returns `false`, leaving the
+/// installed one in place, if one was installed before.
+pub fn set_metrics(metrics: &'static dyn Metrics) -> bool {
+    let installed = Box::into_raw(Box::new(metrics));
+
+    match METRICS.compare_exchange(ptr::null_mut(), installed, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => true,
+        Err(_) => {
+            // SAFETY: the box was not installed, so this is its only owner.
+            drop(unsafe { Box::from_raw(installed) });
+            false
+        }
+    }
+}
+
+/// Calls `f` with the installed Metrics, if any.
+pub(crate) fn record<F>(f: F) where F: FnOnce(&dyn Metrics) {
+    let installed = METRICS.load(Ordering::Acquire);
+
+    if !installed.is_null() {
+        // SAFETY: installed pointers come from a box that is never freed, holding a `'static`
+        // reference.
+        f(unsafe { *installed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use crate::{MerkleTree, TreeConfig};
+
+    thread_local! {
+        /// Calls made on the current thread, as other tests build trees at the same time.
+        static CALLS: RefCell<Vec<(&'static str, usize, usize)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    struct Recorder;
+
+    impl Metrics for Recorder {
+        fn hashes_computed(&self, count: usize) {
+            CALLS.with(|calls| calls.borrow_mut().push(("hashes", count, 0)));
+        }
+
+        fn level_built(&self, height: usize, nodes: usize) {
+            CALLS.with(|calls| calls.borrow_mut().push(("level", height, nodes)));
+        }
+
+        fn proof_generated(&self, hashes: usize) {
+            CALLS.with(|calls| calls.borrow_mut().push(("proof", hashes, 0)));
+        }
+    }
+
+    #[test]
+    fn it_reports_work_to_the_installed_metrics() {
+        assert!(set_metrics(&Recorder));
+        assert!(!set_metrics(&Recorder));
+
+        let mut merkle_tree = MerkleTree::from_vec_with_config(vec!["a", "b", "c", "d", "e"], TreeConfig::RFC6962).unwrap();
+        merkle_tree.proof(4).unwrap();
+        merkle_tree.push("f").unwrap();
+
+        assert_eq!(CALLS.with(|calls| calls.take()), vec![
+            ("hashes", 5, 0),
+            ("hashes", 2, 0), ("level", 1, 3),
+            ("hashes", 1, 0), ("level", 2, 2),
+            ("hashes", 1, 0), ("level", 3, 1),
+            ("proof", 1, 0),
+            // The new leaf, and the two nodes above it that have a sibling.
+            ("hashes", 3, 0),
+        ]);
+    }
+}
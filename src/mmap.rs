@@ -0,0 +1,252 @@
+//! Read-only trees answering queries straight from a memory-mapped file.
+//!
+//! [`FlatMerkleTree::write_to`] writes the heap of a flat tree after a small header, and
+//! [`MappedMerkleTree::open`] maps such a file without reading it: the operating system pages
+//! in the nodes a root or proof query touches, so opening a tree of any size is instant.
+//!
+//! The file holds the bytes of [`FlatMerkleTree::to_bytes`], which start with a header.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::{fmt, ptr, slice};
+
+use blake2::Blake2b;
+use digest::Digest;
+use digest::generic_array::GenericArray;
+
+use crate::to_hex;
+use crate::config::TreeConfig;
+use crate::error::MerkleError;
+use crate::flat::{decode_header, heap_proof, FlatMerkleTree, HEADER_LEN};
+use crate::proof::Proof;
+
+impl<D> FlatMerkleTree<D> where D: Digest {
+    /// Writes the tree in the format of `to_bytes`, which `MappedMerkleTree::open` reads.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.header())?;
+
+        for node in self.nodes() {
+            writer.write_all(node)?;
+        }
+
+        writer.flush()
+    }
+}
+
+/// Read-only tree backed by a file written by `FlatMerkleTree::write_to`, with the same root
+/// and proofs as the tree that was written. The file must not be modified while it is open.
+pub struct MappedMerkleTree<D: Digest = Blake2b> {
+    map: Mmap,
+    config: TreeConfig,
+    leaf_count: usize,
+    depth: usize,
+    digest: PhantomData<D>,
+}
+
+impl MappedMerkleTree<Blake2b> {
+    /// Maps the tree stored at `path`, which must have been hashed with Blake2b.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<MappedMerkleTree> {
+        MappedMerkleTree::open_with_digest(path)
+    }
+}
+
+impl<D> MappedMerkleTree<D> where D: Digest {
+    /// Maps the tree stored at `path`. Only the header is checked, along with the file length
+    /// it implies; the nodes themselves are trusted.
+    pub fn open_with_digest<P: AsRef<Path>>(path: P) -> io::Result<MappedMerkleTree<D>> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+
+        if len < HEADER_LEN {
+            return Err(invalid_data("file is too short for a tree header"));
+        }
+
+        let map = Mmap::map(&file, len)?;
+        let (config, leaf_count, depth) = decode_header::<D>(map.as_slice()).map_err(invalid_data)?;
+
+        Ok(MappedMerkleTree { map, config, leaf_count, depth, digest: PhantomData })
+    }
+
+    pub fn root_hash(&self) -> &[u8] {
+        self.slot(0)
+    }
+
+    /// Returns the root hash as a lowercase hex string.
+    pub fn root_hash_hex(&self) -> String {
+        to_hex(self.root_hash())
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    /// Number of levels above the leaves.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn config(&self) -> TreeConfig {
+        self.config
+    }
+
+    /// Returns the hash of the `index`-th node of the level `height` above the leaves,
+    /// or `None` when the level has no such node, like `FlatMerkleTree::node`.
+    pub fn node(&self, height: usize, index: usize) -> Option<&[u8]> {
+        let level_len = (0..height).fold(self.leaf_count, |count, _| count.div_ceil(2));
+
+        if height > self.depth || index >= level_len {
+            return None;
+        }
+
+        Some(self.slot((1 << (self.depth - height)) - 1 + index))
+    }
+
+    /// Generates an inclusion proof for the leaf at `leaf_index`, reading only the nodes on its
+    /// path from the file.
+    pub fn proof(&self, leaf_index: usize) -> Result<Proof<D>, MerkleError> {
+        heap_proof(self.config, self.leaf_count, self.depth, leaf_index, |slot| GenericArray::clone_from_slice(self.slot(slot)))
+    }
+
+    fn slot(&self, slot: usize) -> &[u8] {
+        let start = HEADER_LEN + slot * D::output_size();
+        &self.map.as_slice()[start..start + D::output_size()]
+    }
+}
+
+impl<D> fmt::Debug for MappedMerkleTree<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MappedMerkleTree")
+            .field("root_hash", &self.root_hash_hex())
+            .field("config", &self.config)
+            .field("leaf_count", &self.leaf_count)
+            .finish()
+    }
+}
+
+/// Read-only shared mapping of a whole file.
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is read-only and owned by this value alone.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Maps the first `len` bytes of `file`, which must not be zero.
+    fn map(file: &File, len: usize) -> io::Result<Mmap> {
+        // SAFETY: a new mapping is requested at an address of the kernel's choosing, so no
+        // existing memory is affected. The file descriptor is valid for the whole call.
+        let ptr = unsafe { libc::mmap(ptr::null_mut(), len, libc::PROT_READ, libc::MAP_SHARED, file.as_raw_fd(), 0) };
+
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Mmap { ptr, len })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        // SAFETY: the mapping is `len` bytes long, readable, and lives as long as `self`.
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created by `map` and no slice of it outlives `self`.
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error where E: Into<Box<dyn std::error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::config::{Padding, Prefixes};
+
+    struct TestFile(PathBuf);
+
+    impl TestFile {
+        fn new(name: &str) -> TestFile {
+            TestFile(std::env::temp_dir().join(format!("merkle-tree-mmap-{}-{}", name, std::process::id())))
+        }
+
+        fn write<D: Digest>(&self, tree: &FlatMerkleTree<D>) {
+            tree.write_to(io::BufWriter::new(File::create(&self.0).unwrap())).unwrap();
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn leaves(len: usize) -> Vec<String> {
+        (0..len).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn it_matches_the_tree_that_was_written() {
+        let file = TestFile::new("matches");
+
+        for &padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            for len in 1..=9 {
+                let config = TreeConfig { prefixes: Some(Prefixes::RFC6962), ..TreeConfig::new(padding) };
+                let flat = FlatMerkleTree::from_vec_with_config(leaves(len), config).unwrap();
+                file.write(&flat);
+
+                let mapped = MappedMerkleTree::open(&file.0).unwrap();
+
+                assert_eq!(mapped.root_hash(), flat.root_hash());
+                assert_eq!(mapped.config(), config);
+                assert_eq!(mapped.node(1, 0), flat.node(1, 0));
+
+                for index in 0..len {
+                    assert_eq!(mapped.proof(index).unwrap(), flat.proof(index).unwrap());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_rejects_other_digests_and_truncated_files() {
+        let file = TestFile::new("rejects");
+        file.write(&FlatMerkleTree::from_vec(leaves(5)).unwrap());
+
+        let error = MappedMerkleTree::<sha2::Sha256>::open_with_digest(&file.0).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let bytes = fs::read(&file.0).unwrap();
+        fs::write(&file.0, &bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(MappedMerkleTree::open(&file.0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        fs::write(&file.0, b"MKLF").unwrap();
+        assert_eq!(MappedMerkleTree::open(&file.0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn it_rejects_out_of_bounds_queries() {
+        let file = TestFile::new("bounds");
+        file.write(&FlatMerkleTree::from_vec(vec!["a", "b", "c"]).unwrap());
+
+        let mapped = MappedMerkleTree::open(&file.0).unwrap();
+
+        assert_eq!(mapped.proof(3), Err(MerkleError::IndexOutOfBounds { index: 3, leaf_count: 3 }));
+        assert_eq!(mapped.node(0, 3), None);
+        assert_eq!(mapped.node(3, 0), None);
+    }
+}
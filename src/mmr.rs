@@ -0,0 +1,402 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use blake2::Blake2b;
+use digest::Digest;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::config::Prefixes;
+use crate::error::MerkleError;
+use crate::{calculate_hash, hash_leaf, hash_pair, Hash};
+
+/// Leaves and nodes are hashed with distinct prefixes, so that a node cannot pass for a leaf.
+const PREFIXES: Option<Prefixes> = Some(Prefixes::RFC6962);
+
+/// Append-only Merkle Mountain Range: a list of perfect binary trees ("mountains") of
+/// decreasing height, one per bit set in the leaf count. Appending a leaf only merges the
+/// rightmost mountains, and the root is obtained by bagging the mountain peaks. Leaves and
+/// nodes are hashed with the RFC 6962 prefixes.
+pub struct MerkleMountainRange<D: Digest = Blake2b> {
+    /// `levels[h]` holds the hashes of the complete nodes of height `h`, left to right,
+    /// starting at index `offsets[h]`.
+    levels: Vec<Vec<Hash<D>>>,
+    /// Number of nodes discarded from the start of each level by pruning.
+    offsets: Vec<usize>,
+    leaf_count: usize,
+}
+
+impl MerkleMountainRange {
+    /// Creates an empty MerkleMountainRange hashing with Blake2b.
+    pub fn new() -> MerkleMountainRange {
+        MerkleMountainRange::with_digest()
+    }
+}
+
+impl Default for MerkleMountainRange {
+    fn default() -> Self {
+        MerkleMountainRange::new()
+    }
+}
+
+impl<D> MerkleMountainRange<D> where D: Digest {
+    /// Creates an empty MerkleMountainRange hashing with the digest `D`.
+    pub fn with_digest() -> MerkleMountainRange<D> {
+        MerkleMountainRange {
+            levels: Vec::new(),
+            offsets: Vec::new(),
+            leaf_count: 0,
+        }
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaf_count == 0
+    }
+
+    /// Appends a leaf and returns its index, merging mountains of equal height.
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        let index = self.leaf_count;
+        let mut hash = hash_leaf::<D>(PREFIXES, data);
+        let mut height = 0;
+
+        loop {
+            if self.levels.len() == height {
+                self.levels.push(Vec::new());
+                self.offsets.push(0);
+            }
+
+            self.levels[height].push(hash);
+
+            // A node with an odd index completes a pair, whose parent becomes the new peak.
+            if (index >> height) & 1 == 0 {
+                break;
+            }
+
+            let level = &self.levels[height];
+            hash = hash_pair::<D>(PREFIXES, &level[level.len() - 2], &level[level.len() - 1]);
+            height += 1;
+        }
+
+        self.leaf_count += 1;
+        index
+    }
+
+    /// Peak hashes of the mountains, from the highest (leftmost) to the lowest.
+    pub fn peaks(&self) -> Vec<&[u8]> {
+        self.peak_hashes().map(|peak| peak.as_slice()).collect()
+    }
+
+    fn peak_hashes(&self) -> impl Iterator<Item = &Hash<D>> {
+        (0..self.levels.len()).rev()
+            .filter(move |height| (self.leaf_count >> height) & 1 == 1)
+            .map(move |height| self.levels[height].last().unwrap())
+    }
+
+    /// Root hash, obtained by bagging the peaks from right to left. An empty range has the
+    /// hash of an empty input as its root.
+    pub fn root_hash(&self) -> Hash<D> {
+        bag_peaks::<D>(&self.peaks())
+    }
+
+    fn node(&self, height: usize, index: usize) -> Option<&Hash<D>> {
+        index.checked_sub(self.offsets[height]).and_then(|i| self.levels[height].get(i))
+    }
+
+    /// Generates an inclusion proof for the leaf at `leaf_index`, made of the path up to its
+    /// mountain's peak and the peaks of all mountains.
+    pub fn proof(&self, leaf_index: usize) -> Result<MmrProof<D>, MerkleError> {
+        if leaf_index >= self.leaf_count {
+            return Err(MerkleError::IndexOutOfBounds { index: leaf_index, leaf_count: self.leaf_count });
+        }
+
+        let (height, _) = mountain_of(leaf_index, self.leaf_count);
+        let mut siblings = Vec::with_capacity(height);
+
+        for h in 0..height {
+            let sibling = self.node(h, (leaf_index >> h) ^ 1).ok_or(MerkleError::Pruned { index: leaf_index })?;
+            siblings.push(sibling.clone());
+        }
+
+        let peaks = self.peak_hashes().cloned().collect();
+
+        Ok(MmrProof::new(leaf_index, self.leaf_count, siblings, peaks))
+    }
+
+    /// Discards the hashes that are only needed to prove leaves before `keep_from`. Leaves from
+    /// `keep_from` onwards can still be proven, and appending is unaffected since the peaks
+    /// are always kept.
+    pub fn prune(&mut self, keep_from: usize) -> Result<(), MerkleError> {
+        if keep_from > self.leaf_count {
+            return Err(MerkleError::IndexOutOfBounds { index: keep_from, leaf_count: self.leaf_count });
+        }
+
+        for (height, level) in self.levels.iter_mut().enumerate() {
+            // Keep the pair holding the first kept leaf's ancestor, as it contains its sibling.
+            let first_kept = (keep_from >> height) & !1;
+
+            if first_kept > self.offsets[height] {
+                level.drain(..first_kept - self.offsets[height]);
+                self.offsets[height] = first_kept;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for MerkleMountainRange<D> where D: Digest {
+    fn clone(&self) -> Self {
+        MerkleMountainRange {
+            levels: self.levels.clone(),
+            offsets: self.offsets.clone(),
+            leaf_count: self.leaf_count,
+        }
+    }
+}
+
+impl<D> fmt::Debug for MerkleMountainRange<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MerkleMountainRange")
+            .field("leaf_count", &self.leaf_count)
+            .field("peaks", &self.peaks())
+            .finish()
+    }
+}
+
+/// Inclusion proof for a leaf of a Merkle Mountain Range hashed with the digest `D`.
+/// `siblings` is the path from the leaf up to its mountain's peak, and `peaks` holds every
+/// peak of the range, from the highest to the lowest.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct MmrProof<D: Digest = Blake2b> {
+    pub leaf_index: usize,
+    pub leaf_count: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::list"))]
+    pub siblings: Vec<Hash<D>>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::list"))]
+    pub peaks: Vec<Hash<D>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    digest: PhantomData<D>,
+}
+
+impl<D> MmrProof<D> where D: Digest {
+    pub fn new(leaf_index: usize, leaf_count: usize, siblings: Vec<Hash<D>>, peaks: Vec<Hash<D>>) -> MmrProof<D> {
+        MmrProof {
+            leaf_index,
+            leaf_count,
+            siblings,
+            peaks,
+            digest: PhantomData,
+        }
+    }
+
+    /// Checks that `leaf_data` is the leaf at `leaf_index` of the range of `leaf_count` leaves
+    /// with the given root hash. The root does not commit to the leaf count, which must come
+    /// from the same trusted source as the root: fails with `MalformedProof` if the proof is for
+    /// a range of another size.
+    pub fn verify(&self, root_hash: &[u8], leaf_count: usize, leaf_data: &[u8]) -> Result<(), MerkleError> {
+        if self.leaf_count != leaf_count
+            || self.leaf_index >= self.leaf_count
+            || self.peaks.len() != self.leaf_count.count_ones() as usize {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let (height, peak) = mountain_of(self.leaf_index, self.leaf_count);
+
+        if self.siblings.len() != height {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let mut hash = hash_leaf::<D>(PREFIXES, leaf_data);
+
+        for (h, sibling) in self.siblings.iter().enumerate() {
+            hash = if (self.leaf_index >> h) & 1 == 1 {
+                hash_pair::<D>(PREFIXES, sibling, &hash)
+            } else {
+                hash_pair::<D>(PREFIXES, &hash, sibling)
+            };
+        }
+
+        let peaks: Vec<&[u8]> = self.peaks.iter().map(|peak| peak.as_slice()).collect();
+
+        if hash == self.peaks[peak] && bag_peaks::<D>(&peaks).as_slice() == root_hash {
+            Ok(())
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for MmrProof<D> where D: Digest {
+    fn clone(&self) -> Self {
+        MmrProof::new(self.leaf_index, self.leaf_count, self.siblings.clone(), self.peaks.clone())
+    }
+}
+
+impl<D> PartialEq for MmrProof<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf_index == other.leaf_index
+            && self.leaf_count == other.leaf_count
+            && self.siblings == other.siblings
+            && self.peaks == other.peaks
+    }
+}
+
+impl<D> fmt::Debug for MmrProof<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MmrProof")
+            .field("leaf_index", &self.leaf_index)
+            .field("leaf_count", &self.leaf_count)
+            .field("siblings", &self.siblings)
+            .field("peaks", &self.peaks)
+            .finish()
+    }
+}
+
+/// Height of the mountain holding the leaf at `leaf_index`, and the position of its peak
+/// among the peaks of a range of `leaf_count` leaves.
+fn mountain_of(leaf_index: usize, leaf_count: usize) -> (usize, usize) {
+    let mut start = 0;
+    let mut peak = 0;
+
+    for height in (0..usize::BITS as usize).rev() {
+        if (leaf_count >> height) & 1 == 1 {
+            if leaf_index < start + (1 << height) {
+                return (height, peak);
+            }
+
+            start += 1 << height;
+            peak += 1;
+        }
+    }
+
+    unreachable!("leaf {} is not in a range of {} leaves", leaf_index, leaf_count)
+}
+
+fn bag_peaks<D: Digest>(peaks: &[&[u8]]) -> Hash<D> {
+    match peaks.split_last() {
+        None => calculate_hash::<D>(&[]),
+        Some((last, rest)) => rest.iter().rev().fold(Hash::<D>::clone_from_slice(last), |bag, peak| {
+            hash_pair::<D>(PREFIXES, peak, &bag)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(len: usize) -> MerkleMountainRange {
+        let mut mmr = MerkleMountainRange::new();
+
+        for i in 0..len {
+            mmr.push(i.to_string().as_bytes());
+        }
+
+        mmr
+    }
+
+    #[test]
+    fn it_keeps_one_peak_per_bit_of_the_leaf_count() {
+        assert!(range(0).peaks().is_empty());
+        assert_eq!(range(1).peaks().len(), 1);
+        assert_eq!(range(8).peaks().len(), 1);
+        assert_eq!(range(11).peaks().len(), 3);
+    }
+
+    #[test]
+    fn it_bags_peaks_from_right_to_left() {
+        let mmr = range(3);
+        let leaf = |data: &[u8]| hash_leaf::<Blake2b>(PREFIXES, data);
+        let left = hash_pair::<Blake2b>(PREFIXES, &leaf(b"0"), &leaf(b"1"));
+        let expected = hash_pair::<Blake2b>(PREFIXES, &left, &leaf(b"2"));
+
+        assert_eq!(mmr.root_hash(), expected);
+        assert_eq!(range(0).root_hash(), calculate_hash::<Blake2b>(&[]));
+    }
+
+    #[test]
+    fn it_verifies_proofs_for_every_leaf() {
+        for len in 1..=19 {
+            let mmr = range(len);
+            let root = mmr.root_hash();
+
+            for index in 0..len {
+                let proof = mmr.proof(index).unwrap();
+
+                assert!(proof.verify(&root, len, index.to_string().as_bytes()).is_ok());
+                assert_eq!(proof.verify(&root, len, b"other"), Err(MerkleError::RootMismatch));
+            }
+        }
+    }
+
+    #[test]
+    fn it_rejects_malformed_proofs() {
+        let mmr = range(6);
+        let root = mmr.root_hash();
+
+        let mut proof = mmr.proof(1).unwrap();
+        proof.siblings.pop();
+        assert_eq!(proof.verify(&root, 6, b"1"), Err(MerkleError::MalformedProof));
+
+        let mut proof = mmr.proof(1).unwrap();
+        proof.peaks.pop();
+        assert_eq!(proof.verify(&root, 6, b"1"), Err(MerkleError::MalformedProof));
+        assert_eq!(mmr.proof(1).unwrap().verify(&root, 7, b"1"), Err(MerkleError::MalformedProof));
+        assert_eq!(mmr.proof(6), Err(MerkleError::IndexOutOfBounds { index: 6, leaf_count: 6 }));
+    }
+
+    #[test]
+    fn it_rejects_nodes_proven_as_leaves() {
+        let mmr = range(4);
+        let root = mmr.root_hash();
+        let left = hash_pair::<Blake2b>(PREFIXES, &hash_leaf::<Blake2b>(PREFIXES, b"0"), &hash_leaf::<Blake2b>(PREFIXES, b"1"));
+        let right = &mmr.proof(0).unwrap().siblings[1];
+
+        // The children of the only peak, proven as the only leaf of a range of 1 leaf.
+        let forged = MmrProof::<Blake2b>::new(0, 1, vec![], vec![root]);
+        let node = [&left[..], &right[..]].concat();
+        assert_eq!(forged.verify(&root, 1, &node), Err(MerkleError::RootMismatch));
+        assert_eq!(forged.verify(&root, 4, &node), Err(MerkleError::MalformedProof));
+    }
+
+    #[test]
+    fn it_prunes_old_leaves_without_changing_the_root() {
+        for keep_from in 0..=13 {
+            let mut mmr = range(13);
+            let root = mmr.root_hash();
+            mmr.prune(keep_from).unwrap();
+
+            assert_eq!(mmr.root_hash(), root);
+
+            for index in keep_from..13 {
+                assert!(mmr.proof(index).unwrap().verify(&root, 13, index.to_string().as_bytes()).is_ok());
+            }
+
+            for len in 14..=20 {
+                mmr.push((len - 1).to_string().as_bytes());
+
+                assert_eq!(mmr.root_hash(), range(len).root_hash());
+            }
+
+            assert!(mmr.proof(19).unwrap().verify(&mmr.root_hash(), 20, b"19").is_ok());
+        }
+    }
+
+    #[test]
+    fn it_refuses_to_prove_pruned_leaves() {
+        let mut mmr = range(8);
+        mmr.prune(6).unwrap();
+
+        assert_eq!(mmr.proof(0), Err(MerkleError::Pruned { index: 0 }));
+        assert!(mmr.proof(4).is_err());
+        assert_eq!(mmr.prune(9), Err(MerkleError::IndexOutOfBounds { index: 9, leaf_count: 8 }));
+    }
+}
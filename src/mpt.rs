@@ -0,0 +1,440 @@
+//! Merkle Patricia Trie as used by Ethereum for its state, transaction and receipt tries.
+//!
+//! Keys are split into nibbles and stored along a radix-16 trie whose shared key fragments are
+//! compressed into extension and leaf nodes. Nodes are RLP-encoded, with keys in hex-prefix
+//! encoding, and referred to by the hash of their encoding, unless the encoding is shorter than
+//! 32 bytes, in which case it is embedded in its parent. With Keccak-256, the default digest,
+//! roots are the ones Ethereum clients compute.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use digest::Digest;
+use sha3::Keccak256;
+
+use crate::Hash;
+
+/// Trie mapping byte string keys to byte string values, hashed with the digest `D`.
+pub struct MerklePatriciaTrie<D: Digest = Keccak256> {
+    root: Option<TrieNode>,
+    len: usize,
+    digest: PhantomData<D>,
+}
+
+/// Node of a trie. Paths are sequences of nibbles.
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum TrieNode {
+    Leaf { path: Vec<u8>, value: Vec<u8> },
+    Extension { path: Vec<u8>, child: Box<TrieNode> },
+    Branch { children: Box<[Option<TrieNode>; 16]>, value: Option<Vec<u8>> },
+}
+
+impl MerklePatriciaTrie<Keccak256> {
+    /// Creates an empty trie hashed with Keccak-256, as Ethereum does.
+    pub fn new() -> MerklePatriciaTrie {
+        MerklePatriciaTrie::with_digest()
+    }
+}
+
+impl Default for MerklePatriciaTrie<Keccak256> {
+    fn default() -> Self {
+        MerklePatriciaTrie::new()
+    }
+}
+
+impl<D> MerklePatriciaTrie<D> where D: Digest {
+    /// Creates an empty trie hashed with the digest `D`.
+    pub fn with_digest() -> MerklePatriciaTrie<D> {
+        MerklePatriciaTrie { root: None, len: 0, digest: PhantomData }
+    }
+
+    /// Number of keys in the trie.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        let path = nibbles(key);
+        let mut path = &path[..];
+        let mut node = self.root.as_ref()?;
+
+        loop {
+            match node {
+                TrieNode::Leaf { path: leaf_path, value } => {
+                    return if leaf_path[..] == *path { Some(value) } else { None };
+                }
+                TrieNode::Extension { path: prefix, child } => {
+                    path = path.strip_prefix(&prefix[..])?;
+                    node = child;
+                }
+                TrieNode::Branch { children, value } => match path.split_first() {
+                    None => return value.as_deref(),
+                    Some((&nibble, rest)) => {
+                        node = children[nibble as usize].as_ref()?;
+                        path = rest;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Sets the value of `key`, returning its previous value if any.
+    pub fn insert(&mut self, key: &[u8], value: Vec<u8>) -> Option<Vec<u8>> {
+        let mut previous = None;
+        self.root = Some(insert(self.root.take(), &nibbles(key), value, &mut previous));
+
+        if previous.is_none() {
+            self.len += 1;
+        }
+
+        previous
+    }
+
+    /// Removes `key` from the trie, returning its value if it was present.
+    pub fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let (root, removed) = match self.root.take() {
+            Some(root) => remove(root, &nibbles(key)),
+            None => (None, None),
+        };
+        self.root = root;
+
+        if removed.is_some() {
+            self.len -= 1;
+        }
+
+        removed
+    }
+
+    /// Hash of the RLP encoding of the root node, or of the empty string for an empty trie.
+    pub fn root_hash(&self) -> Hash<D> {
+        match &self.root {
+            Some(root) => D::digest(&encode::<D>(root)),
+            None => D::digest(&[EMPTY_STRING]),
+        }
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for MerklePatriciaTrie<D> where D: Digest {
+    fn clone(&self) -> Self {
+        MerklePatriciaTrie {
+            root: self.root.clone(),
+            len: self.len,
+            digest: PhantomData,
+        }
+    }
+}
+
+impl<D> PartialEq for MerklePatriciaTrie<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.root == other.root
+    }
+}
+
+impl<D> fmt::Debug for MerklePatriciaTrie<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MerklePatriciaTrie")
+            .field("root_hash", &crate::to_hex(&self.root_hash()))
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+fn nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).collect()
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+fn empty_children() -> Box<[Option<TrieNode>; 16]> {
+    Box::default()
+}
+
+/// Node holding `value` under `path` below a branch: the branch value when the path is empty.
+fn attach(children: &mut [Option<TrieNode>; 16], branch_value: &mut Option<Vec<u8>>, path: &[u8], value: Vec<u8>) {
+    match path.split_first() {
+        None => *branch_value = Some(value),
+        Some((&nibble, rest)) => children[nibble as usize] = Some(TrieNode::Leaf { path: rest.to_vec(), value }),
+    }
+}
+
+/// Puts `node` under an extension of `prefix`, an empty prefix leaving it as it is.
+fn extend(prefix: &[u8], node: TrieNode) -> TrieNode {
+    if prefix.is_empty() {
+        node
+    } else {
+        TrieNode::Extension { path: prefix.to_vec(), child: Box::new(node) }
+    }
+}
+
+/// Prepends `prefix` to the path of `node`, merging it into leaves and extensions.
+fn prepend(prefix: &[u8], node: TrieNode) -> TrieNode {
+    match node {
+        TrieNode::Leaf { path, value } => TrieNode::Leaf { path: [prefix, &path].concat(), value },
+        TrieNode::Extension { path, child } => TrieNode::Extension { path: [prefix, &path].concat(), child },
+        branch => extend(prefix, branch),
+    }
+}
+
+fn insert(node: Option<TrieNode>, path: &[u8], value: Vec<u8>, previous: &mut Option<Vec<u8>>) -> TrieNode {
+    match node {
+        None => TrieNode::Leaf { path: path.to_vec(), value },
+        Some(TrieNode::Leaf { path: leaf_path, value: leaf_value }) => {
+            if leaf_path == path {
+                *previous = Some(leaf_value);
+                return TrieNode::Leaf { path: leaf_path, value };
+            }
+
+            let common = common_prefix(&leaf_path, path);
+            let (mut children, mut branch_value) = (empty_children(), None);
+            attach(&mut children, &mut branch_value, &leaf_path[common..], leaf_value);
+            attach(&mut children, &mut branch_value, &path[common..], value);
+
+            extend(&path[..common], TrieNode::Branch { children, value: branch_value })
+        }
+        Some(TrieNode::Extension { path: prefix, child }) => {
+            let common = common_prefix(&prefix, path);
+
+            if common == prefix.len() {
+                let child = insert(Some(*child), &path[common..], value, previous);
+                return TrieNode::Extension { path: prefix, child: Box::new(child) };
+            }
+
+            // The extension is split where the paths diverge, under a new branch.
+            let (mut children, mut branch_value) = (empty_children(), None);
+            children[prefix[common] as usize] = Some(extend(&prefix[common + 1..], *child));
+            attach(&mut children, &mut branch_value, &path[common..], value);
+
+            extend(&path[..common], TrieNode::Branch { children, value: branch_value })
+        }
+        Some(TrieNode::Branch { mut children, value: mut branch_value }) => {
+            match path.split_first() {
+                None => *previous = branch_value.replace(value),
+                Some((&nibble, rest)) => {
+                    let child = children[nibble as usize].take();
+                    children[nibble as usize] = Some(insert(child, rest, value, previous));
+                }
+            }
+
+            TrieNode::Branch { children, value: branch_value }
+        }
+    }
+}
+
+/// Removes `path` below `node`, returning what is left of the node and the removed value.
+fn remove(node: TrieNode, path: &[u8]) -> (Option<TrieNode>, Option<Vec<u8>>) {
+    match node {
+        TrieNode::Leaf { path: leaf_path, value } => {
+            if leaf_path == path {
+                (None, Some(value))
+            } else {
+                (Some(TrieNode::Leaf { path: leaf_path, value }), None)
+            }
+        }
+        TrieNode::Extension { path: prefix, child } => match path.strip_prefix(&prefix[..]) {
+            Some(rest) => {
+                let (child, removed) = remove(*child, rest);
+                (child.map(|child| prepend(&prefix, child)), removed)
+            }
+            None => (Some(TrieNode::Extension { path: prefix, child }), None),
+        },
+        TrieNode::Branch { mut children, mut value } => {
+            let removed = match path.split_first() {
+                None => value.take(),
+                Some((&nibble, rest)) => match children[nibble as usize].take() {
+                    Some(child) => {
+                        let (child, removed) = remove(child, rest);
+                        children[nibble as usize] = child;
+                        removed
+                    }
+                    None => None,
+                },
+            };
+
+            if removed.is_none() {
+                return (Some(TrieNode::Branch { children, value }), None);
+            }
+
+            (Some(collapse(children, value)), removed)
+        }
+    }
+}
+
+/// Turns a branch left with a single entry into the equivalent leaf or extension.
+fn collapse(mut children: Box<[Option<TrieNode>; 16]>, value: Option<Vec<u8>>) -> TrieNode {
+    let mut remaining = children.iter().enumerate().filter(|(_, child)| child.is_some()).map(|(nibble, _)| nibble);
+
+    match (remaining.next(), remaining.next(), value) {
+        (None, _, Some(value)) => TrieNode::Leaf { path: Vec::new(), value },
+        (Some(nibble), None, None) => {
+            let child = children[nibble].take().expect("child is present");
+            prepend(&[nibble as u8], child)
+        }
+        (_, _, value) => TrieNode::Branch { children, value },
+    }
+}
+
+const EMPTY_STRING: u8 = 0x80;
+
+/// RLP encoding of `node`, whose children larger than a hash are referred to by their `D` hash.
+fn encode<D: Digest>(node: &TrieNode) -> Vec<u8> {
+    let mut payload = Vec::new();
+
+    match node {
+        TrieNode::Leaf { path, value } => {
+            encode_bytes(&hex_prefix(path, true), &mut payload);
+            encode_bytes(value, &mut payload);
+        }
+        TrieNode::Extension { path, child } => {
+            encode_bytes(&hex_prefix(path, false), &mut payload);
+            encode_reference::<D>(child, &mut payload);
+        }
+        TrieNode::Branch { children, value } => {
+            for child in children.iter() {
+                match child {
+                    Some(child) => encode_reference::<D>(child, &mut payload),
+                    None => payload.push(EMPTY_STRING),
+                }
+            }
+
+            encode_bytes(value.as_deref().unwrap_or(&[]), &mut payload);
+        }
+    }
+
+    let mut encoding = Vec::with_capacity(payload.len() + 9);
+    encode_length(payload.len(), 0xc0, &mut encoding);
+    encoding.extend_from_slice(&payload);
+    encoding
+}
+
+/// Appends the child as its parent refers to it: embedded when its encoding is shorter than
+/// 32 bytes, by hash otherwise.
+fn encode_reference<D: Digest>(child: &TrieNode, payload: &mut Vec<u8>) {
+    let encoding = encode::<D>(child);
+
+    if encoding.len() < 32 {
+        payload.extend_from_slice(&encoding);
+    } else {
+        encode_bytes(&D::digest(&encoding), payload);
+    }
+}
+
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    if bytes.len() == 1 && bytes[0] < 0x80 {
+        out.push(bytes[0]);
+    } else {
+        encode_length(bytes.len(), EMPTY_STRING, out);
+        out.extend_from_slice(bytes);
+    }
+}
+
+/// Appends the RLP prefix of a string or list, whose short form starts at `offset`.
+fn encode_length(len: usize, offset: u8, out: &mut Vec<u8>) {
+    if len < 56 {
+        out.push(offset + len as u8);
+    } else {
+        let len_bytes = (len as u64).to_be_bytes();
+        let skipped = len_bytes.iter().take_while(|&&byte| byte == 0).count();
+
+        out.push(offset + 55 + (8 - skipped) as u8);
+        out.extend_from_slice(&len_bytes[skipped..]);
+    }
+}
+
+/// Packs nibbles into bytes behind a flag nibble telling whether the path ends at a leaf and
+/// whether it has an odd number of nibbles.
+fn hex_prefix(path: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 2 } else { 0 } + (path.len() % 2) as u8;
+    let mut bytes = Vec::with_capacity(path.len() / 2 + 1);
+
+    let rest = if path.len() % 2 == 1 {
+        bytes.push(flag << 4 | path[0]);
+        &path[1..]
+    } else {
+        bytes.push(flag << 4);
+        path
+    };
+
+    bytes.extend(rest.chunks(2).map(|pair| pair[0] << 4 | pair[1]));
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_hex;
+
+    fn trie(entries: &[(&str, &str)]) -> MerklePatriciaTrie {
+        let mut trie = MerklePatriciaTrie::new();
+
+        for (key, value) in entries {
+            trie.insert(key.as_bytes(), value.as_bytes().to_vec());
+        }
+
+        trie
+    }
+
+    #[test]
+    fn it_hashes_the_empty_trie() {
+        assert_eq!(to_hex(&MerklePatriciaTrie::new().root_hash()), "56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421");
+    }
+
+    #[test]
+    fn it_matches_ethereum_roots() {
+        // From the `trieanyorder` tests of the Ethereum test suite.
+        let dogs = trie(&[("doe", "reindeer"), ("dog", "puppy"), ("dogglesworth", "cat")]);
+        let puppy = trie(&[("do", "verb"), ("horse", "stallion"), ("doge", "coin"), ("dog", "puppy")]);
+
+        assert_eq!(to_hex(&dogs.root_hash()), "8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3");
+        assert_eq!(to_hex(&puppy.root_hash()), "5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84");
+    }
+
+    #[test]
+    fn it_gets_inserted_values() {
+        let mut trie = trie(&[("do", "verb"), ("horse", "stallion"), ("doge", "coin"), ("dog", "puppy")]);
+
+        assert_eq!(trie.get(b"doge"), Some(&b"coin"[..]));
+        assert_eq!(trie.get(b"do"), Some(&b"verb"[..]));
+        assert_eq!(trie.get(b"d"), None);
+        assert_eq!(trie.get(b"dogs"), None);
+        assert_eq!(trie.len(), 4);
+
+        assert_eq!(trie.insert(b"dog", b"hound".to_vec()), Some(b"puppy".to_vec()));
+        assert_eq!(trie.get(b"dog"), Some(&b"hound"[..]));
+        assert_eq!(trie.len(), 4);
+    }
+
+    #[test]
+    fn it_removes_keys_like_they_were_never_inserted() {
+        let entries = [("do", "verb"), ("horse", "stallion"), ("doge", "coin"), ("dog", "puppy"), ("dogglesworth", "cat")];
+        let long_value = "a value long enough for its leaf to be referred to by hash";
+
+        let mut from_all = trie(&entries);
+        from_all.insert(b"doe", long_value.as_bytes().to_vec());
+
+        assert_eq!(from_all.remove(b"doe"), Some(long_value.as_bytes().to_vec()));
+        assert_eq!(from_all.remove(b"dog"), Some(b"puppy".to_vec()));
+        assert_eq!(from_all.remove(b"do"), Some(b"verb".to_vec()));
+        assert_eq!(from_all.remove(b"missing"), None);
+
+        let remaining = trie(&[("horse", "stallion"), ("doge", "coin"), ("dogglesworth", "cat")]);
+        assert_eq!(from_all, remaining);
+        assert_eq!(from_all.root_hash(), remaining.root_hash());
+        assert_eq!(from_all.len(), 3);
+
+        for (key, _) in &entries[1..] {
+            from_all.remove(key.as_bytes());
+        }
+
+        assert!(from_all.is_empty());
+        assert_eq!(from_all.root_hash(), MerklePatriciaTrie::new().root_hash());
+    }
+}
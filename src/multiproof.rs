@@ -0,0 +1,241 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use blake2::Blake2b;
+use digest::Digest;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::config::{LeafMode, Padding, Prefixes};
+use crate::error::MerkleError;
+use crate::{calculate_hash, check_leaf, hash_children, leaf_hash, Hash};
+
+/// Inclusion proof for several leaves at once.
+/// `hashes` only holds the sibling hashes that cannot be computed from the proven leaves,
+/// in the order they are consumed while hashing up level by level, left to right.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct MultiProof<D: Digest = Blake2b> {
+    /// Sorted, deduplicated indices of the proven leaves.
+    pub leaf_indices: Vec<usize>,
+    pub leaf_count: usize,
+    pub padding: Padding,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::list"))]
+    pub hashes: Vec<Hash<D>>,
+    /// Domain separation prefixes of the tree the proof was generated from.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub prefixes: Option<Prefixes>,
+    /// Whether the tree the proof was generated from hashes siblings in ascending order.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sort_pairs: bool,
+    /// How the tree the proof was generated from turns leaf data into leaf hashes.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub leaf_mode: LeafMode,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    digest: PhantomData<D>,
+}
+
+impl<D> MultiProof<D> where D: Digest {
+    pub fn new(leaf_indices: Vec<usize>, leaf_count: usize, padding: Padding, hashes: Vec<Hash<D>>) -> MultiProof<D> {
+        MultiProof {
+            leaf_indices,
+            leaf_count,
+            padding,
+            hashes,
+            prefixes: None,
+            sort_pairs: false,
+            leaf_mode: LeafMode::Hashed,
+            digest: PhantomData,
+        }
+    }
+
+    /// Checks that `leaves`, given in the order of `leaf_indices`, are all included in the tree
+    /// with the given root hash.
+    pub fn verify(&self, root_hash: &[u8], leaves: &[&[u8]]) -> Result<(), MerkleError> {
+        let well_formed = !self.leaf_indices.is_empty()
+            && leaves.len() == self.leaf_indices.len()
+            && self.leaf_indices.windows(2).all(|w| w[0] < w[1])
+            && self.leaf_indices[self.leaf_indices.len() - 1] < self.leaf_count;
+
+        if !well_formed {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        for (&index, leaf) in self.leaf_indices.iter().zip(leaves) {
+            check_leaf::<D>(self.leaf_mode, index, leaf)?;
+        }
+
+        let mut level: Vec<(usize, Hash<D>)> = self.leaf_indices.iter().cloned()
+            .zip(leaves.iter().map(|leaf| leaf_hash::<D>(self.prefixes, self.leaf_mode, leaf)))
+            .collect();
+        let mut hashes = self.hashes.iter();
+        let mut count = self.leaf_count;
+
+        while count > 1 {
+            let mut next = Vec::with_capacity(level.len());
+            let mut known = level.into_iter().peekable();
+
+            while let Some((index, hash)) = known.next() {
+                let sibling = index ^ 1;
+
+                let parent = if sibling >= count {
+                    match self.padding {
+                        Padding::Promote => hash,
+                        Padding::DuplicateLast => hash_children::<D>(self.prefixes, self.sort_pairs, &hash, &hash),
+                        Padding::EmptyHash => hash_children::<D>(self.prefixes, self.sort_pairs, &hash, &calculate_hash::<D>(&[])),
+                    }
+                } else {
+                    let sibling_hash = match known.peek() {
+                        Some((next_index, _)) if *next_index == sibling => known.next().unwrap().1,
+                        _ => hashes.next().ok_or(MerkleError::MalformedProof)?.clone(),
+                    };
+
+                    if index & 1 == 0 {
+                        hash_children::<D>(self.prefixes, self.sort_pairs, &hash, &sibling_hash)
+                    } else {
+                        hash_children::<D>(self.prefixes, self.sort_pairs, &sibling_hash, &hash)
+                    }
+                };
+
+                next.push((index / 2, parent));
+            }
+
+            level = next;
+            count = count.div_ceil(2);
+        }
+
+        if hashes.next().is_some() {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        if level[0].1.as_slice() == root_hash {
+            Ok(())
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for MultiProof<D> where D: Digest {
+    fn clone(&self) -> Self {
+        MultiProof {
+            leaf_indices: self.leaf_indices.clone(),
+            leaf_count: self.leaf_count,
+            padding: self.padding,
+            hashes: self.hashes.clone(),
+            prefixes: self.prefixes,
+            sort_pairs: self.sort_pairs,
+            leaf_mode: self.leaf_mode,
+            digest: PhantomData,
+        }
+    }
+}
+
+impl<D> PartialEq for MultiProof<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf_indices == other.leaf_indices
+            && self.leaf_count == other.leaf_count
+            && self.padding == other.padding
+            && self.hashes == other.hashes
+            && self.prefixes == other.prefixes
+            && self.sort_pairs == other.sort_pairs
+            && self.leaf_mode == other.leaf_mode
+    }
+}
+
+impl<D> fmt::Debug for MultiProof<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MultiProof")
+            .field("leaf_indices", &self.leaf_indices)
+            .field("leaf_count", &self.leaf_count)
+            .field("padding", &self.padding)
+            .field("hashes", &self.hashes)
+            .field("prefixes", &self.prefixes)
+            .field("sort_pairs", &self.sort_pairs)
+            .field("leaf_mode", &self.leaf_mode)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{MerkleError, MerkleTree, Padding, TreeConfig};
+
+    fn leaves(len: usize) -> Vec<String> {
+        (0..len).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn it_verifies_multi_proofs_for_leaf_subsets() {
+        for padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            for len in 1..=9 {
+                let data = leaves(len);
+                let merkle_tree = MerkleTree::from_vec_with_config(data.clone(), TreeConfig::new(*padding)).unwrap();
+
+                for subset in 1..(1usize << len) {
+                    let indices: Vec<usize> = (0..len).filter(|i| subset >> i & 1 == 1).collect();
+                    let proven: Vec<&[u8]> = indices.iter().map(|&i| data[i].as_bytes()).collect();
+                    let proof = merkle_tree.multi_proof(&indices).unwrap();
+
+                    assert!(proof.verify(merkle_tree.root_hash(), &proven).is_ok());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_shares_sibling_hashes() {
+        let merkle_tree = MerkleTree::from_vec(leaves(8)).unwrap();
+
+        assert_eq!(merkle_tree.multi_proof(&[0, 1]).unwrap().hashes.len(), 2);
+        assert_eq!(merkle_tree.multi_proof(&[0, 1, 2, 3]).unwrap().hashes.len(), 1);
+        assert_eq!(merkle_tree.multi_proof(&[0, 7]).unwrap().hashes.len(), 4);
+        assert!(merkle_tree.multi_proof(&(0..8).collect::<Vec<_>>()).unwrap().hashes.is_empty());
+    }
+
+    #[test]
+    fn it_sorts_and_deduplicates_indices() {
+        let merkle_tree = MerkleTree::from_vec(leaves(5)).unwrap();
+        let proof = merkle_tree.multi_proof(&[4, 1, 4]).unwrap();
+
+        assert_eq!(proof.leaf_indices, vec![1, 4]);
+        assert!(proof.verify(merkle_tree.root_hash(), &["1".as_bytes(), "4".as_bytes()]).is_ok());
+    }
+
+    #[test]
+    fn it_rejects_wrong_leaves() {
+        let merkle_tree = MerkleTree::from_vec(leaves(6)).unwrap();
+        let proof = merkle_tree.multi_proof(&[1, 4]).unwrap();
+
+        assert_eq!(proof.verify(merkle_tree.root_hash(), &["1".as_bytes(), "5".as_bytes()]), Err(MerkleError::RootMismatch));
+        assert_eq!(proof.verify(merkle_tree.root_hash(), &["1".as_bytes()]), Err(MerkleError::MalformedProof));
+    }
+
+    #[test]
+    fn it_rejects_malformed_multi_proofs() {
+        let merkle_tree = MerkleTree::from_vec(leaves(6)).unwrap();
+        let proven = ["1".as_bytes(), "4".as_bytes()];
+
+        let mut proof = merkle_tree.multi_proof(&[1, 4]).unwrap();
+        proof.hashes.pop();
+        assert_eq!(proof.verify(merkle_tree.root_hash(), &proven), Err(MerkleError::MalformedProof));
+
+        let mut proof = merkle_tree.multi_proof(&[1, 4]).unwrap();
+        proof.hashes.push(Default::default());
+        assert_eq!(proof.verify(merkle_tree.root_hash(), &proven), Err(MerkleError::MalformedProof));
+
+        let mut proof = merkle_tree.multi_proof(&[1, 4]).unwrap();
+        proof.leaf_indices = vec![4, 1];
+        assert_eq!(proof.verify(merkle_tree.root_hash(), &proven), Err(MerkleError::MalformedProof));
+    }
+
+    #[test]
+    fn it_fails_to_prove_invalid_indices() {
+        let merkle_tree = MerkleTree::from_vec(leaves(3)).unwrap();
+
+        assert_eq!(merkle_tree.multi_proof(&[]), Err(MerkleError::EmptyInput));
+        assert_eq!(merkle_tree.multi_proof(&[0, 3]), Err(MerkleError::IndexOutOfBounds { index: 3, leaf_count: 3 }));
+    }
+}
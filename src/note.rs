@@ -0,0 +1,427 @@
+//! Signed notes, the text format in which Go's checksum database, Trillian and the witnesses of
+//! transparency logs exchange signed tree heads.
+//!
+//! A note is some text, made of lines ending with a newline, followed by a blank line and one
+//! line per signature, `— <name> <base64 of the key hash and the signature>`. The key hash is the
+//! first four bytes of the SHA-256 hash of the name of the key, a newline, the algorithm byte
+//! (1 for Ed25519) and the public key, which identifies the key among several with the same name.
+//! Keys are written as `<name>+<key hash in hex>+<base64 of the algorithm byte and the key>`, and
+//! secret keys with a `PRIVATE+KEY+` prefix, as by `note.GenerateKey`.
+//!
+//! The text of a tree head is a [`NoteCheckpoint`]: the origin of the log, the tree size in
+//! decimal and the base64 encoded root hash, one per line, followed by optional extension lines.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::fmt;
+
+use digest::Digest;
+use sha2::Sha256;
+
+use crate::error::MerkleError;
+use crate::root_log::{Checkpoint, CheckpointSigner, CheckpointVerifier};
+use crate::signing::{Ed25519Signer, Ed25519Verifier};
+
+/// Algorithm byte of Ed25519 keys.
+const ALGORITHM_ED25519: u8 = 1;
+
+/// Start of every signature line.
+const SIGNATURE_PREFIX: &str = "\u{2014} ";
+
+/// Ed25519 key of a named signer of notes.
+#[derive(Clone, Debug)]
+pub struct NoteSigner {
+    name: String,
+    key_hash: u32,
+    signer: Ed25519Signer,
+}
+
+impl NoteSigner {
+    /// Signer named `name` with the Ed25519 secret key `secret_key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is empty or contains whitespace or `+`, which notes cannot hold.
+    pub fn new(name: &str, secret_key: &[u8; 32]) -> NoteSigner {
+        assert!(is_valid_name(name), "invalid key name {:?}", name);
+
+        let signer = Ed25519Signer::from_bytes(secret_key);
+        let key_hash = key_hash(name, &signer.verifier().to_bytes());
+
+        NoteSigner { name: name.to_string(), key_hash, signer }
+    }
+
+    /// Parses a secret key in the `PRIVATE+KEY+<name>+<hash>+<key>` format, failing with
+    /// `InvalidKey` if it is malformed or its key hash does not match.
+    pub fn from_skey(skey: &str) -> Result<NoteSigner, MerkleError> {
+        let skey = skey.strip_prefix("PRIVATE+KEY+").ok_or(MerkleError::InvalidKey)?;
+        let (name, key_hash, key) = split_key(skey)?;
+        let secret_key: [u8; 32] = key.as_slice().try_into().map_err(|_| MerkleError::InvalidKey)?;
+        let signer = NoteSigner::new(name, &secret_key);
+
+        if signer.key_hash != key_hash {
+            return Err(MerkleError::InvalidKey);
+        }
+
+        Ok(signer)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn key_hash(&self) -> u32 {
+        self.key_hash
+    }
+
+    /// Verifier for the signatures of this signer.
+    pub fn verifier(&self) -> NoteVerifier {
+        NoteVerifier { name: self.name.clone(), key_hash: self.key_hash, verifier: self.signer.verifier() }
+    }
+}
+
+/// Ed25519 public key of a named signer of notes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NoteVerifier {
+    name: String,
+    key_hash: u32,
+    verifier: Ed25519Verifier,
+}
+
+impl NoteVerifier {
+    /// Parses a public key in the `<name>+<hash>+<key>` format, failing with `InvalidKey` if
+    /// it is malformed or its key hash does not match.
+    pub fn from_vkey(vkey: &str) -> Result<NoteVerifier, MerkleError> {
+        let (name, key_hash, key) = split_key(vkey)?;
+        let public_key: [u8; 32] = key.as_slice().try_into().map_err(|_| MerkleError::InvalidKey)?;
+
+        if self::key_hash(name, &public_key) != key_hash {
+            return Err(MerkleError::InvalidKey);
+        }
+
+        Ok(NoteVerifier { name: name.to_string(), key_hash, verifier: Ed25519Verifier::from_bytes(&public_key)? })
+    }
+
+    /// The key in the `<name>+<hash>+<key>` format.
+    pub fn to_vkey(&self) -> String {
+        let mut key = vec![ALGORITHM_ED25519];
+        key.extend_from_slice(&self.verifier.to_bytes());
+        format!("{}+{:08x}+{}", self.name, self.key_hash, to_base64(&key))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn key_hash(&self) -> u32 {
+        self.key_hash
+    }
+}
+
+/// Signature line of a note.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NoteSignature {
+    pub name: String,
+    pub key_hash: u32,
+    pub signature: Vec<u8>,
+}
+
+/// Text along with the signatures of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Note {
+    /// Signed text, ending with a newline.
+    pub text: String,
+    pub signatures: Vec<NoteSignature>,
+}
+
+impl Note {
+    /// Signs `text` with every signer. Fails with `InvalidNote` unless the text ends with a
+    /// newline and has no blank line, which would be taken for the end of the text.
+    pub fn sign(text: &str, signers: &[&NoteSigner]) -> Result<Note, MerkleError> {
+        if !text.ends_with('\n') || text.contains("\n\n") || text.starts_with('\n') {
+            return Err(MerkleError::InvalidNote);
+        }
+
+        let signatures = signers.iter()
+            .map(|signer| NoteSignature {
+                name: signer.name.clone(),
+                key_hash: signer.key_hash,
+                signature: signer.signer.sign(text.as_bytes()),
+            })
+            .collect();
+
+        Ok(Note { text: text.to_string(), signatures })
+    }
+
+    /// Parses a signed note, failing with `InvalidNote` if it is malformed. Signatures are not
+    /// checked, which `verify` does.
+    pub fn parse(message: &str) -> Result<Note, MerkleError> {
+        let split = message.rfind("\n\n").ok_or(MerkleError::InvalidNote)?;
+        let (text, lines) = (&message[..=split], &message[split + 2..]);
+
+        if text.contains("\n\n") || !lines.ends_with('\n') {
+            return Err(MerkleError::InvalidNote);
+        }
+
+        let signatures = lines.lines()
+            .map(|line| {
+                let line = line.strip_prefix(SIGNATURE_PREFIX).ok_or(MerkleError::InvalidNote)?;
+                let (name, encoded) = line.split_once(' ').ok_or(MerkleError::InvalidNote)?;
+                let decoded = from_base64(encoded).ok_or(MerkleError::InvalidNote)?;
+
+                if !is_valid_name(name) || decoded.len() < 5 {
+                    return Err(MerkleError::InvalidNote);
+                }
+
+                Ok(NoteSignature {
+                    name: name.to_string(),
+                    key_hash: u32::from_be_bytes(decoded[..4].try_into().unwrap()),
+                    signature: decoded[4..].to_vec(),
+                })
+            })
+            .collect::<Result<Vec<NoteSignature>, MerkleError>>()?;
+
+        if signatures.is_empty() {
+            return Err(MerkleError::InvalidNote);
+        }
+
+        Ok(Note { text: text.to_string(), signatures })
+    }
+
+    /// Checks the signature of `verifier`, found by its name and key hash, failing with
+    /// `InvalidSignature` if there is none or it does not match the text.
+    pub fn verify(&self, verifier: &NoteVerifier) -> Result<(), MerkleError> {
+        let signature = self.signatures.iter()
+            .find(|signature| signature.name == verifier.name && signature.key_hash == verifier.key_hash)
+            .ok_or(MerkleError::InvalidSignature)?;
+
+        if verifier.verifier.verify(self.text.as_bytes(), &signature.signature) {
+            Ok(())
+        } else {
+            Err(MerkleError::InvalidSignature)
+        }
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.text)?;
+
+        for signature in &self.signatures {
+            let mut encoded = signature.key_hash.to_be_bytes().to_vec();
+            encoded.extend_from_slice(&signature.signature);
+            writeln!(f, "{}{} {}", SIGNATURE_PREFIX, signature.name, to_base64(&encoded))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Signed tree head of a transparency log, as the text of a note.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NoteCheckpoint {
+    /// Unique name of the log, such as `go.sum database tree` for Go's checksum database.
+    pub origin: String,
+    pub tree_size: u64,
+    pub root: Vec<u8>,
+    /// Lines after the root, without their newlines.
+    pub extensions: Vec<String>,
+}
+
+impl NoteCheckpoint {
+    /// Tree head of `checkpoint` for the log named `origin`. The sequence number and timestamp
+    /// of the checkpoint are left out, as the format has no place for them.
+    pub fn from_checkpoint<D: Digest>(origin: &str, checkpoint: &Checkpoint<D>) -> NoteCheckpoint {
+        NoteCheckpoint {
+            origin: origin.to_string(),
+            tree_size: checkpoint.tree_size,
+            root: checkpoint.root.to_vec(),
+            extensions: Vec::new(),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n{}\n{}\n", self.origin, self.tree_size, to_base64(&self.root));
+
+        for extension in &self.extensions {
+            text.push_str(extension);
+            text.push('\n');
+        }
+
+        text
+    }
+
+    /// Parses the text of a tree head, failing with `InvalidNote` if it is malformed.
+    pub fn from_text(text: &str) -> Result<NoteCheckpoint, MerkleError> {
+        let body = text.strip_suffix('\n').ok_or(MerkleError::InvalidNote)?;
+        let mut lines = body.split('\n');
+
+        let origin = lines.next().filter(|origin| !origin.is_empty()).ok_or(MerkleError::InvalidNote)?;
+        let tree_size = lines.next()
+            .filter(|size| size.bytes().all(|b| b.is_ascii_digit()) && (size == &"0" || !size.starts_with('0')))
+            .and_then(|size| size.parse().ok())
+            .ok_or(MerkleError::InvalidNote)?;
+        let root = lines.next().and_then(from_base64).ok_or(MerkleError::InvalidNote)?;
+        let extensions: Vec<String> = lines.map(ToString::to_string).collect();
+
+        if extensions.iter().any(String::is_empty) {
+            return Err(MerkleError::InvalidNote);
+        }
+
+        Ok(NoteCheckpoint { origin: origin.to_string(), tree_size, root, extensions })
+    }
+
+    /// Signs the tree head with every signer.
+    pub fn sign(&self, signers: &[&NoteSigner]) -> Note {
+        Note::sign(&self.to_text(), signers).expect("tree heads end with a newline and have no blank line")
+    }
+}
+
+/// Splits a key into its name, key hash and key, checking the algorithm byte.
+fn split_key(key: &str) -> Result<(&str, u32, Vec<u8>), MerkleError> {
+    let mut parts = key.splitn(3, '+');
+    let (name, hash, key) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(name), Some(hash), Some(key)) => (name, hash, key),
+        _ => return Err(MerkleError::InvalidKey),
+    };
+
+    let key_hash = u32::from_str_radix(hash, 16).ok().filter(|_| hash.len() == 8).ok_or(MerkleError::InvalidKey)?;
+
+    match from_base64(key) {
+        Some(ref key) if key.first() == Some(&ALGORITHM_ED25519) && is_valid_name(name) => Ok((name, key_hash, key[1..].to_vec())),
+        _ => Err(MerkleError::InvalidKey),
+    }
+}
+
+fn key_hash(name: &str, public_key: &[u8; 32]) -> u32 {
+    let mut hasher = Sha256::new();
+    hasher.input(name);
+    hasher.input([b'\n', ALGORITHM_ED25519]);
+    hasher.input(public_key);
+    u32::from_be_bytes(hasher.result()[..4].try_into().unwrap())
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || c == '+')
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 encoding, with padding.
+fn to_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for group in bytes.chunks(3) {
+        let value = group.iter().enumerate().fold(0u32, |value, (i, byte)| value | (*byte as u32) << (16 - 8 * i));
+
+        for i in 0..4 {
+            if i <= group.len() {
+                encoded.push(BASE64_ALPHABET[(value >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Decodes standard base64 with padding, rejecting any other encoding of the bytes.
+fn from_base64(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.as_bytes();
+
+    if !encoded.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(encoded.len() / 4 * 3);
+
+    for (index, group) in encoded.chunks(4).enumerate() {
+        let last = index == encoded.len() / 4 - 1;
+        let padding = group.iter().rev().take_while(|c| **c == b'=').count();
+
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+
+        let mut value = 0u32;
+
+        for (i, c) in group[..4 - padding].iter().enumerate() {
+            let digit = BASE64_ALPHABET.iter().position(|a| a == c)? as u32;
+            value |= digit << (18 - 6 * i);
+        }
+
+        let decoded = value.to_be_bytes();
+        let len = 3 - padding;
+
+        // Bits past the last byte must be zero, for the encoding to be unique.
+        if decoded[1 + len..].iter().any(|b| *b != 0) {
+            return None;
+        }
+
+        bytes.extend_from_slice(&decoded[1..1 + len]);
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MerkleTree, RootLog};
+
+    const SKEY: &str = "PRIVATE+KEY+PeterNeumann+c74f20a3+AYEKFALVFGyNhPJEMzD1QIDr+Y7hfZx09iUvxdXHKDFz";
+    const VKEY: &str = "PeterNeumann+c74f20a3+ARpc2QcUPDhMQegwxbzhKqiBfsVkmqq/LDE4izWy10TW";
+    const TEXT: &str = "If you think cryptography is the answer to your problem,\nthen you don't know what your problem is.\n";
+
+    #[test]
+    fn it_signs_notes_like_go() {
+        let signer = NoteSigner::from_skey(SKEY).unwrap();
+        assert_eq!(signer.verifier().to_vkey(), VKEY);
+
+        let note = Note::sign(TEXT, &[&signer]).unwrap();
+        let message = "If you think cryptography is the answer to your problem,\nthen you don't know what your problem is.\n\n\u{2014} PeterNeumann x08go/ZJkuBS9UG/SffcvIAQxVBtiFupLLr8pAcElZInNIuGUgYN1FFYC2pZSNXgKvqfqdngotpRZb6KE6RyyBwJnAM=\n";
+        assert_eq!(note.to_string(), message);
+
+        let parsed = Note::parse(message).unwrap();
+        assert_eq!(parsed, note);
+        assert_eq!(parsed.verify(&NoteVerifier::from_vkey(VKEY).unwrap()), Ok(()));
+        assert_eq!(parsed.verify(&NoteSigner::new("PeterNeumann", &[9; 32]).verifier()), Err(MerkleError::InvalidSignature));
+
+        let forged = Note { text: TEXT.replace("problem", "solution"), ..parsed };
+        assert_eq!(forged.verify(&NoteVerifier::from_vkey(VKEY).unwrap()), Err(MerkleError::InvalidSignature));
+    }
+
+    #[test]
+    fn it_rejects_malformed_notes_and_keys() {
+        assert_eq!(Note::parse(TEXT), Err(MerkleError::InvalidNote));
+        assert_eq!(Note::parse(&format!("{}\n- PeterNeumann AAAA\n", TEXT)), Err(MerkleError::InvalidNote));
+        assert_eq!(Note::sign("no newline", &[]), Err(MerkleError::InvalidNote));
+        assert_eq!(NoteVerifier::from_vkey(&VKEY.replace("c74f20a3", "c74f20a4")), Err(MerkleError::InvalidKey));
+        assert_eq!(NoteVerifier::from_vkey("PeterNeumann+c74f20a3"), Err(MerkleError::InvalidKey));
+        assert_eq!(from_base64("AB=="), None);
+        assert_eq!(from_base64("AA=="), Some(vec![0]));
+    }
+
+    #[test]
+    fn it_exchanges_tree_heads_as_notes() {
+        let log = RootLog::with_clock(MerkleTree::from_vec_rfc6962(vec!["a", "b", "c"]).unwrap(), || 0);
+        let signer = NoteSigner::new("example.com/log", &[5; 32]);
+        let checkpoint = NoteCheckpoint::from_checkpoint("example.com/log", log.latest());
+
+        let message = checkpoint.sign(&[&signer]).to_string();
+        assert!(message.starts_with(&format!("example.com/log\n3\n{}\n\n\u{2014} example.com/log ", to_base64(log.tree().root_hash()))));
+
+        let note = Note::parse(&message).unwrap();
+        assert_eq!(note.verify(&signer.verifier()), Ok(()));
+
+        let parsed = NoteCheckpoint::from_text(&note.text).unwrap();
+        assert_eq!(parsed, checkpoint);
+        assert_eq!(parsed.root, log.tree().root_hash());
+
+        assert_eq!(NoteCheckpoint::from_text("origin\n03\nAA==\n"), Err(MerkleError::InvalidNote));
+        assert_eq!(NoteCheckpoint::from_text("origin\n3\nAA==\nextension\n").unwrap().extensions, vec!["extension".to_string()]);
+    }
+}
@@ -0,0 +1,324 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use blake2::Blake2b;
+use digest::Digest;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{calculate_hash, hash_children, Hash};
+use crate::config::{Padding, Prefixes};
+use crate::error::MerkleError;
+use crate::tree::{depth_for, MerkleTree};
+
+/// Index and hash of every proven leaf.
+type Matches<D> = Vec<(usize, Hash<D>)>;
+
+/// Subset of the nodes of a tree that is enough to compute its root and to prove a chosen set
+/// of leaves, encoded as Bitcoin encodes the transactions of a merkle block.
+///
+/// The tree is walked depth first from the root. For every node visited, `flags` tells whether
+/// it is an ancestor of a proven leaf, or the proven leaf itself. The children of such nodes
+/// are visited next, while the hash of every other node visited is taken from `hashes`, in
+/// order, and their subtrees are skipped.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct PartialMerkleTree<D: Digest = Blake2b> {
+    pub leaf_count: usize,
+    pub padding: Padding,
+    pub flags: Vec<bool>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::list"))]
+    pub hashes: Vec<Hash<D>>,
+    /// Domain separation prefixes of the tree the partial tree was taken from.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub prefixes: Option<Prefixes>,
+    /// Whether the tree the partial tree was taken from hashes siblings in ascending order.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sort_pairs: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    digest: PhantomData<D>,
+}
+
+impl<D> PartialMerkleTree<D> where D: Digest {
+    pub fn new(leaf_count: usize, padding: Padding, flags: Vec<bool>, hashes: Vec<Hash<D>>) -> PartialMerkleTree<D> {
+        PartialMerkleTree {
+            leaf_count,
+            padding,
+            flags,
+            hashes,
+            prefixes: None,
+            sort_pairs: false,
+            digest: PhantomData,
+        }
+    }
+
+    /// Recomputes the root of the tree and returns it along with the index and hash of every
+    /// proven leaf, in ascending order of index. Fails with `MalformedProof` unless every flag
+    /// and every hash is used exactly once.
+    pub fn extract_matches(&self) -> Result<(Hash<D>, Matches<D>), MerkleError> {
+        if self.leaf_count == 0 || self.hashes.is_empty() || self.hashes.len() > self.leaf_count || self.flags.len() < self.hashes.len() {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let mut walk = Walk { flags: self.flags.iter(), hashes: self.hashes.iter(), matches: Vec::new() };
+        let root = self.extract(&mut walk, depth_for(self.leaf_count), 0)?;
+
+        if walk.flags.next().is_some() || walk.hashes.next().is_some() {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        Ok((root, walk.matches))
+    }
+
+    /// Checks that the partial tree belongs to the tree with the given root hash and returns
+    /// the index and hash of every proven leaf.
+    pub fn verify(&self, root_hash: &[u8]) -> Result<Matches<D>, MerkleError> {
+        let (root, matches) = self.extract_matches()?;
+
+        if root.as_slice() == root_hash {
+            Ok(matches)
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+
+    fn extract(&self, walk: &mut Walk<D>, height: usize, index: usize) -> Result<Hash<D>, MerkleError> {
+        let flag = *walk.flags.next().ok_or(MerkleError::MalformedProof)?;
+
+        if height == 0 || !flag {
+            let hash = walk.hashes.next().ok_or(MerkleError::MalformedProof)?.clone();
+
+            if height == 0 && flag {
+                walk.matches.push((index, hash.clone()));
+            }
+
+            return Ok(hash);
+        }
+
+        let left = self.extract(walk, height - 1, 2 * index)?;
+
+        if 2 * index + 1 < level_count(self.leaf_count, height - 1) {
+            let right = self.extract(walk, height - 1, 2 * index + 1)?;
+
+            // Two equal siblings would let a partial tree for `n` leaves pass as one for the
+            // tree with the last leaves duplicated (CVE-2012-2459).
+            if self.padding == Padding::DuplicateLast && left == right {
+                return Err(MerkleError::MalformedProof);
+            }
+
+            return Ok(hash_children::<D>(self.prefixes, self.sort_pairs, &left, &right));
+        }
+
+        Ok(match self.padding {
+            Padding::Promote => left,
+            Padding::DuplicateLast => hash_children::<D>(self.prefixes, self.sort_pairs, &left, &left),
+            Padding::EmptyHash => hash_children::<D>(self.prefixes, self.sort_pairs, &left, &calculate_hash::<D>(&[])),
+        })
+    }
+}
+
+/// Position in the flags and hashes of a partial tree being walked.
+struct Walk<'a, D: Digest> {
+    flags: core::slice::Iter<'a, bool>,
+    hashes: core::slice::Iter<'a, Hash<D>>,
+    matches: Matches<D>,
+}
+
+impl<T, D> MerkleTree<T, D> where D: Digest {
+    /// Builds the partial tree proving the leaves at `indices`, which may be empty.
+    ///
+    /// Under `Padding::DuplicateLast`, fails with `EqualSiblings` if an ancestor of a proven leaf
+    /// has two children with the same hash, as `verify` rejects such partial trees. This can
+    /// only happen in trees holding duplicate leaves, which `DuplicatePolicy::Reject` rules out.
+    pub fn partial_tree(&self, indices: &[usize]) -> Result<PartialMerkleTree<D>, MerkleError> {
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+
+        if let Some(&index) = indices.last().filter(|&&index| index >= self.leaf_count()) {
+            return Err(MerkleError::IndexOutOfBounds { index, leaf_count: self.leaf_count() });
+        }
+
+        let mut partial = PartialMerkleTree::new(self.leaf_count(), self.config().padding, Vec::new(), Vec::new());
+        partial.prefixes = self.config().prefixes;
+        partial.sort_pairs = self.config().sort_pairs;
+
+        self.build_partial(&mut partial, &indices, depth_for(self.leaf_count()), 0)?;
+
+        Ok(partial)
+    }
+
+    fn build_partial(&self, partial: &mut PartialMerkleTree<D>, indices: &[usize], height: usize, index: usize) -> Result<(), MerkleError> {
+        // Proven leaves under this node, which are contiguous as `indices` is sorted.
+        let start = indices.partition_point(|&i| i < index << height);
+        let end = indices.partition_point(|&i| i < (index + 1) << height);
+        let is_parent_of_match = start < end;
+
+        partial.flags.push(is_parent_of_match);
+
+        if height == 0 || !is_parent_of_match {
+            partial.hashes.push(self.node_at(height, index).hash().clone());
+        } else {
+            let has_right = 2 * index + 1 < level_count(self.leaf_count(), height - 1);
+
+            if has_right && self.config().padding == Padding::DuplicateLast
+                && self.node_at(height - 1, 2 * index).hash() == self.node_at(height - 1, 2 * index + 1).hash() {
+                return Err(MerkleError::EqualSiblings { height: height - 1, index: 2 * index });
+            }
+
+            self.build_partial(partial, &indices[start..end], height - 1, 2 * index)?;
+
+            if has_right {
+                self.build_partial(partial, &indices[start..end], height - 1, 2 * index + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Number of nodes on level `height` of a tree with `leaf_count` leaves.
+fn level_count(leaf_count: usize, height: usize) -> usize {
+    (leaf_count - 1) / (1 << height) + 1
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for PartialMerkleTree<D> where D: Digest {
+    fn clone(&self) -> Self {
+        PartialMerkleTree {
+            leaf_count: self.leaf_count,
+            padding: self.padding,
+            flags: self.flags.clone(),
+            hashes: self.hashes.clone(),
+            prefixes: self.prefixes,
+            sort_pairs: self.sort_pairs,
+            digest: PhantomData,
+        }
+    }
+}
+
+impl<D> PartialEq for PartialMerkleTree<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf_count == other.leaf_count
+            && self.padding == other.padding
+            && self.flags == other.flags
+            && self.hashes == other.hashes
+            && self.prefixes == other.prefixes
+            && self.sort_pairs == other.sort_pairs
+    }
+}
+
+impl<D> fmt::Debug for PartialMerkleTree<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PartialMerkleTree")
+            .field("leaf_count", &self.leaf_count)
+            .field("padding", &self.padding)
+            .field("flags", &self.flags)
+            .field("hashes", &self.hashes)
+            .field("prefixes", &self.prefixes)
+            .field("sort_pairs", &self.sort_pairs)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hash_leaf, BitcoinHash, TreeConfig};
+
+    fn data(len: usize) -> Vec<String> {
+        (0..len).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn it_extracts_proven_leaves() {
+        for padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            for len in 1..12 {
+                let merkle_tree = MerkleTree::from_vec_with_config(data(len), TreeConfig::new(*padding)).unwrap();
+                let indices: Vec<usize> = (0..len).filter(|i| i % 3 == 1).collect();
+                let partial = merkle_tree.partial_tree(&indices).unwrap();
+
+                let matches = partial.verify(merkle_tree.root_hash()).unwrap();
+                let expected: Vec<_> = indices.iter().map(|&i| (i, hash_leaf::<Blake2b>(None, data(len)[i].as_bytes()))).collect();
+
+                assert_eq!(matches, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn it_matches_bitcoin_merkle_blocks() {
+        let txids: Vec<BitcoinHash> = [
+            "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+            "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+            "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+        ].iter().map(|hex| BitcoinHash::from_hex(hex).unwrap()).collect();
+        let merkle_tree = MerkleTree::from_txids(txids.clone()).unwrap();
+        let partial = merkle_tree.partial_tree(&[2]).unwrap();
+
+        // Root, left subtree, then the right subtree down to the matched transaction.
+        assert_eq!(partial.flags, vec![true, false, true, true]);
+        assert_eq!(partial.hashes.len(), 2);
+        assert_eq!(partial.verify(merkle_tree.root_hash()).unwrap()[0].1.as_slice(), &txids[2].0[..]);
+    }
+
+    #[test]
+    fn it_proves_no_leaves_with_the_root_alone() {
+        let merkle_tree = MerkleTree::from_vec(data(5)).unwrap();
+        let partial = merkle_tree.partial_tree(&[]).unwrap();
+
+        assert_eq!(partial.flags, vec![false]);
+        assert_eq!(partial.verify(merkle_tree.root_hash()), Ok(vec![]));
+    }
+
+    #[test]
+    fn it_rejects_malformed_partial_trees() {
+        let merkle_tree = MerkleTree::from_vec(data(6)).unwrap();
+        let partial = merkle_tree.partial_tree(&[1, 4]).unwrap();
+
+        let mut extra_hash = partial.clone();
+        extra_hash.hashes.push(extra_hash.hashes[0]);
+        assert_eq!(extra_hash.verify(merkle_tree.root_hash()), Err(MerkleError::MalformedProof));
+
+        let mut extra_flag = partial.clone();
+        extra_flag.flags.push(false);
+        assert_eq!(extra_flag.verify(merkle_tree.root_hash()), Err(MerkleError::MalformedProof));
+
+        let mut truncated = partial.clone();
+        truncated.flags.truncate(3);
+        assert_eq!(truncated.verify(merkle_tree.root_hash()), Err(MerkleError::MalformedProof));
+
+        assert_eq!(merkle_tree.partial_tree(&[6]), Err(MerkleError::IndexOutOfBounds { index: 6, leaf_count: 6 }));
+    }
+
+    #[test]
+    fn it_rejects_duplicated_siblings() {
+        // A tree of 3 leaves has the same root as the tree with its last leaf duplicated.
+        let short = MerkleTree::from_vec(vec!["a", "b", "c"]).unwrap();
+        let long = MerkleTree::from_vec(vec!["a", "b", "c", "c"]).unwrap();
+        assert_eq!(short.root_hash(), long.root_hash());
+
+        assert_eq!(long.partial_tree(&[3]).err(), Some(MerkleError::EqualSiblings { height: 0, index: 2 }));
+
+        // The same partial tree built by hand still fails to verify.
+        let (n01, c) = (*long.node_at(1, 0).hash(), *long.node_at(0, 3).hash());
+        let partial = PartialMerkleTree::<Blake2b>::new(4, Padding::DuplicateLast, vec![true, false, true, false, true], vec![n01, c, c]);
+        assert_eq!(partial.verify(short.root_hash()), Err(MerkleError::MalformedProof));
+    }
+
+    #[test]
+    fn it_refuses_to_build_partial_trees_over_equal_siblings() {
+        let tree = MerkleTree::from_vec(vec!["a", "a"]).unwrap();
+        assert_eq!(tree.partial_tree(&[0]).err(), Some(MerkleError::EqualSiblings { height: 0, index: 0 }));
+
+        // Equal siblings outside the proven paths are kept in a single hash.
+        let tree = MerkleTree::from_vec(vec!["a", "a", "b", "c"]).unwrap();
+        let partial = tree.partial_tree(&[2]).unwrap();
+        assert_eq!(partial.verify(tree.root_hash()).unwrap().len(), 1);
+
+        // Trees that do not pad by duplication have no such ambiguity.
+        let tree = MerkleTree::from_vec_with_config(vec!["a", "a"], TreeConfig::new(Padding::Promote)).unwrap();
+        assert!(tree.partial_tree(&[0]).unwrap().verify(tree.root_hash()).is_ok());
+    }
+}
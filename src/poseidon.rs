@@ -0,0 +1,309 @@
+//! Merkle trees hashed with Poseidon over the scalar field of BN254, with the parameters of
+//! circomlib, so that inclusion proofs are cheap to check inside zk-SNARK circuits.
+//!
+//! Leaves, siblings and roots are field elements rather than bytes, as circuits take them. A node
+//! is `Poseidon(left, right)` and trees are padded with zero leaves up to a fixed depth, the
+//! layout of the Merkle gadgets of circomlib-based circuits such as Semaphore.
+
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use ark_ff::{BigInteger, PrimeField, Zero};
+use digest::generic_array::GenericArray;
+use digest::generic_array::typenum::U32;
+use light_poseidon::{Poseidon, PoseidonHasher};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::error::MerkleError;
+use crate::tree::NodeCombiner;
+
+/// Element of the scalar field of BN254, which leaves and hashes of Poseidon trees are.
+pub type FieldElement = ark_bn254::Fr;
+
+/// Poseidon hash of two field elements, as `Poseidon(2)` in circomlib.
+pub fn poseidon_hash(left: &FieldElement, right: &FieldElement) -> FieldElement {
+    PoseidonPair::new().hash(left, right)
+}
+
+/// Combines nodes with Poseidon for trees built by hand with `MerkleNode::new`. Child hashes are
+/// read as big-endian integers reduced modulo the field, and the hash of the node is the
+/// big-endian encoding of `poseidon_hash` of them, so leaves may be hashed with any 32-byte digest.
+/// The Poseidon parameters are derived on every call, so `PoseidonMerkleTree` is much faster.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PoseidonCombiner;
+
+impl NodeCombiner<U32> for PoseidonCombiner {
+    fn combine(&self, left: &[u8], right: &[u8]) -> GenericArray<u8, U32> {
+        let left = FieldElement::from_be_bytes_mod_order(left);
+        let right = FieldElement::from_be_bytes_mod_order(right);
+
+        GenericArray::clone_from_slice(&poseidon_hash(&left, &right).into_bigint().to_bytes_be())
+    }
+}
+
+/// Binary Merkle tree of field elements hashed with Poseidon, padded with zero leaves.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoseidonMerkleTree {
+    /// Nodes of every level, from the leaves up to the root, leaving out the zero subtrees at the
+    /// end of each level.
+    levels: Vec<Vec<FieldElement>>,
+    /// Roots of the subtrees of zero leaves of every height up to the depth.
+    zeros: Vec<FieldElement>,
+    leaf_count: usize,
+}
+
+impl PoseidonMerkleTree {
+    /// Creates a tree of the smallest depth holding the leaves.
+    pub fn new(leaves: Vec<FieldElement>) -> Result<PoseidonMerkleTree, MerkleError> {
+        let depth = leaves.len().next_power_of_two().trailing_zeros() as usize;
+        PoseidonMerkleTree::with_depth(leaves, depth)
+    }
+
+    /// Creates a tree of `2^depth` leaves, padded with zeros after the given ones, as a circuit
+    /// with a fixed number of levels expects. Fails with `TreeFull` if there are more leaves.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the depth is 64 or more.
+    pub fn with_depth(leaves: Vec<FieldElement>, depth: usize) -> Result<PoseidonMerkleTree, MerkleError> {
+        assert!(depth < 64, "depth {} is too large", depth);
+
+        if leaves.is_empty() {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        if leaves.len() as u64 > 1 << depth {
+            return Err(MerkleError::TreeFull { capacity: 1 << depth });
+        }
+
+        let mut hasher = PoseidonPair::new();
+        let mut zeros = vec![FieldElement::zero()];
+        let leaf_count = leaves.len();
+        let mut levels = vec![leaves];
+
+        for height in 0..depth {
+            let zero = zeros[height];
+            let level = levels[height]
+                .chunks(2)
+                .map(|pair| hasher.hash(&pair[0], pair.get(1).unwrap_or(&zero)))
+                .collect();
+
+            zeros.push(hasher.hash(&zero, &zero));
+            levels.push(level);
+        }
+
+        Ok(PoseidonMerkleTree { levels, zeros, leaf_count })
+    }
+
+    pub fn root(&self) -> FieldElement {
+        self.levels[self.depth()][0]
+    }
+
+    pub fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    /// Generates an inclusion proof for the leaf at `index`, which may be one of the zero leaves
+    /// padding the tree.
+    pub fn proof(&self, index: usize) -> Result<PoseidonProof, MerkleError> {
+        let capacity = 1 << self.depth();
+
+        if index >= capacity {
+            return Err(MerkleError::IndexOutOfBounds { index, leaf_count: capacity });
+        }
+
+        let siblings = self.levels[..self.depth()].iter().enumerate()
+            .map(|(height, level)| level.get((index >> height) ^ 1).copied().unwrap_or(self.zeros[height]))
+            .collect();
+
+        Ok(PoseidonProof { index, siblings })
+    }
+}
+
+/// Inclusion proof for a leaf of a PoseidonMerkleTree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoseidonProof {
+    pub index: usize,
+    /// Sibling nodes from the leaf up to the root.
+    pub siblings: Vec<FieldElement>,
+}
+
+impl PoseidonProof {
+    /// Side of the node on the path at every height, from the leaf up, with `true` when it is
+    /// a right child, the path indices of circuits.
+    pub fn path_bits(&self) -> Vec<bool> {
+        (0..self.siblings.len()).map(|height| (self.index >> height) & 1 == 1).collect()
+    }
+
+    /// Root of the tree of the given depth the proof was generated from if `leaf` is the leaf
+    /// it proves. Fails with `MalformedProof` unless the proof has one sibling per level and its
+    /// index fits in the tree, as a shorter proof would prove an inner node as a leaf.
+    pub fn compute_root(&self, depth: usize, leaf: &FieldElement) -> Result<FieldElement, MerkleError> {
+        if self.siblings.len() != depth || self.index.checked_shr(depth as u32).unwrap_or(0) != 0 {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        Ok(self.fold_siblings(leaf))
+    }
+
+    /// Inputs of a Merkle inclusion circuit proving that `leaf` is in the tree this proof was
+    /// generated from.
+    pub fn witness(&self, leaf: &FieldElement) -> MerkleWitness {
+        MerkleWitness {
+            leaf: to_decimal(leaf),
+            root: to_decimal(&self.fold_siblings(leaf)),
+            path_elements: self.siblings.iter().map(to_decimal).collect(),
+            path_indices: self.path_bits().into_iter().map(u8::from).collect(),
+        }
+    }
+
+    /// Checks that `leaf` is included in the tree of the given depth with the given root.
+    pub fn verify(&self, root: &FieldElement, depth: usize, leaf: &FieldElement) -> Result<(), MerkleError> {
+        if &self.compute_root(depth, leaf)? == root {
+            Ok(())
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+
+    fn fold_siblings(&self, leaf: &FieldElement) -> FieldElement {
+        let mut hasher = PoseidonPair::new();
+
+        self.siblings.iter().zip(self.path_bits()).fold(*leaf, |node, (sibling, is_right)| {
+            if is_right {
+                hasher.hash(sibling, &node)
+            } else {
+                hasher.hash(&node, sibling)
+            }
+        })
+    }
+}
+
+/// Inputs of a Merkle inclusion circuit, laid out as the `input.json` of the inclusion templates
+/// of circom circuits such as Tornado Cash and Semaphore, which halo2 Merkle gadgets also take
+/// as a path of elements and position bits. Field elements are decimal strings, and path indices
+/// are 1 where the node on the path is a right child, from the leaf up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct MerkleWitness {
+    pub leaf: String,
+    pub root: String,
+    pub path_elements: Vec<String>,
+    pub path_indices: Vec<u8>,
+}
+
+/// Decimal representation of a field element, which the `Display` of arkworks leaves empty for
+/// zero.
+fn to_decimal(element: &FieldElement) -> String {
+    element.into_bigint().to_string()
+}
+
+/// Poseidon with two inputs, whose parameters are only derived once.
+struct PoseidonPair(Poseidon<FieldElement>);
+
+impl PoseidonPair {
+    fn new() -> PoseidonPair {
+        PoseidonPair(Poseidon::<FieldElement>::new_circom(2).expect("circomlib defines Poseidon with two inputs"))
+    }
+
+    fn hash(&mut self, left: &FieldElement, right: &FieldElement) -> FieldElement {
+        self.0.hash(&[*left, *right]).expect("two inputs are hashed with two inputs")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calculate_hash, MerkleLeaf, MerkleNode, Node};
+
+    fn leaves(len: u64) -> Vec<FieldElement> {
+        (1..=len).map(FieldElement::from).collect()
+    }
+
+    #[test]
+    fn it_matches_circomlib() {
+        let hash = poseidon_hash(&FieldElement::from(1u64), &FieldElement::from(2u64));
+        assert_eq!(hash.to_string(), "7853200120776062878684798364095072458815029376092732009249414926327459813530");
+
+        let tree = PoseidonMerkleTree::new(leaves(2)).unwrap();
+        assert_eq!(tree.root(), hash);
+        assert_eq!(tree.depth(), 1);
+    }
+
+    #[test]
+    fn it_proves_leaves_and_padding() {
+        let tree = PoseidonMerkleTree::with_depth(leaves(5), 4).unwrap();
+        let zero = FieldElement::zero();
+
+        for (index, leaf) in leaves(5).iter().chain(&[zero, zero]).enumerate() {
+            let proof = tree.proof(index).unwrap();
+
+            assert_eq!(proof.siblings.len(), 4);
+            assert_eq!(proof.verify(&tree.root(), 4, leaf), Ok(()));
+            assert_eq!(proof.verify(&tree.root(), 4, &FieldElement::from(9u64)), Err(MerkleError::RootMismatch));
+        }
+
+        assert_eq!(tree.proof(5).unwrap().path_bits(), vec![true, false, true, false]);
+        assert_eq!(tree.proof(16), Err(MerkleError::IndexOutOfBounds { index: 16, leaf_count: 16 }));
+        assert_eq!(PoseidonMerkleTree::with_depth(leaves(5), 2), Err(MerkleError::TreeFull { capacity: 4 }));
+    }
+
+    #[test]
+    fn it_rejects_proofs_of_the_wrong_depth() {
+        let tree = PoseidonMerkleTree::with_depth(leaves(4), 2).unwrap();
+        let proof = tree.proof(3).unwrap();
+
+        // Without its first sibling, the proof passes for that of the parent of the leaf.
+        let parent = poseidon_hash(&FieldElement::from(3u64), &FieldElement::from(4u64));
+        let truncated = PoseidonProof { index: 1, siblings: proof.siblings[1..].to_vec() };
+        assert_eq!(truncated.compute_root(1, &parent), Ok(tree.root()));
+        assert_eq!(truncated.verify(&tree.root(), 2, &parent), Err(MerkleError::MalformedProof));
+
+        let moved = PoseidonProof { index: 7, ..proof.clone() };
+        assert_eq!(moved.verify(&tree.root(), 2, &FieldElement::from(4u64)), Err(MerkleError::MalformedProof));
+        assert_eq!(proof.verify(&tree.root(), 2, &FieldElement::from(4u64)), Ok(()));
+    }
+
+    #[test]
+    fn it_exports_circuit_witnesses() {
+        let tree = PoseidonMerkleTree::with_depth(leaves(3), 2).unwrap();
+        let proof = tree.proof(2).unwrap();
+        let witness = proof.witness(&FieldElement::from(3u64));
+
+        assert_eq!(witness.leaf, "3");
+        assert_eq!(witness.root, tree.root().to_string());
+        assert_eq!(witness.path_elements, vec!["0".to_string(), proof.siblings[1].to_string()]);
+        assert_eq!(witness.path_indices, vec![0, 1]);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn it_serializes_witnesses_as_circom_inputs() {
+        let tree = PoseidonMerkleTree::new(leaves(2)).unwrap();
+        let witness = tree.proof(1).unwrap().witness(&FieldElement::from(2u64));
+        let json = serde_json::to_value(&witness).unwrap();
+
+        assert_eq!(json["leaf"], "2");
+        assert_eq!(json["root"], "7853200120776062878684798364095072458815029376092732009249414926327459813530");
+        assert_eq!(json["pathElements"], serde_json::json!(["1"]));
+        assert_eq!(json["pathIndices"], serde_json::json!([1]));
+    }
+
+    #[test]
+    fn it_combines_nodes_built_by_hand() {
+        let a = Node::Leaf(MerkleLeaf::new(&calculate_hash::<sha2::Sha256>, "a"));
+        let b = Node::Leaf(MerkleLeaf::new(&calculate_hash::<sha2::Sha256>, "b"));
+        let node = MerkleNode::new(&PoseidonCombiner, a.clone(), b.clone());
+
+        let field = |node: &Node<&str, U32>| FieldElement::from_be_bytes_mod_order(node.hash());
+        let expected = poseidon_hash(&field(&a), &field(&b));
+        assert_eq!(FieldElement::from_be_bytes_mod_order(&node.hash), expected);
+    }
+}
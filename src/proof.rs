@@ -0,0 +1,538 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use blake2::Blake2b;
+use digest::Digest;
+use digest::generic_array::GenericArray;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{check_leaf, hash_children, leaf_hash, Hash};
+use crate::config::{LeafMode, Padding, Prefixes, TreeConfig};
+use crate::error::MerkleError;
+
+/// Side of the path on which a sibling hash sits.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// Sibling hash on the path of a proof, with the side it sits on.
+pub struct ProofStep<D: Digest = Blake2b> {
+    pub side: Side,
+    pub hash: Hash<D>,
+}
+
+impl<D> ProofStep<D> where D: Digest {
+    pub fn new(side: Side, hash: Hash<D>) -> ProofStep<D> {
+        ProofStep { side, hash }
+    }
+}
+
+/// Inclusion proof for a single leaf of a tree hashed with the digest `D`.
+/// `siblings` holds the sibling hashes on the path from the leaf up to the root.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct Proof<D: Digest = Blake2b> {
+    pub leaf_index: usize,
+    /// Number of leaves of the tree the proof was generated from, which together with `padding`
+    /// fixes the sides of the siblings on the path of `leaf_index`.
+    pub leaf_count: usize,
+    /// How the tree the proof was generated from pairs the last node of a level with an odd
+    /// number of nodes.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub padding: Padding,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::siblings"))]
+    pub siblings: Vec<ProofStep<D>>,
+    /// Domain separation prefixes of the tree the proof was generated from.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub prefixes: Option<Prefixes>,
+    /// Whether the tree the proof was generated from hashes siblings in ascending order.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sort_pairs: bool,
+    /// How the tree the proof was generated from turns leaf data into leaf hashes.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub leaf_mode: LeafMode,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    digest: PhantomData<D>,
+}
+
+impl<D> Proof<D> where D: Digest {
+    pub fn new(leaf_index: usize, leaf_count: usize, padding: Padding, siblings: Vec<ProofStep<D>>) -> Proof<D> {
+        Proof {
+            leaf_index,
+            leaf_count,
+            padding,
+            siblings,
+            prefixes: None,
+            sort_pairs: false,
+            leaf_mode: LeafMode::Hashed,
+            digest: PhantomData,
+        }
+    }
+
+    /// Checks that `leaf_data` is included in the tree with the given root hash.
+    ///
+    /// Hashes as the proof says its tree does, so a prover can turn off the domain separation or
+    /// the position binding of the tree. Proofs from untrusted sources should be checked with
+    /// `verify_with_config` instead.
+    pub fn verify(&self, root_hash: &[u8], leaf_data: &[u8]) -> Result<(), MerkleError> {
+        check_leaf::<D>(self.leaf_mode, self.leaf_index, leaf_data)?;
+        self.verify_hash(root_hash, &leaf_hash::<D>(self.prefixes, self.leaf_mode, leaf_data))
+    }
+
+    /// Checks that `leaf_data` is included in the tree with the given root hash, built with
+    /// `config`. Fails with `ConfigMismatch` unless the proof was generated with `config`.
+    ///
+    /// Hashes and binds positions as `config` says, whatever the proof claims, so the proof of a
+    /// tree hashing pairs in order only verifies at its own `leaf_index`.
+    pub fn verify_with_config(&self, root_hash: &[u8], leaf_data: &[u8], config: TreeConfig) -> Result<(), MerkleError> {
+        self.check_config(config)?;
+        check_leaf::<D>(config.leaf_mode, self.leaf_index, leaf_data)?;
+        self.verify_hash_with_config(root_hash, &leaf_hash::<D>(config.prefixes, config.leaf_mode, leaf_data), config)
+    }
+
+    /// Same as `verify_hash`, for a tree built with `config`. Fails with `ConfigMismatch` unless
+    /// the proof was generated with `config`.
+    pub fn verify_hash_with_config(&self, root_hash: &[u8], leaf_hash: &[u8], config: TreeConfig) -> Result<(), MerkleError> {
+        self.check_config(config)?;
+
+        if self.root_with_config(leaf_hash, config)?.as_slice() == root_hash {
+            Ok(())
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+
+    /// Fails with `ConfigMismatch` unless the padding, prefixes, pair ordering and leaf mode of
+    /// the proof are those of `config`. The duplicate policy is not recorded by proofs.
+    pub fn check_config(&self, config: TreeConfig) -> Result<(), MerkleError> {
+        if self.padding == config.padding
+            && self.prefixes == config.prefixes
+            && self.sort_pairs == config.sort_pairs
+            && self.leaf_mode == config.leaf_mode {
+            Ok(())
+        } else {
+            Err(MerkleError::ConfigMismatch)
+        }
+    }
+
+    /// Checks that the leaf with hash `leaf_hash` is included in the tree with the given root hash,
+    /// for trees whose leaf hashes are not computed from the leaf data by this crate.
+    ///
+    /// Fails with `MalformedProof` when the sides of the siblings are not those of the path of
+    /// leaf `leaf_index` in a tree of `leaf_count` leaves, unless the proof claims to hash sorted
+    /// pairs, where sides are ignored. `verify_hash_with_config` takes that from the verifier.
+    pub fn verify_hash(&self, root_hash: &[u8], leaf_hash: &[u8]) -> Result<(), MerkleError> {
+        if self.root_from(leaf_hash)?.as_slice() == root_hash {
+            Ok(())
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+
+    /// Same as `verify`, but compares the root reached with `root_hash` in constant time, for
+    /// servers checking untrusted proofs against a root the prover must not learn byte by byte.
+    /// Such proofs should first be checked against the config of the tree with `check_config`.
+    #[cfg(feature = "subtle")]
+    pub fn verify_constant_time(&self, root_hash: &[u8], leaf_data: &[u8]) -> Result<(), MerkleError> {
+        check_leaf::<D>(self.leaf_mode, self.leaf_index, leaf_data)?;
+        self.verify_hash_constant_time(root_hash, &leaf_hash::<D>(self.prefixes, self.leaf_mode, leaf_data))
+    }
+
+    /// Same as `verify_hash`, but compares the root reached with `root_hash` in constant time.
+    #[cfg(feature = "subtle")]
+    pub fn verify_hash_constant_time(&self, root_hash: &[u8], leaf_hash: &[u8]) -> Result<(), MerkleError> {
+        use subtle::ConstantTimeEq;
+
+        if bool::from(self.root_from(leaf_hash)?.as_slice().ct_eq(root_hash)) {
+            Ok(())
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+
+    /// Hashes `leaf_hash` up the siblings as the proof says its tree does and returns the root
+    /// reached.
+    pub(crate) fn root_from(&self, leaf_hash: &[u8]) -> Result<Hash<D>, MerkleError> {
+        let config = TreeConfig {
+            prefixes: self.prefixes,
+            sort_pairs: self.sort_pairs,
+            leaf_mode: self.leaf_mode,
+            ..TreeConfig::new(self.padding)
+        };
+
+        self.root_with_config(leaf_hash, config)
+    }
+
+    /// Hashes `leaf_hash` up the siblings as trees built with `config` do and returns the root
+    /// reached. Sides are only ignored when `config` hashes sorted pairs.
+    pub(crate) fn root_with_config(&self, leaf_hash: &[u8], config: TreeConfig) -> Result<Hash<D>, MerkleError> {
+        let mut hash = GenericArray::from_exact_iter(leaf_hash.iter().cloned()).ok_or(MerkleError::MalformedProof)?;
+
+        if !config.sort_pairs && !self.has_consistent_sides(config.padding) {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        for step in &self.siblings {
+            hash = match step.side {
+                Side::Left => hash_children::<D>(config.prefixes, config.sort_pairs, &step.hash, &hash),
+                Side::Right => hash_children::<D>(config.prefixes, config.sort_pairs, &hash, &step.hash),
+            };
+        }
+
+        Ok(hash)
+    }
+
+    /// Whether the sides of the siblings are exactly those of the path of `leaf_index` under
+    /// `padding`, so that the proof of a leaf cannot be passed off as the proof of another
+    /// position.
+    fn has_consistent_sides(&self, padding: Padding) -> bool {
+        self.leaf_index < self.leaf_count
+            && self.siblings.iter().map(|step| step.side).eq(path_sides(self.leaf_index, self.leaf_count, padding))
+    }
+}
+
+/// Sides of the siblings on the path of the leaf at `index` among `count` leaves, from the leaf
+/// up: a left sibling for an odd index and a right one for an even index. Under
+/// `Padding::Promote` the last node of a level with an odd number of nodes has no sibling and
+/// is moved up unchanged, while the other paddings pair it with a right sibling.
+pub(crate) fn path_sides(index: usize, count: usize, padding: Padding) -> PathSides {
+    PathSides { index, count, padding }
+}
+
+/// Iterator over the sides of the siblings on a path, returned by [`path_sides`].
+pub(crate) struct PathSides {
+    index: usize,
+    count: usize,
+    padding: Padding,
+}
+
+impl Iterator for PathSides {
+    type Item = Side;
+
+    fn next(&mut self) -> Option<Side> {
+        while self.count > 1 {
+            let promoted = self.padding == Padding::Promote && self.count % 2 == 1 && self.index == self.count - 1;
+            let side = if self.index % 2 == 1 { Side::Left } else { Side::Right };
+
+            self.index /= 2;
+            self.count = self.count.div_ceil(2);
+
+            if !promoted {
+                return Some(side);
+            }
+        }
+
+        None
+    }
+}
+
+/// Checks an audit path against a trusted root without building a tree or a `Proof`, with
+/// `hasher` applied to the leaf data and then to every concatenated pair of hashes, from the
+/// leaf up. `path` holds the sibling hashes with the side each one sits on.
+pub fn verify_path<H>(root: &[u8], leaf: &[u8], path: &[(Side, Vec<u8>)], hasher: H) -> Result<(), MerkleError>
+    where H: Fn(&[u8]) -> Vec<u8> {
+    let mut hash = hasher(leaf);
+    let mut pair = Vec::new();
+
+    for (side, sibling) in path {
+        pair.clear();
+
+        match side {
+            Side::Left => {
+                pair.extend_from_slice(sibling);
+                pair.extend_from_slice(&hash);
+            }
+            Side::Right => {
+                pair.extend_from_slice(&hash);
+                pair.extend_from_slice(sibling);
+            }
+        }
+
+        hash = hasher(&pair);
+    }
+
+    if hash == root {
+        Ok(())
+    } else {
+        Err(MerkleError::RootMismatch)
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for ProofStep<D> where D: Digest {
+    fn clone(&self) -> Self {
+        ProofStep::new(self.side, self.hash.clone())
+    }
+}
+
+impl<D> PartialEq for ProofStep<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.side == other.side
+            && self.hash == other.hash
+    }
+}
+
+impl<D> fmt::Debug for ProofStep<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProofStep")
+            .field("side", &self.side)
+            .field("hash", &self.hash)
+            .finish()
+    }
+}
+
+impl<D> Clone for Proof<D> where D: Digest {
+    fn clone(&self) -> Self {
+        Proof {
+            leaf_index: self.leaf_index,
+            leaf_count: self.leaf_count,
+            padding: self.padding,
+            siblings: self.siblings.clone(),
+            prefixes: self.prefixes,
+            sort_pairs: self.sort_pairs,
+            leaf_mode: self.leaf_mode,
+            digest: PhantomData,
+        }
+    }
+}
+
+impl<D> PartialEq for Proof<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf_index == other.leaf_index
+            && self.leaf_count == other.leaf_count
+            && self.padding == other.padding
+            && self.siblings == other.siblings
+            && self.prefixes == other.prefixes
+            && self.sort_pairs == other.sort_pairs
+            && self.leaf_mode == other.leaf_mode
+    }
+}
+
+impl<D> fmt::Debug for Proof<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Proof")
+            .field("leaf_index", &self.leaf_index)
+            .field("leaf_count", &self.leaf_count)
+            .field("padding", &self.padding)
+            .field("siblings", &self.siblings)
+            .field("prefixes", &self.prefixes)
+            .field("sort_pairs", &self.sort_pairs)
+            .field("leaf_mode", &self.leaf_mode)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blake2::Blake2b;
+
+    use super::{verify_path, Proof, Side};
+    use crate::{calculate_hash, MerkleError, MerkleTree, Padding, Prefixes, TreeConfig};
+
+    #[test]
+    fn it_verifies_proofs_for_every_leaf() {
+        let data = vec!["a", "b", "c", "d", "e", "f", "g", "h"];
+        let merkle_tree = MerkleTree::from_vec(data.clone()).unwrap();
+
+        for (index, leaf) in data.iter().enumerate() {
+            let proof = merkle_tree.proof(index).unwrap();
+
+            assert_eq!(proof.siblings.len(), 3);
+            assert!(proof.verify(merkle_tree.root_hash(), leaf.as_bytes()).is_ok());
+        }
+    }
+
+    #[test]
+    fn it_rejects_proof_for_wrong_data() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]).unwrap();
+        let proof = merkle_tree.proof(1).unwrap();
+
+        assert_eq!(proof.verify(merkle_tree.root_hash(), "a".as_bytes()), Err(MerkleError::RootMismatch));
+    }
+
+    #[test]
+    fn it_rejects_proof_for_wrong_root() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]).unwrap();
+        let other_tree = MerkleTree::from_vec(vec!["e", "f", "g", "h"]).unwrap();
+        let proof = merkle_tree.proof(2).unwrap();
+
+        assert_eq!(proof.verify(other_tree.root_hash(), "c".as_bytes()), Err(MerkleError::RootMismatch));
+    }
+
+    #[test]
+    fn it_proves_single_leaf_tree() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a"]).unwrap();
+        let proof = merkle_tree.proof(0).unwrap();
+
+        assert!(proof.siblings.is_empty());
+        assert!(proof.verify(merkle_tree.root_hash(), "a".as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn it_verifies_proofs_with_custom_digest() {
+        use blake2::Blake2s;
+
+        let merkle_tree = MerkleTree::<_, Blake2s>::from_vec_with_digest(vec!["a", "b", "c", "d"]).unwrap();
+        let default_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]).unwrap();
+
+        assert!(merkle_tree.proof(3).unwrap().verify(merkle_tree.root_hash(), "d".as_bytes()).is_ok());
+        assert_eq!(default_tree.proof(3).unwrap().verify(merkle_tree.root_hash(), "d".as_bytes()), Err(MerkleError::RootMismatch));
+    }
+
+    #[test]
+    fn it_verifies_proofs_for_any_leaf_count_and_padding() {
+        let data: Vec<String> = (0..17).map(|i| i.to_string()).collect();
+
+        for padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            for len in 1..data.len() {
+                let leaves = data[..len].to_vec();
+                let merkle_tree = MerkleTree::from_vec_with_config(leaves.clone(), TreeConfig::new(*padding)).unwrap();
+
+                for (index, leaf) in leaves.iter().enumerate() {
+                    assert!(merkle_tree.proof(index).unwrap().verify(merkle_tree.root_hash(), leaf.as_bytes()).is_ok());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_verifies_proofs_with_domain_separation() {
+        let data: Vec<String> = (0..9).map(|i| i.to_string()).collect();
+        let config = TreeConfig { prefixes: Some(Prefixes::RFC6962), ..TreeConfig::default() };
+        let merkle_tree = MerkleTree::from_vec_with_config(data.clone(), config).unwrap();
+
+        for (index, leaf) in data.iter().enumerate() {
+            let mut proof = merkle_tree.proof(index).unwrap();
+            assert!(proof.verify(merkle_tree.root_hash(), leaf.as_bytes()).is_ok());
+
+            proof.prefixes = None;
+            assert_eq!(proof.verify(merkle_tree.root_hash(), leaf.as_bytes()), Err(MerkleError::RootMismatch));
+        }
+    }
+
+    #[test]
+    fn it_rejects_proofs_whose_prefixes_were_stripped() {
+        let merkle_tree = MerkleTree::from_vec_with_config(vec!["a", "b", "c", "d"], TreeConfig::RFC6962).unwrap();
+        let root = merkle_tree.root_hash();
+        let left = merkle_tree.proof(2).unwrap().siblings[1].hash;
+        let right = merkle_tree.proof(0).unwrap().siblings[1].hash;
+
+        // Without prefixes, the data of the root node passes for the only leaf of a tree.
+        let inner_node = [&[Prefixes::RFC6962.node][..], &left, &right].concat();
+        let forged: Proof = Proof::new(0, 1, Padding::Promote, vec![]);
+        assert_eq!(forged.verify(root, &inner_node), Ok(()));
+        assert_eq!(forged.verify_with_config(root, &inner_node, TreeConfig::RFC6962), Err(MerkleError::ConfigMismatch));
+
+        assert_eq!(merkle_tree.proof(3).unwrap().verify_with_config(root, b"d", TreeConfig::RFC6962), Ok(()));
+        assert_eq!(merkle_tree.proof(3).unwrap().verify_with_config(root, b"d", TreeConfig::default()), Err(MerkleError::ConfigMismatch));
+    }
+
+    #[test]
+    fn it_rejects_moved_proofs_claiming_sorted_pairs() {
+        let mut data = vec!["a", "b"];
+        data.sort_by_key(|leaf| calculate_hash::<Blake2b>(leaf.as_bytes()));
+        let merkle_tree = MerkleTree::from_vec(data.clone()).unwrap();
+        let root = merkle_tree.root_hash();
+
+        // Sorting the pair puts the siblings back in order, so the sides no longer matter.
+        let mut moved = merkle_tree.proof(1).unwrap();
+        moved.leaf_index = 0;
+        moved.sort_pairs = true;
+        assert_eq!(moved.verify(root, data[1].as_bytes()), Ok(()));
+        assert_eq!(moved.verify_with_config(root, data[1].as_bytes(), TreeConfig::default()), Err(MerkleError::ConfigMismatch));
+
+        moved.sort_pairs = false;
+        assert_eq!(moved.verify_with_config(root, data[1].as_bytes(), TreeConfig::default()), Err(MerkleError::MalformedProof));
+    }
+
+    #[test]
+    fn it_skips_promoted_levels_in_proofs() {
+        let merkle_tree = MerkleTree::from_vec_with_config(vec!["a", "b", "c"], TreeConfig::new(Padding::Promote)).unwrap();
+
+        assert_eq!(merkle_tree.proof(0).unwrap().siblings.len(), 2);
+        assert_eq!(merkle_tree.proof(2).unwrap().siblings.len(), 1);
+    }
+
+    #[test]
+    fn it_verifies_paths_without_a_tree() {
+        use sha2::{Digest, Sha256};
+
+        let merkle_tree = MerkleTree::<_, Sha256>::from_vec_with_digest(vec!["a", "b", "c", "d", "e"]).unwrap();
+        let hasher = |data: &[u8]| Sha256::digest(data).to_vec();
+
+        for (index, leaf) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+            let path: Vec<_> = merkle_tree.proof(index).unwrap().siblings.iter().map(|step| (step.side, step.hash.to_vec())).collect();
+
+            assert_eq!(verify_path(merkle_tree.root_hash(), leaf.as_bytes(), &path, hasher), Ok(()));
+            assert_eq!(verify_path(merkle_tree.root_hash(), b"f", &path, hasher), Err(MerkleError::RootMismatch));
+        }
+    }
+
+    #[test]
+    fn it_rejects_sides_that_do_not_match_the_leaf_index() {
+        let merkle_tree = MerkleTree::from_vec_with_config(vec!["a", "b", "c", "d", "e"], TreeConfig::new(Padding::Promote)).unwrap();
+
+        let mut proof = merkle_tree.proof(4).unwrap();
+        assert_eq!(proof.siblings.len(), 1);
+        assert!(proof.verify(merkle_tree.root_hash(), b"e").is_ok());
+
+        proof.siblings[0].side = Side::Right;
+        assert_eq!(proof.verify(merkle_tree.root_hash(), b"e"), Err(MerkleError::MalformedProof));
+
+        let mut proof = merkle_tree.proof(1).unwrap();
+        proof.leaf_index = 3;
+        assert_eq!(proof.verify(merkle_tree.root_hash(), b"b"), Err(MerkleError::MalformedProof));
+
+        proof.leaf_index = 9;
+        assert_eq!(proof.verify(merkle_tree.root_hash(), b"b"), Err(MerkleError::MalformedProof));
+    }
+
+    #[test]
+    fn it_binds_proofs_to_their_leaf_index() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]).unwrap();
+        let mut proof = merkle_tree.proof(1).unwrap();
+        proof.leaf_index = 2;
+        assert_eq!(proof.verify(merkle_tree.root_hash(), b"b"), Err(MerkleError::MalformedProof));
+
+        // Leaf 2 is promoted past the first level, so its single sibling is a left one, like the
+        // first sibling of leaf 1.
+        let merkle_tree = MerkleTree::from_vec_with_config(vec!["a", "b", "c"], TreeConfig::new(Padding::Promote)).unwrap();
+        let mut proof = merkle_tree.proof(2).unwrap();
+        proof.leaf_index = 1;
+        assert_eq!(proof.verify(merkle_tree.root_hash(), b"c"), Err(MerkleError::MalformedProof));
+
+        let mut proof = merkle_tree.proof(2).unwrap();
+        proof.padding = Padding::DuplicateLast;
+        assert_eq!(proof.verify(merkle_tree.root_hash(), b"c"), Err(MerkleError::MalformedProof));
+
+        let mut proof = merkle_tree.proof(2).unwrap();
+        proof.leaf_count = 4;
+        assert_eq!(proof.verify(merkle_tree.root_hash(), b"c"), Err(MerkleError::MalformedProof));
+    }
+
+    #[cfg(feature = "subtle")]
+    #[test]
+    fn it_verifies_proofs_in_constant_time() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d", "e"]).unwrap();
+
+        for (index, leaf) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+            let proof = merkle_tree.proof(index).unwrap();
+
+            assert_eq!(proof.verify_constant_time(merkle_tree.root_hash(), leaf.as_bytes()), Ok(()));
+            assert_eq!(proof.verify_constant_time(merkle_tree.root_hash(), b"f"), Err(MerkleError::RootMismatch));
+            assert_eq!(proof.verify_constant_time(&merkle_tree.root_hash()[1..], leaf.as_bytes()), Err(MerkleError::RootMismatch));
+        }
+    }
+
+    #[test]
+    fn it_fails_to_prove_out_of_bounds_leaf() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b"]).unwrap();
+
+        assert_eq!(merkle_tree.proof(2), Err(MerkleError::IndexOutOfBounds { index: 2, leaf_count: 2 }));
+    }
+}
@@ -0,0 +1,187 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+
+use blake2::Blake2b;
+use digest::Digest;
+use digest::generic_array::GenericArray;
+
+use crate::{calculate_hash, Hash};
+use crate::config::{Padding, TreeConfig};
+use crate::error::MerkleError;
+use crate::proof::{Proof, ProofStep, Side};
+use crate::tree::depth_for;
+
+/// Assembles inclusion proofs for a tree of `leaf_count` leaves held elsewhere, such as on a
+/// server that only answers requests for single nodes, from a cache of the node hashes known so
+/// far. Nodes missing from the cache are fetched with a callback and kept for later proofs.
+///
+/// Nodes are addressed by their height above the leaves and their index within that level, as
+/// with `FlatMerkleTree::node`. The proofs assembled are only as trustworthy as the root they are
+/// verified against, which must not come from the same source as the nodes.
+pub struct ProofBuilder<F, D: Digest = Blake2b> {
+    leaf_count: usize,
+    config: TreeConfig,
+    fetch: F,
+    cache: BTreeMap<(usize, usize), Hash<D>>,
+}
+
+impl<F> ProofBuilder<F> where F: FnMut(usize, usize) -> Option<Vec<u8>> {
+    /// Creates a ProofBuilder for a tree hashed with Blake2b and the default config, fetching
+    /// the nodes it is missing with `fetch`, which returns the hash of the node at a height and
+    /// index, or `None` if it cannot be fetched.
+    pub fn new(leaf_count: usize, fetch: F) -> ProofBuilder<F> {
+        ProofBuilder::with_config_and_digest(leaf_count, TreeConfig::default(), fetch)
+    }
+
+    pub fn with_config(leaf_count: usize, config: TreeConfig, fetch: F) -> ProofBuilder<F> {
+        ProofBuilder::with_config_and_digest(leaf_count, config, fetch)
+    }
+}
+
+impl<F, D> ProofBuilder<F, D> where F: FnMut(usize, usize) -> Option<Vec<u8>>, D: Digest {
+    /// Creates a ProofBuilder for a tree hashed with the digest `D` and the default config.
+    pub fn with_digest(leaf_count: usize, fetch: F) -> ProofBuilder<F, D> {
+        ProofBuilder::with_config_and_digest(leaf_count, TreeConfig::default(), fetch)
+    }
+
+    /// Creates a ProofBuilder for a tree hashed with the digest `D`.
+    pub fn with_config_and_digest(leaf_count: usize, config: TreeConfig, fetch: F) -> ProofBuilder<F, D> {
+        ProofBuilder { leaf_count, config, fetch, cache: BTreeMap::new() }
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    pub fn config(&self) -> TreeConfig {
+        self.config
+    }
+
+    /// Adds the hash of a node to the cache, such as one received along with an earlier proof.
+    pub fn insert(&mut self, height: usize, index: usize, hash: Hash<D>) {
+        self.cache.insert((height, index), hash);
+    }
+
+    /// Number of node hashes in the cache.
+    pub fn cached(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Assembles the inclusion proof for the leaf at `leaf_index`, identical to the one the tree
+    /// would generate. Fails with `MissingNode` if a node is neither cached nor fetched, and with
+    /// `MalformedProof` if a node fetched is not one hash long.
+    pub fn build(&mut self, leaf_index: usize) -> Result<Proof<D>, MerkleError> {
+        if leaf_index >= self.leaf_count {
+            return Err(MerkleError::IndexOutOfBounds { index: leaf_index, leaf_count: self.leaf_count });
+        }
+
+        let mut siblings = Vec::new();
+        let (mut index, mut count) = (leaf_index, self.leaf_count);
+
+        for height in 0..depth_for(self.leaf_count) {
+            let sibling = index ^ 1;
+
+            if sibling < count {
+                let side = if index & 1 == 1 { Side::Left } else { Side::Right };
+                siblings.push(ProofStep::new(side, self.node(height, sibling)?));
+            } else {
+                match self.config.padding {
+                    Padding::Promote => {}
+                    Padding::DuplicateLast => siblings.push(ProofStep::new(Side::Right, self.node(height, index)?)),
+                    Padding::EmptyHash => siblings.push(ProofStep::new(Side::Right, calculate_hash::<D>(&[]))),
+                }
+            }
+
+            index /= 2;
+            count = count.div_ceil(2);
+        }
+
+        let mut proof = Proof::new(leaf_index, self.leaf_count, self.config.padding, siblings);
+        proof.prefixes = self.config.prefixes;
+        proof.sort_pairs = self.config.sort_pairs;
+        proof.leaf_mode = self.config.leaf_mode;
+
+        Ok(proof)
+    }
+
+    /// Checks that `leaf_data` is the leaf at `leaf_index` of the tree with the given root hash,
+    /// with a proof assembled by `build`.
+    pub fn verify(&mut self, leaf_index: usize, root_hash: &[u8], leaf_data: &[u8]) -> Result<(), MerkleError> {
+        self.build(leaf_index)?.verify(root_hash, leaf_data)
+    }
+
+    fn node(&mut self, height: usize, index: usize) -> Result<Hash<D>, MerkleError> {
+        if let Some(hash) = self.cache.get(&(height, index)) {
+            return Ok(hash.clone());
+        }
+
+        let fetched = (self.fetch)(height, index).ok_or(MerkleError::MissingNode { height, index })?;
+        let hash = GenericArray::from_exact_iter(fetched).ok_or(MerkleError::MalformedProof)?;
+        self.cache.insert((height, index), hash.clone());
+
+        Ok(hash)
+    }
+}
+
+impl<F, D> fmt::Debug for ProofBuilder<F, D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProofBuilder")
+            .field("leaf_count", &self.leaf_count)
+            .field("config", &self.config)
+            .field("cached", &self.cache.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use crate::{FlatMerkleTree, MerkleTree};
+
+    #[test]
+    fn it_builds_the_proofs_of_the_tree() {
+        for config in &[TreeConfig::default(), TreeConfig::RFC6962, TreeConfig::new(Padding::EmptyHash)] {
+            let data: Vec<String> = (0..13).map(|i| i.to_string()).collect();
+            let remote = FlatMerkleTree::from_vec_with_config(data.clone(), *config).unwrap();
+            let local = MerkleTree::from_vec_with_config(data.clone(), *config).unwrap();
+            let mut builder = ProofBuilder::with_config(13, *config, |height, index| remote.node(height, index).map(<[u8]>::to_vec));
+
+            for (index, leaf) in data.iter().enumerate() {
+                assert_eq!(builder.build(index).unwrap(), local.proof(index).unwrap());
+                assert_eq!(builder.verify(index, remote.root_hash(), leaf.as_bytes()), Ok(()));
+            }
+
+            assert_eq!(builder.build(13), Err(MerkleError::IndexOutOfBounds { index: 13, leaf_count: 13 }));
+        }
+    }
+
+    #[test]
+    fn it_only_fetches_nodes_missing_from_the_cache() {
+        let remote = FlatMerkleTree::from_vec(vec!["a", "b", "c", "d", "e", "f", "g", "h"]).unwrap();
+        let fetches = Cell::new(0);
+        let mut builder = ProofBuilder::new(8, |height, index| {
+            fetches.set(fetches.get() + 1);
+            remote.node(height, index).map(<[u8]>::to_vec)
+        });
+
+        builder.insert(2, 1, GenericArray::clone_from_slice(remote.node(2, 1).unwrap()));
+        builder.build(0).unwrap();
+        assert_eq!(fetches.get(), 2);
+
+        // The proof of the sibling leaf shares the upper two nodes.
+        builder.build(1).unwrap();
+        assert_eq!(fetches.get(), 3);
+        assert_eq!(builder.cached(), 4);
+    }
+
+    #[test]
+    fn it_fails_on_missing_and_malformed_nodes() {
+        let mut builder = ProofBuilder::new(4, |height, _| if height == 0 { Some(vec![0; 64]) } else { None });
+        assert_eq!(builder.build(0), Err(MerkleError::MissingNode { height: 1, index: 1 }));
+
+        let mut builder = ProofBuilder::new(4, |_, _| Some(vec![0; 3]));
+        assert_eq!(builder.build(0), Err(MerkleError::MalformedProof));
+    }
+}
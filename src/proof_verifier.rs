@@ -0,0 +1,199 @@
+use core::fmt;
+
+use blake2::Blake2b;
+use digest::Digest;
+use digest::generic_array::GenericArray;
+
+use crate::{check_leaf, hash_children, leaf_hash, Hash};
+use crate::config::{Prefixes, TreeConfig};
+use crate::error::MerkleError;
+use crate::proof::{path_sides, PathSides, Side};
+
+/// Verifies an inclusion proof one sibling at a time, for devices that cannot hold a deep proof
+/// in memory at once, such as when reading it from a network socket or a flash page by page.
+/// Only the hash reached so far is kept, and the sides of the siblings are checked against the
+/// path of the leaf index among the leaf count and padding of the tree as they arrive, exactly
+/// as `Proof::verify` does.
+pub struct ProofVerifier<D: Digest = Blake2b> {
+    hash: Hash<D>,
+    /// Sides of the siblings not pushed yet.
+    sides: PathSides,
+    siblings: usize,
+    prefixes: Option<Prefixes>,
+    sort_pairs: bool,
+}
+
+impl ProofVerifier<Blake2b> {
+    /// Starts verifying the proof of `leaf_data` at `leaf_index` of a tree of `leaf_count`
+    /// leaves, hashed with Blake2b and the default config.
+    pub fn new(leaf_index: usize, leaf_count: usize, leaf_data: &[u8]) -> Result<ProofVerifier, MerkleError> {
+        ProofVerifier::with_config_and_digest(leaf_index, leaf_count, leaf_data, TreeConfig::default())
+    }
+
+    pub fn with_config(leaf_index: usize, leaf_count: usize, leaf_data: &[u8], config: TreeConfig) -> Result<ProofVerifier, MerkleError> {
+        ProofVerifier::with_config_and_digest(leaf_index, leaf_count, leaf_data, config)
+    }
+}
+
+impl<D> ProofVerifier<D> where D: Digest {
+    /// Starts verifying the proof of `leaf_data` at `leaf_index` of a tree of `leaf_count`
+    /// leaves, hashed with the digest `D` and the default config.
+    pub fn with_digest(leaf_index: usize, leaf_count: usize, leaf_data: &[u8]) -> Result<ProofVerifier<D>, MerkleError> {
+        ProofVerifier::with_config_and_digest(leaf_index, leaf_count, leaf_data, TreeConfig::default())
+    }
+
+    /// Starts verifying the proof of `leaf_data` at `leaf_index` of a tree of `leaf_count`
+    /// leaves, hashed with the digest `D`. Fails with `IndexOutOfBounds` unless `leaf_index` is
+    /// below `leaf_count`, and with `LeafMode::Prehashed`, with `InvalidLeafLength` unless
+    /// `leaf_data` is one hash long.
+    pub fn with_config_and_digest(leaf_index: usize, leaf_count: usize, leaf_data: &[u8], config: TreeConfig) -> Result<ProofVerifier<D>, MerkleError> {
+        check_leaf::<D>(config.leaf_mode, leaf_index, leaf_data)?;
+        ProofVerifier::from_hash(leaf_index, leaf_count, leaf_hash::<D>(config.prefixes, config.leaf_mode, leaf_data), config)
+    }
+
+    /// Starts verifying the proof of the leaf with hash `leaf_hash`, as `Proof::verify_hash`
+    /// does. Fails with `MalformedProof` if `leaf_hash` is not one hash long.
+    pub fn with_leaf_hash(leaf_index: usize, leaf_count: usize, leaf_hash: &[u8], config: TreeConfig) -> Result<ProofVerifier<D>, MerkleError> {
+        let hash = GenericArray::from_exact_iter(leaf_hash.iter().cloned()).ok_or(MerkleError::MalformedProof)?;
+        ProofVerifier::from_hash(leaf_index, leaf_count, hash, config)
+    }
+
+    fn from_hash(leaf_index: usize, leaf_count: usize, hash: Hash<D>, config: TreeConfig) -> Result<ProofVerifier<D>, MerkleError> {
+        if leaf_index >= leaf_count {
+            return Err(MerkleError::IndexOutOfBounds { index: leaf_index, leaf_count });
+        }
+
+        Ok(ProofVerifier {
+            hash,
+            sides: path_sides(leaf_index, leaf_count, config.padding),
+            siblings: 0,
+            prefixes: config.prefixes,
+            sort_pairs: config.sort_pairs,
+        })
+    }
+
+    /// Hashes the next sibling, from the leaf up, into the hash reached. Fails with
+    /// `MalformedProof` if `sibling` is not one hash long or if `side` cannot be the side of the
+    /// next sibling of the leaf, unless the tree hashes sorted pairs, where sides are ignored.
+    pub fn push(&mut self, side: Side, sibling: &[u8]) -> Result<(), MerkleError> {
+        if sibling.len() != D::output_size() {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        if !self.sort_pairs && self.sides.next() != Some(side) {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        self.hash = match side {
+            Side::Left => hash_children::<D>(self.prefixes, self.sort_pairs, sibling, &self.hash),
+            Side::Right => hash_children::<D>(self.prefixes, self.sort_pairs, &self.hash, sibling),
+        };
+        self.siblings += 1;
+
+        Ok(())
+    }
+
+    /// Number of siblings pushed so far.
+    pub fn siblings(&self) -> usize {
+        self.siblings
+    }
+
+    /// Hash reached with the siblings pushed so far.
+    pub fn hash(&self) -> &[u8] {
+        &self.hash
+    }
+
+    /// Returns the root reached once every sibling was pushed. Fails with `MalformedProof` if the
+    /// siblings pushed stop short of the root for the leaf index, unless the tree hashes sorted
+    /// pairs.
+    pub fn finish(mut self) -> Result<Hash<D>, MerkleError> {
+        if self.sort_pairs || self.sides.next().is_none() {
+            Ok(self.hash)
+        } else {
+            Err(MerkleError::MalformedProof)
+        }
+    }
+
+    /// Checks that the root reached once every sibling was pushed is `root_hash`.
+    pub fn verify(self, root_hash: &[u8]) -> Result<(), MerkleError> {
+        if self.finish()?.as_slice() == root_hash {
+            Ok(())
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+}
+
+impl<D> fmt::Debug for ProofVerifier<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProofVerifier")
+            .field("hash", &self.hash)
+            .field("siblings", &self.siblings)
+            .field("prefixes", &self.prefixes)
+            .field("sort_pairs", &self.sort_pairs)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::MerkleTree;
+    use crate::Padding;
+
+    #[test]
+    fn it_verifies_proofs_one_sibling_at_a_time() {
+        for config in &[TreeConfig::default(), TreeConfig::RFC6962, TreeConfig::new(Padding::EmptyHash)] {
+            let data: Vec<String> = (0..13).map(|i| i.to_string()).collect();
+            let merkle_tree = MerkleTree::from_vec_with_config(data.clone(), *config).unwrap();
+
+            for (index, leaf) in data.iter().enumerate() {
+                let proof = merkle_tree.proof(index).unwrap();
+                let mut verifier = ProofVerifier::with_config(index, data.len(), leaf.as_bytes(), *config).unwrap();
+
+                for step in &proof.siblings {
+                    verifier.push(step.side, &step.hash).unwrap();
+                }
+
+                assert_eq!(verifier.siblings(), proof.siblings.len());
+                assert_eq!(verifier.verify(merkle_tree.root_hash()), proof.verify(merkle_tree.root_hash(), leaf.as_bytes()));
+            }
+        }
+    }
+
+    #[test]
+    fn it_rejects_siblings_that_do_not_match_the_index() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]).unwrap();
+        let proof = merkle_tree.proof(2).unwrap();
+
+        // Leaf 0 only has right siblings.
+        let mut verifier = ProofVerifier::new(0, 4, b"a").unwrap();
+        assert_eq!(verifier.push(Side::Left, &proof.siblings[0].hash), Err(MerkleError::MalformedProof));
+        assert_eq!(verifier.push(Side::Right, &[0; 3]), Err(MerkleError::MalformedProof));
+
+        let mut verifier = ProofVerifier::new(2, 4, b"c").unwrap();
+        verifier.push(proof.siblings[0].side, &proof.siblings[0].hash).unwrap();
+        assert_eq!(verifier.finish(), Err(MerkleError::MalformedProof));
+
+        let mut verifier = ProofVerifier::new(2, 4, b"d").unwrap();
+        proof.siblings.iter().for_each(|step| verifier.push(step.side, &step.hash).unwrap());
+        assert_eq!(verifier.verify(merkle_tree.root_hash()), Err(MerkleError::RootMismatch));
+    }
+
+    #[test]
+    fn it_rejects_proofs_pushed_for_another_leaf_index() {
+        // The last of 5 leaves is promoted twice, so its only sibling is on the left, as for leaf 2.
+        let merkle_tree = MerkleTree::from_vec_with_config(vec!["a", "b", "c", "d", "c"], TreeConfig::RFC6962).unwrap();
+        let proof = merkle_tree.proof(4).unwrap();
+        assert_eq!(proof.siblings.len(), 1);
+
+        let mut verifier = ProofVerifier::with_config(4, 5, b"c", TreeConfig::RFC6962).unwrap();
+        verifier.push(proof.siblings[0].side, &proof.siblings[0].hash).unwrap();
+        assert_eq!(verifier.verify(merkle_tree.root_hash()), Ok(()));
+
+        let mut verifier = ProofVerifier::with_config(2, 5, b"c", TreeConfig::RFC6962).unwrap();
+        assert_eq!(verifier.push(proof.siblings[0].side, &proof.siblings[0].hash), Err(MerkleError::MalformedProof));
+
+        assert_eq!(ProofVerifier::new(5, 5, b"c").err(), Some(MerkleError::IndexOutOfBounds { index: 5, leaf_count: 5 }));
+    }
+}
@@ -0,0 +1,143 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Range;
+
+use blake2::Blake2b;
+use digest::Digest;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::config::{LeafMode, Padding, Prefixes};
+use crate::error::MerkleError;
+use crate::multiproof::MultiProof;
+use crate::Hash;
+
+/// Proof that a contiguous run of leaves, in order, is committed under a root, such as a page of
+/// an authenticated log. `hashes` holds the sibling hashes of the run that cannot be computed
+/// from its leaves, like a [`MultiProof`] of every index of the range.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct RangeProof<D: Digest = Blake2b> {
+    /// Leaves proven, from `range.start` included to `range.end` excluded.
+    pub range: Range<usize>,
+    pub leaf_count: usize,
+    pub padding: Padding,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::list"))]
+    pub hashes: Vec<Hash<D>>,
+    /// Domain separation prefixes of the tree the proof was generated from.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub prefixes: Option<Prefixes>,
+    /// How the tree the proof was generated from turns leaf data into leaf hashes.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub leaf_mode: LeafMode,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    digest: PhantomData<D>,
+}
+
+impl<D> RangeProof<D> where D: Digest {
+    pub fn new(range: Range<usize>, leaf_count: usize, padding: Padding, hashes: Vec<Hash<D>>) -> RangeProof<D> {
+        RangeProof { range, leaf_count, padding, hashes, prefixes: None, leaf_mode: LeafMode::Hashed, digest: PhantomData }
+    }
+
+    /// Checks that `leaves` are the leaves of the range, in order, of the tree with the given
+    /// root hash. Fails with `MalformedProof` unless there is one leaf per index of the range.
+    pub fn verify(&self, root_hash: &[u8], leaves: &[&[u8]]) -> Result<(), MerkleError> {
+        if leaves.len() != self.range.len() {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let mut proof = MultiProof::<D>::new(self.range.clone().collect(), self.leaf_count, self.padding, self.hashes.clone());
+        proof.prefixes = self.prefixes;
+        proof.leaf_mode = self.leaf_mode;
+
+        proof.verify(root_hash, leaves)
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for RangeProof<D> where D: Digest {
+    fn clone(&self) -> Self {
+        RangeProof {
+            range: self.range.clone(),
+            leaf_count: self.leaf_count,
+            padding: self.padding,
+            hashes: self.hashes.clone(),
+            prefixes: self.prefixes,
+            leaf_mode: self.leaf_mode,
+            digest: PhantomData,
+        }
+    }
+}
+
+impl<D> PartialEq for RangeProof<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.range == other.range
+            && self.leaf_count == other.leaf_count
+            && self.padding == other.padding
+            && self.hashes == other.hashes
+            && self.prefixes == other.prefixes
+            && self.leaf_mode == other.leaf_mode
+    }
+}
+
+impl<D> fmt::Debug for RangeProof<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RangeProof")
+            .field("range", &self.range)
+            .field("leaf_count", &self.leaf_count)
+            .field("padding", &self.padding)
+            .field("hashes", &self.hashes)
+            .field("prefixes", &self.prefixes)
+            .field("leaf_mode", &self.leaf_mode)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{MerkleError, MerkleTree, Padding, TreeConfig};
+
+    fn leaves(len: usize) -> Vec<String> {
+        (0..len).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn it_verifies_pages_of_leaves() {
+        for padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            let data = leaves(13);
+            let merkle_tree = MerkleTree::from_vec_with_config(data.clone(), TreeConfig::new(*padding)).unwrap();
+
+            for start in 0..13 {
+                for end in start + 1..=13 {
+                    let page: Vec<&[u8]> = data[start..end].iter().map(|leaf| leaf.as_bytes()).collect();
+                    let proof = merkle_tree.range_proof(start..end).unwrap();
+
+                    assert_eq!(proof.verify(merkle_tree.root_hash(), &page), Ok(()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_rejects_reordered_or_partial_pages() {
+        let merkle_tree = MerkleTree::from_vec(leaves(10)).unwrap();
+        let proof = merkle_tree.range_proof(2..6).unwrap();
+        let root_hash = merkle_tree.root_hash();
+
+        assert_eq!(proof.verify(root_hash, &[b"2", b"4", b"3", b"5"]), Err(MerkleError::RootMismatch));
+        assert_eq!(proof.verify(root_hash, &[b"2", b"3", b"4"]), Err(MerkleError::MalformedProof));
+        assert_eq!(proof.verify(root_hash, &[b"3", b"4", b"5", b"6"]), Err(MerkleError::RootMismatch));
+    }
+
+    #[test]
+    fn it_fails_for_invalid_ranges_and_sorted_pairs() {
+        let merkle_tree = MerkleTree::from_vec(leaves(4)).unwrap();
+
+        assert_eq!(merkle_tree.range_proof(2..2), Err(MerkleError::EmptyInput));
+        assert_eq!(merkle_tree.range_proof(2..5), Err(MerkleError::IndexOutOfBounds { index: 4, leaf_count: 4 }));
+
+        let sorted = MerkleTree::from_vec_openzeppelin(leaves(4)).unwrap();
+        assert_eq!(sorted.range_proof(0..2), Err(MerkleError::UnsupportedSortedPairs));
+    }
+}
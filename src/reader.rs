@@ -0,0 +1,402 @@
+use std::io::{self, Read};
+
+use blake2::Blake2b;
+use digest::Digest;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::cdc::FastCdc;
+use crate::config::{LeafMode, TreeConfig};
+use crate::error::MerkleError;
+use crate::metrics;
+use crate::{check_leaf, leaf_hash};
+use crate::tree::{MerkleLeaf, MerkleTree, Node};
+
+/// Position of a chunk in the stream a tree was read from. Trees built by `from_reader` store
+/// these instead of the chunks themselves.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Chunk {
+    pub offset: u64,
+    pub len: usize,
+}
+
+/// How a stream is split into chunks.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub(crate) enum Chunking {
+    Fixed(usize),
+    ContentDefined(FastCdc),
+}
+
+impl MerkleTree<Chunk, Blake2b> {
+    /// Reads `reader` to the end in chunks of `chunk_size` bytes, the last one possibly shorter,
+    /// and builds a tree with one leaf per chunk, hashing with Blake2b and the default config.
+    pub fn from_reader<R: Read>(reader: R, chunk_size: usize) -> io::Result<MerkleTree<Chunk>> {
+        MerkleTree::from_reader_with_config_and_digest(reader, chunk_size, TreeConfig::default())
+    }
+
+    /// Reads `reader` to the end in content-defined chunks cut by `cdc`, and builds a tree with
+    /// one leaf per chunk, hashing with Blake2b and the default config.
+    pub fn from_reader_content_defined<R: Read>(reader: R, cdc: FastCdc) -> io::Result<MerkleTree<Chunk>> {
+        MerkleTree::from_reader_content_defined_with_config_and_digest(reader, cdc, TreeConfig::default())
+    }
+
+    /// Reads the asynchronous `reader` to the end in chunks of `chunk_size` bytes and builds the
+    /// tree `from_reader` would build, hashing with Blake2b and the default config.
+    #[cfg(feature = "tokio")]
+    pub async fn from_async_reader<R: AsyncRead + Unpin>(reader: R, chunk_size: usize) -> io::Result<MerkleTree<Chunk>> {
+        MerkleTree::from_async_reader_with_config_and_digest(reader, chunk_size, TreeConfig::default()).await
+    }
+}
+
+impl<D> MerkleTree<Chunk, D> where D: Digest {
+    /// Reads `reader` to the end in chunks of `chunk_size` bytes, hashing with the digest `D`.
+    /// Only one chunk is held in memory at a time. Fails with `InvalidInput` when `chunk_size`
+    /// is zero or the stream is empty.
+    pub fn from_reader_with_config_and_digest<R: Read>(reader: R, chunk_size: usize, config: TreeConfig) -> io::Result<MerkleTree<Chunk, D>> {
+        if chunk_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "chunk size must not be zero"));
+        }
+
+        MerkleTree::from_reader_with_chunking(reader, Chunking::Fixed(chunk_size), config)
+    }
+
+    /// Reads `reader` to the end in content-defined chunks cut by `cdc`, hashing with the digest
+    /// `D`. At most one chunk of `cdc.max_size()` bytes is held in memory at a time. Fails with
+    /// `InvalidInput` when the stream is empty.
+    pub fn from_reader_content_defined_with_config_and_digest<R: Read>(reader: R, cdc: FastCdc, config: TreeConfig) -> io::Result<MerkleTree<Chunk, D>> {
+        MerkleTree::from_reader_with_chunking(reader, Chunking::ContentDefined(cdc), config)
+    }
+
+    /// Reads the asynchronous `reader` to the end in chunks of `chunk_size` bytes, hashing with
+    /// the digest `D`. Each read is awaited and hashed as it arrives, so the executor is never
+    /// blocked on the stream and only one chunk is held in memory at a time. Fails with
+    /// `InvalidInput` when `chunk_size` is zero or the stream is empty.
+    #[cfg(feature = "tokio")]
+    pub async fn from_async_reader_with_config_and_digest<R: AsyncRead + Unpin>(mut reader: R, chunk_size: usize, config: TreeConfig) -> io::Result<MerkleTree<Chunk, D>> {
+        if chunk_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "chunk size must not be zero"));
+        }
+
+        let mut buffer = vec![0; chunk_size];
+        let mut builder = ChunkTreeBuilder::<D>::with_chunking(Chunking::Fixed(chunk_size), config);
+
+        loop {
+            let len = reader.read(&mut buffer).await?;
+
+            if len == 0 {
+                break;
+            }
+
+            builder.write(&buffer[..len]).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        }
+
+        builder.finish().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    pub(crate) fn from_reader_with_chunking<R: Read>(mut reader: R, chunking: Chunking, config: TreeConfig) -> io::Result<MerkleTree<Chunk, D>> {
+        let buffer_size = match chunking {
+            Chunking::Fixed(chunk_size) => chunk_size,
+            Chunking::ContentDefined(cdc) => cdc.max_size(),
+        };
+        let mut buffer = vec![0; buffer_size];
+        let mut builder = ChunkTreeBuilder::<D>::with_chunking(chunking, config);
+
+        loop {
+            let len = read_chunk(&mut reader, &mut buffer)?;
+            builder.write(&buffer[..len]).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+            if len < buffer_size {
+                break;
+            }
+        }
+
+        builder.finish().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+}
+
+/// Builds the tree `MerkleTree::from_reader` would build from bytes written to it as they
+/// arrive, in pieces of any size. It never blocks on input, so it can be fed from
+/// asynchronous streams, like uploads read on an executor.
+pub struct ChunkTreeBuilder<D: Digest = Blake2b> {
+    config: TreeConfig,
+    chunking: Chunking,
+    buffer: Vec<u8>,
+    /// Number of bytes of the buffer scanned for a content-defined cut, and their gear hash.
+    scanned: usize,
+    gear_hash: u64,
+    offset: u64,
+    leaves: Vec<Node<Chunk, D::OutputSize>>,
+}
+
+impl ChunkTreeBuilder<Blake2b> {
+    /// Creates a builder for chunks of `chunk_size` bytes, hashing with Blake2b and the
+    /// default config.
+    pub fn new(chunk_size: usize) -> ChunkTreeBuilder {
+        ChunkTreeBuilder::with_config_and_digest(chunk_size, TreeConfig::default())
+    }
+
+    /// Creates a builder for chunks of `chunk_size` bytes, hashing with Blake2b.
+    pub fn with_config(chunk_size: usize, config: TreeConfig) -> ChunkTreeBuilder {
+        ChunkTreeBuilder::with_config_and_digest(chunk_size, config)
+    }
+
+    /// Creates a builder for content-defined chunks cut by `cdc`, hashing with Blake2b and the
+    /// default config.
+    pub fn content_defined(cdc: FastCdc) -> ChunkTreeBuilder {
+        ChunkTreeBuilder::content_defined_with_config_and_digest(cdc, TreeConfig::default())
+    }
+}
+
+impl<D> ChunkTreeBuilder<D> where D: Digest {
+    /// Creates a builder for chunks of `chunk_size` bytes, hashing with the digest `D`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn with_config_and_digest(chunk_size: usize, config: TreeConfig) -> ChunkTreeBuilder<D> {
+        assert!(chunk_size > 0, "chunk size must not be zero");
+        ChunkTreeBuilder::with_chunking(Chunking::Fixed(chunk_size), config)
+    }
+
+    /// Creates a builder for content-defined chunks cut by `cdc`, hashing with the digest `D`,
+    /// so that the trees of two versions of a stream share the leaves away from the edits.
+    pub fn content_defined_with_config_and_digest(cdc: FastCdc, config: TreeConfig) -> ChunkTreeBuilder<D> {
+        ChunkTreeBuilder::with_chunking(Chunking::ContentDefined(cdc), config)
+    }
+
+    fn with_chunking(chunking: Chunking, config: TreeConfig) -> ChunkTreeBuilder<D> {
+        ChunkTreeBuilder {
+            config,
+            chunking,
+            buffer: Vec::new(),
+            scanned: 0,
+            gear_hash: 0,
+            offset: 0,
+            leaves: Vec::new(),
+        }
+    }
+
+    /// Number of bytes written so far.
+    pub fn len(&self) -> u64 {
+        self.offset + self.buffer.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Hashes every chunk completed by `bytes`, keeping the rest until the next write. Full
+    /// fixed-size chunks of `bytes` are hashed in place rather than copied.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), MerkleError> {
+        match self.chunking {
+            Chunking::Fixed(chunk_size) => self.write_fixed(chunk_size, bytes),
+            Chunking::ContentDefined(cdc) => self.write_content_defined(cdc, bytes),
+        }
+    }
+
+    fn write_fixed(&mut self, chunk_size: usize, mut bytes: &[u8]) -> Result<(), MerkleError> {
+        if !self.buffer.is_empty() {
+            let missing = (chunk_size - self.buffer.len()).min(bytes.len());
+            self.buffer.extend_from_slice(&bytes[..missing]);
+            bytes = &bytes[missing..];
+
+            if self.buffer.len() < chunk_size {
+                return Ok(());
+            }
+
+            let chunk = core::mem::take(&mut self.buffer);
+            self.push_chunk(&chunk)?;
+        }
+
+        let mut chunks = bytes.chunks_exact(chunk_size);
+
+        for chunk in &mut chunks {
+            self.push_chunk(chunk)?;
+        }
+
+        self.buffer.extend_from_slice(chunks.remainder());
+        Ok(())
+    }
+
+    fn write_content_defined(&mut self, cdc: FastCdc, bytes: &[u8]) -> Result<(), MerkleError> {
+        self.buffer.extend_from_slice(bytes);
+
+        let mut start = 0;
+
+        while let Some(len) = cdc.find_cut(&self.buffer[start..], &mut self.scanned, &mut self.gear_hash) {
+            let chunk = self.buffer[start..start + len].to_vec();
+            self.push_chunk(&chunk)?;
+
+            start += len;
+            self.scanned = 0;
+            self.gear_hash = 0;
+        }
+
+        self.buffer.drain(..start);
+        Ok(())
+    }
+
+    /// Hashes the last, possibly shorter, chunk and builds the tree. Fails with `EmptyInput`
+    /// if nothing was written.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(bytes = self.offset + self.buffer.len() as u64)))]
+    pub fn finish(mut self) -> Result<MerkleTree<Chunk, D>, MerkleError> {
+        if !self.buffer.is_empty() {
+            let chunk = core::mem::take(&mut self.buffer);
+            self.push_chunk(&chunk)?;
+        }
+
+        MerkleTree::from_leaves(self.leaves, self.config)
+    }
+
+    fn push_chunk(&mut self, chunk: &[u8]) -> Result<(), MerkleError> {
+        check_leaf::<D>(self.config.leaf_mode, self.leaves.len(), chunk)?;
+
+        let hash = leaf_hash::<D>(self.config.prefixes, self.config.leaf_mode, chunk);
+        self.leaves.push(Node::Leaf(MerkleLeaf { hash, data: Chunk { offset: self.offset, len: chunk.len() } }));
+        if self.config.leaf_mode == LeafMode::Hashed {
+            metrics::record(|metrics| metrics.hashes_computed(1));
+        }
+        self.offset += chunk.len() as u64;
+
+        Ok(())
+    }
+}
+
+/// Fills `buffer` from `reader`, stopping early only at the end of the stream.
+fn read_chunk<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reader returning at most 3 bytes per call, to exercise short reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(3);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn it_builds_the_same_tree_as_the_chunks() {
+        let data: Vec<u8> = (0..100).collect();
+
+        for chunk_size in &[1, 7, 10, 64, 100, 1000] {
+            let merkle_tree = MerkleTree::from_reader(Trickle(&data), *chunk_size).unwrap();
+            let expected = MerkleTree::from_vec(data.chunks(*chunk_size).collect()).unwrap();
+
+            assert_eq!(merkle_tree.root_hash(), expected.root_hash());
+            assert_eq!(merkle_tree.leaf_count(), data.len().div_ceil(*chunk_size));
+            assert_eq!(merkle_tree.proof(0).unwrap(), expected.proof(0).unwrap());
+        }
+    }
+
+    #[test]
+    fn it_records_chunk_positions() {
+        let merkle_tree = MerkleTree::from_reader(&b"abcdefghij"[..], 4).unwrap();
+        let chunks: Vec<Chunk> = merkle_tree.leaves().map(|leaf| leaf.data).collect();
+
+        assert_eq!(chunks, vec![
+            Chunk { offset: 0, len: 4 },
+            Chunk { offset: 4, len: 4 },
+            Chunk { offset: 8, len: 2 },
+        ]);
+    }
+
+    #[test]
+    fn it_builds_the_same_tree_from_writes_of_any_size() {
+        let data: Vec<u8> = (0..100).collect();
+        let expected = MerkleTree::from_reader(&data[..], 8).unwrap();
+
+        for piece_len in &[1, 3, 8, 13, 100] {
+            let mut builder = ChunkTreeBuilder::new(8);
+
+            for piece in data.chunks(*piece_len) {
+                builder.write(piece).unwrap();
+            }
+
+            assert_eq!(builder.len(), 100);
+
+            let merkle_tree = builder.finish().unwrap();
+            assert_eq!(merkle_tree.root_hash(), expected.root_hash());
+            assert!(merkle_tree.leaves().eq(expected.leaves()));
+        }
+
+        assert_eq!(ChunkTreeBuilder::new(8).finish(), Err(MerkleError::EmptyInput));
+    }
+
+    #[test]
+    fn it_builds_content_defined_trees_from_writes_of_any_size() {
+        let cdc = FastCdc::new(64, 256, 1024);
+        let data = crate::cdc::tests::noise(20_000, 3);
+        let expected = MerkleTree::from_vec(cdc.chunks(&data).collect()).unwrap();
+
+        for piece_len in &[1, 100, 1024, 20_000] {
+            let mut builder = ChunkTreeBuilder::content_defined(cdc);
+
+            for piece in data.chunks(*piece_len) {
+                builder.write(piece).unwrap();
+            }
+
+            assert_eq!(builder.finish().unwrap().root_hash(), expected.root_hash());
+        }
+
+        let merkle_tree = MerkleTree::from_reader_content_defined(Trickle(&data), cdc).unwrap();
+        assert_eq!(merkle_tree.root_hash(), expected.root_hash());
+        assert_eq!(merkle_tree.leaves().map(|leaf| leaf.data.len).sum::<usize>(), data.len());
+    }
+
+    #[test]
+    fn it_rejects_empty_streams_and_chunks() {
+        assert_eq!(MerkleTree::from_reader(&b""[..], 4).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(MerkleTree::from_reader(&b"abc"[..], 0).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn it_builds_the_same_tree_from_async_readers() {
+        use tokio::io::AsyncWriteExt;
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let data: Vec<u8> = (0..100).collect();
+
+        runtime.block_on(async {
+            for chunk_size in &[1, 7, 64, 1000] {
+                // A pipe buffering 5 bytes, so that reads return short and wait on the writer.
+                let (mut writer, reader) = tokio::io::duplex(5);
+                let written = data.clone();
+                let writing = tokio::spawn(async move { writer.write_all(&written).await });
+
+                let merkle_tree = MerkleTree::from_async_reader(reader, *chunk_size).await.unwrap();
+                writing.await.unwrap().unwrap();
+
+                let expected = MerkleTree::from_reader(&data[..], *chunk_size).unwrap();
+                assert_eq!(merkle_tree.root_hash(), expected.root_hash());
+                assert!(merkle_tree.leaves().eq(expected.leaves()));
+            }
+
+            assert_eq!(MerkleTree::from_async_reader(&b""[..], 4).await.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(MerkleTree::from_async_reader(&b"abc"[..], 0).await.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        });
+    }
+}
@@ -0,0 +1,342 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+
+use blake2::Blake2b;
+use blake2::crypto_mac::Mac;
+use digest::Digest;
+use digest::generic_array::GenericArray;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+use crate::config::TreeConfig;
+use crate::error::MerkleError;
+use crate::leaf::LeafEncode;
+use crate::tree::MerkleTree;
+use crate::Hash;
+
+/// Source of the timestamps of checkpoints, in any unit the application agrees on with its
+/// auditors.
+type Clock = Box<dyn Fn() -> u64 + Send + Sync>;
+
+/// A MerkleTree recording a [`Checkpoint`] of its root after every push or update, for
+/// transparency-log style auditing. Checkpoints are numbered from 0, the tree it was created
+/// with, and can be handed to auditors signed with a [`CheckpointSigner`].
+pub struct RootLog<T, D: Digest = Blake2b> {
+    tree: MerkleTree<T, D>,
+    checkpoints: Vec<Checkpoint<D>>,
+    clock: Clock,
+}
+
+#[cfg(feature = "std")]
+impl<T> RootLog<T, Blake2b> where T: LeafEncode {
+    /// Creates a RootLog from a vector, hashing with Blake2b and the default config, with
+    /// checkpoints timestamped in seconds since the Unix epoch.
+    pub fn from_vec(data: Vec<T>) -> Result<RootLog<T>, MerkleError> {
+        Ok(RootLog::new(MerkleTree::from_vec(data)?))
+    }
+
+    /// Creates a RootLog from a vector, hashing with Blake2b, with checkpoints timestamped in
+    /// seconds since the Unix epoch.
+    pub fn from_vec_with_config(data: Vec<T>, config: TreeConfig) -> Result<RootLog<T>, MerkleError> {
+        Ok(RootLog::new(MerkleTree::from_vec_with_config(data, config)?))
+    }
+}
+
+impl<T, D> RootLog<T, D> where T: LeafEncode, D: Digest {
+    /// Starts logging the roots of `tree`, with checkpoints timestamped in seconds since the
+    /// Unix epoch.
+    #[cfg(feature = "std")]
+    pub fn new(tree: MerkleTree<T, D>) -> RootLog<T, D> {
+        RootLog::with_clock(tree, || {
+            std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+        })
+    }
+
+    /// Starts logging the roots of `tree`, with checkpoints timestamped by `clock`.
+    pub fn with_clock<C>(tree: MerkleTree<T, D>, clock: C) -> RootLog<T, D> where C: Fn() -> u64 + Send + Sync + 'static {
+        let mut log = RootLog { tree, checkpoints: Vec::new(), clock: Box::new(clock) };
+        log.record();
+        log
+    }
+
+    /// The tree as of the latest checkpoint.
+    pub fn tree(&self) -> &MerkleTree<T, D> {
+        &self.tree
+    }
+
+    /// Appends a leaf and returns the checkpoint recorded. No checkpoint is recorded when the
+    /// push fails.
+    pub fn push(&mut self, data: T) -> Result<&Checkpoint<D>, MerkleError> {
+        self.tree.push(data)?;
+        Ok(self.record())
+    }
+
+    /// Replaces the data of the leaf at `index` and returns the checkpoint recorded. No
+    /// checkpoint is recorded when the update fails.
+    pub fn update(&mut self, index: usize, data: T) -> Result<&Checkpoint<D>, MerkleError> {
+        self.tree.update(index, data)?;
+        Ok(self.record())
+    }
+
+    fn record(&mut self) -> &Checkpoint<D> {
+        let checkpoint = Checkpoint::new(
+            self.checkpoints.len() as u64,
+            self.tree.leaf_count() as u64,
+            GenericArray::clone_from_slice(self.tree.root_hash()),
+            (self.clock)(),
+        );
+
+        self.checkpoints.push(checkpoint);
+        self.latest()
+    }
+}
+
+impl<T, D> RootLog<T, D> where D: Digest {
+    /// Every checkpoint recorded, in sequence order.
+    pub fn checkpoints(&self) -> &[Checkpoint<D>] {
+        &self.checkpoints
+    }
+
+    pub fn latest(&self) -> &Checkpoint<D> {
+        self.checkpoints.last().expect("a checkpoint is recorded on creation")
+    }
+
+    /// The checkpoint with the given sequence number, if it was recorded.
+    pub fn checkpoint(&self, sequence: u64) -> Option<&Checkpoint<D>> {
+        usize::try_from(sequence).ok().and_then(|index| self.checkpoints.get(index))
+    }
+
+    /// The checkpoints recorded from `sequence` onwards, for auditors catching up.
+    pub fn since(&self, sequence: u64) -> &[Checkpoint<D>] {
+        let start = usize::try_from(sequence).unwrap_or(usize::MAX).min(self.checkpoints.len());
+        &self.checkpoints[start..]
+    }
+
+    /// Signs the checkpoint with the given sequence number, or returns `None` if it was not
+    /// recorded.
+    pub fn signed_checkpoint<S: CheckpointSigner>(&self, sequence: u64, signer: &S) -> Option<SignedCheckpoint<D>> {
+        self.checkpoint(sequence).map(|checkpoint| checkpoint.sign(signer))
+    }
+}
+
+impl<T, D> fmt::Debug for RootLog<T, D> where T: fmt::Debug, D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RootLog")
+            .field("tree", &self.tree)
+            .field("latest", self.latest())
+            .finish()
+    }
+}
+
+/// Root of a [`RootLog`] after one of its changes.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct Checkpoint<D: Digest = Blake2b> {
+    /// Position of the checkpoint in the log, increasing by one with every change.
+    pub sequence: u64,
+    /// Number of leaves of the tree.
+    pub tree_size: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash"))]
+    pub root: Hash<D>,
+    pub timestamp: u64,
+}
+
+impl<D> Checkpoint<D> where D: Digest {
+    pub fn new(sequence: u64, tree_size: u64, root: Hash<D>, timestamp: u64) -> Checkpoint<D> {
+        Checkpoint { sequence, tree_size, root, timestamp }
+    }
+
+    /// Message signed for the checkpoint: the sequence number, tree size and timestamp as
+    /// big-endian `u64`s, followed by the root.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(24 + self.root.len());
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.tree_size.to_be_bytes());
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        bytes.extend_from_slice(&self.root);
+        bytes
+    }
+
+    /// Whether this checkpoint and `other` give different roots for the same tree size, which
+    /// an honest log never signs: when both are signed by a log, it showed different trees to
+    /// the clients that received them.
+    pub fn conflicts_with(&self, other: &Checkpoint<D>) -> bool {
+        self.tree_size == other.tree_size && self.root != other.root
+    }
+
+    pub fn sign<S: CheckpointSigner>(&self, signer: &S) -> SignedCheckpoint<D> {
+        SignedCheckpoint { checkpoint: self.clone(), signature: signer.sign(&self.to_bytes()) }
+    }
+}
+
+/// Signs the messages of checkpoints.
+pub trait CheckpointSigner {
+    fn sign(&self, message: &[u8]) -> Vec<u8>;
+}
+
+/// Checks the signatures of checkpoints.
+pub trait CheckpointVerifier {
+    /// Whether `signature` is a valid signature of `message`.
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// Checkpoint along with a signature of its message.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct SignedCheckpoint<D: Digest = Blake2b> {
+    pub checkpoint: Checkpoint<D>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::bytes"))]
+    pub signature: Vec<u8>,
+}
+
+impl<D> SignedCheckpoint<D> where D: Digest {
+    /// Checks the signature, failing with `InvalidSignature` if it does not match the checkpoint.
+    pub fn verify<V: CheckpointVerifier>(&self, verifier: &V) -> Result<(), MerkleError> {
+        if verifier.verify(&self.checkpoint.to_bytes(), &self.signature) {
+            Ok(())
+        } else {
+            Err(MerkleError::InvalidSignature)
+        }
+    }
+}
+
+/// Signs and verifies checkpoints with a Blake2b MAC, for logs audited by parties sharing a
+/// secret key with it. Signatures are checked in constant time.
+#[derive(Clone)]
+pub struct MacSigner {
+    mac: Blake2b,
+}
+
+impl MacSigner {
+    /// # Panics
+    ///
+    /// Panics if the key is longer than 64 bytes.
+    pub fn new(key: &[u8]) -> MacSigner {
+        MacSigner { mac: Blake2b::new_varkey(key).expect("MAC key must be at most 64 bytes") }
+    }
+}
+
+impl CheckpointSigner for MacSigner {
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        let mut mac = self.mac.clone();
+        Mac::input(&mut mac, message);
+        Mac::result(mac).code().to_vec()
+    }
+}
+
+impl CheckpointVerifier for MacSigner {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let mut mac = self.mac.clone();
+        Mac::input(&mut mac, message);
+        Mac::verify(mac, signature).is_ok()
+    }
+}
+
+impl fmt::Debug for MacSigner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MacSigner").finish_non_exhaustive()
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for Checkpoint<D> where D: Digest {
+    fn clone(&self) -> Self {
+        Checkpoint::new(self.sequence, self.tree_size, self.root.clone(), self.timestamp)
+    }
+}
+
+impl<D> PartialEq for Checkpoint<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.sequence == other.sequence
+            && self.tree_size == other.tree_size
+            && self.root == other.root
+            && self.timestamp == other.timestamp
+    }
+}
+
+impl<D> fmt::Debug for Checkpoint<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Checkpoint")
+            .field("sequence", &self.sequence)
+            .field("tree_size", &self.tree_size)
+            .field("root", &crate::to_hex(&self.root))
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+impl<D> Clone for SignedCheckpoint<D> where D: Digest {
+    fn clone(&self) -> Self {
+        SignedCheckpoint { checkpoint: self.checkpoint.clone(), signature: self.signature.clone() }
+    }
+}
+
+impl<D> PartialEq for SignedCheckpoint<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.checkpoint == other.checkpoint
+            && self.signature == other.signature
+    }
+}
+
+impl<D> fmt::Debug for SignedCheckpoint<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SignedCheckpoint")
+            .field("checkpoint", &self.checkpoint)
+            .field("signature", &crate::to_hex(&self.signature))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn leaves(len: usize) -> Vec<String> {
+        (0..len).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn it_records_a_checkpoint_after_every_change() {
+        let ticks = Arc::new(AtomicU64::new(100));
+        let clock = {
+            let ticks = ticks.clone();
+            move || ticks.fetch_add(1, Ordering::SeqCst)
+        };
+        let mut log = RootLog::with_clock(MerkleTree::from_vec(leaves(3)).unwrap(), clock);
+
+        assert_eq!(log.push("3".to_string()).unwrap().sequence, 1);
+        assert_eq!(log.update(0, "updated".to_string()).unwrap().sequence, 2);
+        assert!(log.update(4, "4".to_string()).is_err());
+
+        let sizes: Vec<_> = log.checkpoints().iter().map(|c| (c.sequence, c.tree_size, c.timestamp)).collect();
+        assert_eq!(sizes, vec![(0, 3, 100), (1, 4, 101), (2, 4, 102)]);
+
+        assert_eq!(log.checkpoint(1).unwrap().root.as_slice(), MerkleTree::from_vec(leaves(4)).unwrap().root_hash());
+        assert_eq!(log.latest().root.as_slice(), log.tree().root_hash());
+        assert_eq!(log.since(1), &log.checkpoints()[1..]);
+        assert!(log.since(7).is_empty());
+        assert_eq!(log.checkpoint(3), None);
+    }
+
+    #[test]
+    fn it_signs_checkpoints_for_auditors() {
+        let log = RootLog::with_clock(MerkleTree::from_vec(leaves(5)).unwrap(), || 42);
+        let signer = MacSigner::new(b"log key");
+
+        let signed = log.signed_checkpoint(0, &signer).unwrap();
+        assert_eq!(signed.verify(&signer), Ok(()));
+        assert_eq!(signed.verify(&MacSigner::new(b"other key")), Err(MerkleError::InvalidSignature));
+
+        let mut forged = signed.clone();
+        forged.checkpoint.tree_size = 6;
+        assert_eq!(forged.verify(&signer), Err(MerkleError::InvalidSignature));
+
+        assert_eq!(log.signed_checkpoint(1, &signer), None);
+    }
+}
@@ -0,0 +1,213 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use blake2::Blake2b;
+use digest::Digest;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{apply_duplicate_policy, map_hashes, Hash};
+use crate::config::{LeafMode, Prefixes, TreeConfig};
+use crate::error::MerkleError;
+use crate::leaf::{LeafBytes, LeafEncode};
+use crate::proof::Proof;
+use crate::tree::{MerkleLeaf, MerkleTree, Node};
+
+/// Random bytes hashed before the data of a leaf. Salts must come from a cryptographically
+/// secure random number generator, or they do not hide anything.
+pub type Salt = [u8; 32];
+
+/// A MerkleTree whose leaf hashes mix a secret salt per leaf in with the data. A proof for one
+/// leaf reveals its salt, but the sibling hashes it contains cannot be matched against guessed
+/// values of the other leaves without their salts, as with allowlists of low-entropy addresses.
+pub struct SaltedMerkleTree<T, D: Digest = Blake2b> {
+    tree: MerkleTree<T, D>,
+    salts: Vec<Salt>,
+}
+
+impl<T> SaltedMerkleTree<T, Blake2b> where T: LeafEncode {
+    /// Creates a SaltedMerkleTree salting the `i`-th leaf with `salts[i]`, hashing with Blake2b
+    /// and the default config.
+    pub fn from_vec(data: Vec<T>, salts: Vec<Salt>) -> Result<SaltedMerkleTree<T>, MerkleError> {
+        SaltedMerkleTree::from_vec_with_config_and_digest(data, salts, TreeConfig::default())
+    }
+
+    pub fn from_vec_with_config(data: Vec<T>, salts: Vec<Salt>, config: TreeConfig) -> Result<SaltedMerkleTree<T>, MerkleError> {
+        SaltedMerkleTree::from_vec_with_config_and_digest(data, salts, config)
+    }
+}
+
+impl<T, D> SaltedMerkleTree<T, D> where T: LeafEncode, D: Digest {
+    /// Creates a SaltedMerkleTree salting the `i`-th leaf with `salts[i]`, hashing with the
+    /// digest `D`. The leaf mode of `config` is ignored, as salted data is always hashed. Fails
+    /// with `SaltCountMismatch` unless there is exactly one salt per leaf.
+    pub fn from_vec_with_config_and_digest(data: Vec<T>, salts: Vec<Salt>, config: TreeConfig) -> Result<SaltedMerkleTree<T, D>, MerkleError> {
+        if data.len() != salts.len() {
+            return Err(MerkleError::SaltCountMismatch { leaf_count: data.len(), salt_count: salts.len() });
+        }
+
+        let config = TreeConfig { leaf_mode: LeafMode::Hashed, ..config };
+        let hashes = {
+            let leaves: Vec<(&Salt, LeafBytes)> = salts.iter().zip(data.iter().map(LeafEncode::encode_leaf)).collect();
+            map_hashes(&leaves, |(salt, data)| salted_leaf_hash::<D>(config.prefixes, salt, data))
+        };
+        // Salts are dropped along with the duplicate leaves, so that each one stays with its leaf.
+        let leaves = data.into_iter().zip(hashes).zip(salts).collect();
+        let (leaves, salts) = apply_duplicate_policy(leaves, config.duplicates, |((_, hash), _)| hash)?
+            .into_iter()
+            .map(|((data, hash), salt)| (Node::Leaf(MerkleLeaf { hash, data }), salt))
+            .unzip();
+
+        Ok(SaltedMerkleTree { tree: MerkleTree::from_leaves(leaves, config)?, salts })
+    }
+
+    /// The underlying tree, whose proofs do not carry the salts.
+    pub fn tree(&self) -> &MerkleTree<T, D> {
+        &self.tree
+    }
+
+    pub fn root_hash(&self) -> &[u8] {
+        self.tree.root_hash()
+    }
+
+    pub fn salt(&self, index: usize) -> Option<&Salt> {
+        self.salts.get(index)
+    }
+
+    /// Generates an inclusion proof for the leaf at `leaf_index`, carrying its salt.
+    pub fn proof(&self, leaf_index: usize) -> Result<SaltedProof<D>, MerkleError> {
+        let proof = self.tree.proof(leaf_index)?;
+        Ok(SaltedProof { salt: self.salts[leaf_index], proof })
+    }
+}
+
+/// Inclusion proof for a leaf of a SaltedMerkleTree, with the salt of the leaf.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct SaltedProof<D: Digest = Blake2b> {
+    pub salt: Salt,
+    pub proof: Proof<D>,
+}
+
+impl<D> SaltedProof<D> where D: Digest {
+    /// Checks that `leaf_data`, salted with the proof's salt, is included in the tree with the
+    /// given root hash.
+    pub fn verify(&self, root_hash: &[u8], leaf_data: &[u8]) -> Result<(), MerkleError> {
+        self.proof.verify_hash(root_hash, &salted_leaf_hash::<D>(self.proof.prefixes, &self.salt, leaf_data))
+    }
+
+    /// Same as `verify`, for a tree built with `config`, whose leaf mode is ignored as it is when
+    /// building. Fails with `ConfigMismatch` unless the proof was generated with `config`.
+    pub fn verify_with_config(&self, root_hash: &[u8], leaf_data: &[u8], config: TreeConfig) -> Result<(), MerkleError> {
+        self.proof.check_config(TreeConfig { leaf_mode: LeafMode::Hashed, ..config })?;
+        self.verify(root_hash, leaf_data)
+    }
+}
+
+/// Hashes the salt followed by the data, after the leaf prefix if any.
+fn salted_leaf_hash<D: Digest>(prefixes: Option<Prefixes>, salt: &Salt, data: &[u8]) -> Hash<D> {
+    let mut hasher = D::new();
+
+    if let Some(prefixes) = prefixes {
+        hasher.input([prefixes.leaf]);
+    }
+
+    hasher.input(salt);
+    hasher.input(data);
+    hasher.result()
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for SaltedProof<D> where D: Digest {
+    fn clone(&self) -> Self {
+        SaltedProof {
+            salt: self.salt,
+            proof: self.proof.clone(),
+        }
+    }
+}
+
+impl<D> PartialEq for SaltedProof<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.salt == other.salt
+            && self.proof == other.proof
+    }
+}
+
+impl<D> fmt::Debug for SaltedProof<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SaltedProof")
+            .field("salt", &self.salt)
+            .field("proof", &self.proof)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DuplicatePolicy, Padding};
+
+    fn salts(len: usize) -> Vec<Salt> {
+        (0..len).map(|i| [i as u8 + 1; 32]).collect()
+    }
+
+    #[test]
+    fn it_proves_salted_leaves() {
+        for &padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            let data = vec!["alice", "bob", "carol", "dave", "erin"];
+            let merkle_tree = SaltedMerkleTree::from_vec_with_config(data.clone(), salts(5), TreeConfig::new(padding)).unwrap();
+
+            for (index, leaf) in data.iter().enumerate() {
+                let proof = merkle_tree.proof(index).unwrap();
+
+                assert_eq!(&proof.salt, merkle_tree.salt(index).unwrap());
+                assert!(proof.verify(merkle_tree.root_hash(), leaf.as_bytes()).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn it_rejects_wrong_data_and_salts() {
+        let merkle_tree = SaltedMerkleTree::from_vec(vec!["alice", "bob", "carol"], salts(3)).unwrap();
+        let mut proof = merkle_tree.proof(1).unwrap();
+
+        assert_eq!(proof.verify(merkle_tree.root_hash(), b"carol"), Err(MerkleError::RootMismatch));
+
+        // Without the salt, the leaf cannot be checked against guessed data.
+        assert_eq!(proof.proof.verify(merkle_tree.root_hash(), b"bob"), Err(MerkleError::RootMismatch));
+
+        proof.salt = [0; 32];
+        assert_eq!(proof.verify(merkle_tree.root_hash(), b"bob"), Err(MerkleError::RootMismatch));
+    }
+
+    #[test]
+    fn it_hides_leaves_behind_their_salts() {
+        let data = vec!["alice", "bob"];
+        let salted = SaltedMerkleTree::from_vec(data.clone(), salts(2)).unwrap();
+        let resalted = SaltedMerkleTree::from_vec(data.clone(), vec![[7; 32], [8; 32]]).unwrap();
+        let unsalted = MerkleTree::from_vec(data).unwrap();
+
+        assert_ne!(salted.root_hash(), resalted.root_hash());
+        assert_ne!(salted.tree().get_leaf(0).unwrap().hash, unsalted.get_leaf(0).unwrap().hash);
+    }
+
+    #[test]
+    fn it_requires_one_salt_per_leaf() {
+        assert_eq!(SaltedMerkleTree::from_vec(vec!["alice", "bob"], salts(1)).err(), Some(MerkleError::SaltCountMismatch { leaf_count: 2, salt_count: 1 }));
+        assert_eq!(SaltedMerkleTree::from_vec(vec!["alice"], salts(2)).err(), Some(MerkleError::SaltCountMismatch { leaf_count: 1, salt_count: 2 }));
+    }
+
+    #[test]
+    fn it_keeps_salts_aligned_with_deduplicated_leaves() {
+        let config = TreeConfig { duplicates: DuplicatePolicy::Deduplicate, ..TreeConfig::default() };
+        let data = vec!["alice", "bob", "alice", "carol"];
+        let mut salts = salts(4);
+        salts[2] = salts[0];
+        let merkle_tree = SaltedMerkleTree::from_vec_with_config(data, salts.clone(), config).unwrap();
+
+        assert_eq!(merkle_tree.tree().leaf_count(), 3);
+        assert_eq!(merkle_tree.salt(2), Some(&salts[3]));
+        assert!(merkle_tree.proof(2).unwrap().verify(merkle_tree.root_hash(), b"carol").is_ok());
+    }
+}
@@ -0,0 +1,218 @@
+//! Serde helpers for hash fields.
+//! Hashes are written as hex strings in human-readable formats and as raw bytes otherwise.
+
+use alloc::format;
+use alloc::vec::Vec;
+use core::fmt;
+
+use digest::Digest;
+use digest::generic_array::{ArrayLength, GenericArray};
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::{SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+
+use crate::proof::{ProofStep, Side};
+use crate::{from_hex, to_hex};
+
+pub fn serialize<S>(hash: &[u8], serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&to_hex(hash))
+    } else {
+        serializer.serialize_bytes(hash)
+    }
+}
+
+pub fn deserialize<'de, De, N>(deserializer: De) -> Result<GenericArray<u8, N>, De::Error>
+    where De: Deserializer<'de>, N: ArrayLength<u8> {
+    let bytes = if deserializer.is_human_readable() {
+        deserializer.deserialize_str(HashVisitor)?
+    } else {
+        deserializer.deserialize_bytes(HashVisitor)?
+    };
+
+    GenericArray::from_exact_iter(bytes.iter().cloned())
+        .ok_or_else(|| de::Error::invalid_length(bytes.len(), &&*format!("a hash of {} bytes", N::to_usize())))
+}
+
+struct HashVisitor;
+
+impl<'de> Visitor<'de> for HashVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a hash as a hex string or a byte array")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> where E: de::Error {
+        from_hex(v).map_err(E::custom)
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E> where E: de::Error {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> where E: de::Error {
+        Ok(v)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error> where A: SeqAccess<'de> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+
+        Ok(bytes)
+    }
+}
+
+#[derive(Serialize)]
+struct HashRef<'a>(#[serde(with = "crate::serde_hash")] &'a [u8]);
+
+#[derive(Deserialize)]
+struct HashBuf<N: ArrayLength<u8>>(#[serde(with = "crate::serde_hash")] GenericArray<u8, N>);
+
+/// Same encoding as the parent module, applied to every hash of a list.
+pub mod list {
+    use super::*;
+
+    pub fn serialize<S, N>(hashes: &[GenericArray<u8, N>], serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer, N: ArrayLength<u8> {
+        let mut seq = serializer.serialize_seq(Some(hashes.len()))?;
+
+        for hash in hashes {
+            seq.serialize_element(&HashRef(hash))?;
+        }
+
+        seq.end()
+    }
+
+    pub fn deserialize<'de, De, N>(deserializer: De) -> Result<Vec<GenericArray<u8, N>>, De::Error>
+        where De: Deserializer<'de>, N: ArrayLength<u8> {
+        let hashes = Vec::<HashBuf<N>>::deserialize(deserializer)?;
+
+        Ok(hashes.into_iter().map(|HashBuf(hash)| hash).collect())
+    }
+}
+
+/// Same encoding as the parent module, for byte strings of any length like signatures.
+pub mod bytes {
+    use super::*;
+
+    pub use super::serialize;
+
+    pub fn deserialize<'de, De>(deserializer: De) -> Result<Vec<u8>, De::Error> where De: Deserializer<'de> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(HashVisitor)
+        } else {
+            deserializer.deserialize_bytes(HashVisitor)
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SiblingRef<'a>(Side, #[serde(with = "crate::serde_hash")] &'a [u8]);
+
+#[derive(Deserialize)]
+struct SiblingBuf<N: ArrayLength<u8>>(Side, #[serde(with = "crate::serde_hash")] GenericArray<u8, N>);
+
+/// Same encoding as the parent module, applied to every hash of a proof's sibling list.
+pub mod siblings {
+    use super::*;
+
+    pub fn serialize<S, D>(siblings: &[ProofStep<D>], serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer, D: Digest {
+        let mut seq = serializer.serialize_seq(Some(siblings.len()))?;
+
+        for step in siblings {
+            seq.serialize_element(&SiblingRef(step.side, &step.hash))?;
+        }
+
+        seq.end()
+    }
+
+    pub fn deserialize<'de, De, D>(deserializer: De) -> Result<Vec<ProofStep<D>>, De::Error>
+        where De: Deserializer<'de>, D: Digest {
+        let siblings = Vec::<SiblingBuf<D::OutputSize>>::deserialize(deserializer)?;
+
+        Ok(siblings.into_iter().map(|SiblingBuf(side, hash)| ProofStep::new(side, hash)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ConsistencyProof, MerkleTree, MultiProof, Proof};
+
+    #[test]
+    fn it_round_trips_tree_through_json() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a".to_string(), "b".to_string(), "c".to_string()]).unwrap();
+
+        let json = serde_json::to_string(&merkle_tree).unwrap();
+        let decoded: MerkleTree<String> = serde_json::from_str(&json).unwrap();
+
+        assert!(json.contains(&merkle_tree.root_hash_hex()));
+        assert_eq!(decoded.root, merkle_tree.root);
+        assert_eq!(decoded.proof(2), merkle_tree.proof(2));
+    }
+
+    #[test]
+    fn it_round_trips_proof_through_json() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]).unwrap();
+        let proof = merkle_tree.proof(1).unwrap();
+
+        let json = serde_json::to_value(&proof).unwrap();
+        let decoded: Proof = serde_json::from_value(json.clone()).unwrap();
+
+        assert_eq!(json["siblings"][0][0], "Left");
+        assert_eq!(json["siblings"][0][1], crate::to_hex(&proof.siblings[0].hash));
+        assert_eq!(decoded, proof);
+        assert!(decoded.verify(merkle_tree.root_hash(), "b".as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn it_round_trips_proof_through_binary_format() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]).unwrap();
+        let proof = merkle_tree.proof(3).unwrap();
+
+        let bytes = bincode::serialize(&proof).unwrap();
+        let decoded: Proof = bincode::deserialize(&bytes).unwrap();
+
+        assert_eq!(decoded, proof);
+    }
+
+    #[test]
+    fn it_round_trips_consistency_proof_through_json() {
+        let merkle_tree = MerkleTree::from_vec_rfc6962(vec!["a", "b", "c", "d", "e"]).unwrap();
+        let proof = merkle_tree.consistency_proof(3, 5).unwrap();
+
+        let json = serde_json::to_string(&proof).unwrap();
+        let decoded: ConsistencyProof<sha2::Sha256> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(decoded, proof);
+    }
+
+    #[test]
+    fn it_round_trips_multi_proof_through_json() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d", "e"]).unwrap();
+        let proof = merkle_tree.multi_proof(&[0, 3]).unwrap();
+
+        let json = serde_json::to_string(&proof).unwrap();
+        let decoded: MultiProof = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(decoded, proof);
+    }
+
+    #[test]
+    fn it_rejects_invalid_hex_hashes() {
+        let json = r#"{"leaf_index":0,"siblings":[["Left","zz"]]}"#;
+
+        assert!(serde_json::from_str::<Proof>(json).is_err());
+    }
+
+    #[test]
+    fn it_rejects_hashes_of_the_wrong_length() {
+        let json = r#"{"leaf_index":0,"siblings":[["Left","abcd"]]}"#;
+
+        assert!(serde_json::from_str::<Proof>(json).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+//! Ed25519 signatures of checkpoints, so that anyone holding the public key of a log can check
+//! the roots it publishes.
+//!
+//! Logs cannot show different trees to different clients without signing two checkpoints that
+//! contradict each other, which clients find by gossiping the signed checkpoints they received,
+//! as with `Checkpoint::conflicts_with`.
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+
+use crate::error::MerkleError;
+use crate::root_log::{CheckpointSigner, CheckpointVerifier};
+
+/// Signs checkpoints with an Ed25519 secret key.
+#[derive(Clone)]
+pub struct Ed25519Signer {
+    key: SigningKey,
+}
+
+impl Ed25519Signer {
+    /// Creates a signer from a 32-byte Ed25519 secret key, which must come from a
+    /// cryptographically secure random number generator.
+    pub fn from_bytes(secret_key: &[u8; 32]) -> Ed25519Signer {
+        Ed25519Signer { key: SigningKey::from_bytes(secret_key) }
+    }
+
+    /// Verifier for the signatures of this signer, to hand to auditors.
+    pub fn verifier(&self) -> Ed25519Verifier {
+        Ed25519Verifier { key: self.key.verifying_key() }
+    }
+}
+
+impl CheckpointSigner for Ed25519Signer {
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.key.sign(message).to_bytes().to_vec()
+    }
+}
+
+impl fmt::Debug for Ed25519Signer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Ed25519Signer")
+            .field("verifier", &self.verifier())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Checks the Ed25519 signatures of checkpoints against the public key of a log. Signatures are
+/// checked strictly, rejecting the malleable and weak-key signatures that plain Ed25519
+/// verification accepts.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Ed25519Verifier {
+    key: VerifyingKey,
+}
+
+impl Ed25519Verifier {
+    /// Creates a verifier from a 32-byte Ed25519 public key, failing with `InvalidKey` if it is
+    /// not the encoding of a curve point.
+    pub fn from_bytes(public_key: &[u8; 32]) -> Result<Ed25519Verifier, MerkleError> {
+        VerifyingKey::from_bytes(public_key)
+            .map(|key| Ed25519Verifier { key })
+            .map_err(|_| MerkleError::InvalidKey)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.key.to_bytes()
+    }
+}
+
+impl CheckpointVerifier for Ed25519Verifier {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        Signature::try_from(signature).is_ok_and(|signature| self.key.verify_strict(message, &signature).is_ok())
+    }
+}
+
+impl fmt::Debug for Ed25519Verifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Ed25519Verifier")
+            .field(&crate::to_hex(self.key.as_bytes()))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MerkleTree, RootLog};
+
+    #[test]
+    fn it_signs_and_verifies_checkpoints() {
+        let mut log = RootLog::with_clock(MerkleTree::from_vec(vec!["a", "b", "c"]).unwrap(), || 7);
+        log.push("d").unwrap();
+
+        let signer = Ed25519Signer::from_bytes(&[1; 32]);
+        let verifier = Ed25519Verifier::from_bytes(&signer.verifier().to_bytes()).unwrap();
+        let signed = log.signed_checkpoint(1, &signer).unwrap();
+
+        assert_eq!(signed.signature.len(), 64);
+        assert_eq!(signed.verify(&verifier), Ok(()));
+        assert_eq!(signed.verify(&Ed25519Signer::from_bytes(&[2; 32]).verifier()), Err(MerkleError::InvalidSignature));
+
+        let mut forged = signed.clone();
+        forged.checkpoint.timestamp = 8;
+        assert_eq!(forged.verify(&verifier), Err(MerkleError::InvalidSignature));
+
+        forged.signature.truncate(10);
+        assert_eq!(forged.verify(&verifier), Err(MerkleError::InvalidSignature));
+    }
+
+    #[test]
+    fn it_detects_split_views_from_signed_checkpoints() {
+        let signer = Ed25519Signer::from_bytes(&[3; 32]);
+        let honest = RootLog::with_clock(MerkleTree::from_vec(vec!["a", "b"]).unwrap(), || 1);
+        let forked = RootLog::with_clock(MerkleTree::from_vec(vec!["a", "x"]).unwrap(), || 1);
+
+        let seen_by_alice = honest.signed_checkpoint(0, &signer).unwrap();
+        let seen_by_bob = forked.signed_checkpoint(0, &signer).unwrap();
+
+        assert_eq!(seen_by_alice.verify(&signer.verifier()), Ok(()));
+        assert_eq!(seen_by_bob.verify(&signer.verifier()), Ok(()));
+        assert!(seen_by_alice.checkpoint.conflicts_with(&seen_by_bob.checkpoint));
+        assert!(!seen_by_alice.checkpoint.conflicts_with(&seen_by_alice.checkpoint));
+    }
+}
@@ -0,0 +1,112 @@
+use alloc::sync::Arc;
+use core::fmt;
+use core::ops::Deref;
+
+use blake2::Blake2b;
+use digest::Digest;
+
+use crate::tree::MerkleTree;
+
+/// Immutable view of a MerkleTree as it was when `MerkleTree::snapshot` was called, which
+/// dereferences to that tree to answer root and proof queries. The snapshot shares every node
+/// with the tree: appends and updates copy the nodes on their path instead of modifying them,
+/// so the snapshot stays consistent while the tree keeps changing. Cloning a snapshot is cheap,
+/// in order to hand it to the threads serving proofs.
+pub struct MerkleSnapshot<T, D: Digest = Blake2b> {
+    tree: Arc<MerkleTree<T, D>>,
+}
+
+impl<T, D> MerkleTree<T, D> where T: Clone, D: Digest {
+    /// Takes a snapshot of the tree. Only the root is copied, and the data of the single leaf
+    /// of a tree with one leaf. The snapshot does not copy the index of the leaves either, so
+    /// `index_of` scans its leaves.
+    pub fn snapshot(&self) -> MerkleSnapshot<T, D> {
+        MerkleSnapshot { tree: Arc::new(self.clone_unindexed()) }
+    }
+}
+
+impl<T, D> Deref for MerkleSnapshot<T, D> where D: Digest {
+    type Target = MerkleTree<T, D>;
+
+    fn deref(&self) -> &MerkleTree<T, D> {
+        &self.tree
+    }
+}
+
+// Implemented by hand so that `T` and `D` are not required to implement `Clone`.
+impl<T, D> Clone for MerkleSnapshot<T, D> where D: Digest {
+    fn clone(&self) -> Self {
+        MerkleSnapshot { tree: self.tree.clone() }
+    }
+}
+
+impl<T, D> fmt::Debug for MerkleSnapshot<T, D> where T: fmt::Debug, D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("MerkleSnapshot").field(&*self.tree).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Node, Padding, TreeConfig};
+
+    fn leaves(len: usize) -> Vec<String> {
+        (0..len).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn it_keeps_roots_and_proofs_while_the_tree_changes() {
+        for &padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            let config = TreeConfig::new(padding);
+            let mut merkle_tree = MerkleTree::from_vec_with_config(leaves(5), config).unwrap();
+            let snapshot = merkle_tree.snapshot();
+
+            merkle_tree.push("5".to_string()).unwrap();
+            merkle_tree.update(4, "updated".to_string()).unwrap();
+            merkle_tree.update(0, "updated".to_string()).unwrap();
+
+            let expected = MerkleTree::from_vec_with_config(leaves(5), config).unwrap();
+            assert_eq!(snapshot.root_hash(), expected.root_hash());
+            assert_eq!(snapshot.root, expected.root);
+            assert!(snapshot.proof(4).unwrap().verify(snapshot.root_hash(), b"4").is_ok());
+
+            let mut changed = leaves(6);
+            changed[0] = "updated".to_string();
+            changed[4] = "updated".to_string();
+            assert_eq!(merkle_tree.root, MerkleTree::from_vec_with_config(changed, config).unwrap().root);
+        }
+    }
+
+    #[test]
+    fn it_shares_nodes_off_the_changed_path() {
+        let mut merkle_tree = MerkleTree::from_vec(leaves(4)).unwrap();
+        let snapshot = merkle_tree.snapshot();
+
+        merkle_tree.update(0, "updated".to_string()).unwrap();
+
+        match (&snapshot.root, &merkle_tree.root) {
+            (Node::Node(old), Node::Node(new)) => {
+                assert!(Arc::ptr_eq(&old.right, &new.right));
+                assert!(!Arc::ptr_eq(&old.left, &new.left));
+            }
+            _ => panic!("trees with 4 leaves have an inner root"),
+        }
+    }
+
+    #[test]
+    fn it_serves_proofs_from_other_threads() {
+        let mut merkle_tree = MerkleTree::from_vec(leaves(8)).unwrap();
+        let snapshot = merkle_tree.snapshot();
+        let reader = {
+            let snapshot = snapshot.clone();
+            std::thread::spawn(move || (0..8).all(|i| snapshot.proof(i).unwrap().verify(snapshot.root_hash(), i.to_string().as_bytes()).is_ok()))
+        };
+
+        merkle_tree.extend(leaves(8));
+
+        assert!(reader.join().unwrap());
+        assert_eq!(snapshot.leaf_count(), 8);
+        assert_eq!(merkle_tree.leaf_count(), 16);
+    }
+}
@@ -0,0 +1,410 @@
+//! Off-chain mirror of the concurrent Merkle trees of Solana's account compression program
+//! (`spl-concurrent-merkle-tree`), for indexers serving proofs of compressed accounts.
+//!
+//! Trees have a fixed depth and 32-byte leaves, with empty leaves set to zero bytes. Nodes are
+//! the Keccak-256 hash of their two children without prefixes, so the empty subtree of height
+//! `h` hashes two empty subtrees of height `h - 1`. Every change records a [`SolanaChangeLog`] with
+//! the new nodes on the path of the modified leaf, which lets proofs generated against one of the
+//! recent roots be fast-forwarded to the current one. The top levels of a tree can be cached on
+//! chain in a canopy, in which case clients only submit the lower part of their proofs.
+
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use sha3::{Digest, Keccak256};
+
+use crate::error::MerkleError;
+
+/// Node or leaf of a tree.
+type Node = [u8; 32];
+
+/// Largest depth supported by the on-chain program, whose leaf indices are `u32`s.
+const MAX_DEPTH: usize = 30;
+
+/// Nodes written by one change: `path` holds the new nodes on the path of the leaf at `index`,
+/// from the leaf itself up to the child of the root.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SolanaChangeLog {
+    pub root: Node,
+    pub path: Vec<Node>,
+    pub index: u32,
+}
+
+impl SolanaChangeLog {
+    /// Updates a proof of the leaf at `index` for the tree before this change so that it holds
+    /// for the tree after it, like `ChangeLog::update_proof_or_leaf`. When this change replaced
+    /// that very leaf, the leaf is updated instead.
+    pub fn update_proof_or_leaf(&self, index: u32, proof: &mut [Node], leaf: &mut Node) {
+        if index == self.index {
+            *leaf = self.path[0];
+        } else {
+            // The paths of both leaves meet above the highest bit in which their indices differ,
+            // where the node of this change is the sibling on the path of `index`.
+            let critbit = (u32::BITS - 1 - (index ^ self.index).leading_zeros()) as usize;
+            proof[critbit] = self.path[critbit];
+        }
+    }
+}
+
+impl fmt::Debug for SolanaChangeLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SolanaChangeLog")
+            .field("root", &crate::to_hex(&self.root))
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+/// Full copy of a concurrent Merkle tree of a given depth, replaying appends and replacements
+/// in the order the program applied them. It keeps the last `max_buffer_size` change logs,
+/// as the on-chain account does, to fast-forward proofs of recent roots.
+#[derive(Clone)]
+pub struct SolanaMerkleTree {
+    max_depth: usize,
+    max_buffer_size: usize,
+    canopy_depth: usize,
+    /// `levels[h]` holds the nodes of height `h` covering leaves that were appended, left to
+    /// right. Nodes past them are empty subtrees.
+    levels: Vec<Vec<Node>>,
+    /// Hash of the empty subtree of every height, up to `max_depth`.
+    empty_nodes: Vec<Node>,
+    change_logs: VecDeque<SolanaChangeLog>,
+    sequence_number: u64,
+}
+
+impl SolanaMerkleTree {
+    /// Creates an empty tree, like `ConcurrentMerkleTree::initialize`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_depth` is zero or greater than `MAX_DEPTH`, or if `max_buffer_size` is zero.
+    pub fn new(max_depth: usize, max_buffer_size: usize) -> SolanaMerkleTree {
+        SolanaMerkleTree::with_canopy(max_depth, max_buffer_size, 0)
+    }
+
+    /// Creates an empty tree whose top `canopy_depth` levels below the root are cached on chain.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_depth` is zero or greater than `MAX_DEPTH`, if `max_buffer_size` is zero, or
+    /// if `canopy_depth` is not smaller than `max_depth`.
+    pub fn with_canopy(max_depth: usize, max_buffer_size: usize, canopy_depth: usize) -> SolanaMerkleTree {
+        assert!(max_depth > 0 && max_depth <= MAX_DEPTH, "depth must be between 1 and {}", MAX_DEPTH);
+        assert!(max_buffer_size > 0, "buffer size must not be zero");
+        assert!(canopy_depth < max_depth, "canopy must be smaller than the tree");
+
+        let empty_nodes = empty_nodes(max_depth);
+        let initial = SolanaChangeLog { root: empty_nodes[max_depth], path: empty_nodes[..max_depth].to_vec(), index: 0 };
+
+        SolanaMerkleTree {
+            max_depth,
+            max_buffer_size,
+            canopy_depth,
+            levels: vec![Vec::new(); max_depth + 1],
+            empty_nodes,
+            change_logs: vec![initial].into(),
+            sequence_number: 0,
+        }
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn canopy_depth(&self) -> usize {
+        self.canopy_depth
+    }
+
+    /// Number of changes applied since the tree was created.
+    pub fn sequence_number(&self) -> u64 {
+        self.sequence_number
+    }
+
+    /// Number of leaves appended, which is the index of the next one.
+    pub fn rightmost_index(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn root(&self) -> Node {
+        self.node(self.max_depth, 0)
+    }
+
+    /// Returns the leaf at `index`, which is empty past the rightmost leaf.
+    pub fn leaf(&self, index: usize) -> Node {
+        self.node(0, index)
+    }
+
+    /// The change logs kept, from the oldest to the latest.
+    pub fn change_logs(&self) -> impl Iterator<Item = &SolanaChangeLog> {
+        self.change_logs.iter()
+    }
+
+    /// Appends a leaf, like `ConcurrentMerkleTree::append`, and returns its index. Fails with
+    /// `TreeFull` when all `2^max_depth` leaves were appended.
+    pub fn append(&mut self, leaf: Node) -> Result<usize, MerkleError> {
+        let index = self.rightmost_index();
+
+        if index == 1 << self.max_depth {
+            return Err(MerkleError::TreeFull { capacity: index });
+        }
+
+        self.write(index, leaf);
+        Ok(index)
+    }
+
+    /// Replaces the leaf at `index`, like `ConcurrentMerkleTree::set_leaf`. Only appended leaves
+    /// can be replaced.
+    pub fn replace(&mut self, index: usize, leaf: Node) -> Result<(), MerkleError> {
+        if index >= self.rightmost_index() {
+            return Err(MerkleError::IndexOutOfBounds { index, leaf_count: self.rightmost_index() });
+        }
+
+        self.write(index, leaf);
+        Ok(())
+    }
+
+    /// Full proof of the leaf at `index`, from its sibling up to the child of the root.
+    pub fn proof(&self, index: usize) -> Result<Vec<Node>, MerkleError> {
+        if index >= 1 << self.max_depth {
+            return Err(MerkleError::IndexOutOfBounds { index, leaf_count: 1 << self.max_depth });
+        }
+
+        Ok((0..self.max_depth).map(|height| self.node(height, (index >> height) ^ 1)).collect())
+    }
+
+    /// Proof of the leaf at `index` without the levels cached in the canopy, as submitted to the
+    /// program.
+    pub fn truncated_proof(&self, index: usize) -> Result<Vec<Node>, MerkleError> {
+        let mut proof = self.proof(index)?;
+        proof.truncate(self.max_depth - self.canopy_depth);
+        Ok(proof)
+    }
+
+    /// The canopy as stored in the account: the nodes of the top `canopy_depth` levels below the
+    /// root, level by level from the top, left to right. Nodes that only cover leaves past the
+    /// rightmost one were never written and are zero bytes.
+    pub fn canopy(&self) -> Vec<Node> {
+        let mut canopy = Vec::with_capacity((2 << self.canopy_depth) - 2);
+
+        for depth in 1..=self.canopy_depth {
+            let height = self.max_depth - depth;
+
+            for index in 0..1 << depth {
+                canopy.push(if index < self.levels[height].len() { self.levels[height][index] } else { [0; 32] });
+            }
+        }
+
+        canopy
+    }
+
+    /// Completes a truncated proof of the leaf at `index` with the nodes of `canopy`, like
+    /// `fill_in_proof_from_canopy`, in a tree of `max_depth` levels.
+    pub fn fill_proof_from_canopy(canopy: &[Node], max_depth: usize, index: usize, proof: &mut Vec<Node>) -> Result<(), MerkleError> {
+        let canopy_depth = ((canopy.len() + 2).trailing_zeros() as usize).saturating_sub(1);
+
+        if canopy.len() != (2 << canopy_depth) - 2 || proof.len() + canopy_depth != max_depth {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let empty_nodes = empty_nodes(max_depth);
+
+        for height in proof.len()..max_depth {
+            let depth = max_depth - height;
+            let node = canopy[(1 << depth) + ((index >> height) ^ 1) - 2];
+            proof.push(if node == [0; 32] { empty_nodes[height] } else { node });
+        }
+
+        Ok(())
+    }
+
+    /// Fast-forwards the proof of `leaf` at `index` for `root`, one of the roots of the change
+    /// logs kept, to the current root, as the program does when a proof is submitted after
+    /// other changes were applied. Returns the current leaf at `index`, which differs from
+    /// `leaf` when it was replaced since. Fails with `RootMismatch` when the proof does not hold
+    /// for `root` or the root is no longer in the buffer.
+    pub fn fast_forward(&self, root: &Node, index: usize, leaf: Node, proof: &[Node]) -> Result<(Node, Vec<Node>), MerkleError> {
+        if proof.len() != self.max_depth || index >= 1 << self.max_depth {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        if &SolanaMerkleTree::recompute(leaf, proof, index) != root {
+            return Err(MerkleError::RootMismatch);
+        }
+
+        let start = self.change_logs.iter().rposition(|log| &log.root == root).ok_or(MerkleError::RootMismatch)?;
+        let (mut leaf, mut proof) = (leaf, proof.to_vec());
+
+        for log in self.change_logs.iter().skip(start + 1) {
+            log.update_proof_or_leaf(index as u32, &mut proof, &mut leaf);
+        }
+
+        Ok((leaf, proof))
+    }
+
+    /// Root a full proof of `leaf` at `index` hashes up to, like `recompute`.
+    pub fn recompute(leaf: Node, proof: &[Node], index: usize) -> Node {
+        proof.iter().enumerate().fold(leaf, |node, (height, sibling)| hash_to_parent(node, *sibling, (index >> height) & 1 == 0))
+    }
+
+    fn node(&self, height: usize, index: usize) -> Node {
+        self.levels[height].get(index).copied().unwrap_or(self.empty_nodes[height])
+    }
+
+    /// Writes `leaf` at `index`, recomputes its path and records the change.
+    fn write(&mut self, index: usize, leaf: Node) {
+        let mut path = Vec::with_capacity(self.max_depth);
+        let mut node = leaf;
+
+        for height in 0..=self.max_depth {
+            let position = index >> height;
+            let level = &mut self.levels[height];
+
+            if position == level.len() {
+                level.push(node);
+            } else {
+                level[position] = node;
+            }
+
+            if height < self.max_depth {
+                path.push(node);
+                node = hash_to_parent(node, self.node(height, position ^ 1), position & 1 == 0);
+            }
+        }
+
+        if self.change_logs.len() == self.max_buffer_size {
+            self.change_logs.pop_front();
+        }
+
+        self.change_logs.push_back(SolanaChangeLog { root: node, path, index: index as u32 });
+        self.sequence_number += 1;
+    }
+}
+
+impl fmt::Debug for SolanaMerkleTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SolanaMerkleTree")
+            .field("root", &crate::to_hex(&self.root()))
+            .field("max_depth", &self.max_depth)
+            .field("canopy_depth", &self.canopy_depth)
+            .field("rightmost_index", &self.rightmost_index())
+            .field("sequence_number", &self.sequence_number)
+            .finish()
+    }
+}
+
+fn hash_to_parent(node: Node, sibling: Node, is_left: bool) -> Node {
+    let (left, right) = if is_left { (node, sibling) } else { (sibling, node) };
+    let mut hasher = Keccak256::new();
+    hasher.input(left);
+    hasher.input(right);
+    hasher.result().into()
+}
+
+fn empty_nodes(max_depth: usize) -> Vec<Node> {
+    let mut nodes = vec![[0; 32]];
+
+    for height in 0..max_depth {
+        let empty = nodes[height];
+        nodes.push(hash_to_parent(empty, empty, true));
+    }
+
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(i: usize) -> Node {
+        Keccak256::digest(&i.to_be_bytes()).into()
+    }
+
+    /// Root of a full tree of `2^depth` leaves, computed level by level.
+    fn dense_root(leaves: &[Node], depth: usize) -> Node {
+        let mut level: Vec<Node> = (0..1 << depth).map(|i| leaves.get(i).copied().unwrap_or([0; 32])).collect();
+
+        while level.len() > 1 {
+            level = level.chunks(2).map(|pair| hash_to_parent(pair[0], pair[1], true)).collect();
+        }
+
+        level[0]
+    }
+
+    #[test]
+    fn it_matches_a_dense_tree() {
+        let mut tree = SolanaMerkleTree::new(4, 8);
+        let mut leaves = Vec::new();
+
+        assert_eq!(tree.root(), dense_root(&leaves, 4));
+
+        for i in 0..16 {
+            assert_eq!(tree.append(leaf(i)), Ok(i));
+            leaves.push(leaf(i));
+
+            assert_eq!(tree.root(), dense_root(&leaves, 4));
+
+            for (index, leaf) in leaves.iter().enumerate() {
+                assert_eq!(SolanaMerkleTree::recompute(*leaf, &tree.proof(index).unwrap(), index), tree.root());
+            }
+        }
+
+        assert_eq!(tree.append(leaf(16)), Err(MerkleError::TreeFull { capacity: 16 }));
+
+        tree.replace(5, leaf(100)).unwrap();
+        leaves[5] = leaf(100);
+        assert_eq!(tree.root(), dense_root(&leaves, 4));
+        assert_eq!(tree.sequence_number(), 17);
+    }
+
+    #[test]
+    fn it_fast_forwards_proofs_through_the_change_logs() {
+        let mut tree = SolanaMerkleTree::new(5, 5);
+
+        for i in 0..9 {
+            tree.append(leaf(i)).unwrap();
+        }
+
+        let (root, proof) = (tree.root(), tree.proof(3).unwrap());
+
+        tree.append(leaf(9)).unwrap();
+        tree.replace(6, leaf(60)).unwrap();
+        tree.replace(2, leaf(20)).unwrap();
+
+        assert_eq!(tree.fast_forward(&root, 3, leaf(3), &proof), Ok((leaf(3), tree.proof(3).unwrap())));
+
+        tree.replace(3, leaf(30)).unwrap();
+        assert_eq!(tree.fast_forward(&root, 3, leaf(3), &proof), Ok((leaf(30), tree.proof(3).unwrap())));
+
+        assert_eq!(tree.fast_forward(&root, 3, leaf(4), &proof), Err(MerkleError::RootMismatch));
+
+        // The root fell out of the buffer of 5 change logs.
+        tree.append(leaf(10)).unwrap();
+        assert_eq!(tree.fast_forward(&root, 3, leaf(3), &proof), Err(MerkleError::RootMismatch));
+    }
+
+    #[test]
+    fn it_completes_truncated_proofs_from_the_canopy() {
+        let mut tree = SolanaMerkleTree::with_canopy(6, 8, 3);
+
+        for i in 0..21 {
+            tree.append(leaf(i)).unwrap();
+        }
+
+        let canopy = tree.canopy();
+        assert_eq!(canopy.len(), 14);
+        assert_eq!(canopy[13], [0; 32]);
+
+        for index in &[0, 7, 20, 40, 63] {
+            let mut proof = tree.truncated_proof(*index).unwrap();
+            assert_eq!(proof.len(), 3);
+
+            SolanaMerkleTree::fill_proof_from_canopy(&canopy, 6, *index, &mut proof).unwrap();
+            assert_eq!(proof, tree.proof(*index).unwrap());
+        }
+
+        let mut proof = tree.proof(0).unwrap();
+        assert_eq!(SolanaMerkleTree::fill_proof_from_canopy(&canopy, 6, 0, &mut proof), Err(MerkleError::MalformedProof));
+    }
+}
@@ -0,0 +1,399 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use blake2::Blake2b;
+use digest::Digest;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::config::{LeafMode, Padding, Prefixes, TreeConfig};
+use crate::error::MerkleError;
+use crate::leaf::LeafEncode;
+use crate::proof::Proof;
+use crate::tree::MerkleTree;
+use crate::{check_leaf, hash_leaf, leaf_hash, Hash};
+
+/// A MerkleTree whose leaves are sorted by hash, so that the absence of some data can be
+/// proven by showing the two adjacent leaves whose hashes surround the hash of that data.
+/// Leaves with the same hash are only stored once.
+///
+/// The root therefore commits to the set of leaves rather than to a sequence: any order of the
+/// same data, with or without repetitions, gives the same root, as allowlists need. In exchange,
+/// the position of a leaf is its rank among the hashes, so it changes whenever data is added or
+/// removed, proofs are looked up by data with `proof` rather than by index, and the tree is built
+/// again rather than appended to. Proofs are ordinary inclusion proofs, which verify with
+/// `Proof::verify` like those of any MerkleTree.
+pub struct SortedMerkleTree<T, D: Digest = Blake2b> {
+    tree: MerkleTree<T, D>,
+    config: TreeConfig,
+}
+
+impl<T> SortedMerkleTree<T> where T: LeafEncode {
+    /// Creates a SortedMerkleTree from a vector, hashing with Blake2b and the default config.
+    pub fn from_vec(data: Vec<T>) -> Result<SortedMerkleTree<T>, MerkleError> {
+        SortedMerkleTree::from_vec_with_config_and_digest(data, TreeConfig::default())
+    }
+
+    /// Creates a SortedMerkleTree from a vector, hashing with Blake2b.
+    pub fn from_vec_with_config(data: Vec<T>, config: TreeConfig) -> Result<SortedMerkleTree<T>, MerkleError> {
+        SortedMerkleTree::from_vec_with_config_and_digest(data, config)
+    }
+}
+
+impl<T, D> SortedMerkleTree<T, D> where T: LeafEncode, D: Digest {
+    /// Creates a SortedMerkleTree from a vector, hashing with the digest `D`.
+    pub fn from_vec_with_config_and_digest(data: Vec<T>, config: TreeConfig) -> Result<SortedMerkleTree<T, D>, MerkleError> {
+        for (index, leaf) in data.iter().enumerate() {
+            check_leaf::<D>(config.leaf_mode, index, &leaf.encode_leaf())?;
+        }
+
+        let mut leaves: Vec<(Hash<D>, T)> = data.into_iter()
+            .map(|data| (leaf_hash::<D>(config.prefixes, config.leaf_mode, &data.encode_leaf()), data))
+            .collect();
+
+        leaves.sort_by(|a, b| a.0.cmp(&b.0));
+        leaves.dedup_by(|a, b| a.0 == b.0);
+
+        let tree = MerkleTree::from_vec_with_config_and_digest(leaves.into_iter().map(|(_, data)| data).collect(), config)?;
+
+        Ok(SortedMerkleTree { tree, config })
+    }
+
+    /// The underlying tree, whose leaves are in hash order.
+    pub fn tree(&self) -> &MerkleTree<T, D> {
+        &self.tree
+    }
+
+    pub fn root_hash(&self) -> &[u8] {
+        self.tree.root_hash()
+    }
+
+    /// Binary searches the leaves for `data`, returning its index if present, or the index
+    /// where it would be inserted otherwise.
+    pub fn position(&self, data: &[u8]) -> Result<usize, usize> {
+        let key = sort_key::<D>(self.config.prefixes, self.config.leaf_mode, data);
+        let (mut low, mut high) = (0, self.tree.leaf_count());
+
+        while low < high {
+            let mid = low + (high - low) / 2;
+
+            match self.leaf_hash(mid).as_slice().cmp(&key) {
+                core::cmp::Ordering::Less => low = mid + 1,
+                core::cmp::Ordering::Greater => high = mid,
+                core::cmp::Ordering::Equal => return Ok(mid),
+            }
+        }
+
+        Err(low)
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.tree.leaf_count()
+    }
+
+    pub fn contains(&self, data: &[u8]) -> bool {
+        self.position(data).is_ok()
+    }
+
+    /// Generates the inclusion proof of `data`, wherever it sorts among the leaves, or `None` if
+    /// it is not in the tree.
+    pub fn proof(&self, data: &[u8]) -> Result<Option<Proof<D>>, MerkleError> {
+        match self.position(data) {
+            Ok(index) => self.tree.proof(index).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn leaf_hash(&self, index: usize) -> Hash<D> {
+        self.tree.get_leaf(index).expect("index is smaller than the leaf count").hash.clone()
+    }
+
+    fn neighbor(&self, index: usize) -> Result<Neighbor<D>, MerkleError> {
+        Ok(Neighbor { hash: self.leaf_hash(index), proof: self.tree.proof(index)? })
+    }
+
+    /// Generates a proof that `data` is not in the tree, or `None` if it is. Fails with
+    /// `UnsupportedSortedPairs` if the tree hashes sorted pairs, as the proofs of its leaves do
+    /// not show that they are adjacent.
+    pub fn absence_proof(&self, data: &[u8]) -> Result<Option<AbsenceProof<D>>, MerkleError> {
+        if self.config.sort_pairs {
+            return Err(MerkleError::UnsupportedSortedPairs);
+        }
+
+        let position = match self.position(data) {
+            Ok(_) => return Ok(None),
+            Err(position) => position,
+        };
+
+        let left = if position > 0 { Some(self.neighbor(position - 1)?) } else { None };
+        let right = if position < self.tree.leaf_count() { Some(self.neighbor(position)?) } else { None };
+
+        Ok(Some(AbsenceProof { padding: self.config.padding, left, right }))
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<T, D> Clone for SortedMerkleTree<T, D> where T: Clone, D: Digest {
+    fn clone(&self) -> Self {
+        SortedMerkleTree { tree: self.tree.clone(), config: self.config }
+    }
+}
+
+impl<T, D> fmt::Debug for SortedMerkleTree<T, D> where T: fmt::Debug, D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SortedMerkleTree").field("tree", &self.tree).finish()
+    }
+}
+
+/// Leaf of a sorted tree next to the position of some absent data, with its inclusion proof.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct Neighbor<D: Digest = Blake2b> {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash"))]
+    pub hash: Hash<D>,
+    pub proof: Proof<D>,
+}
+
+/// Proof that some data is not among the leaves of a sorted tree. `left` and `right` are the
+/// adjacent leaves whose hashes surround the hash of that data; one of them is missing when
+/// the data would sort before the first or after the last leaf.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct AbsenceProof<D: Digest = Blake2b> {
+    pub padding: Padding,
+    pub left: Option<Neighbor<D>>,
+    pub right: Option<Neighbor<D>>,
+}
+
+impl<D> AbsenceProof<D> where D: Digest {
+    /// Checks that `data` is not a leaf of the sorted tree of `leaf_count` leaves with the given
+    /// root hash. The root does not commit to the leaf count, which must come from the same
+    /// trusted source as the root.
+    ///
+    /// Each neighbor must be the leaf at its index in a tree of `leaf_count` leaves, the right
+    /// neighbor must follow the left one, and a lone neighbor must be the last or the first
+    /// leaf. Fails with `MalformedProof` if the neighbors disagree on the tree they come from,
+    /// and with `UnsupportedSortedPairs` for trees hashing sorted pairs, whose proofs do not bind
+    /// the position of their leaf.
+    pub fn verify(&self, root_hash: &[u8], leaf_count: usize, data: &[u8]) -> Result<(), MerkleError> {
+        let first = match (&self.left, &self.right) {
+            (Some(neighbor), _) | (None, Some(neighbor)) => &neighbor.proof,
+            (None, None) => return Err(MerkleError::MalformedProof),
+        };
+
+        for neighbor in self.left.iter().chain(self.right.iter()) {
+            let proof = &neighbor.proof;
+
+            if proof.sort_pairs {
+                return Err(MerkleError::UnsupportedSortedPairs);
+            }
+
+            if proof.leaf_count != leaf_count || proof.padding != self.padding || proof.prefixes != first.prefixes || proof.leaf_mode != first.leaf_mode {
+                return Err(MerkleError::MalformedProof);
+            }
+
+            proof.verify_hash(root_hash, &neighbor.hash)?;
+        }
+
+        let key = sort_key::<D>(first.prefixes, first.leaf_mode, data);
+        let surrounded = self.left.as_ref().is_none_or(|left| left.hash.as_slice() < key.as_slice())
+            && self.right.as_ref().is_none_or(|right| key.as_slice() < right.hash.as_slice());
+
+        let adjacent = match (&self.left, &self.right) {
+            (Some(left), Some(right)) => left.proof.leaf_index.checked_add(1) == Some(right.proof.leaf_index),
+            (Some(left), None) => left.proof.leaf_index == leaf_count - 1,
+            (None, Some(right)) => right.proof.leaf_index == 0,
+            (None, None) => false,
+        };
+
+        if surrounded && adjacent {
+            Ok(())
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+
+    /// Same as `verify`, for a tree built with `config`. Fails with `ConfigMismatch` unless both
+    /// neighbors were proven with `config`.
+    pub fn verify_with_config(&self, root_hash: &[u8], leaf_count: usize, data: &[u8], config: TreeConfig) -> Result<(), MerkleError> {
+        for neighbor in self.left.iter().chain(self.right.iter()) {
+            neighbor.proof.check_config(config)?;
+        }
+
+        self.verify(root_hash, leaf_count, data)
+    }
+}
+
+/// Bytes the leaves are sorted by. Prehashed data that is not one hash long still sorts among
+/// the leaf hashes, without being equal to any of them.
+fn sort_key<D: Digest>(prefixes: Option<Prefixes>, leaf_mode: LeafMode, data: &[u8]) -> Vec<u8> {
+    match leaf_mode {
+        LeafMode::Hashed => hash_leaf::<D>(prefixes, data).to_vec(),
+        LeafMode::Prehashed => data.to_vec(),
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for Neighbor<D> where D: Digest {
+    fn clone(&self) -> Self {
+        Neighbor { hash: self.hash.clone(), proof: self.proof.clone() }
+    }
+}
+
+impl<D> PartialEq for Neighbor<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.proof == other.proof
+    }
+}
+
+impl<D> fmt::Debug for Neighbor<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Neighbor")
+            .field("hash", &self.hash)
+            .field("proof", &self.proof)
+            .finish()
+    }
+}
+
+impl<D> Clone for AbsenceProof<D> where D: Digest {
+    fn clone(&self) -> Self {
+        AbsenceProof { padding: self.padding, left: self.left.clone(), right: self.right.clone() }
+    }
+}
+
+impl<D> PartialEq for AbsenceProof<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.padding == other.padding && self.left == other.left && self.right == other.right
+    }
+}
+
+impl<D> fmt::Debug for AbsenceProof<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AbsenceProof")
+            .field("padding", &self.padding)
+            .field("left", &self.left)
+            .field("right", &self.right)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(len: usize) -> Vec<String> {
+        (0..len).map(|i| format!("present-{}", i)).collect()
+    }
+
+    #[test]
+    fn it_sorts_and_deduplicates_leaves() {
+        let tree = SortedMerkleTree::from_vec(vec!["c", "a", "b", "a"]).unwrap();
+        let hashes: Vec<_> = tree.tree().leaves().map(|leaf| leaf.hash).collect();
+
+        assert_eq!(tree.tree().leaf_count(), 3);
+        assert!(hashes.windows(2).all(|w| w[0] < w[1]));
+        assert!(tree.position(b"a").is_ok());
+        assert_eq!(tree.absence_proof(b"b").unwrap(), None);
+    }
+
+    #[test]
+    fn it_commits_to_sets_regardless_of_order() {
+        let allowlist = SortedMerkleTree::from_vec(vec!["carol", "alice", "bob"]).unwrap();
+        let reordered = SortedMerkleTree::from_vec(vec!["bob", "carol", "alice", "bob"]).unwrap();
+
+        assert_eq!(allowlist.root_hash(), reordered.root_hash());
+        assert_eq!(allowlist.leaf_count(), 3);
+
+        for member in &["alice", "bob", "carol"] {
+            let proof = reordered.proof(member.as_bytes()).unwrap().unwrap();
+            assert_eq!(proof.verify(allowlist.root_hash(), member.as_bytes()), Ok(()));
+        }
+
+        assert!(!allowlist.contains(b"mallory"));
+        assert_eq!(allowlist.proof(b"mallory"), Ok(None));
+    }
+
+    #[test]
+    fn it_proves_absence_for_every_gap() {
+        for &padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            for len in 1..=9 {
+                let tree = SortedMerkleTree::from_vec_with_config(data(len), TreeConfig::new(padding)).unwrap();
+
+                for absent in 0..20 {
+                    let absent = format!("absent-{}", absent);
+                    let proof = tree.absence_proof(absent.as_bytes()).unwrap().unwrap();
+
+                    assert!(proof.verify(tree.root_hash(), len, absent.as_bytes()).is_ok());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_rejects_absence_proofs_for_present_data() {
+        let tree = SortedMerkleTree::from_vec(data(6)).unwrap();
+        let proof = tree.absence_proof(b"absent").unwrap().unwrap();
+
+        for present in data(6) {
+            assert_eq!(proof.verify(tree.root_hash(), 6, present.as_bytes()), Err(MerkleError::RootMismatch));
+        }
+    }
+
+    #[test]
+    fn it_rejects_non_adjacent_neighbors() {
+        let tree = SortedMerkleTree::from_vec(data(8)).unwrap();
+
+        for absent in 0..20 {
+            let absent = format!("absent-{}", absent);
+            let position = tree.position(absent.as_bytes()).unwrap_err();
+            let proof = tree.absence_proof(absent.as_bytes()).unwrap().unwrap();
+
+            if position >= 2 {
+                let mut widened = proof.clone();
+                widened.left = Some(tree.neighbor(position - 2).unwrap());
+                assert_eq!(widened.verify(tree.root_hash(), 8, absent.as_bytes()), Err(MerkleError::RootMismatch));
+            }
+
+            if proof.left.is_some() && proof.right.is_some() {
+                let mut truncated = proof.clone();
+                truncated.right = None;
+                assert_eq!(truncated.verify(tree.root_hash(), 8, absent.as_bytes()), Err(MerkleError::RootMismatch));
+
+                let mut truncated = proof;
+                truncated.left = None;
+                assert_eq!(truncated.verify(tree.root_hash(), 8, absent.as_bytes()), Err(MerkleError::RootMismatch));
+            }
+        }
+    }
+
+    #[test]
+    fn it_rejects_forged_neighbors() {
+        let tree = SortedMerkleTree::from_vec(vec!["alice", "bob", "carol", "dave", "erin"]).unwrap();
+        let root = tree.root_hash();
+
+        // A neighbor standing for the whole tree, as the only leaf of the tree it claims.
+        for &claimed in &[5, 1] {
+            let mut neighbor = tree.neighbor(0).unwrap();
+            neighbor.hash = Hash::<Blake2b>::clone_from_slice(root);
+            neighbor.proof.siblings.clear();
+            neighbor.proof.leaf_count = claimed;
+
+            let right_only = AbsenceProof { padding: Padding::DuplicateLast, left: None, right: Some(neighbor.clone()) };
+            neighbor.proof.leaf_index = claimed - 1;
+            let left_only = AbsenceProof { padding: Padding::DuplicateLast, left: Some(neighbor), right: None };
+
+            for member in &["alice", "bob", "carol", "dave", "erin"] {
+                assert!(right_only.verify(root, 5, member.as_bytes()).is_err());
+                assert!(left_only.verify(root, 5, member.as_bytes()).is_err());
+            }
+        }
+
+        let mut proof = tree.absence_proof(b"mallory").unwrap().unwrap();
+        assert_eq!(proof.verify_with_config(root, 5, b"mallory", TreeConfig::default()), Ok(()));
+        assert_eq!(proof.verify_with_config(root, 5, b"mallory", TreeConfig::RFC6962), Err(MerkleError::ConfigMismatch));
+        assert_eq!(proof.verify(root, 6, b"mallory"), Err(MerkleError::MalformedProof));
+
+        proof.left.iter_mut().chain(proof.right.iter_mut()).for_each(|neighbor| neighbor.proof.sort_pairs = true);
+        assert_eq!(proof.verify(root, 5, b"mallory"), Err(MerkleError::UnsupportedSortedPairs));
+    }
+}
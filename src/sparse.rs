@@ -0,0 +1,387 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
+use core::marker::PhantomData;
+
+use blake2::Blake2b;
+use digest::Digest;
+
+use crate::error::MerkleError;
+use crate::{calculate_hash, hash_pair, to_hex, Hash};
+
+/// Number of bits in a key path, and so the height of every sparse tree.
+const KEY_BITS: usize = 256;
+
+/// Position of a key in the sparse tree: the first 256 bits of the key's hash.
+type KeyPath = [u8; KEY_BITS / 8];
+
+/// Merkle tree over a fixed 256-bit keyspace, where each key is stored at the leaf found by
+/// following the bits of its hash from the root. Empty subtrees are never stored: their hashes
+/// only depend on their height, so they are computed once and shared.
+///
+/// The digest `D` must produce at least 32 bytes.
+pub struct SparseMerkleTree<K, V, D: Digest = Blake2b> {
+    entries: BTreeMap<KeyPath, (K, V)>,
+    /// Hashes of the non-empty nodes, by height above the leaves and path with the bits below
+    /// that height cleared.
+    nodes: BTreeMap<(usize, KeyPath), Hash<D>>,
+    /// `defaults[h]` is the hash of an empty subtree of height `h`.
+    defaults: Vec<Hash<D>>,
+}
+
+impl<K, V> SparseMerkleTree<K, V> where K: AsRef<[u8]>, V: AsRef<[u8]> {
+    /// Creates an empty SparseMerkleTree hashing with Blake2b.
+    pub fn new() -> SparseMerkleTree<K, V> {
+        SparseMerkleTree::with_digest()
+    }
+}
+
+impl<K, V> Default for SparseMerkleTree<K, V> where K: AsRef<[u8]>, V: AsRef<[u8]> {
+    fn default() -> Self {
+        SparseMerkleTree::new()
+    }
+}
+
+impl<K, V, D> SparseMerkleTree<K, V, D> where K: AsRef<[u8]>, V: AsRef<[u8]>, D: Digest {
+    /// Creates an empty SparseMerkleTree hashing with the digest `D`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `D` produces fewer than 32 bytes.
+    pub fn with_digest() -> SparseMerkleTree<K, V, D> {
+        assert!(D::output_size() >= KEY_BITS / 8, "sparse merkle trees need a digest of at least 32 bytes");
+
+        SparseMerkleTree {
+            entries: BTreeMap::new(),
+            nodes: BTreeMap::new(),
+            defaults: default_hashes::<D>(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn root_hash(&self) -> &[u8] {
+        self.nodes.get(&(KEY_BITS, [0; KEY_BITS / 8])).unwrap_or(&self.defaults[KEY_BITS])
+    }
+
+    /// Root hash as a lowercase hexadecimal string.
+    pub fn root_hash_hex(&self) -> String {
+        to_hex(self.root_hash())
+    }
+
+    /// Returns the value stored for `key`.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(&key_path::<D>(key.as_ref()))
+            .filter(|(stored, _)| stored.as_ref() == key.as_ref())
+            .map(|(_, value)| value)
+    }
+
+    /// Stores `value` for `key`, returning the previous value, and rehashes the 256 nodes on
+    /// the key's path.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let mut path = key_path::<D>(key.as_ref());
+        let mut hash = leaf_hash::<D>(&path, value.as_ref());
+        let previous = self.entries.insert(path, (key, value)).map(|(_, value)| value);
+
+        for height in 0..KEY_BITS {
+            self.nodes.insert((height, path), hash.clone());
+
+            let sibling = self.nodes.get(&(height, flip_bit(&path, height))).unwrap_or(&self.defaults[height]);
+            hash = if bit(&path, height) {
+                hash_pair::<D>(None, sibling, &hash)
+            } else {
+                hash_pair::<D>(None, &hash, sibling)
+            };
+
+            clear_bit(&mut path, height);
+        }
+
+        self.nodes.insert((KEY_BITS, path), hash);
+
+        previous
+    }
+
+    /// Stores every entry like `insert`, a later entry for a key replacing an earlier one, but
+    /// rehashes every node above the entries once, computing the nodes shared by the paths of
+    /// several entries for all of them rather than once per entry.
+    pub fn insert_batch<I>(&mut self, entries: I) where I: IntoIterator<Item = (K, V)> {
+        let mut dirty = BTreeSet::new();
+
+        for (key, value) in entries {
+            let path = key_path::<D>(key.as_ref());
+            self.nodes.insert((0, path), leaf_hash::<D>(&path, value.as_ref()));
+            self.entries.insert(path, (key, value));
+            dirty.insert(path);
+        }
+
+        for height in 0..KEY_BITS {
+            let mut parents = BTreeSet::new();
+
+            for mut path in dirty {
+                clear_bit(&mut path, height);
+
+                // The sibling of an entry already updated its parent.
+                if !parents.insert(path) {
+                    continue;
+                }
+
+                let left = self.nodes.get(&(height, path)).unwrap_or(&self.defaults[height]);
+                let right = self.nodes.get(&(height, flip_bit(&path, height))).unwrap_or(&self.defaults[height]);
+                let hash = hash_pair::<D>(None, left, right);
+                self.nodes.insert((height + 1, path), hash);
+            }
+
+            dirty = parents;
+        }
+    }
+
+    /// Generates a proof for `key`, which shows either the value stored for it or, when the
+    /// key is absent, that its leaf is empty.
+    pub fn proof(&self, key: &K) -> SparseProof<D> {
+        let mut path = key_path::<D>(key.as_ref());
+        let mut siblings = Vec::with_capacity(KEY_BITS);
+
+        for height in 0..KEY_BITS {
+            siblings.push(self.nodes.get(&(height, flip_bit(&path, height))).cloned());
+            clear_bit(&mut path, height);
+        }
+
+        SparseProof::new(siblings)
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<K, V, D> Clone for SparseMerkleTree<K, V, D> where K: Clone, V: Clone, D: Digest {
+    fn clone(&self) -> Self {
+        SparseMerkleTree {
+            entries: self.entries.clone(),
+            nodes: self.nodes.clone(),
+            defaults: self.defaults.clone(),
+        }
+    }
+}
+
+impl<K, V, D> fmt::Debug for SparseMerkleTree<K, V, D> where K: AsRef<[u8]>, V: AsRef<[u8]>, D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SparseMerkleTree")
+            .field("root_hash", &self.root_hash_hex())
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Inclusion or non-inclusion proof for a key of a sparse tree hashed with the digest `D`.
+/// `siblings` holds the 256 sibling hashes from the leaf up to the root, with `None` standing
+/// for an empty subtree.
+pub struct SparseProof<D: Digest = Blake2b> {
+    pub siblings: Vec<Option<Hash<D>>>,
+    digest: PhantomData<D>,
+}
+
+impl<D> SparseProof<D> where D: Digest {
+    pub fn new(siblings: Vec<Option<Hash<D>>>) -> SparseProof<D> {
+        SparseProof {
+            siblings,
+            digest: PhantomData,
+        }
+    }
+
+    /// Checks that `key` is mapped to `value` in the tree with the given root hash.
+    pub fn verify_inclusion(&self, root_hash: &[u8], key: &[u8], value: &[u8]) -> Result<(), MerkleError> {
+        let path = key_path::<D>(key);
+
+        self.verify(root_hash, &path, leaf_hash::<D>(&path, value))
+    }
+
+    /// Checks that `key` has no value in the tree with the given root hash.
+    pub fn verify_non_inclusion(&self, root_hash: &[u8], key: &[u8]) -> Result<(), MerkleError> {
+        self.verify(root_hash, &key_path::<D>(key), calculate_hash::<D>(&[]))
+    }
+
+    fn verify(&self, root_hash: &[u8], path: &KeyPath, leaf: Hash<D>) -> Result<(), MerkleError> {
+        if self.siblings.len() != KEY_BITS {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let mut default = calculate_hash::<D>(&[]);
+        let mut hash = leaf;
+
+        for (height, sibling) in self.siblings.iter().enumerate() {
+            let sibling = sibling.as_ref().unwrap_or(&default);
+            hash = if bit(path, height) {
+                hash_pair::<D>(None, sibling, &hash)
+            } else {
+                hash_pair::<D>(None, &hash, sibling)
+            };
+
+            default = hash_pair::<D>(None, &default, &default);
+        }
+
+        if hash.as_slice() == root_hash {
+            Ok(())
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for SparseProof<D> where D: Digest {
+    fn clone(&self) -> Self {
+        SparseProof::new(self.siblings.clone())
+    }
+}
+
+impl<D> PartialEq for SparseProof<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.siblings == other.siblings
+    }
+}
+
+impl<D> fmt::Debug for SparseProof<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SparseProof")
+            .field("siblings", &self.siblings)
+            .finish()
+    }
+}
+
+fn default_hashes<D: Digest>() -> Vec<Hash<D>> {
+    let mut defaults = Vec::with_capacity(KEY_BITS + 1);
+    defaults.push(calculate_hash::<D>(&[]));
+
+    for height in 0..KEY_BITS {
+        let hash = hash_pair::<D>(None, &defaults[height], &defaults[height]);
+        defaults.push(hash);
+    }
+
+    defaults
+}
+
+fn key_path<D: Digest>(key: &[u8]) -> KeyPath {
+    let mut path = [0; KEY_BITS / 8];
+    path.copy_from_slice(&calculate_hash::<D>(key)[..KEY_BITS / 8]);
+    path
+}
+
+/// Hash of a leaf, bound to its path so that a value cannot be moved to another key.
+fn leaf_hash<D: Digest>(path: &KeyPath, value: &[u8]) -> Hash<D> {
+    let mut hasher = D::new();
+    hasher.input(path);
+    hasher.input(value);
+    hasher.result()
+}
+
+/// Whether the path goes right at `height` levels above the leaves.
+fn bit(path: &KeyPath, height: usize) -> bool {
+    let index = KEY_BITS - 1 - height;
+    path[index / 8] >> (7 - index % 8) & 1 == 1
+}
+
+fn flip_bit(path: &KeyPath, height: usize) -> KeyPath {
+    let index = KEY_BITS - 1 - height;
+    let mut flipped = *path;
+    flipped[index / 8] ^= 1 << (7 - index % 8);
+    flipped
+}
+
+fn clear_bit(path: &mut KeyPath, height: usize) {
+    let index = KEY_BITS - 1 - height;
+    path[index / 8] &= !(1 << (7 - index % 8));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(entries: &[(&'static str, &'static str)]) -> SparseMerkleTree<&'static str, &'static str> {
+        let mut tree = SparseMerkleTree::new();
+
+        for (key, value) in entries {
+            tree.insert(*key, *value);
+        }
+
+        tree
+    }
+
+    #[test]
+    fn it_starts_with_the_empty_subtree_hash() {
+        let tree = tree(&[]);
+
+        assert!(tree.is_empty());
+        assert_eq!(tree.root_hash(), default_hashes::<Blake2b>()[KEY_BITS].as_slice());
+    }
+
+    #[test]
+    fn it_inserts_and_gets_values() {
+        let mut tree = tree(&[("a", "1"), ("b", "2")]);
+
+        assert_eq!(tree.get(&"a"), Some(&"1"));
+        assert_eq!(tree.get(&"c"), None);
+        assert_eq!(tree.insert("a", "3"), Some("1"));
+        assert_eq!(tree.get(&"a"), Some(&"3"));
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn it_does_not_depend_on_insertion_order() {
+        let tree_1 = tree(&[("a", "1"), ("b", "2"), ("c", "3")]);
+        let tree_2 = tree(&[("c", "3"), ("a", "1"), ("b", "2")]);
+        let tree_3 = tree(&[("a", "0"), ("c", "3"), ("b", "2"), ("a", "1")]);
+
+        assert_eq!(tree_1.root_hash(), tree_2.root_hash());
+        assert_eq!(tree_1.root_hash(), tree_3.root_hash());
+        assert_ne!(tree_1.root_hash(), tree(&[("a", "1"), ("b", "2")]).root_hash());
+    }
+
+    #[test]
+    fn it_inserts_batches_like_single_entries() {
+        let mut batched = tree(&[("a", "0"), ("z", "9")]);
+        batched.insert_batch(vec![("b", "2"), ("a", "1"), ("c", "3"), ("b", "4")]);
+
+        let single = tree(&[("a", "0"), ("z", "9"), ("b", "2"), ("a", "1"), ("c", "3"), ("b", "4")]);
+
+        assert_eq!(batched.root_hash(), single.root_hash());
+        assert_eq!(batched.nodes, single.nodes);
+        assert_eq!(batched.get(&"b"), Some(&"4"));
+        assert!(batched.proof(&"c").verify_inclusion(batched.root_hash(), b"c", b"3").is_ok());
+    }
+
+    #[test]
+    fn it_proves_inclusion() {
+        let tree = tree(&[("a", "1"), ("b", "2"), ("c", "3")]);
+        let proof = tree.proof(&"b");
+
+        assert!(proof.verify_inclusion(tree.root_hash(), b"b", b"2").is_ok());
+        assert_eq!(proof.verify_inclusion(tree.root_hash(), b"b", b"3"), Err(MerkleError::RootMismatch));
+        assert_eq!(proof.verify_non_inclusion(tree.root_hash(), b"b"), Err(MerkleError::RootMismatch));
+    }
+
+    #[test]
+    fn it_proves_non_inclusion() {
+        let tree = tree(&[("a", "1"), ("b", "2")]);
+        let proof = tree.proof(&"z");
+
+        assert!(proof.verify_non_inclusion(tree.root_hash(), b"z").is_ok());
+        assert_eq!(proof.verify_inclusion(tree.root_hash(), b"z", b""), Err(MerkleError::RootMismatch));
+
+        let empty = SparseMerkleTree::<&str, &str>::new();
+        assert!(empty.proof(&"z").verify_non_inclusion(empty.root_hash(), b"z").is_ok());
+    }
+
+    #[test]
+    fn it_rejects_malformed_proofs() {
+        let tree = tree(&[("a", "1")]);
+        let mut proof = tree.proof(&"a");
+        proof.siblings.pop();
+
+        assert_eq!(proof.verify_inclusion(tree.root_hash(), b"a", b"1"), Err(MerkleError::MalformedProof));
+    }
+}
@@ -0,0 +1,216 @@
+//! Merkleization of SSZ, the serialization of the Ethereum consensus layer, so that roots match
+//! `hash_tree_root` in the consensus specs.
+//!
+//! Values are packed into 32-byte chunks, which are hashed with SHA-256 as a binary tree padded
+//! with zero chunks up to the next power of two of their limit. Lists mix their length into the
+//! root of their chunks. Unlike `MerkleTree`, there are no prefixes and padding nodes are the
+//! roots of empty subtrees, so a tree of a million chunks with a limit of `2^40` only hashes the
+//! nodes above actual chunks.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use sha2::{Digest, Sha256};
+
+use crate::error::MerkleError;
+
+/// Chunk of SSZ merkleization, or root of some SSZ value.
+pub type SszChunk = [u8; 32];
+
+/// Packs serialized basic values, such as the little-endian bytes of a list of `uint64`s, into
+/// chunks, padding the last one with zeros. No bytes are packed into no chunks.
+pub fn ssz_pack(bytes: &[u8]) -> Vec<SszChunk> {
+    bytes.chunks(32)
+        .map(|bytes| {
+            let mut chunk = [0; 32];
+            chunk[..bytes.len()].copy_from_slice(bytes);
+            chunk
+        })
+        .collect()
+}
+
+/// Root of `chunks` padded with zero chunks up to `limit` chunks, rounded up to a power of two,
+/// or up to the number of chunks when there is no limit, like `merkleize`. Fails with
+/// `LimitExceeded` if there are more chunks than the limit.
+pub fn ssz_merkleize(chunks: &[SszChunk], limit: Option<usize>) -> Result<SszChunk, MerkleError> {
+    let depth = ssz_depth(chunks.len(), limit)?;
+    let zero_hashes = zero_hashes(depth);
+    let mut level = chunks.to_vec();
+
+    for zero_hash in &zero_hashes[..depth] {
+        if level.len() % 2 == 1 {
+            level.push(*zero_hash);
+        }
+
+        level = level.chunks(2).map(|pair| hash_pair(&pair[0], &pair[1])).collect();
+    }
+
+    Ok(level.first().copied().unwrap_or(zero_hashes[depth]))
+}
+
+/// Mixes the length of a list into the root of its chunks, like `mix_in_length`.
+pub fn ssz_mix_in_length(root: &SszChunk, length: usize) -> SszChunk {
+    let mut length_chunk = [0; 32];
+    length_chunk[..8].copy_from_slice(&(length as u64).to_le_bytes());
+    hash_pair(root, &length_chunk)
+}
+
+/// Root of a list of basic values given serialized, with room for `limit` of them of
+/// `item_size` bytes each, such as a `ByteList[limit]` with an item size of 1. Fails with
+/// `LimitExceeded` if the values take more chunks than the limit.
+pub fn ssz_basic_list_root(bytes: &[u8], item_size: usize, limit: usize) -> Result<SszChunk, MerkleError> {
+    let chunk_limit = (limit * item_size).div_ceil(32);
+    Ok(ssz_mix_in_length(&ssz_merkleize(&ssz_pack(bytes), Some(chunk_limit))?, bytes.len() / item_size))
+}
+
+/// Root of a list of composite values given by their roots, with room for `limit` of them.
+/// Fails with `LimitExceeded` if there are more roots than the limit.
+pub fn ssz_list_root(roots: &[SszChunk], limit: usize) -> Result<SszChunk, MerkleError> {
+    Ok(ssz_mix_in_length(&ssz_merkleize(roots, Some(limit))?, roots.len()))
+}
+
+/// Sibling hashes from the chunk at `index` up to the root of `ssz_merkleize(chunks, limit)`,
+/// the branch `is_valid_merkle_branch` checks. For a list, appending the length chunk gives the
+/// branch to the root with its length mixed in.
+///
+/// Fails with `LimitExceeded` if there are more chunks than the limit, and with
+/// `IndexOutOfBounds` if `index` is not within the limit rounded up to a power of two.
+pub fn ssz_branch(chunks: &[SszChunk], limit: Option<usize>, index: usize) -> Result<Vec<SszChunk>, MerkleError> {
+    let depth = ssz_depth(chunks.len(), limit)?;
+
+    if index.checked_shr(depth as u32).unwrap_or(0) != 0 {
+        return Err(MerkleError::IndexOutOfBounds { index, leaf_count: 1 << depth });
+    }
+
+    let zero_hashes = zero_hashes(depth);
+    let mut level = chunks.to_vec();
+    let mut branch = Vec::with_capacity(depth);
+
+    for (height, zero_hash) in zero_hashes[..depth].iter().enumerate() {
+        branch.push(level.get((index >> height) ^ 1).copied().unwrap_or(*zero_hash));
+
+        if level.len() % 2 == 1 {
+            level.push(*zero_hash);
+        }
+
+        level = level.chunks(2).map(|pair| hash_pair(&pair[0], &pair[1])).collect();
+    }
+
+    Ok(branch)
+}
+
+/// Checks that `leaf` is the chunk at `index` under `root`, `branch` holding its `depth`
+/// siblings from the bottom up, like `is_valid_merkle_branch`.
+pub fn verify_ssz_branch(leaf: &SszChunk, branch: &[SszChunk], depth: usize, index: usize, root: &SszChunk) -> bool {
+    if branch.len() != depth {
+        return false;
+    }
+
+    let computed = branch.iter().enumerate().fold(*leaf, |node, (height, sibling)| {
+        if (index >> height) & 1 == 1 {
+            hash_pair(sibling, &node)
+        } else {
+            hash_pair(&node, sibling)
+        }
+    });
+
+    &computed == root
+}
+
+/// Height of the tree of `len` chunks with the given limit.
+fn ssz_depth(len: usize, limit: Option<usize>) -> Result<usize, MerkleError> {
+    let limit = limit.unwrap_or(len);
+
+    if len > limit {
+        return Err(MerkleError::LimitExceeded { len, limit });
+    }
+
+    Ok((usize::BITS - limit.saturating_sub(1).leading_zeros()) as usize)
+}
+
+/// Roots of the empty subtrees of every height up to `depth`.
+fn zero_hashes(depth: usize) -> Vec<SszChunk> {
+    let mut hashes = vec![[0; 32]];
+
+    for height in 0..depth {
+        let zero = hashes[height];
+        hashes.push(hash_pair(&zero, &zero));
+    }
+
+    hashes
+}
+
+fn hash_pair(left: &SszChunk, right: &SszChunk) -> SszChunk {
+    let mut hasher = Sha256::new();
+    hasher.input(left);
+    hasher.input(right);
+    hasher.result().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_hex;
+
+    #[test]
+    fn it_pads_with_the_roots_of_empty_subtrees() {
+        // Zero hashes of the deposit contract.
+        assert_eq!(to_hex(&ssz_merkleize(&[], Some(2)).unwrap()), "f5a5fd42d16a20302798ef6ed309979b43003d2320d9f0e8ea9831a92759fb4b");
+        assert_eq!(to_hex(&ssz_merkleize(&[], Some(4)).unwrap()), "db56114e00fdd4c1f85c892bf35ac9a89289aaecb1ebd0a96cde606a748b5d71");
+        assert_eq!(to_hex(&ssz_merkleize(&[[0; 32]], Some(8)).unwrap()), "c78009fdf07fc56a11f122370658a353aaa542ed63e44c4bc15ff4cd105ab33c");
+
+        let chunks: Vec<SszChunk> = (0..5u8).map(|i| [i + 1; 32]).collect();
+        let mut padded = chunks.clone();
+        padded.resize(16, [0; 32]);
+
+        assert_eq!(ssz_merkleize(&chunks, Some(16)), ssz_merkleize(&padded, None));
+        assert_eq!(ssz_merkleize(&chunks[..1], None), Ok(chunks[0]));
+    }
+
+    #[test]
+    fn it_computes_list_roots() {
+        // A List[uint64, 4] of [1, 2, 3] fits in a single chunk.
+        let bytes: Vec<u8> = [1u64, 2, 3].iter().flat_map(|value| value.to_le_bytes()).collect();
+        let mut length = [0; 32];
+        length[0] = 3;
+
+        assert_eq!(ssz_basic_list_root(&bytes, 8, 4), Ok(hash_pair(&ssz_pack(&bytes)[0], &length)));
+        assert_eq!(ssz_basic_list_root(&[], 1, 32), Ok(ssz_mix_in_length(&[0; 32], 0)));
+        length[0] = 1;
+        assert_eq!(ssz_list_root(&[[7; 32]], 1), Ok(hash_pair(&[7; 32], &length)));
+    }
+
+    #[test]
+    fn it_generates_branches_verified_like_the_specs() {
+        let chunks: Vec<SszChunk> = (1..=6u8).map(|i| [i; 32]).collect();
+        let root = ssz_merkleize(&chunks, Some(32)).unwrap();
+
+        for index in &[0, 3, 5, 6, 31] {
+            let branch = ssz_branch(&chunks, Some(32), *index).unwrap();
+            let leaf = chunks.get(*index).copied().unwrap_or([0; 32]);
+
+            assert!(verify_ssz_branch(&leaf, &branch, 5, *index, &root));
+            assert!(!verify_ssz_branch(&[9; 32], &branch, 5, *index, &root));
+        }
+
+        // The length chunk extends the branch to the root of the list.
+        let mut branch = ssz_branch(&chunks, Some(32), 2).unwrap();
+        branch.push(ssz_pack(&6u64.to_le_bytes())[0]);
+        assert!(verify_ssz_branch(&chunks[2], &branch, 6, 2, &ssz_list_root(&chunks, 32).unwrap()));
+    }
+
+    #[test]
+    fn it_rejects_chunks_and_indices_past_the_limit() {
+        let chunks: Vec<SszChunk> = (1..=5u8).map(|i| [i; 32]).collect();
+
+        assert_eq!(ssz_merkleize(&chunks, Some(4)), Err(MerkleError::LimitExceeded { len: 5, limit: 4 }));
+        assert_eq!(ssz_list_root(&chunks, 4), Err(MerkleError::LimitExceeded { len: 5, limit: 4 }));
+        assert_eq!(ssz_basic_list_root(&[0; 33], 1, 32), Err(MerkleError::LimitExceeded { len: 2, limit: 1 }));
+        assert_eq!(ssz_branch(&chunks, Some(4), 0), Err(MerkleError::LimitExceeded { len: 5, limit: 4 }));
+
+        // A limit of 6 is rounded up to 8 chunks.
+        assert!(ssz_branch(&chunks, Some(6), 7).is_ok());
+        assert_eq!(ssz_branch(&chunks, Some(6), 8), Err(MerkleError::IndexOutOfBounds { index: 8, leaf_count: 8 }));
+        assert!(ssz_branch(&chunks, Some(usize::MAX), usize::MAX).is_ok());
+    }
+}
@@ -0,0 +1,755 @@
+//! Trees whose node hashes live in a [`NodeStore`] rather than in memory.
+//!
+//! A [`StoredMerkleTree`] only keeps its root in memory and reads the nodes it needs from the
+//! store on demand, so it can hold more leaves than fit in memory. Nodes are addressed by their
+//! height and their index within that height, as in a [`FlatMerkleTree`](crate::FlatMerkleTree).
+//! [`FileStore`] keeps them in a file and makes every commit atomic.
+//!
+//! The top levels of a tree, which every proof goes through, can be kept in memory as well with
+//! [`StoredMerkleTree::set_canopy`], so that proofs only read the levels below them.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use blake2::Blake2b;
+use digest::Digest;
+use digest::generic_array::GenericArray;
+
+use crate::{calculate_hash, check_leaf, leaf_hash, to_hex, Hash};
+use crate::config::{DuplicatePolicy, LeafMode, Padding, Prefixes, TreeConfig};
+use crate::error::MerkleError;
+use crate::flat::FlatMerkleTree;
+use crate::leaf::LeafEncode;
+use crate::proof::{Proof, ProofStep, Side};
+use crate::tree::depth_for;
+
+/// Root of a stored tree, written when a change to the tree is committed.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct StoredRoot {
+    pub leaf_count: usize,
+    pub config: TreeConfig,
+    pub hash: Vec<u8>,
+}
+
+/// Storage for the node hashes of a [`StoredMerkleTree`].
+pub trait NodeStore {
+    /// Hash of the node at `index` on level `height`, counting from the leaves, if one was put.
+    /// Nodes put since the last commit are returned as well.
+    fn get(&self, height: usize, index: usize) -> io::Result<Option<Vec<u8>>>;
+
+    fn put(&mut self, height: usize, index: usize, hash: &[u8]) -> io::Result<()>;
+
+    /// Root of the last committed tree, or `None` if nothing was committed yet.
+    fn root(&self) -> Option<&StoredRoot>;
+
+    /// Makes the nodes put since the last commit and the new root durable, all at once.
+    fn commit(&mut self, root: StoredRoot) -> io::Result<()>;
+}
+
+/// Merkle tree over a [`NodeStore`], with the same root and proofs as a
+/// [`MerkleTree`](crate::MerkleTree) built from the same data and config.
+pub struct StoredMerkleTree<S, D: Digest = Blake2b> {
+    store: S,
+    config: TreeConfig,
+    leaf_count: usize,
+    root_hash: Hash<D>,
+    canopy_levels: usize,
+    /// Nodes of the top `canopy_levels` levels below the root, from the highest level down.
+    canopy: Vec<Vec<Hash<D>>>,
+    digest: PhantomData<D>,
+}
+
+impl<S> StoredMerkleTree<S, Blake2b> where S: NodeStore {
+    /// Hashes `data` into `store` with Blake2b and the default config, and commits the tree.
+    pub fn build<T, I>(store: S, data: I) -> io::Result<StoredMerkleTree<S>> where T: LeafEncode, I: IntoIterator<Item=T> {
+        StoredMerkleTree::build_with_config_and_digest(store, data, TreeConfig::default())
+    }
+
+    pub fn build_with_config<T, I>(store: S, data: I, config: TreeConfig) -> io::Result<StoredMerkleTree<S>>
+        where T: LeafEncode, I: IntoIterator<Item=T> {
+        StoredMerkleTree::build_with_config_and_digest(store, data, config)
+    }
+
+    /// Loads the tree last committed to `store`, hashed with Blake2b.
+    pub fn open(store: S) -> io::Result<StoredMerkleTree<S>> {
+        StoredMerkleTree::open_with_digest(store)
+    }
+}
+
+impl<S, D> StoredMerkleTree<S, D> where S: NodeStore, D: Digest {
+    /// Hashes `data` into `store` one leaf at a time and commits the tree, replacing any tree
+    /// the store held. Each level is then computed from the one below it as read back from the
+    /// store, so memory usage does not depend on the number of leaves. Fails with
+    /// `UnsupportedDuplicatePolicy` unless `config` allows duplicates, as no index of the leaves
+    /// is kept to find them.
+    pub fn build_with_config_and_digest<T, I>(mut store: S, data: I, config: TreeConfig) -> io::Result<StoredMerkleTree<S, D>>
+        where T: LeafEncode, I: IntoIterator<Item=T> {
+        if config.duplicates != DuplicatePolicy::Allow {
+            return Err(invalid_input(MerkleError::UnsupportedDuplicatePolicy(config.duplicates)));
+        }
+
+        let mut leaf_count = 0;
+
+        for data in data {
+            check_leaf::<D>(config.leaf_mode, leaf_count, &data.encode_leaf()).map_err(invalid_input)?;
+            store.put(0, leaf_count, &leaf_hash::<D>(config.prefixes, config.leaf_mode, &data.encode_leaf()))?;
+            leaf_count += 1;
+        }
+
+        if leaf_count == 0 {
+            return Err(invalid_input(MerkleError::EmptyInput));
+        }
+
+        let mut tree = StoredMerkleTree {
+            store,
+            config,
+            leaf_count,
+            root_hash: GenericArray::default(),
+            canopy_levels: 0,
+            canopy: Vec::new(),
+            digest: PhantomData,
+        };
+
+        for height in 1..=depth_for(leaf_count) {
+            let below = level_count(leaf_count, height - 1);
+
+            for index in 0..level_count(leaf_count, height) {
+                let left = tree.node(height - 1, 2 * index)?;
+                let right = if 2 * index + 1 < below { Some(tree.node(height - 1, 2 * index + 1)?) } else { None };
+
+                let parent = FlatMerkleTree::<D>::parent_hash(config, &left, right.as_ref());
+                tree.store.put(height, index, &parent)?;
+            }
+        }
+
+        tree.root_hash = tree.node(depth_for(leaf_count), 0)?;
+        tree.commit()?;
+
+        Ok(tree)
+    }
+
+    /// Loads the tree last committed to `store`. Nodes are only read when they are needed.
+    pub fn open_with_digest(store: S) -> io::Result<StoredMerkleTree<S, D>> {
+        let root = store.root().cloned().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "store holds no tree"))?;
+        let root_hash = GenericArray::from_exact_iter(root.hash).ok_or_else(|| invalid_data("root hash has the wrong length"))?;
+
+        Ok(StoredMerkleTree {
+            store,
+            config: root.config,
+            leaf_count: root.leaf_count,
+            root_hash,
+            canopy_levels: 0,
+            canopy: Vec::new(),
+            digest: PhantomData,
+        })
+    }
+
+    pub fn root_hash(&self) -> &[u8] {
+        &self.root_hash
+    }
+
+    pub fn root_hash_hex(&self) -> String {
+        to_hex(&self.root_hash)
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    pub fn config(&self) -> TreeConfig {
+        self.config
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    /// Keeps the nodes of the top `levels` levels below the root in memory, reading them from
+    /// the store now, so that proofs and updates only read the nodes of the levels below them.
+    /// The canopy is kept up to date by `update` and `push`, and read again when a push adds a
+    /// level to the tree. A canopy of `n` levels holds fewer than `2^(n + 1)` nodes.
+    pub fn set_canopy(&mut self, levels: usize) -> io::Result<()> {
+        self.canopy_levels = levels;
+        self.load_canopy()
+    }
+
+    /// Number of levels below the root kept in memory, all of them if the tree is not as deep.
+    pub fn canopy_levels(&self) -> usize {
+        self.canopy_levels
+    }
+
+    /// Generates an inclusion proof for the leaf at `leaf_index`, reading one node per level.
+    pub fn proof(&self, leaf_index: usize) -> io::Result<Proof<D>> {
+        self.check_index(leaf_index)?;
+
+        let mut siblings = Vec::new();
+
+        for height in 0..depth_for(self.leaf_count) {
+            let index = leaf_index >> height;
+
+            if index & 1 == 1 {
+                siblings.push(ProofStep::new(Side::Left, self.node(height, index - 1)?));
+            } else if index + 1 < level_count(self.leaf_count, height) {
+                siblings.push(ProofStep::new(Side::Right, self.node(height, index + 1)?));
+            } else {
+                match self.config.padding {
+                    Padding::Promote => {}
+                    Padding::DuplicateLast => siblings.push(ProofStep::new(Side::Right, self.node(height, index)?)),
+                    Padding::EmptyHash => siblings.push(ProofStep::new(Side::Right, calculate_hash::<D>(&[]))),
+                }
+            }
+        }
+
+        let mut proof = Proof::new(leaf_index, self.leaf_count, self.config.padding, siblings);
+        proof.prefixes = self.config.prefixes;
+        proof.sort_pairs = self.config.sort_pairs;
+        proof.leaf_mode = self.config.leaf_mode;
+
+        Ok(proof)
+    }
+
+    /// Replaces the data of the leaf at `index`, rewriting the nodes on its path and committing
+    /// the new root.
+    pub fn update<T>(&mut self, index: usize, data: T) -> io::Result<()> where T: LeafEncode {
+        self.check_index(index)?;
+        self.rehash_from(index, &data.encode_leaf())
+    }
+
+    /// Appends a leaf, rewriting the nodes on its path and committing the new root.
+    pub fn push<T>(&mut self, data: T) -> io::Result<()> where T: LeafEncode {
+        let depth = depth_for(self.leaf_count);
+        self.leaf_count += 1;
+
+        // The levels of the canopy move down when the tree gets deeper.
+        let deeper = depth_for(self.leaf_count) != depth;
+        if deeper {
+            self.canopy.clear();
+        }
+
+        let result = self.rehash_from(self.leaf_count - 1, &data.encode_leaf());
+        if result.is_err() {
+            self.leaf_count -= 1;
+        }
+
+        if deeper {
+            self.load_canopy()?;
+        }
+
+        result
+    }
+
+    fn rehash_from(&mut self, leaf_index: usize, data: &[u8]) -> io::Result<()> {
+        check_leaf::<D>(self.config.leaf_mode, leaf_index, data).map_err(invalid_input)?;
+
+        let mut hash = leaf_hash::<D>(self.config.prefixes, self.config.leaf_mode, data);
+        self.put(0, leaf_index, &hash)?;
+
+        for height in 0..depth_for(self.leaf_count) {
+            let index = leaf_index >> height;
+
+            hash = if index & 1 == 1 {
+                let left = self.node(height, index - 1)?;
+                FlatMerkleTree::<D>::parent_hash(self.config, &left, Some(&hash))
+            } else if index + 1 < level_count(self.leaf_count, height) {
+                let right = self.node(height, index + 1)?;
+                FlatMerkleTree::<D>::parent_hash(self.config, &hash, Some(&right))
+            } else {
+                FlatMerkleTree::<D>::parent_hash(self.config, &hash, None)
+            };
+
+            self.put(height + 1, index >> 1, &hash)?;
+        }
+
+        let old_root = std::mem::replace(&mut self.root_hash, hash);
+        let result = self.commit();
+        if result.is_err() {
+            self.root_hash = old_root;
+        }
+
+        result
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        self.store.commit(StoredRoot { leaf_count: self.leaf_count, config: self.config, hash: self.root_hash.to_vec() })
+    }
+
+    fn load_canopy(&mut self) -> io::Result<()> {
+        let depth = depth_for(self.leaf_count);
+        self.canopy.clear();
+
+        for height in (depth.saturating_sub(self.canopy_levels)..depth).rev() {
+            let level = (0..level_count(self.leaf_count, height))
+                .map(|index| self.read(height, index))
+                .collect::<io::Result<Vec<Hash<D>>>>()?;
+            self.canopy.push(level);
+        }
+
+        Ok(())
+    }
+
+    /// Position in the canopy of level `height`, if it is in memory.
+    fn canopy_level(&self, height: usize) -> Option<usize> {
+        let depth = depth_for(self.leaf_count);
+
+        if height < depth && depth - height <= self.canopy.len() {
+            Some(depth - 1 - height)
+        } else {
+            None
+        }
+    }
+
+    /// Puts a node into the store, and into the canopy if it belongs to it.
+    fn put(&mut self, height: usize, index: usize, hash: &Hash<D>) -> io::Result<()> {
+        self.store.put(height, index, hash)?;
+
+        if let Some(level) = self.canopy_level(height) {
+            let level = &mut self.canopy[level];
+
+            if index < level.len() {
+                level[index] = hash.clone();
+            } else {
+                level.push(hash.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn node(&self, height: usize, index: usize) -> io::Result<Hash<D>> {
+        let cached = self.canopy_level(height).and_then(|level| self.canopy[level].get(index));
+
+        match cached {
+            Some(hash) => Ok(hash.clone()),
+            None => self.read(height, index),
+        }
+    }
+
+    fn read(&self, height: usize, index: usize) -> io::Result<Hash<D>> {
+        let hash = self.store.get(height, index)?.ok_or_else(|| invalid_data("node missing from store"))?;
+        GenericArray::from_exact_iter(hash).ok_or_else(|| invalid_data("node hash has the wrong length"))
+    }
+
+    fn check_index(&self, index: usize) -> io::Result<()> {
+        if index >= self.leaf_count {
+            return Err(invalid_input(MerkleError::IndexOutOfBounds { index, leaf_count: self.leaf_count }));
+        }
+
+        Ok(())
+    }
+}
+
+impl<S, D> fmt::Debug for StoredMerkleTree<S, D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StoredMerkleTree")
+            .field("config", &self.config)
+            .field("leaf_count", &self.leaf_count)
+            .field("root_hash", &self.root_hash)
+            .field("canopy_levels", &self.canopy_levels)
+            .finish()
+    }
+}
+
+/// Number of nodes on level `height` of a tree with `leaf_count` leaves.
+fn level_count(leaf_count: usize, height: usize) -> usize {
+    (leaf_count - 1) / (1 << height) + 1
+}
+
+fn invalid_input(error: MerkleError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, error)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+const NODES_FILE: &str = "nodes";
+const ROOT_FILE: &str = "root";
+const JOURNAL_FILE: &str = "journal";
+
+/// [`NodeStore`] keeping nodes in a directory of files.
+///
+/// Nodes are stored in fixed-size slots of a single file, ordered as an in-order traversal of
+/// the tree, so that the file can grow with the tree without moving any node. As long as no
+/// tree has been committed, nodes are written to the file directly. Afterwards, they are held
+/// in memory until the next commit, which first writes them along with the new root to a
+/// journal, so that a crash at any point leaves either the old or the new tree. The journal of
+/// an interrupted commit is replayed when the store is opened.
+pub struct FileStore {
+    dir: PathBuf,
+    nodes: File,
+    hash_len: usize,
+    root: Option<StoredRoot>,
+    staged: BTreeMap<(usize, usize), Vec<u8>>,
+}
+
+impl FileStore {
+    /// Opens the store in `dir`, creating the directory if needed, for hashes of `hash_len` bytes.
+    pub fn open<P: AsRef<Path>>(dir: P, hash_len: usize) -> io::Result<FileStore> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let nodes = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(dir.join(NODES_FILE))?;
+        let root = match fs::read(dir.join(ROOT_FILE)) {
+            Ok(bytes) => Some(decode_root(&mut &bytes[..], hash_len)?),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        let mut store = FileStore { dir, nodes, hash_len, root, staged: BTreeMap::new() };
+
+        // A journal is only renamed into place once complete, so it is always safe to replay.
+        match fs::read(store.dir.join(JOURNAL_FILE)) {
+            Ok(journal) => store.replay(&journal)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(store)
+    }
+
+    fn slot_offset(&self, height: usize, index: usize) -> u64 {
+        // Position of the node in an in-order traversal of a complete tree.
+        let position = ((index as u64) << (height + 1)) | ((1 << height) - 1);
+        position * (self.hash_len as u64 + 1)
+    }
+
+    fn write_slot(&self, height: usize, index: usize, hash: &[u8]) -> io::Result<()> {
+        let mut nodes = &self.nodes;
+        nodes.seek(SeekFrom::Start(self.slot_offset(height, index)))?;
+        nodes.write_all(&[1])?;
+        nodes.write_all(hash)
+    }
+
+    fn replay(&mut self, journal: &[u8]) -> io::Result<()> {
+        let mut reader = journal;
+        let count = read_u64(&mut reader)?;
+
+        for _ in 0..count {
+            let (height, index) = (read_u64(&mut reader)? as usize, read_u64(&mut reader)? as usize);
+            let hash = read_bytes(&mut reader, self.hash_len)?;
+            self.write_slot(height, index, &hash)?;
+        }
+
+        let root = decode_root(&mut reader, self.hash_len)?;
+        self.nodes.sync_data()?;
+        write_atomically(&self.dir, ROOT_FILE, &encode_root(&root))?;
+        fs::remove_file(self.dir.join(JOURNAL_FILE))?;
+
+        self.root = Some(root);
+        Ok(())
+    }
+}
+
+impl NodeStore for FileStore {
+    fn get(&self, height: usize, index: usize) -> io::Result<Option<Vec<u8>>> {
+        if let Some(hash) = self.staged.get(&(height, index)) {
+            return Ok(Some(hash.clone()));
+        }
+
+        let mut nodes = &self.nodes;
+        let mut slot = vec![0; self.hash_len + 1];
+        nodes.seek(SeekFrom::Start(self.slot_offset(height, index)))?;
+
+        match nodes.read_exact(&mut slot) {
+            Ok(()) if slot[0] == 1 => Ok(Some(slot.split_off(1))),
+            Ok(()) => Ok(None),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn put(&mut self, height: usize, index: usize, hash: &[u8]) -> io::Result<()> {
+        if hash.len() != self.hash_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "hash has the wrong length"));
+        }
+
+        if self.root.is_none() {
+            // Nothing committed can be corrupted yet.
+            self.write_slot(height, index, hash)
+        } else {
+            self.staged.insert((height, index), hash.to_vec());
+            Ok(())
+        }
+    }
+
+    fn root(&self) -> Option<&StoredRoot> {
+        self.root.as_ref()
+    }
+
+    fn commit(&mut self, root: StoredRoot) -> io::Result<()> {
+        if self.root.is_none() {
+            self.nodes.sync_data()?;
+            write_atomically(&self.dir, ROOT_FILE, &encode_root(&root))?;
+            self.root = Some(root);
+            return Ok(());
+        }
+
+        let mut journal = (self.staged.len() as u64).to_be_bytes().to_vec();
+
+        for ((height, index), hash) in &self.staged {
+            journal.extend_from_slice(&(*height as u64).to_be_bytes());
+            journal.extend_from_slice(&(*index as u64).to_be_bytes());
+            journal.extend_from_slice(hash);
+        }
+
+        journal.extend_from_slice(&encode_root(&root));
+        write_atomically(&self.dir, JOURNAL_FILE, &journal)?;
+
+        self.staged.clear();
+        self.replay(&journal)
+    }
+}
+
+impl fmt::Debug for FileStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileStore")
+            .field("dir", &self.dir)
+            .field("hash_len", &self.hash_len)
+            .field("root", &self.root)
+            .field("staged", &self.staged.len())
+            .finish()
+    }
+}
+
+/// Replaces `dir/name` with `bytes`, so that readers see either the old or the new contents.
+fn write_atomically(dir: &Path, name: &str, bytes: &[u8]) -> io::Result<()> {
+    let temporary = dir.join(format!("{}.tmp", name));
+
+    let mut file = File::create(&temporary)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&temporary, dir.join(name))?;
+
+    // Makes the rename itself durable. Directories cannot be opened on every platform.
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+
+    Ok(())
+}
+
+/// Bits of the flags byte of an encoded root.
+const SORT_PAIRS: u8 = 0b01;
+const PREHASHED: u8 = 0b10;
+
+fn encode_root(root: &StoredRoot) -> Vec<u8> {
+    let mut bytes = (root.leaf_count as u64).to_be_bytes().to_vec();
+
+    bytes.push(match root.config.padding {
+        Padding::DuplicateLast => 0,
+        Padding::Promote => 1,
+        Padding::EmptyHash => 2,
+    });
+    let mut flags = 0;
+
+    if root.config.sort_pairs {
+        flags |= SORT_PAIRS;
+    }
+
+    if root.config.leaf_mode == LeafMode::Prehashed {
+        flags |= PREHASHED;
+    }
+
+    bytes.push(flags);
+
+    match root.config.prefixes {
+        Some(prefixes) => bytes.extend_from_slice(&[1, prefixes.leaf, prefixes.node]),
+        None => bytes.extend_from_slice(&[0, 0, 0]),
+    }
+
+    bytes.extend_from_slice(&root.hash);
+    bytes
+}
+
+fn decode_root(reader: &mut &[u8], hash_len: usize) -> io::Result<StoredRoot> {
+    let leaf_count = read_u64(reader)? as usize;
+    let header = read_bytes(reader, 5)?;
+
+    let padding = match header[0] {
+        0 => Padding::DuplicateLast,
+        1 => Padding::Promote,
+        2 => Padding::EmptyHash,
+        _ => return Err(invalid_data("unknown padding")),
+    };
+    let leaf_mode = if header[1] & PREHASHED != 0 { LeafMode::Prehashed } else { LeafMode::Hashed };
+    let prefixes = if header[2] == 1 { Some(Prefixes { leaf: header[3], node: header[4] }) } else { None };
+    let hash = read_bytes(reader, hash_len)?;
+
+    if !reader.is_empty() {
+        return Err(invalid_data("trailing bytes after root"));
+    }
+
+    Ok(StoredRoot { leaf_count, config: TreeConfig { padding, prefixes, sort_pairs: header[1] & SORT_PAIRS != 0, leaf_mode, duplicates: DuplicatePolicy::Allow }, hash })
+}
+
+fn read_bytes(reader: &mut &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u64(reader: &mut &[u8]) -> io::Result<u64> {
+    Ok(u64::from_be_bytes(read_bytes(reader, 8)?[..].try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MerkleTree;
+
+    /// Empty directory unique to the test, removed when dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> TestDir {
+            let dir = std::env::temp_dir().join(format!("merkle-tree-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            TestDir(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn data(len: usize) -> Vec<String> {
+        (0..len).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn it_matches_in_memory_trees() {
+        let dir = TestDir::new("matches");
+
+        for padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            for len in &[1, 2, 5, 8, 13] {
+                let config = TreeConfig::new(*padding);
+                let store = FileStore::open(dir.0.join(format!("{:?}-{}", padding, len)), 64).unwrap();
+                let stored = StoredMerkleTree::build_with_config(store, data(*len), config).unwrap();
+                let merkle_tree = MerkleTree::from_vec_with_config(data(*len), config).unwrap();
+
+                assert_eq!(stored.root_hash(), merkle_tree.root_hash());
+
+                for index in 0..*len {
+                    assert_eq!(stored.proof(index).unwrap(), merkle_tree.proof(index).unwrap());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_updates_and_reopens_trees() {
+        let dir = TestDir::new("reopens");
+        let config = TreeConfig::new(Padding::Promote);
+        let mut expected = MerkleTree::from_vec_with_config(data(6), config).unwrap();
+
+        let mut stored = StoredMerkleTree::build_with_config(FileStore::open(&dir.0, 64).unwrap(), data(6), config).unwrap();
+        stored.update(2, "updated").unwrap();
+        stored.push("pushed").unwrap();
+        drop(stored);
+
+        expected.update(2, "updated".to_string()).unwrap();
+        expected.push("pushed".to_string()).unwrap();
+
+        let stored = StoredMerkleTree::open(FileStore::open(&dir.0, 64).unwrap()).unwrap();
+
+        assert_eq!(stored.leaf_count(), 7);
+        assert_eq!(stored.config(), config);
+        assert_eq!(stored.root_hash(), expected.root_hash());
+        assert_eq!(stored.proof(6).unwrap(), expected.proof(6).unwrap());
+    }
+
+    #[test]
+    fn it_replays_interrupted_commits() {
+        let dir = TestDir::new("replays");
+        let stored = StoredMerkleTree::build(FileStore::open(&dir.0, 64).unwrap(), data(4)).unwrap();
+        let old_root = stored.root_hash().to_vec();
+        drop(stored);
+
+        // A commit that crashed before being renamed into place leaves the old tree.
+        fs::write(dir.0.join("journal.tmp"), b"partial").unwrap();
+        assert_eq!(StoredMerkleTree::open(FileStore::open(&dir.0, 64).unwrap()).unwrap().root_hash(), &old_root[..]);
+
+        // Simulates a crash after the journal was written, by writing it without replaying it.
+        let new_root = vec![7; 64];
+        let mut journal = 1u64.to_be_bytes().to_vec();
+        journal.extend_from_slice(&[0; 16]);
+        journal.extend_from_slice(&[9; 64]);
+        journal.extend_from_slice(&encode_root(&StoredRoot { leaf_count: 4, config: TreeConfig::default(), hash: new_root.clone() }));
+        write_atomically(&dir.0, JOURNAL_FILE, &journal).unwrap();
+
+        let reopened = FileStore::open(&dir.0, 64).unwrap();
+        assert_eq!(reopened.root().unwrap().hash, new_root);
+        assert_eq!(reopened.get(0, 0).unwrap(), Some(vec![9; 64]));
+        assert!(!dir.0.join(JOURNAL_FILE).exists());
+    }
+
+    /// FileStore counting the nodes read from it.
+    struct CountingStore(FileStore, std::cell::Cell<usize>);
+
+    impl NodeStore for CountingStore {
+        fn get(&self, height: usize, index: usize) -> io::Result<Option<Vec<u8>>> {
+            self.1.set(self.1.get() + 1);
+            self.0.get(height, index)
+        }
+
+        fn put(&mut self, height: usize, index: usize, hash: &[u8]) -> io::Result<()> {
+            self.0.put(height, index, hash)
+        }
+
+        fn root(&self) -> Option<&StoredRoot> {
+            self.0.root()
+        }
+
+        fn commit(&mut self, root: StoredRoot) -> io::Result<()> {
+            self.0.commit(root)
+        }
+    }
+
+    #[test]
+    fn it_serves_proofs_from_the_canopy() {
+        let dir = TestDir::new("canopy");
+        let config = TreeConfig::new(Padding::DuplicateLast);
+        let store = CountingStore(FileStore::open(&dir.0, 64).unwrap(), std::cell::Cell::new(0));
+        let mut stored = StoredMerkleTree::build_with_config(store, data(64), config).unwrap();
+        let mut expected = MerkleTree::from_vec_with_config(data(64), config).unwrap();
+
+        stored.set_canopy(4).unwrap();
+        stored.store.1.set(0);
+        assert_eq!(stored.proof(37).unwrap(), expected.proof(37).unwrap());
+        assert_eq!(stored.store.1.get(), 2);
+
+        stored.update(5, "updated").unwrap();
+        expected.update(5, "updated".to_string()).unwrap();
+        stored.push("pushed").unwrap();
+        expected.push("pushed".to_string()).unwrap();
+
+        assert_eq!(stored.root_hash(), expected.root_hash());
+        for index in &[0, 5, 63, 64] {
+            assert_eq!(stored.proof(*index).unwrap(), expected.proof(*index).unwrap());
+        }
+    }
+
+    #[test]
+    fn it_fails_to_open_empty_stores() {
+        let dir = TestDir::new("empty");
+        let error = StoredMerkleTree::open(FileStore::open(&dir.0, 64).unwrap()).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn it_only_allows_duplicates() {
+        let dir = TestDir::new("duplicates");
+        let config = TreeConfig { duplicates: DuplicatePolicy::Reject, ..TreeConfig::default() };
+        let error = StoredMerkleTree::build_with_config(FileStore::open(&dir.0, 64).unwrap(), data(4), config).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
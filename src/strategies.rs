@@ -0,0 +1,114 @@
+//! Strategies generating leaves, configs, trees and proofs for property tests written with
+//! proptest, such as checking that any generated proof verifies against its root.
+//!
+//! Configs are generated with `LeafMode::Hashed` and `DuplicatePolicy::Allow` only, so that any
+//! leaf data is valid.
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use digest::Digest;
+
+use crate::config::{DuplicatePolicy, LeafMode, Padding, Prefixes, TreeConfig};
+use crate::proof::Proof;
+use crate::tree::MerkleTree;
+
+/// Largest number of leaves of the trees generated by `any::<MerkleTree<Vec<u8>, D>>()`.
+const DEFAULT_MAX_LEAVES: usize = 64;
+
+impl Arbitrary for Padding {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Padding>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![Just(Padding::DuplicateLast), Just(Padding::Promote), Just(Padding::EmptyHash)].boxed()
+    }
+}
+
+impl Arbitrary for Prefixes {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Prefixes>;
+
+    /// Distinct prefixes, as equal ones would not separate leaves from nodes.
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (any::<u8>(), any::<u8>())
+            .prop_filter("prefixes must differ", |(leaf, node)| leaf != node)
+            .prop_map(|(leaf, node)| Prefixes { leaf, node })
+            .boxed()
+    }
+}
+
+impl Arbitrary for TreeConfig {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<TreeConfig>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (any::<Padding>(), any::<Option<Prefixes>>(), any::<bool>())
+            .prop_map(|(padding, prefixes, sort_pairs)| TreeConfig { padding, prefixes, sort_pairs, leaf_mode: LeafMode::Hashed, duplicates: DuplicatePolicy::Allow })
+            .boxed()
+    }
+}
+
+impl<D> Arbitrary for MerkleTree<Vec<u8>, D> where D: Digest + 'static {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<MerkleTree<Vec<u8>, D>>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (any::<TreeConfig>(), arb_leaves(DEFAULT_MAX_LEAVES))
+            .prop_map(|(config, leaves)| MerkleTree::from_vec_with_config_and_digest(leaves, config).expect("leaves are generated non-empty"))
+            .boxed()
+    }
+}
+
+/// Between 1 and `max_leaves` leaves of up to 64 bytes.
+///
+/// # Panics
+///
+/// Panics if `max_leaves` is 0.
+pub fn arb_leaves(max_leaves: usize) -> impl Strategy<Value = Vec<Vec<u8>>> {
+    assert!(max_leaves > 0, "trees hold at least one leaf");
+    vec(vec(any::<u8>(), 0..64), 1..=max_leaves)
+}
+
+/// Trees of up to `max_leaves` leaves hashed with Blake2b and any config.
+pub fn arb_tree(max_leaves: usize) -> impl Strategy<Value = MerkleTree<Vec<u8>>> {
+    (any::<TreeConfig>(), arb_leaves(max_leaves))
+        .prop_map(|(config, leaves)| MerkleTree::from_vec_with_config(leaves, config).expect("leaves are generated non-empty"))
+}
+
+/// Proofs of a leaf of trees of up to `max_leaves` leaves hashed with Blake2b and any config,
+/// along with the root hash of the tree and the data of the leaf.
+pub fn arb_proof(max_leaves: usize) -> impl Strategy<Value = (Vec<u8>, Vec<u8>, Proof)> {
+    (any::<TreeConfig>(), arb_leaves(max_leaves))
+        .prop_flat_map(|(config, leaves)| {
+            let len = leaves.len();
+            (Just(config), Just(leaves), 0..len)
+        })
+        .prop_map(|(config, leaves, index)| {
+            let leaf = leaves[index].clone();
+            let merkle_tree = MerkleTree::from_vec_with_config(leaves, config).expect("leaves are generated non-empty");
+            let proof = merkle_tree.proof(index).expect("index is generated within the leaves");
+
+            (merkle_tree.root_hash().to_vec(), leaf, proof)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::Sha256;
+
+    proptest! {
+        #[test]
+        fn it_generates_proofs_that_verify((root, leaf, proof) in arb_proof(32)) {
+            prop_assert_eq!(proof.verify(&root, &leaf), Ok(()));
+        }
+
+        #[test]
+        fn it_generates_trees_within_bounds(merkle_tree in arb_tree(8), other in any::<MerkleTree<Vec<u8>, Sha256>>()) {
+            prop_assert!(merkle_tree.leaf_count() >= 1 && merkle_tree.leaf_count() <= 8);
+            prop_assert!(other.leaf_count() <= DEFAULT_MAX_LEAVES);
+            prop_assert_eq!(other.config().leaf_mode, LeafMode::Hashed);
+        }
+    }
+}
@@ -0,0 +1,349 @@
+//! Messages for reconciling two trees over a transport chosen by the application.
+//!
+//! The peer that wants to catch up drives a [`Reconciler`] built over its own tree: it sends the
+//! reconciler's requests to the other peer, which answers them with [`MerkleTree::answer`], and
+//! feeds the node hashes back until the differing leaves are known. Subtrees with the same hash
+//! on both sides are never descended into, so only the hashes on the paths to differing leaves
+//! are exchanged.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use blake2::Blake2b;
+use digest::Digest;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::tree::{depth_for, DiffEntry, MerkleTree};
+use crate::Hash;
+
+/// Node covering the `2^height` leaves from `index << height`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeId {
+    pub height: usize,
+    pub index: usize,
+}
+
+impl NodeId {
+    fn start(&self) -> usize {
+        self.index << self.height
+    }
+
+    /// Number of leaves of a tree of `leaf_count` leaves under this node.
+    fn leaf_count_in(&self, leaf_count: usize) -> usize {
+        leaf_count.min((self.index + 1) << self.height).saturating_sub(self.start())
+    }
+
+    fn children(&self) -> [NodeId; 2] {
+        let height = self.height - 1;
+        [NodeId { height, index: 2 * self.index }, NodeId { height, index: 2 * self.index + 1 }]
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SyncRequest {
+    /// Asks for the hashes of these nodes.
+    Nodes(Vec<NodeId>),
+    /// Asks for the data of every leaf under these nodes.
+    Leaves(Vec<NodeId>),
+}
+
+/// Hash of a node, as sent by the answering peer.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct NodeHash<D: Digest = Blake2b> {
+    pub id: NodeId,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash"))]
+    pub hash: Hash<D>,
+}
+
+/// Answer to `SyncRequest::Nodes`. Nodes the answering tree has no leaves under are left out.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct NodesResponse<D: Digest = Blake2b> {
+    pub leaf_count: usize,
+    pub hashes: Vec<NodeHash<D>>,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>")))]
+pub enum SyncResponse<T, D: Digest = Blake2b> {
+    Nodes(NodesResponse<D>),
+    /// Index and data of the requested leaves.
+    Leaves(Vec<(usize, T)>),
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for NodeHash<D> where D: Digest {
+    fn clone(&self) -> Self {
+        NodeHash { id: self.id, hash: self.hash.clone() }
+    }
+}
+
+impl<D> PartialEq for NodeHash<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.hash == other.hash
+    }
+}
+
+impl<D> fmt::Debug for NodeHash<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NodeHash")
+            .field("id", &self.id)
+            .field("hash", &self.hash)
+            .finish()
+    }
+}
+
+impl<D> Clone for NodesResponse<D> where D: Digest {
+    fn clone(&self) -> Self {
+        NodesResponse { leaf_count: self.leaf_count, hashes: self.hashes.clone() }
+    }
+}
+
+impl<D> PartialEq for NodesResponse<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaf_count == other.leaf_count && self.hashes == other.hashes
+    }
+}
+
+impl<D> fmt::Debug for NodesResponse<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NodesResponse")
+            .field("leaf_count", &self.leaf_count)
+            .field("hashes", &self.hashes)
+            .finish()
+    }
+}
+
+impl<T, D> Clone for SyncResponse<T, D> where T: Clone, D: Digest {
+    fn clone(&self) -> Self {
+        match self {
+            SyncResponse::Nodes(nodes) => SyncResponse::Nodes(nodes.clone()),
+            SyncResponse::Leaves(leaves) => SyncResponse::Leaves(leaves.clone()),
+        }
+    }
+}
+
+impl<T, D> PartialEq for SyncResponse<T, D> where T: PartialEq, D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (SyncResponse::Nodes(a), SyncResponse::Nodes(b)) => a == b,
+            (SyncResponse::Leaves(a), SyncResponse::Leaves(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl<T, D> fmt::Debug for SyncResponse<T, D> where T: fmt::Debug, D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncResponse::Nodes(nodes) => f.debug_tuple("Nodes").field(nodes).finish(),
+            SyncResponse::Leaves(leaves) => f.debug_tuple("Leaves").field(leaves).finish(),
+        }
+    }
+}
+
+impl<T, D> MerkleTree<T, D> where T: Clone, D: Digest {
+    /// Answers a request sent by a peer's Reconciler.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn answer(&self, request: &SyncRequest) -> SyncResponse<T, D> {
+        let leaf_count = self.leaf_count();
+
+        match request {
+            SyncRequest::Nodes(ids) => SyncResponse::Nodes(NodesResponse {
+                leaf_count,
+                hashes: ids.iter()
+                    .filter(|id| id.leaf_count_in(leaf_count) > 0)
+                    .map(|id| NodeHash { id: *id, hash: self.node_at(id.height, id.index).hash().clone() })
+                    .collect(),
+            }),
+            SyncRequest::Leaves(ids) => SyncResponse::Leaves(ids.iter()
+                .flat_map(|id| id.start()..id.start() + id.leaf_count_in(leaf_count))
+                .filter_map(|index| self.get_leaf(index).map(|leaf| (index, leaf.data.clone())))
+                .collect()),
+        }
+    }
+}
+
+/// Finds the leaves at which a local tree differs from a remote one, by comparing node
+/// hashes level by level from the root. Both trees are expected to share a config and digest.
+pub struct Reconciler<'a, T, D: Digest = Blake2b> {
+    local: &'a MerkleTree<T, D>,
+    remote_leaf_count: Option<usize>,
+    pending: Vec<NodeId>,
+    entries: Vec<DiffEntry>,
+}
+
+impl<'a, T, D> Reconciler<'a, T, D> where D: Digest {
+    pub fn new(local: &'a MerkleTree<T, D>) -> Reconciler<'a, T, D> {
+        Reconciler {
+            local,
+            remote_leaf_count: None,
+            pending: vec![NodeId { height: depth_for(local.leaf_count()), index: 0 }],
+            entries: Vec::new(),
+        }
+    }
+
+    /// First request to send, asking for the hash of the local tree's root.
+    pub fn start(&self) -> SyncRequest {
+        SyncRequest::Nodes(self.pending.clone())
+    }
+
+    /// Compares the answered hashes with the local ones, and returns the next request to send,
+    /// or `None` once the differing leaves are known.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(hashes = response.hashes.len(), entries = tracing::field::Empty)))]
+    pub fn handle(&mut self, response: &NodesResponse<D>) -> Option<SyncRequest> {
+        let (local_count, remote_count) = (self.local.leaf_count(), response.leaf_count);
+        let root = NodeId { height: depth_for(local_count.max(remote_count)), index: 0 };
+
+        // A larger remote tree has a higher root, which has to be asked for first.
+        if self.remote_leaf_count.is_none() && self.pending != [root] {
+            self.remote_leaf_count = Some(remote_count);
+            self.pending = vec![root];
+            return Some(self.start());
+        }
+
+        self.remote_leaf_count = Some(remote_count);
+
+        let mut next = Vec::new();
+
+        for id in core::mem::take(&mut self.pending) {
+            let (local_in, remote_in) = (id.leaf_count_in(local_count), id.leaf_count_in(remote_count));
+            let remote_hash = response.hashes.iter().find(|node| node.id == id).map(|node| &node.hash);
+
+            if local_in == 0 {
+                self.entries.extend((id.start()..id.start() + remote_in).map(DiffEntry::Added));
+            } else if remote_in == 0 {
+                self.entries.extend((id.start()..id.start() + local_in).map(DiffEntry::Removed));
+            } else if local_in == remote_in && remote_hash == Some(self.local.node_at(id.height, id.index).hash()) {
+                continue;
+            } else if id.height == 0 {
+                self.entries.push(DiffEntry::Changed(id.index));
+            } else {
+                next.extend_from_slice(&id.children());
+            }
+        }
+
+        if next.is_empty() {
+            trace_record!("entries", self.entries.len());
+            self.entries.sort_by_key(|entry| match entry {
+                DiffEntry::Changed(index) | DiffEntry::Added(index) | DiffEntry::Removed(index) => *index,
+            });
+            return None;
+        }
+
+        self.pending = next;
+        Some(SyncRequest::Nodes(self.pending.clone()))
+    }
+
+    /// Leaves that differ between the local and remote trees, once `handle` returned `None`.
+    pub fn entries(&self) -> &[DiffEntry] {
+        &self.entries
+    }
+
+    /// Request for the remote data of every changed or added leaf, if there is any.
+    pub fn leaves_request(&self) -> Option<SyncRequest> {
+        let ids: Vec<NodeId> = self.entries.iter()
+            .filter_map(|entry| match entry {
+                DiffEntry::Changed(index) | DiffEntry::Added(index) => Some(NodeId { height: 0, index: *index }),
+                DiffEntry::Removed(_) => None,
+            })
+            .collect();
+
+        if ids.is_empty() {
+            None
+        } else {
+            Some(SyncRequest::Leaves(ids))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Padding, TreeConfig};
+
+    fn reconcile(local: &MerkleTree<String>, remote: &MerkleTree<String>) -> (Vec<DiffEntry>, usize) {
+        let mut reconciler = Reconciler::new(local);
+        let mut request = Some(reconciler.start());
+        let mut round_trips = 0;
+
+        while let Some(SyncRequest::Nodes(ids)) = request {
+            round_trips += 1;
+            request = match remote.answer(&SyncRequest::Nodes(ids)) {
+                SyncResponse::Nodes(response) => reconciler.handle(&response),
+                SyncResponse::Leaves(_) => unreachable!(),
+            };
+        }
+
+        (reconciler.entries().to_vec(), round_trips)
+    }
+
+    #[test]
+    fn it_finds_the_same_leaves_as_diff() {
+        let data: Vec<String> = (0..13).map(|i| i.to_string()).collect();
+
+        for padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            let config = TreeConfig::new(*padding);
+
+            for len in 1..=data.len() {
+                for remote_len in 1..=data.len() {
+                    let mut remote_data = data[..remote_len].to_vec();
+                    remote_data[remote_len / 3] = "changed".to_string();
+
+                    let local = MerkleTree::from_vec_with_config(data[..len].to_vec(), config).unwrap();
+                    let remote = MerkleTree::from_vec_with_config(remote_data, config).unwrap();
+
+                    assert_eq!(reconcile(&local, &remote).0, local.diff(&remote));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_stops_at_the_root_of_identical_trees() {
+        let local = MerkleTree::from_vec((0..100).map(|i| i.to_string()).collect()).unwrap();
+
+        assert_eq!(reconcile(&local, &local.clone()), (vec![], 1));
+    }
+
+    #[test]
+    fn it_fetches_the_leaves_needed_to_catch_up() {
+        let remote_data: Vec<String> = (0..11).map(|i| i.to_string()).collect();
+        let remote = MerkleTree::from_vec(remote_data.clone()).unwrap();
+        let mut local_data = remote_data[..7].to_vec();
+        local_data[2] = "stale".to_string();
+        let mut local = MerkleTree::from_vec(local_data).unwrap();
+
+        let mut reconciler = Reconciler::new(&local);
+        let mut request = Some(reconciler.start());
+
+        while let Some(SyncRequest::Nodes(ids)) = request {
+            request = match remote.answer(&SyncRequest::Nodes(ids)) {
+                SyncResponse::Nodes(response) => reconciler.handle(&response),
+                SyncResponse::Leaves(_) => unreachable!(),
+            };
+        }
+
+        let leaves = match remote.answer(&reconciler.leaves_request().unwrap()) {
+            SyncResponse::Leaves(leaves) => leaves,
+            SyncResponse::Nodes(_) => unreachable!(),
+        };
+
+        assert_eq!(leaves.iter().map(|(index, _)| *index).collect::<Vec<_>>(), vec![2, 7, 8, 9, 10]);
+
+        for (index, data) in leaves {
+            if index < local.leaf_count() {
+                local.update(index, data).unwrap();
+            } else {
+                local.push(data).unwrap();
+            }
+        }
+
+        assert_eq!(local.root_hash(), remote.root_hash());
+    }
+}
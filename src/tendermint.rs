@@ -0,0 +1,199 @@
+//! Compatibility with the simple Merkle tree of Tendermint and CometBFT (`crypto/merkle`), used
+//! for block, validator set and IBC commitments.
+//!
+//! The tree is RFC 6962's: SHA-256, leaves prefixed with 0x00, inner nodes with 0x01, and every
+//! subtree split at the largest power of two smaller than its leaf count. The only difference
+//! is that an empty list of items has the hash of an empty input as its root.
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+
+use sha2::Sha256;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Prefixes, TreeConfig};
+use crate::error::MerkleError;
+use crate::leaf::LeafEncode;
+use crate::tree::MerkleTree;
+use crate::{calculate_hash, hash_leaf, hash_pair, Hash};
+
+impl<T> MerkleTree<T, Sha256> where T: LeafEncode {
+    /// Creates a MerkleTree whose root is the one `merkle.HashFromByteSlices` computes for the
+    /// same items.
+    pub fn from_vec_tendermint(data: Vec<T>) -> Result<MerkleTree<T, Sha256>, MerkleError> {
+        MerkleTree::from_vec_with_config_and_digest(data, TreeConfig::RFC6962)
+    }
+
+    /// Generates the proof `merkle.ProofsFromByteSlices` returns for the item at `index`.
+    /// Fails with `MalformedProof` for trees not built with the RFC 6962 config.
+    pub fn tendermint_proof(&self, index: usize) -> Result<TendermintProof, MerkleError> {
+        if self.config() != TreeConfig::RFC6962 {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let proof = self.proof(index)?;
+        let leaf_hash = self.get_leaf(index).expect("index is smaller than the leaf count").hash;
+
+        Ok(TendermintProof {
+            total: self.leaf_count() as u64,
+            index: index as u64,
+            leaf_hash,
+            aunts: proof.siblings.into_iter().map(|step| step.hash).collect(),
+        })
+    }
+}
+
+/// Root `merkle.HashFromByteSlices` computes for `items`, including the empty list.
+pub fn tendermint_root<T: LeafEncode>(items: &[T]) -> Hash<Sha256> {
+    match items.len() {
+        0 => calculate_hash::<Sha256>(&[]),
+        1 => hash_leaf::<Sha256>(Some(Prefixes::RFC6962), &items[0].encode_leaf()),
+        len => {
+            let split = split_point(len);
+            let left = tendermint_root(&items[..split]);
+            let right = tendermint_root(&items[split..]);
+
+            hash_pair::<Sha256>(Some(Prefixes::RFC6962), &left, &right)
+        }
+    }
+}
+
+/// Inclusion proof in the layout of Tendermint's `merkle.Proof`: `aunts` holds the sibling
+/// hashes from the leaf up to the root.
+#[derive(PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TendermintProof {
+    /// Number of items in the tree.
+    pub total: u64,
+    pub index: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash"))]
+    pub leaf_hash: Hash<Sha256>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::list"))]
+    pub aunts: Vec<Hash<Sha256>>,
+}
+
+impl TendermintProof {
+    /// Checks that `leaf` is included in the tree with the given root hash, like `Proof.Verify`:
+    /// the leaf must hash to `leaf_hash`, which must hash up to the root through the aunts.
+    pub fn verify(&self, root_hash: &[u8], leaf: &[u8]) -> Result<(), MerkleError> {
+        if hash_leaf::<Sha256>(Some(Prefixes::RFC6962), leaf) != self.leaf_hash {
+            return Err(MerkleError::RootMismatch);
+        }
+
+        if self.compute_root_hash()?.as_slice() == root_hash {
+            Ok(())
+        } else {
+            Err(MerkleError::RootMismatch)
+        }
+    }
+
+    /// Root the proof hashes up to, failing with `MalformedProof` when the aunts do not match
+    /// the shape of a tree of `total` items, like `Proof.ComputeRootHash` returning nil.
+    pub fn compute_root_hash(&self) -> Result<Hash<Sha256>, MerkleError> {
+        let index = usize::try_from(self.index).map_err(|_| MerkleError::MalformedProof)?;
+        let total = usize::try_from(self.total).map_err(|_| MerkleError::MalformedProof)?;
+
+        hash_from_aunts(index, total, &self.leaf_hash, &self.aunts).ok_or(MerkleError::MalformedProof)
+    }
+}
+
+/// Port of `computeHashFromAunts`.
+fn hash_from_aunts(index: usize, total: usize, leaf_hash: &Hash<Sha256>, aunts: &[Hash<Sha256>]) -> Option<Hash<Sha256>> {
+    if index >= total || total == 0 {
+        return None;
+    }
+
+    if total == 1 {
+        return if aunts.is_empty() { Some(*leaf_hash) } else { None };
+    }
+
+    let (last, rest) = aunts.split_last()?;
+    let left_count = split_point(total);
+
+    if index < left_count {
+        let left = hash_from_aunts(index, left_count, leaf_hash, rest)?;
+        Some(hash_pair::<Sha256>(Some(Prefixes::RFC6962), &left, last))
+    } else {
+        let right = hash_from_aunts(index - left_count, total - left_count, leaf_hash, rest)?;
+        Some(hash_pair::<Sha256>(Some(Prefixes::RFC6962), last, &right))
+    }
+}
+
+/// Largest power of two smaller than `total`, which must be at least 2.
+fn split_point(total: usize) -> usize {
+    1 << (usize::BITS - 1 - (total - 1).leading_zeros())
+}
+
+// Implemented by hand so that hashes are shown in hex.
+impl fmt::Debug for TendermintProof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TendermintProof")
+            .field("total", &self.total)
+            .field("index", &self.index)
+            .field("leaf_hash", &crate::to_hex(&self.leaf_hash))
+            .field("aunts", &self.aunts.iter().map(|aunt| crate::to_hex(aunt)).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_hex;
+
+    #[test]
+    fn it_matches_the_go_implementation() {
+        // Vectors of `TestHashFromByteSlices` in crypto/merkle/tree_test.go.
+        let cases: Vec<(Vec<Vec<u8>>, &str)> = vec![
+            (vec![], "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            (vec![vec![1, 2, 3]], "054edec1d0211f624fed0cbca9d4f9400b0e491c43742af2c5b0abebf0c990d8"),
+            (vec![vec![]], "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d"),
+            (vec![vec![1, 2, 3], vec![4, 5, 6]], "82e6cfce00453804379b53962939eaa7906b39904be0813fcadd31b100773c4b"),
+            (vec![vec![1, 2], vec![3, 4], vec![5, 6], vec![7, 8], vec![9, 10]], "f326493eceab4f2d9ffbc78c59432a0a005d6ea98392045c74df5d14a113be18"),
+        ];
+
+        for (items, expected) in cases {
+            assert_eq!(to_hex(&tendermint_root(&items)), expected);
+
+            if !items.is_empty() {
+                assert_eq!(MerkleTree::from_vec_tendermint(items).unwrap().root_hash_hex(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn it_generates_proofs_verified_like_go() {
+        for len in 1..=20 {
+            let items: Vec<Vec<u8>> = (0..len).map(|i| vec![i as u8; i]).collect();
+            let merkle_tree = MerkleTree::from_vec_tendermint(items.clone()).unwrap();
+
+            assert_eq!(merkle_tree.root_hash(), tendermint_root(&items).as_slice());
+
+            for (index, item) in items.iter().enumerate() {
+                let proof = merkle_tree.tendermint_proof(index).unwrap();
+
+                assert_eq!(proof.verify(merkle_tree.root_hash(), item), Ok(()));
+                assert_eq!(proof.verify(merkle_tree.root_hash(), b"other"), Err(MerkleError::RootMismatch));
+            }
+        }
+    }
+
+    #[test]
+    fn it_rejects_proofs_of_the_wrong_shape() {
+        let merkle_tree = MerkleTree::from_vec_tendermint(vec!["a", "b", "c", "d", "e"]).unwrap();
+        let proof = merkle_tree.tendermint_proof(4).unwrap();
+
+        let mut moved = proof.clone();
+        moved.index = 5;
+        assert_eq!(moved.compute_root_hash(), Err(MerkleError::MalformedProof));
+
+        let mut shortened = proof.clone();
+        shortened.aunts.pop();
+        assert_eq!(shortened.verify(merkle_tree.root_hash(), b"e"), Err(MerkleError::MalformedProof));
+
+        let sha256 = MerkleTree::<_, Sha256>::from_vec_with_digest(vec!["a", "b"]).unwrap();
+        assert_eq!(sha256.tendermint_proof(0), Err(MerkleError::MalformedProof));
+    }
+}
@@ -0,0 +1,241 @@
+//! Known-answer vectors of the roots and proofs of this crate, for checking that another
+//! implementation, or a build of this one, hashes trees the same way.
+//!
+//! Every vector gives the leaves of a tree in hex, the digest and config it is hashed with, its
+//! root and the siblings of the proof of one leaf. Some vectors come in pairs showing how a
+//! config can be malleable: with `Padding::DuplicateLast`, appending a copy of an odd last leaf
+//! keeps the root, as in CVE-2012-2459; without prefixes, a single leaf holding the hashes of
+//! two leaves has the root of the tree of those two leaves, which the prefixes of RFC 6962 rule
+//! out; and with sorted pairs, swapping two sibling leaves keeps the root.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use blake2::Blake2b;
+use digest::Digest;
+use sha2::Sha256;
+use sha3::Keccak256;
+
+use crate::{from_hex, to_hex};
+use crate::config::{DuplicatePolicy, LeafMode, Padding, TreeConfig};
+use crate::error::MerkleError;
+use crate::tree::MerkleTree;
+
+/// Digest a test vector is hashed with.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum VectorDigest {
+    Blake2b,
+    Sha256,
+    Keccak256,
+}
+
+/// A tree and the proof of one of its leaves.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct TestVector {
+    pub name: &'static str,
+    pub digest: VectorDigest,
+    pub config: TreeConfig,
+    /// Data of the leaves, in hex.
+    pub leaves: &'static [&'static str],
+    /// Root hash, in hex.
+    pub root: &'static str,
+    pub leaf_index: usize,
+    /// Sibling hashes of the proof of the leaf at `leaf_index`, from the leaf up, in hex.
+    pub proof: &'static [&'static str],
+}
+
+impl TestVector {
+    /// Builds the tree of the vector, failing with `RootMismatch` if its root is not the one of
+    /// the vector and with `MalformedProof` if the proof of the leaf has other siblings.
+    pub fn check(&self) -> Result<(), MerkleError> {
+        match self.digest {
+            VectorDigest::Blake2b => self.check_with::<Blake2b>(),
+            VectorDigest::Sha256 => self.check_with::<Sha256>(),
+            VectorDigest::Keccak256 => self.check_with::<Keccak256>(),
+        }
+    }
+
+    fn check_with<D: Digest>(&self) -> Result<(), MerkleError> {
+        let leaves = self.leaves.iter().map(|leaf| from_hex(leaf)).collect::<Result<Vec<Vec<u8>>, MerkleError>>()?;
+        let merkle_tree = MerkleTree::<Vec<u8>, D>::from_vec_with_config_and_digest(leaves, self.config)?;
+
+        if merkle_tree.root_hash_hex() != self.root {
+            return Err(MerkleError::RootMismatch);
+        }
+
+        let proof = merkle_tree.proof(self.leaf_index)?;
+        let siblings: Vec<String> = proof.siblings.iter().map(|step| to_hex(&step.hash)).collect();
+
+        if siblings != self.proof {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        proof.verify_with_config(merkle_tree.root_hash(), &from_hex(self.leaves[self.leaf_index])?, self.config)
+    }
+}
+
+/// Checks every vector of `TEST_VECTORS`, failing with the error of the first one that does not
+/// match.
+pub fn check_test_vectors() -> Result<(), MerkleError> {
+    TEST_VECTORS.iter().try_for_each(TestVector::check)
+}
+
+const DUPLICATE_LAST: TreeConfig = TreeConfig {
+    padding: Padding::DuplicateLast,
+    prefixes: None,
+    sort_pairs: false,
+    leaf_mode: LeafMode::Hashed,
+    duplicates: DuplicatePolicy::Allow,
+};
+
+const EMPTY_HASH: TreeConfig = TreeConfig { padding: Padding::EmptyHash, ..DUPLICATE_LAST };
+
+pub const TEST_VECTORS: &[TestVector] = &[
+    TestVector {
+        name: "blake2b-duplicate-last",
+        digest: VectorDigest::Blake2b,
+        config: DUPLICATE_LAST,
+        leaves: &["61", "62", "63", "64", "65"],
+        root: "0532b716f946706cb48e822d1f4abf7ef007a2f841f03f31ed78a9017a57627877fc226e8c64fffbfaec8610626363c06114d12322ece6c016fdf7092cb817e6",
+        leaf_index: 4,
+        proof: &[
+            "faaa8a2965e6e1c5448eb4e6e647683333635103abcbf41ab013f8cf5e33df43ef2f9574042959f86f95ecca8cef9ca7d631ff3bd0bb213fa2a6769a319cfb4d",
+            "ad67cacc59ca9d2cb6eb0524a4bee1a25e5c1aa5f1c24d8862225c94aa90ce056818a492d660765d0f9d2a7e83b1898e041cd1267267fee0e1a17a7f93c78fa4",
+            "57148d9356c76fde4d7bfa73ff2ec024caec694252497ff1a0fb926085bbfda6063f0fcb759db2801851073709b79d5b38c0585a2d8c8e646152bf71c4e57a20",
+        ],
+    },
+    TestVector {
+        name: "blake2b-duplicate-last-odd",
+        digest: VectorDigest::Blake2b,
+        config: DUPLICATE_LAST,
+        leaves: &["61", "62", "63"],
+        root: "1db4becc73885cc1b2c7d8f9df2a688255dfaccbedc0613d3d7748f2b5df329687cf2db8cc1912748ac1f19696bcebf4e297e48607015e141a4f2adc66964753",
+        leaf_index: 2,
+        proof: &[
+            "437f3ef49ef1381c1dcb18dbc11ee6efba9b74591da881fb9a7895aa70f140563cfdd2b308beb05211ead01871c8de33efc566fcfea887377129d2a1aeadfd3e",
+            "7027cee7ccfd7ba4ae5e281acfc6ad80e5dd2bc6300993c949c047979b9cccd50b321980da9325ea282773f606eb61509b791a31815f8057dd3535a80d071bf0",
+        ],
+    },
+    TestVector {
+        name: "blake2b-duplicate-last-odd-extended",
+        digest: VectorDigest::Blake2b,
+        config: DUPLICATE_LAST,
+        leaves: &["61", "62", "63", "63"],
+        root: "1db4becc73885cc1b2c7d8f9df2a688255dfaccbedc0613d3d7748f2b5df329687cf2db8cc1912748ac1f19696bcebf4e297e48607015e141a4f2adc66964753",
+        leaf_index: 3,
+        proof: &[
+            "437f3ef49ef1381c1dcb18dbc11ee6efba9b74591da881fb9a7895aa70f140563cfdd2b308beb05211ead01871c8de33efc566fcfea887377129d2a1aeadfd3e",
+            "7027cee7ccfd7ba4ae5e281acfc6ad80e5dd2bc6300993c949c047979b9cccd50b321980da9325ea282773f606eb61509b791a31815f8057dd3535a80d071bf0",
+        ],
+    },
+    TestVector {
+        name: "blake2b-unprefixed-pair",
+        digest: VectorDigest::Blake2b,
+        config: DUPLICATE_LAST,
+        leaves: &["61", "62"],
+        root: "7027cee7ccfd7ba4ae5e281acfc6ad80e5dd2bc6300993c949c047979b9cccd50b321980da9325ea282773f606eb61509b791a31815f8057dd3535a80d071bf0",
+        leaf_index: 1,
+        proof: &[
+            "333fcb4ee1aa7c115355ec66ceac917c8bfd815bf7587d325aec1864edd24e34d5abe2c6b1b5ee3face62fed78dbef802f2a85cb91d455a8f5249d330853cb3c",
+        ],
+    },
+    TestVector {
+        name: "blake2b-unprefixed-pair-concatenated",
+        digest: VectorDigest::Blake2b,
+        config: DUPLICATE_LAST,
+        leaves: &["333fcb4ee1aa7c115355ec66ceac917c8bfd815bf7587d325aec1864edd24e34d5abe2c6b1b5ee3face62fed78dbef802f2a85cb91d455a8f5249d330853cb3cc029c24b2c89db037fbf8b04930569fd8422f7c0d62f36c8dae35d03332139e546a1126f6c75be43685598f48cefff1d05a3c74d804fcd5c0a53734cfb0bb862"],
+        root: "7027cee7ccfd7ba4ae5e281acfc6ad80e5dd2bc6300993c949c047979b9cccd50b321980da9325ea282773f606eb61509b791a31815f8057dd3535a80d071bf0",
+        leaf_index: 0,
+        proof: &[
+        ],
+    },
+    TestVector {
+        name: "sha256-rfc6962",
+        digest: VectorDigest::Sha256,
+        config: TreeConfig::RFC6962,
+        leaves: &["", "00", "10", "2021", "3031", "40414243", "5051525354555657", "606162636465666768696a6b6c6d6e6f"],
+        root: "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+        leaf_index: 5,
+        proof: &[
+            "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+            "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        ],
+    },
+    TestVector {
+        name: "sha256-rfc6962-pair",
+        digest: VectorDigest::Sha256,
+        config: TreeConfig::RFC6962,
+        leaves: &["61", "62"],
+        root: "b137985ff484fb600db93107c77b0365c80d78f5b429ded0fd97361d077999eb",
+        leaf_index: 0,
+        proof: &[
+            "57eb35615d47f34ec714cacdf5fd74608a5e8e102724e80b24b287c0c27b6a31",
+        ],
+    },
+    TestVector {
+        name: "sha256-rfc6962-pair-concatenated",
+        digest: VectorDigest::Sha256,
+        config: TreeConfig::RFC6962,
+        leaves: &["022a6979e6dab7aa5ae4c3e5e45f7e977112a7e63593820dbec1ec738a24f93c57eb35615d47f34ec714cacdf5fd74608a5e8e102724e80b24b287c0c27b6a31"],
+        root: "8caadc8a584ea884ef39e2831102f201c4520c2041d49eca38a98c5a7c69aae1",
+        leaf_index: 0,
+        proof: &[
+        ],
+    },
+    TestVector {
+        name: "sha256-empty-hash",
+        digest: VectorDigest::Sha256,
+        config: EMPTY_HASH,
+        leaves: &["61", "62", "63"],
+        root: "8b647566a3d9a04e3e11b3dbe6253aee49611f98147525b6583f8e45d028c015",
+        leaf_index: 2,
+        proof: &[
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            "e5a01fee14e0ed5c48714f22180f25ad8365b53f9779f79dc4a3d7e93963f94a",
+        ],
+    },
+    TestVector {
+        name: "keccak256-openzeppelin",
+        digest: VectorDigest::Keccak256,
+        config: TreeConfig::OPENZEPPELIN,
+        leaves: &["61", "62", "63"],
+        root: "5842148bc6ebeb52af882a317c765fccd3ae80589b21a9b8cbf21abb630e46a7",
+        leaf_index: 0,
+        proof: &[
+            "b5553de315e0edf504d9150af82dafa5c4667fa618ed0a6f19c69b41166c5510",
+            "0b42b6393c1f53060fe3ddbfcd7aadcca894465a5a438f69c87d790b2299b9b2",
+        ],
+    },
+    TestVector {
+        name: "keccak256-openzeppelin-swapped",
+        digest: VectorDigest::Keccak256,
+        config: TreeConfig::OPENZEPPELIN,
+        leaves: &["62", "61", "63"],
+        root: "5842148bc6ebeb52af882a317c765fccd3ae80589b21a9b8cbf21abb630e46a7",
+        leaf_index: 1,
+        proof: &[
+            "b5553de315e0edf504d9150af82dafa5c4667fa618ed0a6f19c69b41166c5510",
+            "0b42b6393c1f53060fe3ddbfcd7aadcca894465a5a438f69c87d790b2299b9b2",
+        ],
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_passes_its_own_test_vectors() {
+        assert_eq!(check_test_vectors(), Ok(()));
+    }
+
+    #[test]
+    fn it_catches_mismatching_vectors() {
+        let vector = TEST_VECTORS[0];
+
+        assert_eq!(TestVector { root: TEST_VECTORS[1].root, ..vector }.check(), Err(MerkleError::RootMismatch));
+        assert_eq!(TestVector { proof: &vector.proof[1..], ..vector }.check(), Err(MerkleError::MalformedProof));
+        assert_eq!(TestVector { digest: VectorDigest::Sha256, ..vector }.check(), Err(MerkleError::RootMismatch));
+    }
+}
@@ -0,0 +1,456 @@
+//! Tiles of the hashes of a log, the layout in which Go's checksum database and other tile-based
+//! transparency logs serve their trees.
+//!
+//! The hashes of a tree are grouped in tiles of `height` levels: the tile at `level` L and
+//! `index` N holds the hashes of up to `2^height` consecutive complete subtrees of height
+//! `L * height`, from the `N * 2^height`-th on, and every node of the `height` levels above them
+//! is computed from the tile alone. A full tile never changes, so servers and caches keep it
+//! forever, and the tile at the right edge of a level is served partial, with its width in its
+//! path. A [`TiledLog`] builds proofs from the tiles it reads, which are checked against a signed
+//! tree head like proofs served by the log, so a log serving wrong tiles is caught the same way.
+//! Data tiles, holding the leaves themselves, are not supported.
+
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+
+use digest::Digest;
+use digest::generic_array::GenericArray;
+use sha2::Sha256;
+
+use crate::{hash_children, Hash};
+use crate::config::{Padding, Prefixes};
+use crate::consistency::ConsistencyProof;
+use crate::error::MerkleError;
+use crate::leaf::LeafEncode;
+use crate::proof::{Proof, ProofStep, Side};
+use crate::tree::MerkleTree;
+
+/// Largest tile height, as in Go's `tlog` package.
+const MAX_HEIGHT: usize = 30;
+
+/// A tile of the hashes of a log.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+pub struct Tile {
+    /// Number of tree levels a tile spans.
+    pub height: usize,
+    /// Level of the tile, whose hashes are those of subtrees of height `level * height`.
+    pub level: usize,
+    /// Index of the tile within its level.
+    pub index: usize,
+    /// Number of hashes in the tile, `2^height` unless it is partial.
+    pub width: usize,
+}
+
+impl Tile {
+    /// # Panics
+    ///
+    /// Panics unless `0 < height <= 30` and `0 < width <= 2^height`.
+    pub fn new(height: usize, level: usize, index: usize, width: usize) -> Tile {
+        assert!(height > 0 && height <= MAX_HEIGHT, "tile height {} is not between 1 and {}", height, MAX_HEIGHT);
+        assert!(width > 0 && width <= 1 << height, "tile width {} is not between 1 and {}", width, 1 << height);
+
+        Tile { height, level, index, width }
+    }
+
+    /// Every tile of a tree of `tree_size` leaves, level by level, with a partial tile at the end
+    /// of the levels whose hashes do not fill their last tile.
+    pub fn all_for_size(height: usize, tree_size: usize) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        let mut level = 0;
+
+        while level * height < usize::BITS as usize && tree_size >> (level * height) > 0 {
+            let hashes = tree_size >> (level * height);
+            let full = hashes >> height;
+
+            tiles.extend((0..full).map(|index| Tile::new(height, level, index, 1 << height)));
+
+            if hashes & ((1 << height) - 1) != 0 {
+                tiles.push(Tile::new(height, level, full, hashes & ((1 << height) - 1)));
+            }
+
+            level += 1;
+        }
+
+        tiles
+    }
+
+    /// Whether the tile holds fewer than `2^height` hashes.
+    pub fn is_partial(&self) -> bool {
+        self.width < 1 << self.height
+    }
+
+    /// Path the tile is served at, such as `tile/8/0/x001/x234/067` or `tile/8/1/003.p/5` for a
+    /// partial tile, with the index split in groups of three digits.
+    pub fn path(&self) -> String {
+        let mut index = format!("{:03}", self.index % 1000);
+        let mut rest = self.index / 1000;
+
+        while rest > 0 {
+            index = format!("x{:03}/{}", rest % 1000, index);
+            rest /= 1000;
+        }
+
+        if self.is_partial() {
+            format!("tile/{}/{}/{}.p/{}", self.height, self.level, index, self.width)
+        } else {
+            format!("tile/{}/{}/{}", self.height, self.level, index)
+        }
+    }
+
+    /// Parses the path of a tile, failing with `InvalidTile` if it is not one.
+    pub fn from_path(path: &str) -> Result<Tile, MerkleError> {
+        let mut parts: Vec<&str> = path.strip_prefix("tile/").ok_or(MerkleError::InvalidTile)?.split('/').collect();
+
+        if parts.len() < 3 {
+            return Err(MerkleError::InvalidTile);
+        }
+
+        let height = parse_number(parts[0]).filter(|height| *height > 0 && *height <= MAX_HEIGHT).ok_or(MerkleError::InvalidTile)?;
+        let level = parse_number(parts[1]).ok_or(MerkleError::InvalidTile)?;
+        let mut width = 1 << height;
+
+        if let Some(partial) = parts[parts.len() - 2].strip_suffix(".p") {
+            width = parse_number(parts[parts.len() - 1]).filter(|width| *width > 0 && *width < 1 << height).ok_or(MerkleError::InvalidTile)?;
+            parts.pop();
+            let last = parts.len() - 1;
+            parts[last] = partial;
+        }
+
+        let (last, groups) = parts[2..].split_last().ok_or(MerkleError::InvalidTile)?;
+        let mut index: usize = 0;
+
+        for group in groups.iter().map(|group| group.strip_prefix('x')).chain(Some(Some(*last))) {
+            let group = group.filter(|group| group.len() == 3).and_then(parse_number).ok_or(MerkleError::InvalidTile)?;
+            index = index.checked_mul(1000).and_then(|index| index.checked_add(group)).ok_or(MerkleError::InvalidTile)?;
+        }
+
+        Ok(Tile { height, level, index, width })
+    }
+
+    /// Splits the data of the tile into its hashes, failing with `InvalidTile` unless it holds
+    /// exactly `width` hashes of the digest `D`.
+    pub fn parse_hashes<D: Digest>(&self, data: &[u8]) -> Result<Vec<Hash<D>>, MerkleError> {
+        let len = D::output_size();
+
+        if data.len() != self.width * len {
+            return Err(MerkleError::InvalidTile);
+        }
+
+        Ok(data.chunks(len).map(GenericArray::clone_from_slice).collect())
+    }
+}
+
+impl fmt::Display for Tile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.path())
+    }
+}
+
+/// Decimal number without leading zeros, other than in groups of the index of a tile.
+fn parse_number(digits: &str) -> Option<usize> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    digits.parse().ok()
+}
+
+/// Source of the tiles of a log, such as an HTTP client fetching them from the paths of
+/// `Tile::path` under the URL of the log.
+pub trait TileReader {
+    /// Reads the data of a tile, the hashes it holds concatenated.
+    fn read_tile(&self, tile: &Tile) -> io::Result<Vec<u8>>;
+}
+
+impl<F> TileReader for F where F: Fn(&Tile) -> io::Result<Vec<u8>> {
+    fn read_tile(&self, tile: &Tile) -> io::Result<Vec<u8>> {
+        self(tile)
+    }
+}
+
+/// A log of `tree_size` leaves read from its tiles, hashed as in RFC 6962, for light clients
+/// proving inclusion and consistency against the tree heads the log signs.
+pub struct TiledLog<R, D: Digest = Sha256> {
+    reader: R,
+    height: usize,
+    tree_size: usize,
+    digest: PhantomData<D>,
+}
+
+/// Hashes of the tiles read while building one proof.
+type TileCache<D> = BTreeMap<Tile, Vec<Hash<D>>>;
+
+impl<R> TiledLog<R, Sha256> where R: TileReader {
+    /// Reads a log of `tree_size` leaves hashed with SHA-256 from tiles of `height` levels.
+    pub fn new(reader: R, height: usize, tree_size: usize) -> TiledLog<R, Sha256> {
+        TiledLog::with_digest(reader, height, tree_size)
+    }
+}
+
+impl<R, D> TiledLog<R, D> where R: TileReader, D: Digest {
+    /// Reads a log of `tree_size` leaves hashed with the digest `D` from tiles of `height` levels.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless `0 < height <= 30`.
+    pub fn with_digest(reader: R, height: usize, tree_size: usize) -> TiledLog<R, D> {
+        assert!(height > 0 && height <= MAX_HEIGHT, "tile height {} is not between 1 and {}", height, MAX_HEIGHT);
+
+        TiledLog { reader, height, tree_size, digest: PhantomData }
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn tree_size(&self) -> usize {
+        self.tree_size
+    }
+
+    /// Root hash of the log, computed from its tiles.
+    pub fn root_hash(&self) -> io::Result<Hash<D>> {
+        if self.tree_size == 0 {
+            return Err(invalid_input(MerkleError::EmptyInput));
+        }
+
+        self.range_hash(0, self.tree_size, &mut TileCache::<D>::new())
+    }
+
+    /// Generates an inclusion proof for the leaf at `leaf_index`, equal to the proof of a
+    /// MerkleTree built with `TreeConfig::RFC6962` from the leaves of the log.
+    pub fn proof(&self, leaf_index: usize) -> io::Result<Proof<D>> {
+        if leaf_index >= self.tree_size {
+            return Err(invalid_input(MerkleError::IndexOutOfBounds { index: leaf_index, leaf_count: self.tree_size }));
+        }
+
+        let mut siblings = Vec::new();
+        self.inclusion_path(leaf_index, 0, self.tree_size, &mut TileCache::<D>::new(), &mut siblings)?;
+
+        let mut proof = Proof::new(leaf_index, self.tree_size, Padding::Promote, siblings);
+        proof.prefixes = Some(Prefixes::RFC6962);
+
+        Ok(proof)
+    }
+
+    /// Generates an RFC 6962 proof that the tree of the first `old_size` leaves is a prefix of
+    /// the log.
+    pub fn consistency_proof(&self, old_size: usize) -> io::Result<ConsistencyProof<D>> {
+        if old_size == 0 || old_size > self.tree_size {
+            return Err(invalid_input(MerkleError::InvalidSizes { old_size, new_size: self.tree_size }));
+        }
+
+        let mut hashes = Vec::new();
+        self.consistency_subproof(old_size, 0, self.tree_size, true, &mut TileCache::<D>::new(), &mut hashes)?;
+
+        let mut proof = ConsistencyProof::new(old_size, self.tree_size, hashes);
+        proof.prefixes = Some(Prefixes::RFC6962);
+
+        Ok(proof)
+    }
+
+    /// RFC 6962 PATH of the leaf at `index` within the leaves in `start..end`, from the leaf up.
+    fn inclusion_path(&self, index: usize, start: usize, end: usize, cache: &mut TileCache<D>, siblings: &mut Vec<ProofStep<D>>) -> io::Result<()> {
+        let size = end - start;
+
+        if size == 1 {
+            return Ok(());
+        }
+
+        let split = size.next_power_of_two() / 2;
+
+        if index < start + split {
+            self.inclusion_path(index, start, start + split, cache, siblings)?;
+            siblings.push(ProofStep::new(Side::Right, self.range_hash(start + split, end, cache)?));
+        } else {
+            self.inclusion_path(index, start + split, end, cache, siblings)?;
+            siblings.push(ProofStep::new(Side::Left, self.range_hash(start, start + split, cache)?));
+        }
+
+        Ok(())
+    }
+
+    /// RFC 6962 SUBPROOF over the leaves in `start..end`, where `old_size` counts from `start`.
+    fn consistency_subproof(&self, old_size: usize, start: usize, end: usize, complete: bool, cache: &mut TileCache<D>, hashes: &mut Vec<Hash<D>>) -> io::Result<()> {
+        let size = end - start;
+
+        if old_size == size {
+            if !complete {
+                hashes.push(self.range_hash(start, end, cache)?);
+            }
+            return Ok(());
+        }
+
+        let split = size.next_power_of_two() / 2;
+
+        if old_size <= split {
+            self.consistency_subproof(old_size, start, start + split, complete, cache, hashes)?;
+            hashes.push(self.range_hash(start + split, end, cache)?);
+        } else {
+            self.consistency_subproof(old_size - split, start + split, end, false, cache, hashes)?;
+            hashes.push(self.range_hash(start, start + split, cache)?);
+        }
+
+        Ok(())
+    }
+
+    /// RFC 6962 Merkle Tree Hash of the leaves in `start..end`.
+    fn range_hash(&self, start: usize, end: usize, cache: &mut TileCache<D>) -> io::Result<Hash<D>> {
+        let size = end - start;
+
+        if size.is_power_of_two() && start & (size - 1) == 0 {
+            let height = size.trailing_zeros() as usize;
+            return self.node_hash(height, start >> height, cache);
+        }
+
+        let split = size.next_power_of_two() / 2;
+        let left = self.range_hash(start, start + split, cache)?;
+        let right = self.range_hash(start + split, end, cache)?;
+
+        Ok(hash_children::<D>(Some(Prefixes::RFC6962), false, &left, &right))
+    }
+
+    /// Hash of the complete subtree at `index` on the level `height` levels above the leaves,
+    /// computed from the hashes of the tile holding its descendants at the base of the tile.
+    fn node_hash(&self, height: usize, index: usize, cache: &mut TileCache<D>) -> io::Result<Hash<D>> {
+        let level = height / self.height;
+        let base = level * self.height;
+        let first = index << (height - base);
+        let tile_index = first >> self.height;
+        let width = ((self.tree_size >> base) - (tile_index << self.height)).min(1 << self.height);
+        let tile = Tile::new(self.height, level, tile_index, width);
+
+        let hashes = match cache.entry(tile) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let data = self.reader.read_tile(&tile)?;
+                entry.insert(tile.parse_hashes::<D>(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?)
+            }
+        };
+
+        let offset = first - (tile_index << self.height);
+        let mut level_hashes = hashes[offset..offset + (1 << (height - base))].to_vec();
+
+        while level_hashes.len() > 1 {
+            level_hashes = level_hashes.chunks(2)
+                .map(|pair| hash_children::<D>(Some(Prefixes::RFC6962), false, &pair[0], &pair[1]))
+                .collect();
+        }
+
+        Ok(level_hashes.remove(0))
+    }
+}
+
+impl<R, D> fmt::Debug for TiledLog<R, D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TiledLog")
+            .field("height", &self.height)
+            .field("tree_size", &self.tree_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, D> MerkleTree<T, D> where T: LeafEncode, D: Digest {
+    /// Data of `tile` for serving the tree as a tiled log, the hashes it holds concatenated.
+    /// Fails with `UnsupportedPadding` unless the tree was built with `Padding::Promote`, as
+    /// RFC 6962 trees are, and with `InvalidTile` if the tree does not hold all of its hashes.
+    pub fn tile(&self, tile: &Tile) -> Result<Vec<u8>, MerkleError> {
+        if self.config().padding != Padding::Promote {
+            return Err(MerkleError::UnsupportedPadding(self.config().padding));
+        }
+
+        let base = tile.level * tile.height;
+        let first = tile.index << tile.height;
+
+        if base >= usize::BITS as usize || first + tile.width > self.leaf_count() >> base {
+            return Err(MerkleError::InvalidTile);
+        }
+
+        Ok((first..first + tile.width).flat_map(|index| self.node_at(base, index).hash().to_vec()).collect())
+    }
+}
+
+fn invalid_input(error: MerkleError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn it_formats_and_parses_tile_paths() {
+        assert_eq!(Tile::new(8, 0, 1_234_067, 256).path(), "tile/8/0/x001/x234/067");
+        assert_eq!(Tile::new(8, 1, 3, 5).path(), "tile/8/1/003.p/5");
+
+        for tile in &[Tile::new(8, 0, 1_234_067, 256), Tile::new(8, 1, 3, 5), Tile::new(2, 4, 1000, 1)] {
+            assert_eq!(Tile::from_path(&tile.path()), Ok(*tile));
+        }
+
+        for path in &["tile/8/0/1", "tile/8/0/x1/067", "tile/8/0/003.p/256", "tile/8/data/003", "tile/0/0/003", "tile/8/0"] {
+            assert_eq!(Tile::from_path(path), Err(MerkleError::InvalidTile), "{}", path);
+        }
+
+        assert_eq!(Tile::new(1, 0, 0, 2).parse_hashes::<Sha256>(&[0; 63]), Err(MerkleError::InvalidTile));
+    }
+
+    #[test]
+    fn it_lists_the_tiles_of_a_tree() {
+        let tiles = Tile::all_for_size(2, 11);
+
+        assert_eq!(tiles, vec![
+            Tile::new(2, 0, 0, 4), Tile::new(2, 0, 1, 4), Tile::new(2, 0, 2, 3),
+            Tile::new(2, 1, 0, 2),
+        ]);
+    }
+
+    #[test]
+    fn it_builds_proofs_from_tiles() {
+        let data: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+        let merkle_tree = MerkleTree::from_vec_rfc6962(data.clone()).unwrap();
+        let reads = Cell::new(0);
+        let reader = |tile: &Tile| {
+            reads.set(reads.get() + 1);
+            merkle_tree.tile(tile).map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))
+        };
+
+        for tree_size in &[1, 7, 256, 1000] {
+            let log = TiledLog::new(&reader, 3, *tree_size);
+            let tree = MerkleTree::from_vec_rfc6962(data[..*tree_size].to_vec()).unwrap();
+
+            assert_eq!(log.root_hash().unwrap().as_slice(), tree.root_hash());
+
+            for &index in &[0, tree_size / 2, tree_size - 1] {
+                let proof = log.proof(index).unwrap();
+                assert_eq!(proof, tree.proof(index).unwrap());
+                assert_eq!(proof.verify(tree.root_hash(), data[index].as_bytes()), Ok(()));
+            }
+
+            for &old_size in &[1, tree_size / 3 + 1, *tree_size] {
+                assert_eq!(log.consistency_proof(old_size).unwrap(), merkle_tree.consistency_proof(old_size, *tree_size).unwrap());
+            }
+        }
+
+        // Each tile is read once per proof, one per level of tiles at most on either side of the path.
+        reads.set(0);
+        TiledLog::new(&reader, 3, 1000).proof(500).unwrap();
+        assert!(reads.get() <= 8, "{} tiles read", reads.get());
+
+        let error = TiledLog::new(&reader, 3, 1001).root_hash().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(TiledLog::new(|_: &Tile| Ok(vec![0; 5]), 3, 10).root_hash().is_err());
+    }
+
+    #[test]
+    fn it_serves_tiles_of_rfc6962_trees_only() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c"]).unwrap();
+        assert!(matches!(merkle_tree.tile(&Tile::new(1, 0, 0, 2)), Err(MerkleError::UnsupportedPadding(_))));
+
+        let merkle_tree = MerkleTree::from_vec_rfc6962(vec!["a", "b", "c"]).unwrap();
+        assert_eq!(merkle_tree.tile(&Tile::new(1, 0, 1, 1)).unwrap(), merkle_tree.node_at(0, 2).hash().to_vec());
+        assert_eq!(merkle_tree.tile(&Tile::new(1, 0, 1, 2)), Err(MerkleError::InvalidTile));
+        assert_eq!(merkle_tree.tile(&Tile::new(1, 1, 0, 1)).unwrap(), merkle_tree.node_at(1, 0).hash().to_vec());
+    }
+}
@@ -1,164 +1,2505 @@
-use crate::calculate_hash;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::FromIterator;
+use core::marker::PhantomData;
+use core::ops::Range;
 
-type HashFn = Fn(&[u8]) -> Vec<u8>;
+use blake2::Blake2b;
+use digest::Digest;
+use digest::generic_array::typenum::U64;
+use digest::generic_array::{ArrayLength, GenericArray};
+use sha2::Sha256;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
-pub struct MerkleTree<T> {
-    pub root: Node<T>,
+use crate::{calculate_hash, check_leaf, hash_children, leaf_hash, map_hashes, to_hex, Hash};
+use crate::batch::{self, BatchDigest};
+use crate::config::{DuplicatePolicy, LeafMode, Padding, TreeConfig};
+use crate::consistency::ConsistencyProof;
+use crate::error::MerkleError;
+use crate::leaf::{LeafBytes, LeafEncode};
+use crate::metrics;
+use crate::multiproof::MultiProof;
+use crate::proof::{Proof, ProofStep, Side};
+use crate::range::RangeProof;
+
+type HashFn<'a, N> = dyn Fn(&[u8]) -> GenericArray<u8, N> + 'a;
+
+/// Hashes the two child hashes of a node into the hash of the node, for nodes built by hand with
+/// `MerkleNode::new`. Implemented by every `Fn(&[u8]) -> GenericArray<u8, N>`, applied to the
+/// concatenated child hashes, and by hand for other combiners, such as ones hashing pairs in
+/// ascending order or arithmetic-friendly hashes working on field elements.
+pub trait NodeCombiner<N: ArrayLength<u8>> {
+    fn combine(&self, left: &[u8], right: &[u8]) -> GenericArray<u8, N>;
+}
+
+impl<F, N> NodeCombiner<N> for F where F: Fn(&[u8]) -> GenericArray<u8, N>, N: ArrayLength<u8> {
+    fn combine(&self, left: &[u8], right: &[u8]) -> GenericArray<u8, N> {
+        let mut concat = left.to_vec();
+        concat.extend_from_slice(right);
+        self(&concat)
+    }
+}
+
+/// Node of a tree hashed with the digest `D`.
+type TreeNode<T, D> = Node<T, <D as Digest>::OutputSize>;
+
+/// Child of an inner node, which snapshots of the tree may share.
+type SharedNode<T, D> = Arc<TreeNode<T, D>>;
+
+/// Indices of the leaves holding each leaf hash.
+type LeafIndex<D> = BTreeMap<Hash<D>, BTreeSet<usize>>;
+
+/// Node compared by `diff`, with its number of leaves and its depth, or `None` when the tree
+/// has no leaves in the compared range.
+type DiffSide<'a, T, D> = Option<(&'a TreeNode<T, D>, usize, usize)>;
+
+/// A Merkle tree whose hashes are computed with the digest `D`.
+///
+/// Building a tree never recurses. The operations walking it, such as `push`, `update`,
+/// `proofs` and dropping the tree, recurse once per level, and a tree has at most `usize::BITS`
+/// levels, so the stack they use is bounded whatever the number of leaves.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>")))]
+pub struct MerkleTree<T, D: Digest = Blake2b> {
+    pub root: TreeNode<T, D>,
+    config: TreeConfig,
+    leaf_count: usize,
+    /// Index of the leaves by hash, for `index_of`. Snapshots and deserialized trees have none.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    indices: Option<LeafIndex<D>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    digest: PhantomData<D>,
+}
+
+impl<T> MerkleTree<T> where T: LeafEncode {
+    /// Creates a MerkleTree from a vector, hashing with Blake2b and the default config.
+    pub fn from_vec(data: Vec<T>) -> Result<MerkleTree<T>, MerkleError> {
+        MerkleTree::from_vec_with_digest(data)
+    }
+
+    /// Creates a MerkleTree from a vector, hashing with Blake2b.
+    pub fn from_vec_with_config(data: Vec<T>, config: TreeConfig) -> Result<MerkleTree<T>, MerkleError> {
+        MerkleTree::from_vec_with_config_and_digest(data, config)
+    }
+
+    /// Creates a MerkleTree from a vector like `from_vec`, failing with `RootMismatch` unless its
+    /// root hash is `expected_root`, as when checking a downloaded dataset against a published root.
+    pub fn from_vec_verified(data: Vec<T>, expected_root: &[u8]) -> Result<MerkleTree<T>, MerkleError> {
+        MerkleTree::from_vec_verified_with_config_and_digest(data, expected_root, TreeConfig::default())
+    }
+
+    pub fn from_vec_verified_with_config(data: Vec<T>, expected_root: &[u8], config: TreeConfig) -> Result<MerkleTree<T>, MerkleError> {
+        MerkleTree::from_vec_verified_with_config_and_digest(data, expected_root, config)
+    }
+}
+
+impl<T> MerkleTree<T, Sha256> where T: LeafEncode {
+    /// Creates a MerkleTree whose root is the RFC 6962 (Certificate Transparency) Merkle Tree Hash
+    /// of the data, so it can be compared against CT log roots.
+    pub fn from_vec_rfc6962(data: Vec<T>) -> Result<MerkleTree<T, Sha256>, MerkleError> {
+        MerkleTree::from_vec_with_config_and_digest(data, TreeConfig::RFC6962)
+    }
+}
+
+impl<const N: usize> MerkleTree<[u8; N]> {
+    /// Creates a MerkleTree of fixed-size leaves, hashing with Blake2b several leaves at a time
+    /// and the default config.
+    pub fn from_fixed_vec(data: Vec<[u8; N]>) -> Result<MerkleTree<[u8; N]>, MerkleError> {
+        MerkleTree::from_fixed_vec_with_config_and_digest(data, TreeConfig::default())
+    }
+
+    /// Creates a MerkleTree of fixed-size leaves, hashing with Blake2b several leaves at a time.
+    pub fn from_fixed_vec_with_config(data: Vec<[u8; N]>, config: TreeConfig) -> Result<MerkleTree<[u8; N]>, MerkleError> {
+        MerkleTree::from_fixed_vec_with_config_and_digest(data, config)
+    }
+}
+
+impl<D, const N: usize> MerkleTree<[u8; N], D> where D: BatchDigest {
+    /// Creates a MerkleTree of fixed-size leaves, hashing with the digest `D` and the default config.
+    pub fn from_fixed_vec_with_digest(data: Vec<[u8; N]>) -> Result<MerkleTree<[u8; N], D>, MerkleError> {
+        MerkleTree::from_fixed_vec_with_config_and_digest(data, TreeConfig::default())
+    }
+
+    /// Creates a MerkleTree of fixed-size leaves, hashing with the digest `D` `D::lanes()` leaves
+    /// per call, such as 8 SHA-256 leaves at a time with AVX2. The tree is the one
+    /// `from_vec_with_config_and_digest` builds from the same data.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(leaves = data.len(), bytes = data.len() * N)))]
+    pub fn from_fixed_vec_with_config_and_digest(data: Vec<[u8; N]>, config: TreeConfig) -> Result<MerkleTree<[u8; N], D>, MerkleError> {
+        if config.leaf_mode == LeafMode::Prehashed {
+            return MerkleTree::from_vec_with_config_and_digest(data, config);
+        }
+
+        let hashes = {
+            let slices: Vec<&[u8]> = data.iter().map(|leaf| &leaf[..]).collect();
+            batch::hash_leaves::<D>(config.prefixes.map(|prefixes| prefixes.leaf), &slices)
+        };
+        metrics::record(|metrics| metrics.hashes_computed(hashes.len()));
+        let leaves = data.into_iter().zip(hashes).map(|(data, hash)| Node::Leaf(MerkleLeaf { hash, data }));
+
+        MerkleTree::from_leaves(leaves.collect(), config)
+    }
+}
+
+impl<T, D> MerkleTree<T, D> where T: LeafEncode, D: Digest {
+    /// Wraps an already built root, which is assumed to follow the default config.
+    pub fn new(root: TreeNode<T, D>) -> MerkleTree<T, D> {
+        MerkleTree::with_root(root, TreeConfig::default())
+    }
+
+    /// Creates a MerkleTree from a vector, hashing with the digest `D` and the default config.
+    pub fn from_vec_with_digest(data: Vec<T>) -> Result<MerkleTree<T, D>, MerkleError> {
+        MerkleTree::from_vec_with_config_and_digest(data, TreeConfig::default())
+    }
+
+    /// Creates a MerkleTree from a vector, hashing with the digest `D`.
+    /// With `LeafMode::Prehashed`, fails with `InvalidLeafLength` unless every leaf is one hash long.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(leaves = data.len(), bytes = tracing::field::Empty)))]
+    pub fn from_vec_with_config_and_digest(data: Vec<T>, config: TreeConfig) -> Result<MerkleTree<T, D>, MerkleError> {
+        for (index, leaf) in data.iter().enumerate() {
+            check_leaf::<D>(config.leaf_mode, index, &leaf.encode_leaf())?;
+        }
+
+        MerkleTree::from_leaves(MerkleTree::<T, D>::hash_leaves(data, config), config)
+    }
+
+    /// Hashes the leaves holding `data`, which must have been accepted by `check_leaf`.
+    pub(crate) fn hash_leaves(data: Vec<T>, config: TreeConfig) -> Vec<TreeNode<T, D>> {
+        let hashes = {
+            let slices: Vec<LeafBytes> = data.iter().map(LeafEncode::encode_leaf).collect();
+            trace_record!("bytes", slices.iter().map(|slice| slice.len()).sum::<usize>());
+            map_hashes(&slices, |d| leaf_hash::<D>(config.prefixes, config.leaf_mode, d))
+        };
+        if config.leaf_mode == LeafMode::Hashed {
+            metrics::record(|metrics| metrics.hashes_computed(hashes.len()));
+        }
+
+        data.into_iter().zip(hashes).map(|(data, hash)| Node::Leaf(MerkleLeaf { hash, data })).collect()
+    }
+
+    /// Creates a MerkleTree from a vector, hashing with the digest `D`, failing with
+    /// `RootMismatch` unless its root hash is `expected_root`.
+    pub fn from_vec_verified_with_config_and_digest(data: Vec<T>, expected_root: &[u8], config: TreeConfig) -> Result<MerkleTree<T, D>, MerkleError> {
+        let merkle_tree = MerkleTree::from_vec_with_config_and_digest(data, config)?;
+
+        if merkle_tree.root_hash() != expected_root {
+            return Err(MerkleError::RootMismatch);
+        }
+
+        Ok(merkle_tree)
+    }
+
+    fn leaf(config: TreeConfig, index: usize, data: T) -> Result<TreeNode<T, D>, MerkleError> {
+        check_leaf::<D>(config.leaf_mode, index, &data.encode_leaf())?;
+        Ok(Node::Leaf(MerkleLeaf::new(&|data| leaf_hash::<D>(config.prefixes, config.leaf_mode, data), data)))
+    }
+
+    fn node(config: TreeConfig, left: SharedNode<T, D>, right: SharedNode<T, D>) -> SharedNode<T, D> {
+        Arc::new(Node::Node(MerkleNode {
+            hash: hash_children::<D>(config.prefixes, config.sort_pairs, left.hash(), right.hash()),
+            left,
+            right,
+        }))
+    }
+
+    /// Lifts a node without a sibling one level up, according to the padding strategy.
+    fn pad(node: SharedNode<T, D>, config: TreeConfig) -> SharedNode<T, D> {
+        let hash = match config.padding {
+            Padding::Promote => return node,
+            Padding::DuplicateLast => node.hash().clone(),
+            Padding::EmptyHash => calculate_hash::<D>(&[]),
+        };
+
+        MerkleTree::<T, D>::node(config, node, Arc::new(Node::Padding(hash)))
+    }
+
+    /// Appends a leaf, rehashing only the nodes on the path from the new leaf to the root, and
+    /// returns its index. Nodes shared with snapshots are copied rather than modified.
+    ///
+    /// Fails with `InvalidLeafLength` if the tree uses `LeafMode::Prehashed` and `data` is not
+    /// one hash long. When the leaf duplicates another one, fails with `DuplicateLeaf` under
+    /// `DuplicatePolicy::Reject`, and appends nothing and returns the index of the first copy
+    /// under `DuplicatePolicy::Deduplicate`.
+    pub fn push(&mut self, data: T) -> Result<usize, MerkleError> {
+        let leaf = MerkleTree::<T, D>::leaf(self.config, self.leaf_count, data)?;
+
+        if let Some(first) = self.first_duplicate(leaf.hash(), self.leaf_count) {
+            match self.config.duplicates {
+                DuplicatePolicy::Allow => {}
+                DuplicatePolicy::Reject => return Err(MerkleError::DuplicateLeaf { index: self.leaf_count, first }),
+                DuplicatePolicy::Deduplicate => return Ok(first),
+            }
+        }
+
+        self.push_leaf(leaf);
+
+        let hashed = 1 + path_node_count(self.leaf_count - 1, self.leaf_count, self.config.padding);
+        metrics::record(|metrics| metrics.hashes_computed(hashed));
+
+        Ok(self.leaf_count - 1)
+    }
+
+    /// Pushes every item as a new leaf and returns the index `push` returned for the first one,
+    /// or the leaf count when there are none. Stops at the first item `push` fails for, with its
+    /// error, keeping the leaves appended before it.
+    pub fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<usize, MerkleError> {
+        let mut first = None;
+
+        for data in iter {
+            first.get_or_insert(self.push(data)?);
+        }
+
+        Ok(first.unwrap_or(self.leaf_count))
+    }
+
+    /// Index of the first leaf other than the one at `index` with the given hash when the
+    /// duplicate policy needs it, indexing the leaves of snapshots and deserialized trees first.
+    fn first_duplicate(&mut self, hash: &Hash<D>, index: usize) -> Option<usize> {
+        self.first_duplicate_outside(hash, index..index + 1)
+    }
+
+    /// Index of the first leaf outside `range` with the given hash, like `first_duplicate`.
+    fn first_duplicate_outside(&mut self, hash: &Hash<D>, range: Range<usize>) -> Option<usize> {
+        if self.config.duplicates == DuplicatePolicy::Allow {
+            return None;
+        }
+
+        if self.indices.is_none() {
+            self.indices = Some(self.index_leaves());
+        }
+
+        self.indices.as_ref()?.get(hash).and_then(|leaves| leaves.iter().copied().find(|leaf| !range.contains(leaf)))
+    }
+
+    /// Appends a leaf already hashed, rehashing the nodes on its path to the root.
+    fn push_leaf(&mut self, leaf: TreeNode<T, D>) {
+        let config = self.config;
+        let leaf = Arc::new(leaf);
+        let leaf_hash = leaf.hash().clone();
+        let depth = depth_for(self.leaf_count);
+        let root = Arc::new(core::mem::replace(&mut self.root, Node::Padding(GenericArray::default())));
+
+        let root = if self.leaf_count.is_power_of_two() {
+            let right = MerkleTree::<T, D>::lift(leaf, depth, config);
+            MerkleTree::<T, D>::node(config, root, right)
+        } else {
+            MerkleTree::<T, D>::append(root, leaf, self.leaf_count, depth, config)
+        };
+
+        if let Some(indices) = &mut self.indices {
+            indices.entry(leaf_hash).or_default().insert(self.leaf_count);
+        }
+
+        // The new root was just created, so nothing else refers to it.
+        self.root = Arc::try_unwrap(root).unwrap_or_else(|_| unreachable!("new root is shared"));
+        self.leaf_count += 1;
+    }
+
+    /// Appends leaves already hashed, applying the duplicate policy before modifying anything.
+    /// When at least as many leaves are appended as the tree holds, every inner node is built
+    /// again level by level, as `from_vec` would; otherwise the paths of the new leaves are
+    /// rehashed one leaf after the other.
+    pub(crate) fn append_leaves(&mut self, mut leaves: Vec<TreeNode<T, D>>) -> Result<(), MerkleError> where T: Clone {
+        if self.config.duplicates != DuplicatePolicy::Allow {
+            let mut appended = BTreeMap::new();
+            let mut kept = Vec::with_capacity(leaves.len());
+
+            for leaf in leaves {
+                let index = self.leaf_count + kept.len();
+                let first = self.first_duplicate(leaf.hash(), index).or_else(|| appended.get(leaf.hash()).copied());
+
+                match (first, self.config.duplicates) {
+                    (Some(first), DuplicatePolicy::Reject) => return Err(MerkleError::DuplicateLeaf { index, first }),
+                    (Some(_), _) => continue,
+                    (None, _) => {
+                        appended.insert(leaf.hash().clone(), index);
+                        kept.push(leaf);
+                    }
+                }
+            }
+
+            leaves = kept;
+        }
+
+        if leaves.len() >= self.leaf_count {
+            let mut all = Vec::with_capacity(self.leaf_count + leaves.len());
+            let root = core::mem::replace(&mut self.root, Node::Padding(GenericArray::default()));
+            MerkleTree::<T, D>::collect_leaves(root, &mut all);
+            all.extend(leaves);
+
+            *self = MerkleTree::from_leaves(all, self.config)?;
+            return Ok(());
+        }
+
+        let mut hashed = 0;
+        for leaf in leaves {
+            self.push_leaf(leaf);
+            hashed += path_node_count(self.leaf_count - 1, self.leaf_count, self.config.padding);
+        }
+        metrics::record(|metrics| metrics.hashes_computed(hashed));
+
+        Ok(())
+    }
+
+    /// Lifts a lone leaf up `levels` levels, as the rightmost node of a new subtree.
+    fn lift(mut node: SharedNode<T, D>, levels: usize, config: TreeConfig) -> SharedNode<T, D> {
+        for _ in 0..levels {
+            node = MerkleTree::<T, D>::pad(node, config);
+        }
+
+        node
+    }
+
+    /// Appends `leaf` to `node`, a subtree of height `depth` holding `count` leaves,
+    /// where `count` is less than the subtree's capacity.
+    fn append(node: SharedNode<T, D>, leaf: SharedNode<T, D>, count: usize, depth: usize, config: TreeConfig) -> SharedNode<T, D> {
+        let half = 1 << (depth - 1);
+
+        if config.padding == Padding::Promote && count <= half {
+            // The subtree was promoted from one level below, so it has no node of its own.
+            return if count == half {
+                let right = MerkleTree::<T, D>::lift(leaf, depth - 1, config);
+                MerkleTree::<T, D>::node(config, node, right)
+            } else {
+                MerkleTree::<T, D>::append(node, leaf, count, depth - 1, config)
+            };
+        }
+
+        let (left, right) = match &*node {
+            Node::Node(n) => (n.left.clone(), n.right.clone()),
+            _ => unreachable!("subtree with {} leaves at depth {} must be an inner node", count, depth),
+        };
+
+        if count < half {
+            let left = MerkleTree::<T, D>::append(left, leaf, count, depth - 1, config);
+            MerkleTree::<T, D>::pad(left, config)
+        } else if count == half {
+            let right = MerkleTree::<T, D>::lift(leaf, depth - 1, config);
+            MerkleTree::<T, D>::node(config, left, right)
+        } else {
+            let right = MerkleTree::<T, D>::append(right, leaf, count - half, depth - 1, config);
+            MerkleTree::<T, D>::node(config, left, right)
+        }
+    }
+
+    /// Replaces the data of the leaf at `index`, rehashing only the nodes on its path to the root.
+    /// Nodes shared with snapshots are copied rather than modified.
+    ///
+    /// Unless the tree allows duplicates, fails with `DuplicateLeaf` when the new leaf would
+    /// have the hash of another one, as there is no leaf to drop under
+    /// `DuplicatePolicy::Deduplicate`.
+    pub fn update(&mut self, index: usize, data: T) -> Result<(), MerkleError> {
+        if index >= self.leaf_count {
+            return Err(MerkleError::IndexOutOfBounds { index, leaf_count: self.leaf_count });
+        }
+
+        let leaf = MerkleTree::<T, D>::leaf(self.config, index, data)?;
+
+        if let Some(first) = self.first_duplicate(leaf.hash(), index) {
+            return Err(MerkleError::DuplicateLeaf { index, first });
+        }
+
+        let old_hash = self.get_leaf(index).map(|old| old.hash.clone());
+
+        if let (Some(indices), Some(old_hash)) = (&mut self.indices, old_hash) {
+            if let Some(leaves) = indices.get_mut(&old_hash) {
+                leaves.remove(&index);
+
+                if leaves.is_empty() {
+                    indices.remove(&old_hash);
+                }
+            }
+
+            indices.entry(leaf.hash().clone()).or_default().insert(index);
+        }
+
+        let depth = depth_for(self.leaf_count);
+        MerkleTree::<T, D>::replace(&mut self.root, leaf, 0, index, self.leaf_count, depth, self.config);
+
+        let hashed = 1 + path_node_count(index, self.leaf_count, self.config.padding);
+        metrics::record(|metrics| metrics.hashes_computed(hashed));
+
+        Ok(())
+    }
+
+    /// Replaces the node of height `height` covering the leaf at `index` of `node`, a subtree of
+    /// height `depth` holding `count` leaves, with `subtree`.
+    fn replace(node: &mut TreeNode<T, D>, subtree: TreeNode<T, D>, height: usize, index: usize, count: usize, depth: usize, config: TreeConfig) {
+        if depth == height {
+            *node = subtree;
+            return;
+        }
+
+        let half = 1 << (depth - 1);
+
+        if config.padding == Padding::Promote && count <= half {
+            return MerkleTree::<T, D>::replace(node, subtree, height, index, count, depth - 1, config);
+        }
+
+        if let Node::Node(n) = node {
+            if index < half {
+                MerkleTree::<T, D>::replace_child(&mut n.left, subtree, height, index, count.min(half), depth - 1, config);
+
+                if let (Node::Padding(_), Padding::DuplicateLast) = (&*n.right, config.padding) {
+                    n.right = Arc::new(Node::Padding(n.left.hash().clone()));
+                }
+            } else {
+                MerkleTree::<T, D>::replace_child(&mut n.right, subtree, height, index - half, count - half, depth - 1, config);
+            }
+
+            n.hash = hash_children::<D>(config.prefixes, config.sort_pairs, n.left.hash(), n.right.hash());
+        }
+    }
+
+    /// Replaces a node of `child` like `replace`, first copying the child if it is shared. Only
+    /// the child itself is copied; its own children stay shared.
+    fn replace_child(child: &mut SharedNode<T, D>, subtree: TreeNode<T, D>, height: usize, index: usize, count: usize, depth: usize, config: TreeConfig) {
+        // Leaves are replaced as a whole, which also covers leaves promoted to a higher level.
+        if let Node::Leaf(_) = **child {
+            *child = Arc::new(subtree);
+            return;
+        }
+
+        if Arc::get_mut(child).is_none() {
+            let copy = match &**child {
+                Node::Node(n) => Node::Node(MerkleNode { hash: n.hash.clone(), left: n.left.clone(), right: n.right.clone() }),
+                Node::Padding(hash) => Node::Padding(hash.clone()),
+                Node::Leaf(_) => unreachable!("leaves are replaced, not copied"),
+            };
+            *child = Arc::new(copy);
+        }
+
+        let node = Arc::get_mut(child).expect("copied node is not shared");
+        MerkleTree::<T, D>::replace(node, subtree, height, index, count, depth, config);
+    }
+
+    /// Replaces the leaves from `position` with those of `subtree`, built separately with the same
+    /// config, rehashing only the nodes on the path from the grafted subtree to the root. The
+    /// subtree must take the place of a single node: fails with `MisalignedSubtree` unless its
+    /// leaf count is a power of two dividing `position`, and its leaves are all within the tree.
+    /// Fails with `ConfigMismatch` when the subtree was built with another config and, like
+    /// `update`, with `DuplicateLeaf` when a grafted leaf would have the hash of another one
+    /// unless the tree allows duplicates.
+    pub fn graft(&mut self, position: usize, mut subtree: MerkleTree<T, D>) -> Result<(), MerkleError> {
+        let size = subtree.leaf_count;
+
+        if !size.is_power_of_two() || !position.is_multiple_of(size) || position + size > self.leaf_count {
+            return Err(MerkleError::MisalignedSubtree { position, leaf_count: size });
+        }
+
+        if subtree.config != self.config {
+            return Err(MerkleError::ConfigMismatch);
+        }
+
+        let new_hashes: Vec<Hash<D>> = subtree.leaves().map(|leaf| leaf.hash.clone()).collect();
+
+        for (offset, hash) in new_hashes.iter().enumerate() {
+            if let Some(first) = subtree.first_duplicate(hash, offset) {
+                return Err(MerkleError::DuplicateLeaf { index: position + offset, first: position + first });
+            }
+
+            if let Some(first) = self.first_duplicate_outside(hash, position..position + size) {
+                return Err(MerkleError::DuplicateLeaf { index: position + offset, first });
+            }
+        }
+
+        let old_hashes: Vec<Hash<D>> = self.leaves().skip(position).take(size).map(|leaf| leaf.hash.clone()).collect();
+
+        if let Some(indices) = &mut self.indices {
+            for (offset, old_hash) in old_hashes.into_iter().enumerate() {
+                if let Some(leaves) = indices.get_mut(&old_hash) {
+                    leaves.remove(&(position + offset));
+
+                    if leaves.is_empty() {
+                        indices.remove(&old_hash);
+                    }
+                }
+            }
+
+            for (offset, hash) in new_hashes.into_iter().enumerate() {
+                indices.entry(hash).or_default().insert(position + offset);
+            }
+        }
+
+        let height = size.trailing_zeros() as usize;
+        let depth = depth_for(self.leaf_count);
+        MerkleTree::<T, D>::replace(&mut self.root, subtree.root, height, position, self.leaf_count, depth, self.config);
+
+        Ok(())
+    }
+
+    /// Extracts a standalone tree over the leaves in `range`, with the same config. The leaves
+    /// keep their hashes, and when `range` covers the leaves of a single node, as `graft`
+    /// requires, the new tree shares that node instead of hashing anything.
+    pub fn subtree(&self, range: Range<usize>) -> Result<MerkleTree<T, D>, MerkleError> where T: Clone {
+        if range.start >= range.end {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        if range.end > self.leaf_count {
+            return Err(MerkleError::IndexOutOfBounds { index: range.end - 1, leaf_count: self.leaf_count });
+        }
+
+        let size = range.end - range.start;
+
+        if size.is_power_of_two() && range.start.is_multiple_of(size) {
+            let height = size.trailing_zeros() as usize;
+            return Ok(MerkleTree::with_root(self.node_at(height, range.start >> height).clone(), self.config));
+        }
+
+        let leaves = self.leaves().skip(range.start).take(size).map(|leaf| Node::Leaf(leaf.clone())).collect();
+        MerkleTree::from_leaves(leaves, self.config)
+    }
+
+    /// Removes the leaf at `index` and returns its data. The leaves after it move one position
+    /// to the left and are paired again according to the padding, so the tree is the one built
+    /// from the remaining leaves. Leaf hashes are kept, but every inner node is rehashed.
+    /// Leaves shared with snapshots are cloned rather than moved.
+    ///
+    /// Fails with `EmptyInput` when removing the only leaf, as a tree needs at least one leaf.
+    pub fn remove(&mut self, index: usize) -> Result<T, MerkleError> where T: Clone {
+        if index >= self.leaf_count {
+            return Err(MerkleError::IndexOutOfBounds { index, leaf_count: self.leaf_count });
+        }
+
+        if self.leaf_count == 1 {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        let mut leaves = Vec::with_capacity(self.leaf_count);
+        let root = core::mem::replace(&mut self.root, Node::Padding(GenericArray::default()));
+        MerkleTree::<T, D>::collect_leaves(root, &mut leaves);
+
+        let removed = match leaves.remove(index) {
+            Node::Leaf(leaf) => leaf.data,
+            _ => unreachable!("only leaves are collected"),
+        };
+
+        self.root = MerkleTree::<T, D>::build_until_root(leaves, self.config);
+        self.leaf_count -= 1;
+
+        // Every leaf after the removed one moved, so the index is built again.
+        if self.indices.is_some() {
+            self.indices = Some(self.index_leaves());
+        }
+
+        Ok(removed)
+    }
+
+    /// Hashes every node again from the leaves up, as the tree would be built from its leaves
+    /// with its config, to detect trees corrupted on disk or in transit. Fails with
+    /// `CorruptNode` and the path to the first node found whose hash does not match its data or
+    /// children, visiting children before their parent and left before right. Padding nodes
+    /// must be what the padding strategy would have put there, and the leaves must be as many
+    /// as the tree reports.
+    pub fn verify_structure(&self) -> Result<(), MerkleError> {
+        MerkleTree::<T, D>::verify_node(self.config, &self.root, &mut Vec::new())?;
+
+        if self.root.leaf_count() == self.leaf_count {
+            Ok(())
+        } else {
+            Err(MerkleError::CorruptNode { path: Vec::new() })
+        }
+    }
+
+    /// Checks the subtree of `node`, found by following `path` from the root.
+    fn verify_node(config: TreeConfig, node: &TreeNode<T, D>, path: &mut Vec<Side>) -> Result<(), MerkleError> {
+        let valid = match node {
+            Node::Node(n) => {
+                for (side, child) in [(Side::Left, &n.left), (Side::Right, &n.right)] {
+                    path.push(side);
+                    MerkleTree::<T, D>::verify_node(config, child, path)?;
+                    path.pop();
+                }
+
+                let padding_valid = match &*n.right {
+                    Node::Padding(hash) => match config.padding {
+                        Padding::Promote => false,
+                        Padding::DuplicateLast => hash == n.left.hash(),
+                        Padding::EmptyHash => *hash == calculate_hash::<D>(&[]),
+                    },
+                    _ => true,
+                };
+
+                padding_valid
+                    && !matches!(*n.left, Node::Padding(_))
+                    && n.hash == hash_children::<D>(config.prefixes, config.sort_pairs, n.left.hash(), n.right.hash())
+            }
+            Node::Leaf(l) => {
+                let data = l.data.encode_leaf();
+                check_leaf::<D>(config.leaf_mode, 0, &data).is_ok() && l.hash == leaf_hash::<D>(config.prefixes, config.leaf_mode, &data)
+            }
+            // Padding is checked by its parent, against its sibling. A tree cannot be only padding.
+            Node::Padding(_) => !path.is_empty(),
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(MerkleError::CorruptNode { path: path.clone() })
+        }
+    }
+
+    /// Moves the leaves of `node` into `leaves`, from left to right.
+    fn collect_leaves(node: TreeNode<T, D>, leaves: &mut Vec<TreeNode<T, D>>) where T: Clone {
+        match node {
+            Node::Node(n) => {
+                for child in [n.left, n.right] {
+                    let child = Arc::try_unwrap(child).unwrap_or_else(|shared| (*shared).clone());
+                    MerkleTree::<T, D>::collect_leaves(child, leaves);
+                }
+            }
+            Node::Leaf(_) => leaves.push(node),
+            Node::Padding(_) => {}
+        }
+    }
+}
+
+impl MerkleTree<()> {
+    /// Creates a MerkleTree from leaf hashes computed with Blake2b and the default config.
+    pub fn from_hashes(hashes: Vec<Hash<Blake2b>>) -> Result<MerkleTree<()>, MerkleError> {
+        MerkleTree::from_hashes_with_digest(hashes)
+    }
+
+    /// Creates a MerkleTree from leaf hashes computed with Blake2b and `config`.
+    pub fn from_hashes_with_config(hashes: Vec<Hash<Blake2b>>, config: TreeConfig) -> Result<MerkleTree<()>, MerkleError> {
+        MerkleTree::from_hashes_with_config_and_digest(hashes, config)
+    }
+}
+
+impl<D> MerkleTree<(), D> where D: Digest {
+    /// Creates a MerkleTree from leaf hashes computed with the digest `D` and the default config.
+    pub fn from_hashes_with_digest(hashes: Vec<Hash<D>>) -> Result<MerkleTree<(), D>, MerkleError> {
+        MerkleTree::from_hashes_with_config_and_digest(hashes, TreeConfig::default())
+    }
+
+    /// Creates a MerkleTree that only keeps the hashes of its leaves, not their data. The
+    /// hashes must have been computed like `leaf_hash` does, in which case the tree has the
+    /// same root and proofs as the tree built from the data, and its proofs verify against
+    /// the data. Hash-only trees cannot be pushed to or updated, as that takes leaf data.
+    pub fn from_hashes_with_config_and_digest(hashes: Vec<Hash<D>>, config: TreeConfig) -> Result<MerkleTree<(), D>, MerkleError> {
+        let leaves = hashes.into_iter().map(|hash| Node::Leaf(MerkleLeaf { hash, data: () }));
+        MerkleTree::from_leaves(leaves.collect(), config)
+    }
+
+    /// Hash of the leaf holding `data` in a tree built with `config`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config` uses `LeafMode::Prehashed` and `data` is not one hash long.
+    pub fn leaf_hash(config: TreeConfig, data: &[u8]) -> Hash<D> {
+        check_leaf::<D>(config.leaf_mode, 0, data).unwrap_or_else(|e| panic!("{}", e));
+        leaf_hash::<D>(config.prefixes, config.leaf_mode, data)
+    }
+}
+
+impl<T, D> MerkleTree<T, D> where D: Digest {
+    /// Wraps a root built with `config`.
+    pub(crate) fn with_root(root: TreeNode<T, D>, config: TreeConfig) -> MerkleTree<T, D> {
+        let mut tree = MerkleTree {
+            leaf_count: root.leaf_count(),
+            root,
+            config,
+            indices: None,
+            digest: PhantomData,
+        };
+        tree.indices = Some(tree.index_leaves());
+        tree
+    }
+
+    /// Wraps leaves whose hashes are already computed into a tree built with `config`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(leaves = leaves.len(), nodes_hashed = hashed_node_count(leaves.len(), config.padding))))]
+    pub(crate) fn from_leaves(leaves: Vec<TreeNode<T, D>>, config: TreeConfig) -> Result<MerkleTree<T, D>, MerkleError> {
+        if leaves.is_empty() {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        let mut indices = LeafIndex::<D>::new();
+        let mut kept = Vec::with_capacity(leaves.len());
+
+        for leaf in leaves {
+            let positions = indices.entry(leaf.hash().clone()).or_default();
+
+            if let Some(&first) = positions.iter().next() {
+                match config.duplicates {
+                    DuplicatePolicy::Allow => {}
+                    DuplicatePolicy::Reject => return Err(MerkleError::DuplicateLeaf { index: kept.len(), first }),
+                    DuplicatePolicy::Deduplicate => continue,
+                }
+            }
+
+            positions.insert(kept.len());
+            kept.push(leaf);
+        }
+
+        let leaf_count = kept.len();
+        let root = MerkleTree::<T, D>::build_until_root(kept, config);
+
+        Ok(MerkleTree {
+            root,
+            config,
+            leaf_count,
+            indices: Some(indices),
+            digest: PhantomData,
+        })
+    }
+
+    fn index_leaves(&self) -> LeafIndex<D> {
+        let mut indices = LeafIndex::<D>::new();
+
+        for (index, leaf) in self.leaves().enumerate() {
+            indices.entry(leaf.hash.clone()).or_default().insert(index);
+        }
+
+        indices
+    }
+
+    /// Returns the index of the first leaf whose data is `data`, looked up by its leaf hash.
+    /// Snapshots and deserialized trees have no index of their leaves and scan them instead.
+    pub fn index_of(&self, data: &[u8]) -> Option<usize> {
+        check_leaf::<D>(self.config.leaf_mode, 0, data).ok()?;
+        let hash = leaf_hash::<D>(self.config.prefixes, self.config.leaf_mode, data);
+
+        match &self.indices {
+            Some(indices) => indices.get(&hash).and_then(|leaves| leaves.iter().next().copied()),
+            None => self.leaves().position(|leaf| leaf.hash == hash),
+        }
+    }
+
+    /// Copies the tree like `clone`, leaving out the index of its leaves.
+    pub(crate) fn clone_unindexed(&self) -> MerkleTree<T, D> where T: Clone {
+        MerkleTree {
+            root: self.root.clone(),
+            config: self.config,
+            leaf_count: self.leaf_count,
+            indices: None,
+            digest: PhantomData,
+        }
+    }
+
+    /// Builds the tree level by level, reusing the buffer of the level below for each new level.
+    /// Stack usage does not depend on the number of leaves; the other operations only recurse
+    /// once per level, so at most `usize::BITS` times, the height of a tree of `usize::MAX`
+    /// leaves.
+    fn build_until_root(mut nodes: Vec<TreeNode<T, D>>, config: TreeConfig) -> TreeNode<T, D> {
+        let mut height = 0;
+
+        while nodes.len() > 1 {
+            // Parent hashes are computed up front, so that they can be spread across threads.
+            let hashes = {
+                let children: Vec<&Hash<D>> = nodes.iter().map(Node::hash).collect();
+                let pairs: Vec<&[&Hash<D>]> = children.chunks(2).collect();
+                map_hashes(&pairs, |pair| MerkleTree::<T, D>::parent_hash(config, pair))
+            };
+
+            let len = nodes.len();
+
+            // Parent `i` only depends on children `2i` and `2i + 1`, so it can be written in place.
+            for (i, hash) in hashes.into_iter().enumerate() {
+                let left = core::mem::replace(&mut nodes[2 * i], Node::Padding(GenericArray::default()));
+                let right = match (nodes.get_mut(2 * i + 1), config.padding) {
+                    (Some(right), _) => core::mem::replace(right, Node::Padding(GenericArray::default())),
+                    (None, Padding::Promote) => {
+                        nodes[i] = left;
+                        continue;
+                    }
+                    (None, Padding::DuplicateLast) => Node::Padding(left.hash().clone()),
+                    (None, Padding::EmptyHash) => Node::Padding(calculate_hash::<D>(&[])),
+                };
+
+                nodes[i] = Node::Node(MerkleNode { hash, left: Arc::new(left), right: Arc::new(right) });
+            }
+
+            nodes.truncate(len.div_ceil(2));
+            height += 1;
+
+            // A lone node promoted without a sibling keeps its hash.
+            let hashed = if config.padding == Padding::Promote { len / 2 } else { nodes.len() };
+            metrics::record(|metrics| {
+                metrics.hashes_computed(hashed);
+                metrics.level_built(height, nodes.len());
+            });
+        }
+
+        nodes.pop().unwrap()
+    }
+
+    /// Hash of the parent of one or two sibling hashes, padding a lone child as configured.
+    fn parent_hash(config: TreeConfig, children: &[&Hash<D>]) -> Hash<D> {
+        let left = children[0];
+        let right = match (children.get(1), config.padding) {
+            (Some(right), _) => (*right).clone(),
+            (None, Padding::Promote) => return left.clone(),
+            (None, Padding::DuplicateLast) => left.clone(),
+            (None, Padding::EmptyHash) => calculate_hash::<D>(&[]),
+        };
+
+        hash_children::<D>(config.prefixes, config.sort_pairs, left, &right)
+    }
+
+    /// Generates an inclusion proof for the leaf at `leaf_index`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(self)))]
+    pub fn proof(&self, leaf_index: usize) -> Result<Proof<D>, MerkleError> {
+        if leaf_index >= self.leaf_count {
+            return Err(MerkleError::IndexOutOfBounds { index: leaf_index, leaf_count: self.leaf_count });
+        }
+
+        // From the leaves up, whether the path goes through a right child at each level,
+        // or `None` when the node on the path was promoted without a sibling.
+        let mut moves = Vec::new();
+        let (mut index, mut count) = (leaf_index, self.leaf_count);
+
+        while count > 1 {
+            if self.config.padding == Padding::Promote && index == count - 1 && count & 1 == 1 {
+                moves.push(None);
+            } else {
+                moves.push(Some(index & 1 == 1));
+            }
+
+            index /= 2;
+            count = count.div_ceil(2);
+        }
+
+        let mut siblings = Vec::with_capacity(moves.len());
+        let mut node = &self.root;
+
+        for is_right in moves.into_iter().rev().flatten() {
+            if let Node::Node(n) = node {
+                if is_right {
+                    siblings.push(ProofStep::new(Side::Left, n.left.hash().clone()));
+                    node = &n.right;
+                } else {
+                    siblings.push(ProofStep::new(Side::Right, n.right.hash().clone()));
+                    node = &n.left;
+                }
+            }
+        }
+
+        siblings.reverse();
+        metrics::record(|metrics| metrics.proof_generated(siblings.len()));
+
+        let mut proof = Proof::new(leaf_index, self.leaf_count, self.config.padding, siblings);
+        proof.prefixes = self.config.prefixes;
+        proof.sort_pairs = self.config.sort_pairs;
+        proof.leaf_mode = self.config.leaf_mode;
+
+        Ok(proof)
+    }
+
+    /// Generates the proofs of the leaves at `indices`, in the same order, walking down the tree
+    /// once for all of them: nodes above several of the leaves are visited once rather than once
+    /// per leaf. With the `rayon` feature, subtrees holding many of the leaves are handled on
+    /// several threads.
+    pub fn proofs(&self, indices: &[usize]) -> Result<Vec<Proof<D>>, MerkleError> where T: Send + Sync {
+        let mut sorted: Vec<(usize, usize)> = indices.iter().copied().zip(0..).collect();
+        sorted.sort_unstable();
+
+        if let Some(&(index, _)) = sorted.last() {
+            if index >= self.leaf_count {
+                return Err(MerkleError::IndexOutOfBounds { index, leaf_count: self.leaf_count });
+            }
+        }
+
+        let depth = depth_for(self.leaf_count);
+        let paths = MerkleTree::<T, D>::collect_paths(self.config, &self.root, 0, self.leaf_count, depth, &sorted, Vec::new());
+
+        let mut proofs: Vec<Option<Proof<D>>> = vec![None; indices.len()];
+        for ((index, position), siblings) in sorted.into_iter().zip(paths) {
+            let mut proof = Proof::new(index, self.leaf_count, self.config.padding, siblings);
+            proof.prefixes = self.config.prefixes;
+            proof.sort_pairs = self.config.sort_pairs;
+            proof.leaf_mode = self.config.leaf_mode;
+            proofs[position] = Some(proof);
+        }
+
+        Ok(proofs.into_iter().map(|proof| proof.expect("every index has a proof")).collect())
+    }
+
+    /// Sibling hashes of the leaves at `indices`, sorted, under `node`, which covers the `count`
+    /// leaves from `low` in a subtree of height `depth`. `path` holds the sibling hashes from the
+    /// root down to `node`.
+    fn collect_paths(config: TreeConfig, node: &TreeNode<T, D>, low: usize, count: usize, depth: usize, indices: &[(usize, usize)], mut path: Vec<ProofStep<D>>) -> Vec<Vec<ProofStep<D>>> where T: Send + Sync {
+        if indices.is_empty() {
+            return Vec::new();
+        }
+
+        if depth == 0 || matches!(node, Node::Leaf(_)) {
+            path.reverse();
+            return vec![path; indices.len()];
+        }
+
+        let half = 1 << (depth - 1);
+
+        // Promoted subtrees have no node of their own.
+        if config.padding == Padding::Promote && count <= half {
+            return MerkleTree::<T, D>::collect_paths(config, node, low, count, depth - 1, indices, path);
+        }
+
+        let n = match node {
+            Node::Node(n) => n,
+            _ => unreachable!("subtree with {} leaves at depth {} must be an inner node", count, depth),
+        };
+
+        let (left_indices, right_indices) = indices.split_at(indices.partition_point(|&(index, _)| index < low + half));
+
+        let mut left_path = path.clone();
+        left_path.push(ProofStep::new(Side::Right, n.right.hash().clone()));
+        path.push(ProofStep::new(Side::Left, n.left.hash().clone()));
+
+        let left = || MerkleTree::<T, D>::collect_paths(config, &n.left, low, count.min(half), depth - 1, left_indices, left_path);
+        let right = || MerkleTree::<T, D>::collect_paths(config, &n.right, low + half, count.saturating_sub(half), depth - 1, right_indices, path);
+
+        #[cfg(feature = "rayon")]
+        let (mut left, right) = if indices.len() >= PARALLEL_PROOFS {
+            rayon::join(left, right)
+        } else {
+            (left(), right())
+        };
+
+        #[cfg(not(feature = "rayon"))]
+        let (mut left, right) = (left(), right());
+
+        left.extend(right);
+        left
+    }
+
+    /// Generates an RFC 6962 proof that the tree of the first `old_size` leaves is a prefix
+    /// of the tree of the first `new_size` leaves.
+    /// Only trees built with `Padding::Promote` are split the way RFC 6962 expects.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub fn consistency_proof(&self, old_size: usize, new_size: usize) -> Result<ConsistencyProof<D>, MerkleError> {
+        if self.config.padding != Padding::Promote {
+            return Err(MerkleError::UnsupportedPadding(self.config.padding));
+        }
+
+        if old_size == 0 || old_size > new_size || new_size > self.leaf_count {
+            return Err(MerkleError::InvalidSizes { old_size, new_size });
+        }
+
+        let mut hashes = Vec::new();
+        self.consistency_subproof(old_size, 0, new_size, true, &mut hashes);
+        metrics::record(|metrics| metrics.proof_generated(hashes.len()));
+
+        let mut proof = ConsistencyProof::new(old_size, new_size, hashes);
+        proof.prefixes = self.config.prefixes;
+        proof.sort_pairs = self.config.sort_pairs;
+
+        Ok(proof)
+    }
+
+    /// RFC 6962 SUBPROOF over the leaves in `start..end`, where `old_size` counts from `start`.
+    fn consistency_subproof(&self, old_size: usize, start: usize, end: usize, complete: bool, hashes: &mut Vec<Hash<D>>) {
+        let size = end - start;
+
+        if old_size == size {
+            if !complete {
+                hashes.push(self.range_hash(start, end));
+            }
+            return;
+        }
+
+        let split = 1 << (depth_for(size) - 1);
+
+        if old_size <= split {
+            self.consistency_subproof(old_size, start, start + split, complete, hashes);
+            hashes.push(self.range_hash(start + split, end));
+        } else {
+            self.consistency_subproof(old_size - split, start + split, end, false, hashes);
+            hashes.push(self.range_hash(start, start + split));
+        }
+    }
+
+    /// RFC 6962 Merkle Tree Hash of the leaves in `start..end`, reusing the stored hash of
+    /// every complete subtree.
+    fn range_hash(&self, start: usize, end: usize) -> Hash<D> {
+        let size = end - start;
+
+        if size.is_power_of_two() && start & (size - 1) == 0 {
+            return self.complete_subtree(start, size).hash().clone();
+        }
+
+        let split = 1 << (depth_for(size) - 1);
+
+        hash_children::<D>(self.config.prefixes, self.config.sort_pairs, &self.range_hash(start, start + split), &self.range_hash(start + split, end))
+    }
+
+    /// Finds the node covering the `size` leaves from `start`, where `size` is a power of two
+    /// dividing `start` and all of those leaves are present.
+    fn complete_subtree(&self, start: usize, size: usize) -> &TreeNode<T, D> {
+        let height = size.trailing_zeros() as usize;
+
+        self.node_at(height, start >> height)
+    }
+
+    /// Finds the node at `index` on the level `height` levels above the leaves.
+    pub(crate) fn node_at(&self, height: usize, index: usize) -> &TreeNode<T, D> {
+        let start = index << height;
+        let mut node = &self.root;
+        let (mut low, mut count, mut depth) = (0, self.leaf_count, depth_for(self.leaf_count));
+
+        while depth > height {
+            let half = 1 << (depth - 1);
+            depth -= 1;
+
+            // Promoted subtrees have no node of their own.
+            if self.config.padding == Padding::Promote && count <= half {
+                continue;
+            }
+
+            if let Node::Node(n) = node {
+                if start < low + half {
+                    node = &n.left;
+                    count = count.min(half);
+                } else {
+                    node = &n.right;
+                    low += half;
+                    count -= half;
+                }
+            }
+        }
+
+        node
+    }
+
+    /// Generates a single proof for all the leaves at `indices`, sharing the sibling hashes
+    /// their paths have in common.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(leaves = indices.len())))]
+    pub fn multi_proof(&self, indices: &[usize]) -> Result<MultiProof<D>, MerkleError> {
+        let mut leaf_indices = indices.to_vec();
+        leaf_indices.sort_unstable();
+        leaf_indices.dedup();
+
+        match leaf_indices.last() {
+            None => return Err(MerkleError::EmptyInput),
+            Some(&index) if index >= self.leaf_count =>
+                return Err(MerkleError::IndexOutOfBounds { index, leaf_count: self.leaf_count }),
+            _ => {}
+        }
+
+        let mut hashes = Vec::new();
+        let mut level = leaf_indices.clone();
+        let (mut height, mut count) = (0, self.leaf_count);
+
+        while count > 1 {
+            let mut next = Vec::with_capacity(level.len());
+            let mut known = level.into_iter().peekable();
+
+            while let Some(index) = known.next() {
+                let sibling = index ^ 1;
+
+                if known.peek() == Some(&sibling) {
+                    known.next();
+                } else if sibling < count {
+                    hashes.push(self.node_at(height, sibling).hash().clone());
+                }
+
+                next.push(index / 2);
+            }
+
+            level = next;
+            height += 1;
+            count = count.div_ceil(2);
+        }
+
+        metrics::record(|metrics| metrics.proof_generated(hashes.len()));
+
+        let mut proof = MultiProof::new(leaf_indices, self.leaf_count, self.config.padding, hashes);
+        proof.prefixes = self.config.prefixes;
+        proof.sort_pairs = self.config.sort_pairs;
+        proof.leaf_mode = self.config.leaf_mode;
+
+        Ok(proof)
+    }
+
+    /// Generates a proof of the leaves in `range`, in order. Fails with `UnsupportedSortedPairs`
+    /// for trees hashing pairs in ascending order, whose proofs do not commit to the order of
+    /// sibling leaves.
+    pub fn range_proof(&self, range: Range<usize>) -> Result<RangeProof<D>, MerkleError> {
+        if self.config.sort_pairs {
+            return Err(MerkleError::UnsupportedSortedPairs);
+        }
+
+        if range.start >= range.end {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        let indices: Vec<usize> = range.clone().collect();
+        let multi_proof = self.multi_proof(&indices)?;
+
+        let mut proof = RangeProof::new(range, self.leaf_count, self.config.padding, multi_proof.hashes);
+        proof.prefixes = self.config.prefixes;
+        proof.leaf_mode = self.config.leaf_mode;
+
+        Ok(proof)
+    }
+
+    /// Lists the leaves that differ between this tree and `other`, in index order, skipping
+    /// every subtree whose hash is the same in both. Both trees are expected to share a config.
+    pub fn diff(&self, other: &MerkleTree<T, D>) -> Vec<DiffEntry> {
+        let mut entries = Vec::new();
+        let depth = depth_for(self.leaf_count).max(depth_for(other.leaf_count));
+        let ours = Some((&self.root, self.leaf_count, depth_for(self.leaf_count)));
+        let theirs = Some((&other.root, other.leaf_count, depth_for(other.leaf_count)));
+
+        self.diff_subtrees(ours, theirs, depth, 0, &mut entries);
+
+        entries
+    }
+
+    /// Compares the parts of two trees covering the `2^depth` leaves from `low`. Each side is
+    /// the node covering those leaves, with its number of leaves and its own depth, which is
+    /// less than `depth` when the node was promoted or belongs to a smaller tree.
+    fn diff_subtrees(&self, ours: DiffSide<T, D>, theirs: DiffSide<T, D>, depth: usize, low: usize, entries: &mut Vec<DiffEntry>) {
+        match (ours, theirs) {
+            (None, None) => return,
+            (Some((a, a_count, _)), Some((b, b_count, _))) if a_count == b_count && a.hash() == b.hash() => return,
+            _ => {}
+        }
+
+        if depth == 0 {
+            entries.push(match (ours, theirs) {
+                (Some(_), Some(_)) => DiffEntry::Changed(low),
+                (Some(_), None) => DiffEntry::Removed(low),
+                _ => DiffEntry::Added(low),
+            });
+            return;
+        }
+
+        let (our_left, our_right) = self.split_for_diff(ours, depth);
+        let (their_left, their_right) = self.split_for_diff(theirs, depth);
+
+        self.diff_subtrees(our_left, their_left, depth - 1, low, entries);
+        self.diff_subtrees(our_right, their_right, depth - 1, low + (1 << (depth - 1)), entries);
+    }
+
+    /// Splits a side of a diff at `depth` into the sides covering each half of its leaves.
+    fn split_for_diff<'a>(&self, side: DiffSide<'a, T, D>, depth: usize) -> (DiffSide<'a, T, D>, DiffSide<'a, T, D>) {
+        let (node, count, node_depth) = match side {
+            Some(side) if side.2 == depth => side,
+            // The node only covers the left half, so descending leaves it unchanged.
+            _ => return (side, None),
+        };
+
+        let n = match node {
+            Node::Node(n) => n,
+            _ => unreachable!("subtree with {} leaves at depth {} must be an inner node", count, node_depth),
+        };
+
+        let (left_count, right_count) = (count.min(1 << (depth - 1)), count.saturating_sub(1 << (depth - 1)));
+        let child_depth = |count| if self.config.padding == Padding::Promote { depth_for(count) } else { depth - 1 };
+
+        let left = Some((&*n.left, left_count, child_depth(left_count)));
+        let right = if right_count > 0 { Some((&*n.right, right_count, child_depth(right_count))) } else { None };
+
+        (left, right)
+    }
+}
+
+impl<T, D> MerkleTree<T, D> where D: Digest {
+    pub(crate) fn root(&self) -> &TreeNode<T, D> {
+        &self.root
+    }
+
+    pub fn root_hash(&self) -> &[u8] {
+        self.root.hash()
+    }
+
+    /// Root hash as a lowercase hexadecimal string.
+    pub fn root_hash_hex(&self) -> String {
+        to_hex(self.root_hash())
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    pub fn config(&self) -> TreeConfig {
+        self.config
+    }
+
+    /// Iterates over the leaves from left to right, skipping padding.
+    pub fn leaves(&self) -> impl Iterator<Item = &MerkleLeaf<T, D::OutputSize>> {
+        let mut stack = vec![&self.root];
+
+        core::iter::from_fn(move || {
+            while let Some(node) = stack.pop() {
+                match node {
+                    Node::Node(n) => {
+                        stack.push(&n.right);
+                        stack.push(&n.left);
+                    }
+                    Node::Leaf(l) => return Some(l),
+                    Node::Padding(_) => {}
+                }
+            }
+
+            None
+        })
+    }
+
+    /// Returns the leaf at `index`, walking down a single path from the root.
+    pub fn get_leaf(&self, index: usize) -> Option<&MerkleLeaf<T, D::OutputSize>> {
+        if index >= self.leaf_count {
+            return None;
+        }
+
+        match self.node_at(0, index) {
+            Node::Leaf(l) => Some(l),
+            _ => None,
+        }
+    }
+
+    /// Sizes of the tree, for capacity planning and for tests bounding its memory footprint.
+    pub fn stats(&self) -> TreeStats {
+        let mut node_count = 0;
+        let mut stack = vec![&self.root];
+
+        while let Some(node) = stack.pop() {
+            node_count += 1;
+
+            if let Node::Node(n) = node {
+                stack.push(&n.right);
+                stack.push(&n.left);
+            }
+        }
+
+        // Every node but the root is held in its own `Arc`, next to its reference counts.
+        let node_bytes = (node_count - 1) * (2 * core::mem::size_of::<usize>() + core::mem::size_of::<TreeNode<T, D>>());
+        let index_bytes = self.indices.as_ref().map_or(0, |indices| {
+            indices.len() * (core::mem::size_of::<Hash<D>>() + core::mem::size_of::<BTreeSet<usize>>())
+                + self.leaf_count * core::mem::size_of::<usize>()
+        });
+
+        TreeStats {
+            leaf_count: self.leaf_count,
+            node_count,
+            depth: depth_for(self.leaf_count),
+            hash_bytes: node_count * D::output_size(),
+            heap_bytes: node_bytes + index_bytes,
+        }
+    }
+
+    /// Compares the hashes of this tree and `other`, finding where they diverge by descending
+    /// into the only child that differs until both children of a node differ or a node has no
+    /// children on one side. Only hashes are compared, so either tree may hold other data, or
+    /// none, as trees from `from_hashes` do.
+    pub fn compare<U>(&self, other: &MerkleTree<U, D>) -> Comparison {
+        let (mut ours, mut theirs) = (&self.root, &other.root);
+        let mut path = Vec::new();
+
+        if ours.hash() == theirs.hash() {
+            return Comparison::Equal;
+        }
+
+        while let (Node::Node(a), Node::Node(b)) = (ours, theirs) {
+            let left_differs = a.left.hash() != b.left.hash();
+            let right_differs = a.right.hash() != b.right.hash();
+
+            if left_differs == right_differs {
+                break;
+            }
+
+            let (side, a, b) = if left_differs { (Side::Left, &a.left, &b.left) } else { (Side::Right, &a.right, &b.right) };
+            path.push(side);
+            ours = a;
+            theirs = b;
+        }
+
+        Comparison::Diverged { path }
+    }
+}
+
+/// Sizes of a MerkleTree, as reported by `MerkleTree::stats`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct TreeStats {
+    pub leaf_count: usize,
+    /// Number of leaves, inner nodes and padding nodes.
+    pub node_count: usize,
+    /// Number of levels above the leaves.
+    pub depth: usize,
+    /// Bytes taken by the hashes of every node.
+    pub hash_bytes: usize,
+    /// Estimate of the heap memory held by the nodes and the index of the leaves by hash, leaving
+    /// out the heap memory owned by the data of the leaves and the overhead of the allocator.
+    pub heap_bytes: usize,
+}
+
+/// Outcome of `MerkleTree::compare`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Comparison {
+    /// Both trees have the same root hash.
+    Equal,
+    /// The trees agree on every node outside the subtree reached from the root through the
+    /// children on `path`, the shallowest node whose subtree holds every difference. The length
+    /// of the path is the depth at which the trees diverge.
+    Diverged { path: Vec<Side> },
+}
+
+impl Comparison {
+    /// Depth of the node at which the trees diverge, or `None` if they are equal.
+    pub fn divergence_depth(&self) -> Option<usize> {
+        match self {
+            Comparison::Equal => None,
+            Comparison::Diverged { path } => Some(path.len()),
+        }
+    }
+}
+
+/// Number of proofs from which `proofs` generates those of both children of a node in parallel.
+#[cfg(feature = "rayon")]
+const PARALLEL_PROOFS: usize = 256;
+
+/// Height of a tree holding `leaf_count` leaves.
+pub(crate) fn depth_for(leaf_count: usize) -> usize {
+    (usize::BITS - leaf_count.saturating_sub(1).leading_zeros()) as usize
+}
+
+/// Number of nodes above the leaves hashed to build a tree of `leaf_count` leaves, leaving out
+/// the nodes promoted without a sibling.
+#[cfg(feature = "tracing")]
+fn hashed_node_count(leaf_count: usize, padding: Padding) -> usize {
+    let (mut count, mut hashed) = (leaf_count, 0);
+
+    while count > 1 {
+        hashed += if padding == Padding::Promote { count / 2 } else { count.div_ceil(2) };
+        count = count.div_ceil(2);
+    }
+
+    hashed
+}
+
+/// Number of nodes above the leaf at `leaf_index` of a tree of `leaf_count` leaves hashed on its
+/// path to the root, leaving out the nodes promoted without a sibling.
+fn path_node_count(leaf_index: usize, leaf_count: usize, padding: Padding) -> usize {
+    let (mut index, mut count, mut hashed) = (leaf_index, leaf_count, 0);
+
+    while count > 1 {
+        if !(padding == Padding::Promote && index == count - 1 && count & 1 == 1) {
+            hashed += 1;
+        }
+
+        index /= 2;
+        count = count.div_ceil(2);
+    }
+
+    hashed
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<T, D> Clone for MerkleTree<T, D> where T: Clone, D: Digest {
+    fn clone(&self) -> Self {
+        MerkleTree {
+            root: self.root.clone(),
+            config: self.config,
+            leaf_count: self.leaf_count,
+            indices: self.indices.clone(),
+            digest: PhantomData,
+        }
+    }
+}
+
+impl<T, D> fmt::Debug for MerkleTree<T, D> where T: fmt::Debug, D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MerkleTree")
+            .field("root", &self.root)
+            .field("config", &self.config)
+            .field("leaf_count", &self.leaf_count)
+            .finish()
+    }
+}
+
+impl<T, D> PartialEq for MerkleTree<T, D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.root.hash().eq(other.root.hash())
+    }
+}
+
+/// Number of hex digits of each hash shown by `render_ascii`.
+const RENDERED_HEX_DIGITS: usize = 8;
+
+impl<T, D> MerkleTree<T, D> where D: Digest {
+    /// Draws the tree with one node per line, indented below its parent, showing the first
+    /// hex digits of each hash and whether a node is a leaf, with its index, or padding.
+    /// Comparing the drawings of two trees shows where they diverge.
+    pub fn render_ascii(&self) -> String {
+        self.to_string()
+    }
+
+    fn render(f: &mut fmt::Formatter<'_>, node: &TreeNode<T, D>, indent: &mut String, leaf_index: &mut usize) -> fmt::Result {
+        let hash = to_hex(node.hash());
+        let hash = &hash[..hash.len().min(RENDERED_HEX_DIGITS)];
+
+        match node {
+            Node::Node(n) => {
+                write!(f, "{}", hash)?;
+
+                for (child, last) in [(&n.left, false), (&n.right, true)] {
+                    write!(f, "\n{}{}", indent, if last { "`-- " } else { "+-- " })?;
+
+                    let len = indent.len();
+                    indent.push_str(if last { "    " } else { "|   " });
+                    MerkleTree::<T, D>::render(f, child, indent, leaf_index)?;
+                    indent.truncate(len);
+                }
+
+                Ok(())
+            }
+            Node::Leaf(_) => {
+                *leaf_index += 1;
+                write!(f, "{} (leaf {})", hash, *leaf_index - 1)
+            }
+            Node::Padding(_) => write!(f, "{} (padding)", hash),
+        }
+    }
+}
+
+impl<T, D> MerkleTree<T, D> where T: LeafEncode, D: Digest {
+    /// Describes the tree as a Graphviz digraph, with an edge from every inner node to each of
+    /// its children. Nodes are labelled with the first hex digits of their hashes, and leaves
+    /// with their data as well: as text if it is printable UTF-8, in hex otherwise, and cut
+    /// short if it is long. Padding nodes are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph merkle_tree {\n    node [shape=box, fontname=\"monospace\"];\n");
+        MerkleTree::<T, D>::write_dot(&self.root, &mut dot, &mut 0);
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Appends the statements of `node` and its subtree, numbering nodes from `next_id`, and
+    /// returns the id of `node`.
+    fn write_dot(node: &TreeNode<T, D>, dot: &mut String, next_id: &mut usize) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+
+        let hash = to_hex(node.hash());
+        let hash = &hash[..hash.len().min(RENDERED_HEX_DIGITS)];
+
+        match node {
+            Node::Node(n) => {
+                dot.push_str(&format!("    n{} [label=\"{}\"];\n", id, hash));
+
+                for child in [&n.left, &n.right] {
+                    let child_id = MerkleTree::<T, D>::write_dot(child, dot, next_id);
+                    dot.push_str(&format!("    n{} -> n{};\n", id, child_id));
+                }
+            }
+            Node::Leaf(l) => dot.push_str(&format!("    n{} [label=\"{}\\n{}\", shape=ellipse];\n", id, hash, dot_data_label(&l.data.encode_leaf()))),
+            Node::Padding(_) => dot.push_str(&format!("    n{} [label=\"{}\", style=dashed];\n", id, hash)),
+        }
+
+        id
+    }
+}
+
+/// Number of bytes of leaf data shown by `to_dot`.
+const RENDERED_DATA_LEN: usize = 16;
+
+/// Leaf data as it appears in a DOT label, escaped for a quoted DOT string.
+fn dot_data_label(data: &[u8]) -> String {
+    let shown = &data[..data.len().min(RENDERED_DATA_LEN)];
+    let ellipsis = if shown.len() < data.len() { "..." } else { "" };
+
+    match core::str::from_utf8(shown) {
+        Ok(text) if !text.chars().any(char::is_control) => {
+            let escaped = text.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("\\\"{}{}\\\"", escaped, ellipsis)
+        }
+        _ => format!("0x{}{}", to_hex(shown), ellipsis),
+    }
+}
+
+/// Draws the tree like `render_ascii`.
+impl<T, D> fmt::Display for MerkleTree<T, D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        MerkleTree::<T, D>::render(f, &self.root, &mut String::new(), &mut 0)
+    }
+}
+
+/// Builds a tree with the default config.
+///
+/// # Panics
+///
+/// Panics if the iterator is empty, as a tree needs at least one leaf.
+impl<T, D> FromIterator<T> for MerkleTree<T, D> where T: LeafEncode, D: Digest {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        MerkleTree::from_vec_with_digest(iter.into_iter().collect()).expect("cannot build a MerkleTree without leaves")
+    }
+}
+
+/// Pushes every item as a new leaf.
+impl<T, D> Extend<T> for MerkleTree<T, D> where T: LeafEncode, D: Digest {
+    /// # Panics
+    ///
+    /// Panics if `push` fails for an item, as `LeafMode::Prehashed` and
+    /// `DuplicatePolicy::Reject` can make it. `try_extend` returns the error instead.
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.try_extend(iter).unwrap_or_else(|e| panic!("{}", e));
+    }
+}
+
+/// Leaf index at which two trees differ, as reported by `MerkleTree::diff`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DiffEntry {
+    /// Both trees have a leaf at this index, with different data.
+    Changed(usize),
+    /// Only the other tree has a leaf at this index.
+    Added(usize),
+    /// Only this tree has a leaf at this index.
+    Removed(usize),
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>")))]
+pub enum Node<T, N: ArrayLength<u8> = U64> {
+    Node(MerkleNode<T, N>),
+    Leaf(MerkleLeaf<T, N>),
+    /// Stand-in sibling for a node left unpaired on its level.
+    Padding(#[cfg_attr(feature = "serde", serde(with = "crate::serde_hash"))] GenericArray<u8, N>),
+}
+
+impl<T, N> Node<T, N> where N: ArrayLength<u8> {
+    pub fn hash(&self) -> &GenericArray<u8, N> {
+        match self {
+            Node::Node(n) => &n.hash,
+            Node::Leaf(l) => &l.hash,
+            Node::Padding(hash) => hash
+        }
+    }
+
+    fn leaf_count(&self) -> usize {
+        match self {
+            Node::Node(n) => n.left.leaf_count() + n.right.leaf_count(),
+            Node::Leaf(_) => 1,
+            Node::Padding(_) => 0
+        }
+    }
+}
+
+// Implemented by hand so that `N` is not required to implement these traits.
+impl<T, N> Clone for Node<T, N> where T: Clone, N: ArrayLength<u8> {
+    fn clone(&self) -> Self {
+        match self {
+            Node::Node(n) => Node::Node(n.clone()),
+            Node::Leaf(l) => Node::Leaf(l.clone()),
+            Node::Padding(hash) => Node::Padding(hash.clone()),
+        }
+    }
+}
+
+impl<T, N> PartialEq for Node<T, N> where T: PartialEq, N: ArrayLength<u8> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Node::Node(a), Node::Node(b)) => a == b,
+            (Node::Leaf(a), Node::Leaf(b)) => a == b,
+            (Node::Padding(a), Node::Padding(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl<T, N> fmt::Debug for Node<T, N> where T: fmt::Debug, N: ArrayLength<u8> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Node(n) => f.debug_tuple("Node").field(n).finish(),
+            Node::Leaf(l) => f.debug_tuple("Leaf").field(l).finish(),
+            Node::Padding(hash) => f.debug_tuple("Padding").field(hash).finish(),
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>")))]
+pub struct MerkleNode<T, N: ArrayLength<u8> = U64> {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash"))]
+    pub hash: GenericArray<u8, N>,
+    pub left: Arc<Node<T, N>>,
+    pub right: Arc<Node<T, N>>,
+}
+
+impl<T, N> MerkleNode<T, N> where T: LeafEncode, N: ArrayLength<u8> {
+    /// Creates a node whose hash is the child hashes combined by `combiner`.
+    pub fn new<C>(combiner: &C, left: Node<T, N>, right: Node<T, N>) -> MerkleNode<T, N> where C: NodeCombiner<N> + ?Sized {
+        MerkleNode {
+            hash: combiner.combine(left.hash(), right.hash()),
+            left: Arc::new(left),
+            right: Arc::new(right),
+        }
+    }
+}
+
+impl<T, N> Clone for MerkleNode<T, N> where T: Clone, N: ArrayLength<u8> {
+    fn clone(&self) -> Self {
+        MerkleNode {
+            hash: self.hash.clone(),
+            left: self.left.clone(),
+            right: self.right.clone(),
+        }
+    }
+}
+
+impl<T, N> PartialEq for MerkleNode<T, N> where T: PartialEq, N: ArrayLength<u8> {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.left == other.left && self.right == other.right
+    }
+}
+
+impl<T, N> fmt::Debug for MerkleNode<T, N> where T: fmt::Debug, N: ArrayLength<u8> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MerkleNode")
+            .field("hash", &self.hash)
+            .field("left", &self.left)
+            .field("right", &self.right)
+            .finish()
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>")))]
+pub struct MerkleLeaf<T, N: ArrayLength<u8> = U64> {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash"))]
+    pub hash: GenericArray<u8, N>,
+    pub data: T,
+}
+
+impl<T, N> MerkleLeaf<T, N> where T: LeafEncode, N: ArrayLength<u8> {
+    pub fn new(hash_fn: &HashFn<N>, data: T) -> MerkleLeaf<T, N> {
+        MerkleLeaf {
+            hash: hash_fn(&data.encode_leaf()),
+            data,
+        }
+    }
+}
+
+impl<T, N> Clone for MerkleLeaf<T, N> where T: Clone, N: ArrayLength<u8> {
+    fn clone(&self) -> Self {
+        MerkleLeaf {
+            hash: self.hash.clone(),
+            data: self.data.clone(),
+        }
+    }
+}
+
+impl<T, N> PartialEq for MerkleLeaf<T, N> where T: PartialEq, N: ArrayLength<u8> {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.data == other.data
+    }
+}
+
+impl<T, N> fmt::Debug for MerkleLeaf<T, N> where T: fmt::Debug, N: ArrayLength<u8> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MerkleLeaf")
+            .field("hash", &self.hash)
+            .field("data", &self.data)
+            .finish()
+    }
+}
+
+impl<T> From<T> for MerkleLeaf<T>
+    where T: LeafEncode {
+    fn from(data: T) -> Self {
+        MerkleLeaf::new(&calculate_hash::<Blake2b>, data)
+    }
 }
 
-impl<T> MerkleTree<T> where T: AsRef<[u8]> {
-    pub fn new(root: Node<T>) -> MerkleTree<T> {
-        MerkleTree {
-            root,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{LeafMode, Prefixes};
+    use crate::{ArenaMerkleTree, FlatMerkleTree, KaryMerkleTree};
+
+    #[test]
+    fn it_hashes_leaf_data_correctly() {
+        let data = "test_data".as_bytes();
+        let merkle_leaf = MerkleLeaf::from(data);
+
+        assert_eq!(merkle_leaf.data, data);
+        assert_eq!(merkle_leaf.hash.to_vec(), vec![249, 124, 220, 236, 144, 165, 213, 107, 109, 161, 237, 2, 189, 209, 247, 92, 37, 154, 19, 252, 148, 61, 177, 152, 191, 210, 99, 37, 220, 74, 109, 173, 226, 207, 47, 193, 127, 30, 50, 125, 215, 44, 65, 50, 171, 129, 48, 75, 122, 77, 104, 172, 67, 6, 244, 15, 43, 221, 31, 185, 131, 100, 229, 140]);
+    }
+
+    #[test]
+    fn it_applies_the_duplicate_policy() {
+        let data = vec!["a", "b", "a", "c", "b"];
+        let reject = TreeConfig { duplicates: DuplicatePolicy::Reject, ..TreeConfig::default() };
+        let deduplicate = TreeConfig { duplicates: DuplicatePolicy::Deduplicate, ..TreeConfig::default() };
+
+        assert_eq!(MerkleTree::from_vec(data.clone()).unwrap().leaf_count(), 5);
+        assert_eq!(MerkleTree::from_vec_with_config(data.clone(), reject), Err(MerkleError::DuplicateLeaf { index: 2, first: 0 }));
+
+        let mut merkle_tree = MerkleTree::from_vec_with_config(data, deduplicate).unwrap();
+        assert_eq!(merkle_tree.root_hash(), MerkleTree::from_vec(vec!["a", "b", "c"]).unwrap().root_hash());
+        assert_eq!(merkle_tree.push("b"), Ok(1));
+        assert_eq!(merkle_tree.push("d"), Ok(3));
+        assert_eq!(merkle_tree.leaf_count(), 4);
+
+        // Copies of snapshots have no index of their leaves, so one is built for the first append.
+        let mut merkle_tree = MerkleTree::from_vec_with_config(vec!["a", "b"], reject).unwrap().clone_unindexed();
+        assert_eq!(merkle_tree.push("a"), Err(MerkleError::DuplicateLeaf { index: 2, first: 0 }));
+        assert_eq!(merkle_tree.push("c"), Ok(2));
+        assert_eq!(merkle_tree.update(2, "b"), Err(MerkleError::DuplicateLeaf { index: 2, first: 1 }));
+        assert_eq!(merkle_tree.update(2, "c"), Ok(()));
+        assert_eq!(merkle_tree.try_extend(vec!["d", "a"]), Err(MerkleError::DuplicateLeaf { index: 4, first: 0 }));
+        assert_eq!(merkle_tree.try_extend(vec!["e"]), Ok(4));
+
+        // Grafted leaves may only take the hash of a leaf they replace.
+        let mut merkle_tree = MerkleTree::from_vec_with_config(vec!["a", "b", "c", "d"], reject).unwrap();
+        assert_eq!(merkle_tree.graft(2, MerkleTree::from_vec_with_config(vec!["a", "e"], reject).unwrap()), Err(MerkleError::DuplicateLeaf { index: 2, first: 0 }));
+        assert_eq!(merkle_tree.graft(2, MerkleTree::from_vec_with_config(vec!["d", "c"], reject).unwrap()), Ok(()));
+        assert_eq!(merkle_tree.update(0, "c"), Err(MerkleError::DuplicateLeaf { index: 0, first: 3 }));
+    }
+
+    #[test]
+    fn it_applies_the_duplicate_policy_in_every_builder() {
+        type Build = fn(Vec<&str>, TreeConfig) -> Result<(Vec<u8>, usize), MerkleError>;
+        let builders: [(&str, Build); 4] = [
+            ("boxed", |data, config| MerkleTree::from_vec_with_config(data, config).map(|tree| (tree.root_hash().to_vec(), tree.leaf_count()))),
+            ("flat", |data, config| FlatMerkleTree::from_vec_with_config(data, config).map(|tree| (tree.root_hash().to_vec(), tree.leaf_count()))),
+            ("arena", |data, config| ArenaMerkleTree::from_vec_with_config(data, config).map(|tree| (tree.root_hash().to_vec(), tree.leaf_count()))),
+            ("kary", |data, config| KaryMerkleTree::from_vec_with_config(data, 3, config).map(|tree| (tree.root_hash().to_vec(), tree.leaf_count()))),
+        ];
+        let reject = TreeConfig { duplicates: DuplicatePolicy::Reject, ..TreeConfig::default() };
+        let deduplicate = TreeConfig { duplicates: DuplicatePolicy::Deduplicate, ..TreeConfig::default() };
+
+        for (name, build) in &builders {
+            let deduplicated = build(vec!["a", "b", "a", "c"], deduplicate);
+            assert_eq!(deduplicated, build(vec!["a", "b", "c"], TreeConfig::default()), "{}", name);
+            assert_eq!(deduplicated.unwrap().1, 3, "{}", name);
+            assert_eq!(build(vec!["a", "b", "a"], reject), Err(MerkleError::DuplicateLeaf { index: 2, first: 0 }), "{}", name);
+        }
+    }
+
+    #[test]
+    fn it_reports_tree_sizes() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d", "e"]).unwrap();
+        let stats = merkle_tree.stats();
+
+        // Two levels pad their last node, and the root is inline.
+        assert_eq!(stats.node_count, 13);
+        assert_eq!((stats.leaf_count, stats.depth, stats.hash_bytes), (5, 3, 13 * 64));
+        assert!(stats.heap_bytes >= 12 * core::mem::size_of::<Node<&str>>());
+
+        let merkle_tree = MerkleTree::from_vec_rfc6962(vec!["a", "b", "c", "d", "e"]).unwrap();
+        assert_eq!((merkle_tree.stats().node_count, merkle_tree.stats().hash_bytes), (9, 9 * 32));
+
+        let larger = MerkleTree::from_vec_rfc6962(vec!["a"; 10]).unwrap();
+        assert!(larger.stats().heap_bytes > merkle_tree.stats().heap_bytes);
+    }
+
+    #[test]
+    fn it_builds_fixed_size_leaves_like_any_leaves() {
+        let data: Vec<[u8; 32]> = (0..37u8).map(|i| [i; 32]).collect();
+
+        for config in &[TreeConfig::default(), TreeConfig::RFC6962, TreeConfig::new(Padding::EmptyHash)] {
+            let fixed = MerkleTree::<_, Sha256>::from_fixed_vec_with_config_and_digest(data.clone(), *config).unwrap();
+            assert_eq!(fixed, MerkleTree::from_vec_with_config_and_digest(data.clone(), *config).unwrap());
         }
+
+        let fixed = MerkleTree::from_fixed_vec(data.clone()).unwrap();
+        assert_eq!(fixed, MerkleTree::from_vec(data).unwrap());
+        assert_eq!(MerkleTree::<[u8; 32]>::from_fixed_vec(vec![]), Err(MerkleError::EmptyInput));
+    }
+
+    #[test]
+    fn it_hashes_node_from_leaves() {
+        let merkle_leaf = Node::Leaf(MerkleLeaf::from("test_data".as_bytes()));
+        let merkle_leaf_2 = Node::Leaf(MerkleLeaf::from("test_data".as_bytes()));
+
+        let merkle_node = MerkleNode::new(&calculate_hash::<Blake2b>, merkle_leaf.clone(), merkle_leaf_2.clone());
+
+        assert_eq!(*merkle_node.left, merkle_leaf);
+        assert_eq!(*merkle_node.right, merkle_leaf_2);
+        assert_eq!(merkle_node.hash.to_vec(), vec![86, 212, 98, 60, 48, 40, 135, 164, 65, 171, 245, 66, 46, 100, 170, 222, 5, 167, 146, 71, 129, 154, 168, 28, 81, 169, 177, 176, 169, 44, 219, 22, 192, 226, 56, 186, 175, 151, 152, 182, 105, 166, 119, 22, 182, 40, 139, 10, 210, 153, 159, 114, 133, 194, 226, 99, 178, 148, 175, 2, 224, 65, 189, 34]);
     }
 
-    /// Creates a MerkleTree from a vector.
-    /// The vector is assumed to have an even number of data points.
-    pub fn from_vec(data: Vec<T>) -> MerkleTree<T> {
-        let nodes = data.into_iter().map(|d| Node::Leaf(MerkleLeaf {
-            hash: calculate_hash(d.as_ref()),
-            data: d,
-        }));
+    #[test]
+    fn it_combines_child_hashes_with_custom_combiners() {
+        struct SortedPairs;
+
+        impl NodeCombiner<U64> for SortedPairs {
+            fn combine(&self, left: &[u8], right: &[u8]) -> GenericArray<u8, U64> {
+                hash_children::<Blake2b>(None, true, left, right)
+            }
+        }
 
-        let root = MerkleTree::build_until_root(nodes.collect());
+        let a = Node::Leaf(MerkleLeaf::from("a"));
+        let b = Node::Leaf(MerkleLeaf::from("b"));
+        let ab = MerkleNode::new(&SortedPairs, a.clone(), b.clone());
 
-        MerkleTree {
-            root
+        assert_eq!(ab.hash, MerkleNode::new(&SortedPairs, b, a).hash);
+
+        let config = TreeConfig { sort_pairs: true, ..TreeConfig::default() };
+        assert_eq!(ab.hash.as_slice(), MerkleTree::from_vec_with_config(vec!["b", "a"], config).unwrap().root_hash());
+    }
+
+    #[test]
+    fn it_builds_merkle_tree_from_vec() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]).unwrap();
+
+        assert_eq!(merkle_tree.root,
+                   Node::Node(MerkleNode::new(
+                       &calculate_hash::<Blake2b>,
+                       Node::Node(MerkleNode::new(&calculate_hash::<Blake2b>, Node::Leaf(MerkleLeaf::from("a")), Node::Leaf(MerkleLeaf::from("b")))),
+                       Node::Node(MerkleNode::new(&calculate_hash::<Blake2b>, Node::Leaf(MerkleLeaf::from("c")), Node::Leaf(MerkleLeaf::from("d"))),
+                       ))));
+    }
+
+    #[test]
+    fn it_fails_to_build_merkle_tree_from_empty_vec() {
+        assert_eq!(MerkleTree::<&str>::from_vec(vec![]), Err(MerkleError::EmptyInput));
+    }
+
+    #[test]
+    fn it_duplicates_last_node_of_odd_len_vec() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c"]).unwrap();
+        let c = Node::Leaf(MerkleLeaf::from("c"));
+
+        assert_eq!(merkle_tree.root,
+                   Node::Node(MerkleNode::new(
+                       &calculate_hash::<Blake2b>,
+                       Node::Node(MerkleNode::new(&calculate_hash::<Blake2b>, Node::Leaf(MerkleLeaf::from("a")), Node::Leaf(MerkleLeaf::from("b")))),
+                       Node::Node(MerkleNode::new(&calculate_hash::<Blake2b>, c.clone(), Node::Padding(*c.hash()))),
+                   )));
+    }
+
+    #[test]
+    fn it_promotes_last_node_of_odd_len_vec() {
+        let merkle_tree = MerkleTree::from_vec_with_config(vec!["a", "b", "c"], TreeConfig::new(Padding::Promote)).unwrap();
+
+        assert_eq!(merkle_tree.root,
+                   Node::Node(MerkleNode::new(
+                       &calculate_hash::<Blake2b>,
+                       Node::Node(MerkleNode::new(&calculate_hash::<Blake2b>, Node::Leaf(MerkleLeaf::from("a")), Node::Leaf(MerkleLeaf::from("b")))),
+                       Node::Leaf(MerkleLeaf::from("c")),
+                   )));
+    }
+
+    #[test]
+    fn it_pads_last_node_of_odd_len_vec_with_empty_hash() {
+        let merkle_tree = MerkleTree::from_vec_with_config(vec!["a", "b", "c"], TreeConfig::new(Padding::EmptyHash)).unwrap();
+
+        assert_eq!(merkle_tree.root,
+                   Node::Node(MerkleNode::new(
+                       &calculate_hash::<Blake2b>,
+                       Node::Node(MerkleNode::new(&calculate_hash::<Blake2b>, Node::Leaf(MerkleLeaf::from("a")), Node::Leaf(MerkleLeaf::from("b")))),
+                       Node::Node(MerkleNode::new(&calculate_hash::<Blake2b>, Node::Leaf(MerkleLeaf::from("c")), Node::Padding(calculate_hash::<Blake2b>(&[])))),
+                   )));
+    }
+
+    #[test]
+    fn it_compares_merkle_trees() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]).unwrap();
+        let eq_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]).unwrap();
+        let diff_tree = MerkleTree::from_vec(vec!["d", "b", "c", "d"]).unwrap();
+
+        assert_eq!(merkle_tree, eq_tree);
+        assert_ne!(merkle_tree, diff_tree);
+    }
+
+    #[test]
+    fn it_pushes_leaves_like_building_from_vec() {
+        let data: Vec<String> = (0..17).map(|i| i.to_string()).collect();
+
+        for padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            let config = TreeConfig::new(*padding);
+            let mut merkle_tree = MerkleTree::from_vec_with_config(vec![data[0].clone()], config).unwrap();
+
+            for len in 2..=data.len() {
+                merkle_tree.push(data[len - 1].clone()).unwrap();
+                let expected = MerkleTree::from_vec_with_config(data[..len].to_vec(), config).unwrap();
+
+                assert_eq!(merkle_tree.root, expected.root);
+                assert_eq!(merkle_tree.proof(len - 1), expected.proof(len - 1));
+            }
         }
     }
 
-    fn build_until_root(mut nodes: Vec<Node<T>>) -> Node<T> {
-        if nodes.len() == 1 {
-            return nodes.remove(0);
+    #[test]
+    fn it_collects_and_extends_like_building_from_vec() {
+        let data: Vec<String> = (0..9).map(|i| i.to_string()).collect();
+        let mut merkle_tree: MerkleTree<String> = data[..5].iter().cloned().collect();
+
+        assert_eq!(merkle_tree.root, MerkleTree::from_vec(data[..5].to_vec()).unwrap().root);
+
+        merkle_tree.extend(data[5..].iter().cloned());
+        let expected = MerkleTree::from_vec(data).unwrap();
+
+        assert_eq!(merkle_tree.root, expected.root);
+        assert_eq!(merkle_tree.proof(8), expected.proof(8));
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_panics_when_collecting_an_empty_iterator() {
+        let _: MerkleTree<&str> = std::iter::empty().collect();
+    }
+
+    #[test]
+    fn it_iterates_over_leaves_in_order() {
+        let data: Vec<String> = (0..11).map(|i| i.to_string()).collect();
+
+        for padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            let merkle_tree = MerkleTree::from_vec_with_config(data.clone(), TreeConfig::new(*padding)).unwrap();
+            let leaves: Vec<&String> = merkle_tree.leaves().map(|leaf| &leaf.data).collect();
+
+            assert_eq!(merkle_tree.leaf_count(), data.len());
+            assert_eq!(leaves, data.iter().collect::<Vec<_>>());
+
+            for (index, leaf) in merkle_tree.leaves().enumerate() {
+                assert_eq!(merkle_tree.get_leaf(index), Some(leaf));
+            }
+
+            assert_eq!(merkle_tree.get_leaf(data.len()), None);
         }
+    }
+
+    #[test]
+    fn it_diffs_trees_leaf_by_leaf() {
+        let data: Vec<String> = (0..13).map(|i| i.to_string()).collect();
+
+        for padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            let config = TreeConfig::new(*padding);
 
-        let mut iter = nodes.into_iter();
-        let (mut left, mut right) = (iter.next(), iter.next());
-        let mut nodes: Vec<Node<T>> = Vec::new();
+            for len in 1..=data.len() {
+                for other_len in 1..=data.len() {
+                    let mut other_data = data[..other_len].to_vec();
+                    other_data[other_len / 2] = "changed".to_string();
 
-        while left.is_some() {
-            nodes.push(Node::Node(MerkleNode::new(&calculate_hash, left.unwrap(), right.unwrap())));
+                    let merkle_tree = MerkleTree::from_vec_with_config(data[..len].to_vec(), config).unwrap();
+                    let other_tree = MerkleTree::from_vec_with_config(other_data.clone(), config).unwrap();
 
-            left = iter.next();
-            right = iter.next();
+                    let expected: Vec<DiffEntry> = (0..len.max(other_len))
+                        .filter_map(|i| match (data[..len].get(i), other_data.get(i)) {
+                            (Some(a), Some(b)) if a == b => None,
+                            (Some(_), Some(_)) => Some(DiffEntry::Changed(i)),
+                            (Some(_), None) => Some(DiffEntry::Removed(i)),
+                            _ => Some(DiffEntry::Added(i)),
+                        })
+                        .collect();
+
+                    assert_eq!(merkle_tree.diff(&other_tree), expected);
+                }
+            }
+
+            let merkle_tree = MerkleTree::from_vec_with_config(data.clone(), config).unwrap();
+            assert!(merkle_tree.diff(&merkle_tree.clone()).is_empty());
         }
+    }
+
+    #[test]
+    fn it_updates_leaves_like_building_from_vec() {
+        let data: Vec<String> = (0..11).map(|i| i.to_string()).collect();
+
+        for padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            let config = TreeConfig::new(*padding);
+
+            for len in 1..=data.len() {
+                let mut merkle_tree = MerkleTree::from_vec_with_config(data[..len].to_vec(), config).unwrap();
+
+                for index in 0..len {
+                    let mut expected_data = data[..len].to_vec();
+                    expected_data[index] = "updated".to_string();
+                    let expected = MerkleTree::from_vec_with_config(expected_data, config).unwrap();
 
-        MerkleTree::build_until_root(nodes)
+                    merkle_tree.update(index, "updated".to_string()).unwrap();
+                    assert_eq!(merkle_tree.root, expected.root);
+
+                    merkle_tree.update(index, data[index].clone()).unwrap();
+                }
+            }
+        }
     }
-}
 
-impl<T> PartialEq for MerkleTree<T> {
-    fn eq(&self, other: &Self) -> bool {
-        self.root.hash().eq(other.root.hash())
+    #[test]
+    fn it_fails_to_update_out_of_bounds_leaf() {
+        let mut merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c"]).unwrap();
+
+        assert_eq!(merkle_tree.update(3, "d"), Err(MerkleError::IndexOutOfBounds { index: 3, leaf_count: 3 }));
     }
-}
 
-#[derive(PartialEq, Debug, Clone)]
-pub enum Node<T> {
-    Node(MerkleNode<T>),
-    Leaf(MerkleLeaf<T>),
-}
+    #[test]
+    fn it_removes_leaves_like_building_from_vec() {
+        let data: Vec<String> = (0..11).map(|i| i.to_string()).collect();
 
-impl<T> Node<T> {
-    fn hash(&self) -> &Vec<u8> {
-        match self {
-            Node::Node(n) => &n.hash,
-            Node::Leaf(l) => &l.hash
+        for padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            let config = TreeConfig::new(*padding);
+
+            for len in 2..=data.len() {
+                for index in 0..len {
+                    let mut merkle_tree = MerkleTree::from_vec_with_config(data[..len].to_vec(), config).unwrap();
+                    let snapshot = merkle_tree.snapshot();
+
+                    let mut expected_data = data[..len].to_vec();
+                    expected_data.remove(index);
+                    let expected = MerkleTree::from_vec_with_config(expected_data, config).unwrap();
+
+                    assert_eq!(merkle_tree.remove(index), Ok(data[index].clone()));
+                    assert_eq!(merkle_tree.root, expected.root);
+                    assert_eq!(merkle_tree.leaf_count(), len - 1);
+                    assert_eq!(snapshot.leaf_count(), len);
+                }
+            }
         }
     }
-}
 
-#[derive(PartialEq, Debug, Clone)]
-pub struct MerkleNode<T> {
-    pub hash: Vec<u8>,
-    pub left: Box<Node<T>>,
-    pub right: Box<Node<T>>,
-}
+    #[test]
+    fn it_finds_leaves_by_data() {
+        let mut merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "b"]).unwrap();
 
-impl<T> MerkleNode<T> where T: AsRef<[u8]> {
-    pub fn new<'a>(hash_fn: &HashFn, left: Node<T>, right: Node<T>) -> MerkleNode<T> {
-        let mut concat = left.hash().clone();
-        concat.extend_from_slice(right.hash());
+        assert_eq!(merkle_tree.index_of(b"b"), Some(1));
+        assert_eq!(merkle_tree.index_of(b"e"), None);
 
-        MerkleNode {
-            left: Box::new(left),
-            right: Box::new(right),
-            hash: hash_fn(concat.as_slice()),
+        merkle_tree.push("e").unwrap();
+        merkle_tree.update(1, "f").unwrap();
+        assert_eq!(merkle_tree.index_of(b"e"), Some(4));
+        assert_eq!(merkle_tree.index_of(b"b"), Some(3));
+        assert_eq!(merkle_tree.index_of(b"f"), Some(1));
+
+        let snapshot = merkle_tree.snapshot();
+        merkle_tree.remove(0).unwrap();
+        assert_eq!(merkle_tree.index_of(b"e"), Some(3));
+        assert_eq!(merkle_tree.index_of(b"a"), None);
+        assert_eq!(snapshot.index_of(b"e"), Some(4));
+        assert_eq!(snapshot.index_of(b"a"), Some(0));
+
+        let proof = merkle_tree.proof(merkle_tree.index_of(b"c").unwrap()).unwrap();
+        assert!(proof.verify(merkle_tree.root_hash(), b"c").is_ok());
+    }
+
+    #[test]
+    fn it_renders_trees_as_ascii() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c"]).unwrap();
+        let short = |node: &Node<&str>| to_hex(node.hash())[..8].to_string();
+        let (left, right) = match &merkle_tree.root {
+            Node::Node(n) => (&n.left, &n.right),
+            _ => panic!("trees with 3 leaves have an inner root"),
+        };
+        let expected = [
+            short(&merkle_tree.root),
+            format!("+-- {}", short(left)),
+            format!("|   +-- {} (leaf 0)", short(merkle_tree.node_at(0, 0))),
+            format!("|   `-- {} (leaf 1)", short(merkle_tree.node_at(0, 1))),
+            format!("`-- {}", short(right)),
+            format!("    +-- {} (leaf 2)", short(merkle_tree.node_at(0, 2))),
+            format!("    `-- {} (padding)", short(merkle_tree.node_at(0, 2))),
+        ];
+
+        assert_eq!(merkle_tree.render_ascii(), expected.join("\n"));
+        assert_eq!(merkle_tree.to_string(), merkle_tree.render_ascii());
+        assert_eq!(MerkleTree::from_vec(vec!["a"]).unwrap().to_string(), format!("{} (leaf 0)", short(merkle_tree.node_at(0, 0))));
+    }
+
+    #[test]
+    fn it_exports_trees_to_dot() {
+        let merkle_tree = MerkleTree::from_vec(vec![&b"a\"b"[..], &[0, 1][..], &[b'x'; 20][..]]).unwrap();
+        let short = |node: &Node<&[u8]>| to_hex(node.hash())[..8].to_string();
+        let dot = merkle_tree.to_dot();
+
+        assert!(dot.starts_with("digraph merkle_tree {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains(&format!("    n0 [label=\"{}\"];\n", short(&merkle_tree.root))));
+        assert!(dot.contains(&format!("    n2 [label=\"{}\\n\\\"a\\\"b\\\"\", shape=ellipse];\n", short(merkle_tree.node_at(0, 0)))));
+        assert!(dot.contains("\\n0x0001\", shape=ellipse];\n"));
+        assert!(dot.contains(&format!("\\n\\\"{}...\\\"\", shape=ellipse];\n", "x".repeat(16))));
+        assert!(dot.contains(&format!("    n6 [label=\"{}\", style=dashed];\n", short(merkle_tree.node_at(0, 2)))));
+        assert_eq!(dot.matches(" -> ").count(), 6);
+        assert!(dot.contains("    n0 -> n1;\n") && dot.contains("    n1 -> n2;\n") && dot.contains("    n0 -> n4;\n"));
+    }
+
+    #[test]
+    fn it_builds_trees_from_leaf_hashes_alone() {
+        let data: Vec<String> = (0..7).map(|i| i.to_string()).collect();
+        let config = TreeConfig::RFC6962;
+        let hashes = data.iter().map(|d| MerkleTree::<(), Sha256>::leaf_hash(config, d.as_bytes())).collect();
+
+        let hash_only = MerkleTree::<(), Sha256>::from_hashes_with_config_and_digest(hashes, config).unwrap();
+        let expected = MerkleTree::from_vec_rfc6962(data.clone()).unwrap();
+
+        assert_eq!(hash_only.root_hash(), expected.root_hash());
+        assert_eq!(hash_only.index_of(b"3"), Some(3));
+
+        for (index, leaf) in data.iter().enumerate() {
+            let proof = hash_only.proof(index).unwrap();
+            assert_eq!(proof, expected.proof(index).unwrap());
+            assert!(proof.verify(hash_only.root_hash(), leaf.as_bytes()).is_ok());
         }
+
+        assert_eq!(MerkleTree::from_hashes(vec![]), Err(MerkleError::EmptyInput));
     }
-}
 
-#[derive(PartialEq, Debug, Clone)]
-pub struct MerkleLeaf<T> {
-    pub hash: Vec<u8>,
-    pub data: T,
-}
+    #[test]
+    fn it_fails_to_remove_out_of_bounds_or_last_leaf() {
+        let mut merkle_tree = MerkleTree::from_vec(vec!["a", "b"]).unwrap();
 
-impl<'a, T> From<T> for MerkleLeaf<T>
-    where T: AsRef<[u8]> {
-    fn from(data: T) -> Self {
-        let hash = calculate_hash(data.as_ref());
+        assert_eq!(merkle_tree.remove(2), Err(MerkleError::IndexOutOfBounds { index: 2, leaf_count: 2 }));
+        assert_eq!(merkle_tree.remove(0), Ok("a"));
+        assert_eq!(merkle_tree.remove(0), Err(MerkleError::EmptyInput));
+        assert_eq!(merkle_tree.root, MerkleTree::from_vec(vec!["b"]).unwrap().root);
+    }
 
-        MerkleLeaf {
-            data,
-            hash,
+    #[test]
+    fn it_prefixes_leaf_and_node_hashes() {
+        let config = TreeConfig { prefixes: Some(Prefixes::RFC6962), ..TreeConfig::default() };
+        let merkle_tree = MerkleTree::from_vec_with_config(vec!["a", "b"], config).unwrap();
+
+        let leaf_a = calculate_hash::<Blake2b>(&[&[0x00][..], "a".as_bytes()].concat());
+        let leaf_b = calculate_hash::<Blake2b>(&[&[0x00][..], "b".as_bytes()].concat());
+        let root = calculate_hash::<Blake2b>(&[&[0x01][..], &leaf_a, &leaf_b].concat());
+
+        assert_eq!(merkle_tree.root_hash(), root.as_slice());
+        assert_ne!(merkle_tree, MerkleTree::from_vec(vec!["a", "b"]).unwrap());
+    }
+
+    #[test]
+    fn it_keeps_prefixes_when_pushing_and_updating() {
+        let config = TreeConfig { prefixes: Some(Prefixes { leaf: 0xaa, node: 0xbb }), ..TreeConfig::default() };
+        let mut merkle_tree = MerkleTree::from_vec_with_config(vec!["a", "b", "x"], config).unwrap();
+
+        merkle_tree.push("d").unwrap();
+        merkle_tree.update(2, "c").unwrap();
+
+        assert_eq!(merkle_tree.root, MerkleTree::from_vec_with_config(vec!["a", "b", "c", "d"], config).unwrap().root);
+    }
+
+    #[test]
+    fn it_builds_trees_over_prehashed_leaves() {
+        let config = TreeConfig { leaf_mode: LeafMode::Prehashed, ..TreeConfig::default() };
+        let hashes: Vec<_> = ["a", "b", "c"].iter().map(|data| calculate_hash::<Blake2b>(data.as_bytes()).to_vec()).collect();
+        let mut merkle_tree = MerkleTree::from_vec_with_config(hashes.clone(), config).unwrap();
+
+        assert_eq!(merkle_tree.root_hash(), MerkleTree::from_vec(vec!["a", "b", "c"]).unwrap().root_hash());
+        assert!(merkle_tree.proof(1).unwrap().verify(merkle_tree.root_hash(), &hashes[1]).is_ok());
+        assert_eq!(merkle_tree.proof(1).unwrap().verify(merkle_tree.root_hash(), b"b"), Err(MerkleError::InvalidLeafLength { index: 1, len: 1, expected: 64 }));
+
+        assert_eq!(merkle_tree.update(0, b"a".to_vec()), Err(MerkleError::InvalidLeafLength { index: 0, len: 1, expected: 64 }));
+        assert_eq!(MerkleTree::from_vec_with_config(vec![vec![0; 64], vec![0; 32]], config), Err(MerkleError::InvalidLeafLength { index: 1, len: 32, expected: 64 }));
+    }
+
+    // Leaves and roots from the Certificate Transparency reference implementation test suite.
+    const RFC6962_LEAVES: [&[u8]; 8] = [
+        &[], &[0x00], &[0x10], &[0x20, 0x21], &[0x30, 0x31], &[0x40, 0x41, 0x42, 0x43],
+        &[0x50, 0x51, 0x52, 0x53, 0x54, 0x55, 0x56, 0x57],
+        &[0x60, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x6b, 0x6c, 0x6d, 0x6e, 0x6f],
+    ];
+    const RFC6962_ROOTS: [&str; 8] = [
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+    ];
+
+    #[test]
+    fn it_matches_rfc6962_roots() {
+        for len in 1..=RFC6962_LEAVES.len() {
+            let merkle_tree = MerkleTree::from_vec_rfc6962(RFC6962_LEAVES[..len].to_vec()).unwrap();
+
+            assert_eq!(merkle_tree.root_hash_hex(), RFC6962_ROOTS[len - 1]);
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn it_matches_rfc6962_roots_when_pushing() {
+        let mut merkle_tree = MerkleTree::from_vec_rfc6962(vec![RFC6962_LEAVES[0]]).unwrap();
+
+        for len in 2..=RFC6962_LEAVES.len() {
+            merkle_tree.push(RFC6962_LEAVES[len - 1]).unwrap();
+
+            assert_eq!(merkle_tree.root_hash_hex(), RFC6962_ROOTS[len - 1]);
+        }
+    }
 
     #[test]
-    fn it_hashes_leaf_data_correctly() {
-        let data = "test_data".as_bytes();
-        let merkle_leaf = MerkleLeaf::from(data);
+    fn it_builds_wide_trees_on_a_small_stack() {
+        let builder = std::thread::Builder::new().stack_size(128 * 1024);
+        let handle = builder.spawn(|| {
+            let data: Vec<[u8; 4]> = (0..1u32 << 17).map(u32::to_le_bytes).collect();
+            let merkle_tree = MerkleTree::from_vec(data).unwrap();
 
-        assert_eq!(merkle_leaf.data, data);
-        assert_eq!(merkle_leaf.hash, vec![249, 124, 220, 236, 144, 165, 213, 107, 109, 161, 237, 2, 189, 209, 247, 92, 37, 154, 19, 252, 148, 61, 177, 152, 191, 210, 99, 37, 220, 74, 109, 173, 226, 207, 47, 193, 127, 30, 50, 125, 215, 44, 65, 50, 171, 129, 48, 75, 122, 77, 104, 172, 67, 6, 244, 15, 43, 221, 31, 185, 131, 100, 229, 140]);
+            merkle_tree.proof((1 << 17) - 1).unwrap().siblings.len()
+        }).unwrap();
+
+        assert_eq!(handle.join().unwrap(), 17);
     }
 
     #[test]
-    fn it_hashes_node_from_leaves() {
-        let merkle_leaf = Node::Leaf(MerkleLeaf::from("test_data".as_bytes()));
-        let merkle_leaf_2 = Node::Leaf(MerkleLeaf::from("test_data".as_bytes()));
+    fn it_recurses_once_per_level_at_the_maximum_height() {
+        let builder = std::thread::Builder::new().stack_size(256 * 1024);
+        let handle = builder.spawn(|| {
+            // The left subtree holds 2^(usize::BITS - 1) leaves with a single node per level, shared
+            // by both children of the node above, and the promoted last leaf puts the root
+            // `usize::BITS` levels up.
+            let config = TreeConfig::new(Padding::Promote);
+            let mut left = Arc::new(MerkleTree::<&str>::leaf(config, 0, "a").unwrap());
 
-        let merkle_node = MerkleNode::new(&calculate_hash, merkle_leaf.clone(), merkle_leaf_2.clone());
+            for _ in 1..usize::BITS {
+                let hash = hash_children::<Blake2b>(None, false, left.hash(), left.hash());
+                left = Arc::new(Node::Node(MerkleNode { hash, left: left.clone(), right: left }));
+            }
 
-        assert_eq!(*merkle_node.left, merkle_leaf);
-        assert_eq!(*merkle_node.right, merkle_leaf_2);
-        assert_eq!(merkle_node.hash, vec![86, 212, 98, 60, 48, 40, 135, 164, 65, 171, 245, 66, 46, 100, 170, 222, 5, 167, 146, 71, 129, 154, 168, 28, 81, 169, 177, 176, 169, 44, 219, 22, 192, 226, 56, 186, 175, 151, 152, 182, 105, 166, 119, 22, 182, 40, 139, 10, 210, 153, 159, 114, 133, 194, 226, 99, 178, 148, 175, 2, 224, 65, 189, 34]);
+            let leaf_count = (1 << (usize::BITS - 1)) + 1;
+            let right = Arc::new(MerkleTree::<&str>::leaf(config, leaf_count - 1, "b").unwrap());
+            let hash = hash_children::<Blake2b>(None, false, left.hash(), right.hash());
+            let root = Node::Node(MerkleNode { hash, left, right });
+            let mut merkle_tree = MerkleTree::<_, Blake2b> { root, config, leaf_count, indices: None, digest: PhantomData };
+
+            let proof = merkle_tree.proofs(&[1]).unwrap().remove(0);
+            assert_eq!(proof.siblings.len(), usize::BITS as usize);
+            assert_eq!(proof.verify(merkle_tree.root_hash(), b"a"), Ok(()));
+
+            merkle_tree.update(1, "c").unwrap();
+            assert_eq!(merkle_tree.proof(1).unwrap().verify(merkle_tree.root_hash(), b"c"), Ok(()));
+
+            merkle_tree.push("d").unwrap();
+            assert_eq!(merkle_tree.proof(leaf_count).unwrap().verify(merkle_tree.root_hash(), b"d"), Ok(()));
+        }).unwrap();
+
+        handle.join().unwrap();
     }
 
     #[test]
-    fn it_builds_merkle_tree_from_vec() {
-        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]);
+    fn it_exposes_root_hash() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]).unwrap();
+
+        assert_eq!(merkle_tree.root_hash(), merkle_tree.root.hash().as_slice());
+        assert_eq!(merkle_tree.root_hash_hex().len(), 128);
+        assert_eq!(merkle_tree.root_hash_hex(), to_hex(merkle_tree.root_hash()));
+    }
+
+    #[test]
+    fn it_compares_trees_down_to_their_divergence() {
+        let data: Vec<String> = (0..8).map(|i| i.to_string()).collect();
+        let merkle_tree = MerkleTree::from_vec(data.clone()).unwrap();
+        let hashes = MerkleTree::from_hashes(merkle_tree.leaves().map(|leaf| leaf.hash).collect()).unwrap();
+
+        assert_eq!(merkle_tree.compare(&hashes), Comparison::Equal);
+        assert_eq!(merkle_tree.compare(&hashes).divergence_depth(), None);
+
+        let mut changed = merkle_tree.clone();
+        changed.update(5, "five".to_string()).unwrap();
+        assert_eq!(merkle_tree.compare(&changed), Comparison::Diverged { path: vec![Side::Right, Side::Left, Side::Right] });
+
+        changed.update(4, "four".to_string()).unwrap();
+        assert_eq!(merkle_tree.compare(&changed), Comparison::Diverged { path: vec![Side::Right, Side::Left] });
+
+        changed.update(0, "zero".to_string()).unwrap();
+        assert_eq!(changed.compare(&hashes).divergence_depth(), Some(0));
+    }
+
+    #[test]
+    fn it_builds_trees_matching_an_expected_root() {
+        let data = vec!["a", "b", "c", "d", "e"];
+        let root = MerkleTree::from_vec(data.clone()).unwrap().root_hash().to_vec();
+
+        assert_eq!(MerkleTree::from_vec_verified(data.clone(), &root).unwrap().root_hash(), root.as_slice());
+        assert_eq!(MerkleTree::from_vec_verified(vec!["a", "b", "c", "d", "x"], &root).err(), Some(MerkleError::RootMismatch));
+        assert_eq!(MerkleTree::from_vec_verified_with_config(data, &root, TreeConfig::new(Padding::Promote)).err(), Some(MerkleError::RootMismatch));
+    }
+
+    #[test]
+    fn it_verifies_the_structure_of_built_trees() {
+        for &padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            let config = TreeConfig { prefixes: Some(Prefixes::RFC6962), sort_pairs: true, ..TreeConfig::new(padding) };
+
+            for len in 1..=9 {
+                let mut merkle_tree = MerkleTree::from_vec_with_config((0..len).map(|i| i.to_string()).collect(), config).unwrap();
+                assert_eq!(merkle_tree.verify_structure(), Ok(()));
+
+                merkle_tree.push("pushed".to_string()).unwrap();
+                assert_eq!(merkle_tree.verify_structure(), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn it_reports_the_path_to_corrupt_nodes() {
+        let mut merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c"]).unwrap();
+
+        if let Node::Node(root) = &mut merkle_tree.root {
+            if let Node::Node(right) = Arc::make_mut(&mut root.right) {
+                *Arc::make_mut(&mut right.right) = Node::Padding(calculate_hash::<Blake2b>(&[]));
+            }
+        }
+
+        let error = merkle_tree.verify_structure().unwrap_err();
+        assert_eq!(error, MerkleError::CorruptNode { path: vec![Side::Right] });
+        assert_eq!(error.to_string(), "corrupt node at root/right");
+
+        let mut merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]).unwrap();
+
+        if let Node::Node(root) = &mut merkle_tree.root {
+            if let Node::Node(left) = Arc::make_mut(&mut root.left) {
+                if let Node::Leaf(leaf) = Arc::make_mut(&mut left.right) {
+                    leaf.data = "x";
+                }
+            }
+        }
+
+        assert_eq!(merkle_tree.verify_structure(), Err(MerkleError::CorruptNode { path: vec![Side::Left, Side::Right] }));
+    }
+
+    #[test]
+    fn it_extracts_subtrees() {
+        let data: Vec<String> = (0..11).map(|i| i.to_string()).collect();
+        let merkle_tree = MerkleTree::from_vec(data.clone()).unwrap();
+
+        for range in [4..8, 8..11, 3..9, 10..11] {
+            let subtree = merkle_tree.subtree(range.clone()).unwrap();
+
+            assert_eq!(subtree.root_hash(), MerkleTree::from_vec(data[range.clone()].to_vec()).unwrap().root_hash());
+            assert_eq!(subtree.index_of(data[range.start].as_bytes()), Some(0));
+        }
+
+        assert_eq!(merkle_tree.subtree(4..12).unwrap_err(), MerkleError::IndexOutOfBounds { index: 11, leaf_count: 11 });
+        assert_eq!(merkle_tree.subtree(4..4).unwrap_err(), MerkleError::EmptyInput);
+    }
+
+    #[test]
+    fn it_grafts_subtrees_built_separately() {
+        for &padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            let config = TreeConfig::new(padding);
+            let mut data: Vec<String> = (0..13).map(|i| i.to_string()).collect();
+            let mut merkle_tree = MerkleTree::from_vec_with_config(data.clone(), config).unwrap();
+
+            for (position, size) in [(8, 4), (12, 1), (0, 8), (4, 2)] {
+                let segment: Vec<String> = (0..size).map(|i| format!("{} {}", position, i)).collect();
+                data.splice(position..position + size, segment.clone());
+
+                merkle_tree.graft(position, MerkleTree::from_vec_with_config(segment, config).unwrap()).unwrap();
+
+                assert_eq!(merkle_tree.root_hash(), MerkleTree::from_vec_with_config(data.clone(), config).unwrap().root_hash());
+                assert_eq!(merkle_tree.verify_structure(), Ok(()));
+                assert_eq!(merkle_tree.index_of(data[position].as_bytes()), Some(position));
+            }
+
+            let pair = MerkleTree::from_vec_with_config(vec!["a".to_string(), "b".to_string()], config).unwrap();
+            assert_eq!(merkle_tree.graft(3, pair.clone()), Err(MerkleError::MisalignedSubtree { position: 3, leaf_count: 2 }));
+            assert_eq!(merkle_tree.graft(12, pair), Err(MerkleError::MisalignedSubtree { position: 12, leaf_count: 2 }));
+        }
+
+        let mut merkle_tree = MerkleTree::from_vec(vec!["a", "b"]).unwrap();
+        let other = MerkleTree::from_vec_with_config(vec!["c"], TreeConfig::RFC6962).unwrap();
+        assert_eq!(merkle_tree.graft(0, other), Err(MerkleError::ConfigMismatch));
+    }
+
+    #[test]
+    fn it_generates_batches_of_proofs() {
+        for &padding in &[Padding::DuplicateLast, Padding::Promote, Padding::EmptyHash] {
+            for len in [1, 2, 7, 300, 1025] {
+                let merkle_tree = MerkleTree::from_vec_with_config((0..len).map(|i| i.to_string()).collect(), TreeConfig::new(padding)).unwrap();
+                let indices: Vec<usize> = (0..len).rev().chain([0, len / 2]).collect();
+                let proofs = merkle_tree.proofs(&indices).unwrap();
+
+                assert_eq!(proofs.len(), indices.len());
+
+                for (index, proof) in indices.iter().zip(&proofs) {
+                    assert_eq!(proof, &merkle_tree.proof(*index).unwrap());
+                }
+            }
+        }
 
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c"]).unwrap();
+        assert_eq!(merkle_tree.proofs(&[]), Ok(vec![]));
+        assert_eq!(merkle_tree.proofs(&[1, 3]), Err(MerkleError::IndexOutOfBounds { index: 3, leaf_count: 3 }));
+    }
+
+    #[test]
+    fn it_builds_merkle_tree_with_custom_digest() {
+        use blake2::Blake2s;
+
+        let merkle_tree = MerkleTree::<_, Blake2s>::from_vec_with_digest(vec!["a", "b", "c", "d"]).unwrap();
+        let default_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]).unwrap();
+
+        assert_eq!(merkle_tree.root.hash().len(), 32);
         assert_eq!(merkle_tree.root,
                    Node::Node(MerkleNode::new(
-                       &calculate_hash,
-                       Node::Node(MerkleNode::new(&calculate_hash, Node::Leaf(MerkleLeaf::from("a")), Node::Leaf(MerkleLeaf::from("b")))),
-                       Node::Node(MerkleNode::new(&calculate_hash, Node::Leaf(MerkleLeaf::from("c")), Node::Leaf(MerkleLeaf::from("d"))),
+                       &calculate_hash::<Blake2s>,
+                       Node::Node(MerkleNode::new(&calculate_hash::<Blake2s>, Node::Leaf(MerkleLeaf::new(&calculate_hash::<Blake2s>, "a")), Node::Leaf(MerkleLeaf::new(&calculate_hash::<Blake2s>, "b")))),
+                       Node::Node(MerkleNode::new(&calculate_hash::<Blake2s>, Node::Leaf(MerkleLeaf::new(&calculate_hash::<Blake2s>, "c")), Node::Leaf(MerkleLeaf::new(&calculate_hash::<Blake2s>, "d"))),
                        ))));
+        assert_ne!(merkle_tree.root.hash().as_slice(), default_tree.root.hash().as_slice());
     }
 
     #[test]
-    #[should_panic]
-    fn it_panics_when_building_merkle_tree_from_odd_len_vec() {
-        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c"]);
-    }
+    #[cfg(feature = "tracing")]
+    fn it_traces_construction_with_counters() {
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing_core::span::Current;
+        use tracing::{Event, Metadata};
 
+        /// Metadata and unsigned fields of a span.
+        type Recorded = (&'static Metadata<'static>, Vec<(&'static str, u64)>);
 
-    #[test]
-    fn it_compares_merkle_trees() {
-        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]);
-        let eq_tree = MerkleTree::from_vec(vec!["a", "b", "c", "d"]);
-        let diff_tree = MerkleTree::from_vec(vec!["d", "b", "c", "d"]);
+        /// Subscriber keeping the spans created, with the fields recorded later on, and the stack
+        /// of spans entered.
+        #[derive(Clone, Default)]
+        struct Spans(Arc<Mutex<(Vec<Recorded>, Vec<Id>)>>);
 
-        assert_eq!(merkle_tree, eq_tree);
-        assert_ne!(merkle_tree, diff_tree);
+        struct Fields<'a>(&'a mut Vec<(&'static str, u64)>);
+
+        impl Visit for Fields<'_> {
+            fn record_u64(&mut self, field: &Field, value: u64) {
+                self.0.push((field.name(), value));
+            }
+
+            fn record_debug(&mut self, _: &Field, _: &dyn core::fmt::Debug) {}
+        }
+
+        impl tracing::Subscriber for Spans {
+            fn enabled(&self, _: &Metadata) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes) -> Id {
+                let mut spans = self.0.lock().unwrap();
+                let mut fields = Vec::new();
+                span.record(&mut Fields(&mut fields));
+                spans.0.push((span.metadata(), fields));
+                Id::from_u64(spans.0.len() as u64)
+            }
+
+            fn record(&self, id: &Id, values: &Record) {
+                values.record(&mut Fields(&mut self.0.lock().unwrap().0[id.into_u64() as usize - 1].1));
+            }
+
+            fn current_span(&self) -> Current {
+                let spans = self.0.lock().unwrap();
+                match spans.1.last() {
+                    Some(id) => Current::new(id.clone(), spans.0[id.into_u64() as usize - 1].0),
+                    None => Current::none(),
+                }
+            }
+
+            fn enter(&self, id: &Id) {
+                self.0.lock().unwrap().1.push(id.clone());
+            }
+
+            fn exit(&self, _: &Id) {
+                self.0.lock().unwrap().1.pop();
+            }
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event) {}
+        }
+
+        let spans = Spans::default();
+        tracing::subscriber::with_default(spans.clone(), || {
+            MerkleTree::from_vec(vec!["a", "bc", "def", "g", "h"]).unwrap().proof(3).unwrap();
+        });
+
+        let spans: Vec<_> = spans.0.lock().unwrap().0.iter().map(|(metadata, fields)| (metadata.name(), fields.clone())).collect();
+        assert_eq!(spans, vec![
+            ("from_vec_with_config_and_digest", vec![("leaves", 5), ("bytes", 8)]),
+            ("from_leaves", vec![("leaves", 5), ("nodes_hashed", 6)]),
+            ("proof", vec![("leaf_index", 3)]),
+        ]);
     }
 }
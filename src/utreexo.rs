@@ -0,0 +1,581 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use core::fmt;
+
+use digest::Digest;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use sha2::Sha512Trunc256;
+
+use crate::error::MerkleError;
+use crate::{hash_pair, Hash};
+
+/// Node of a forest, as its row above the leaves and its index in the row counted from the
+/// leftmost tree. Deletions move nodes up to the parent of their sibling, but every row and index
+/// keeps covering the same leaves.
+type Coord = (usize, usize);
+
+/// Roots of a utreexo forest and its number of leaves, all a stateless node keeps to verify that
+/// the leaves it is handed are in the forest and to follow additions and deletions. Roots of
+/// trees whose leaves were all deleted are empty, which is written as a hash of zeros.
+///
+/// Leaves are hashes computed by the application, such as the hashes of bitcoin UTXOs, and are
+/// expected to be unique. Nodes hash their children with SHA-512/256 by default, as in the
+/// published algorithm, and positions are numbered as in it: the leaves first, then every row
+/// after the one below, as if the forest were a single perfect tree.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct UtreexoStump<D: Digest = Sha512Trunc256> {
+    /// Roots from the highest (leftmost) tree to the lowest.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::list"))]
+    pub roots: Vec<Hash<D>>,
+    /// Number of leaves ever added, including those deleted since.
+    pub leaves: usize,
+}
+
+impl UtreexoStump {
+    /// Creates the stump of an empty forest hashing with SHA-512/256.
+    pub fn new() -> UtreexoStump {
+        UtreexoStump::with_digest()
+    }
+}
+
+impl Default for UtreexoStump {
+    fn default() -> Self {
+        UtreexoStump::new()
+    }
+}
+
+impl<D> UtreexoStump<D> where D: Digest {
+    /// Creates the stump of an empty forest hashing with the digest `D`.
+    pub fn with_digest() -> UtreexoStump<D> {
+        UtreexoStump { roots: Vec::new(), leaves: 0 }
+    }
+
+    /// Checks that the leaves `del_hashes` are at the targets of `proof`, in the same order.
+    pub fn verify(&self, proof: &UtreexoProof<D>, del_hashes: &[Hash<D>]) -> Result<(), MerkleError> {
+        Forest::from_stump(self)?.ingest(proof, del_hashes).map(drop)
+    }
+
+    /// Deletes the leaves `del_hashes` proven by `proof`, and then adds the leaves `adds`. The
+    /// stump is left unchanged when the proof does not verify.
+    pub fn modify(&mut self, adds: &[Hash<D>], del_hashes: &[Hash<D>], proof: &UtreexoProof<D>) -> Result<(), MerkleError> {
+        let mut forest = Forest::from_stump(self)?;
+        let deleted = forest.ingest(proof, del_hashes)?;
+        forest.delete(&deleted)?;
+        adds.iter().for_each(|leaf| forest.add(leaf.clone()));
+
+        *self = forest.stump();
+        Ok(())
+    }
+}
+
+/// A utreexo forest holding every node, as bridge nodes do to hand proofs to stateless nodes
+/// that only keep its [`UtreexoStump`].
+pub struct UtreexoForest<D: Digest = Sha512Trunc256> {
+    forest: Forest<D>,
+    /// Index in the leaf row of every leaf not deleted.
+    leaves: BTreeMap<Hash<D>, usize>,
+}
+
+impl UtreexoForest {
+    /// Creates an empty forest hashing with SHA-512/256.
+    pub fn new() -> UtreexoForest {
+        UtreexoForest::with_digest()
+    }
+}
+
+impl Default for UtreexoForest {
+    fn default() -> Self {
+        UtreexoForest::new()
+    }
+}
+
+impl<D> UtreexoForest<D> where D: Digest {
+    /// Creates an empty forest hashing with the digest `D`.
+    pub fn with_digest() -> UtreexoForest<D> {
+        UtreexoForest { forest: Forest { nodes: BTreeMap::new(), leaf_count: 0 }, leaves: BTreeMap::new() }
+    }
+
+    /// Number of leaves ever added, including those deleted since.
+    pub fn leaf_count(&self) -> usize {
+        self.forest.leaf_count
+    }
+
+    pub fn stump(&self) -> UtreexoStump<D> {
+        self.forest.stump()
+    }
+
+    /// Whether `leaf` was added and not deleted since.
+    pub fn contains(&self, leaf: &Hash<D>) -> bool {
+        self.leaves.contains_key(leaf)
+    }
+
+    /// Deletes the leaves `del_hashes` and then adds the leaves `adds`, as
+    /// [`UtreexoStump::modify`] does with a proof of `del_hashes`. Fails with `UnknownLeaf`, and
+    /// leaves the forest unchanged, if a leaf to delete is not in the forest.
+    pub fn modify(&mut self, adds: &[Hash<D>], del_hashes: &[Hash<D>]) -> Result<(), MerkleError> {
+        let deleted = del_hashes.iter()
+            .map(|leaf| self.leaves.get(leaf).map(|index| (0, *index)).ok_or(MerkleError::UnknownLeaf))
+            .collect::<Result<Vec<_>, MerkleError>>()?;
+
+        self.forest.delete(&deleted)?;
+        del_hashes.iter().for_each(|leaf| { self.leaves.remove(leaf); });
+
+        for leaf in adds {
+            self.leaves.insert(leaf.clone(), self.forest.leaf_count);
+            self.forest.add(leaf.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Generates a proof of the given leaves, whose targets are their positions in the same
+    /// order. Fails with `UnknownLeaf` if a leaf is not in the forest.
+    pub fn prove(&self, leaves: &[Hash<D>]) -> Result<UtreexoProof<D>, MerkleError> {
+        let targets = leaves.iter()
+            .map(|leaf| self.leaves.get(leaf).map(|index| (0, *index)).ok_or(MerkleError::UnknownLeaf))
+            .collect::<Result<Vec<_>, MerkleError>>()?;
+
+        self.forest.prove(&targets)
+    }
+}
+
+impl<D> fmt::Debug for UtreexoForest<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UtreexoForest")
+            .field("stump", &self.stump())
+            .field("leaves", &self.leaves.len())
+            .finish()
+    }
+}
+
+/// Proof of a batch of leaves of a utreexo forest: the positions of the leaves, and the hashes
+/// of the nodes needed to hash them up to their roots, in position order.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct UtreexoProof<D: Digest = Sha512Trunc256> {
+    pub targets: Vec<usize>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_hash::list"))]
+    pub hashes: Vec<Hash<D>>,
+}
+
+impl<D> UtreexoProof<D> where D: Digest {
+    /// Proof of no leaf, from which only additions can be applied.
+    pub fn empty() -> UtreexoProof<D> {
+        UtreexoProof { targets: Vec::new(), hashes: Vec::new() }
+    }
+
+    /// Brings a proof of the leaves `target_hashes` up to date with the change made by
+    /// `stump.modify(adds, del_hashes, del_proof)`, given `stump` as it was before the change,
+    /// so that stateless nodes keep proving their own leaves without a bridge node.
+    ///
+    /// The leaves deleted by the change are no longer proven, and the leaves `adds[i]` for every
+    /// `i` in `remember` are proven from then on. The new targets are in position order, and the
+    /// hashes of the leaves they prove are returned in the same order.
+    pub fn update(&mut self, stump: &UtreexoStump<D>, target_hashes: &[Hash<D>], adds: &[Hash<D>], remember: &[usize], del_hashes: &[Hash<D>], del_proof: &UtreexoProof<D>) -> Result<Vec<Hash<D>>, MerkleError> {
+        let mut forest = Forest::from_stump(stump)?;
+        let targets = forest.ingest(self, target_hashes)?;
+        let deleted = forest.ingest(del_proof, del_hashes)?;
+        forest.delete(&deleted)?;
+
+        let first_added = forest.leaf_count;
+        adds.iter().for_each(|leaf| forest.add(leaf.clone()));
+
+        let remembered = remember.iter()
+            .map(|i| adds.get(*i).map(|leaf| ((0, first_added + i), leaf.clone())).ok_or(MerkleError::UnknownLeaf))
+            .collect::<Result<Vec<_>, MerkleError>>()?;
+
+        let (targets, hashes): (Vec<Coord>, Vec<Hash<D>>) = targets.into_iter()
+            .zip(target_hashes.iter().cloned())
+            .filter(|(target, _)| !deleted.contains(target))
+            .chain(remembered)
+            .unzip();
+        *self = forest.prove(&targets)?;
+
+        // The hashes of a proof do not depend on the order of its targets.
+        let mut targets: Vec<(usize, Hash<D>)> = self.targets.iter().cloned().zip(hashes).collect();
+        targets.sort_by_key(|(target, _)| *target);
+
+        let (targets, hashes) = targets.into_iter().unzip();
+        self.targets = targets;
+        Ok(hashes)
+    }
+}
+
+/// The hashes known of a forest, by node. Empty nodes hold `None`.
+struct Forest<D: Digest> {
+    nodes: BTreeMap<Coord, Option<Hash<D>>>,
+    leaf_count: usize,
+}
+
+impl<D> Forest<D> where D: Digest {
+    /// The forest of which only the roots are known. Fails with `InvalidState` if there is not
+    /// one root per bit set in the number of leaves.
+    fn from_stump(stump: &UtreexoStump<D>) -> Result<Forest<D>, MerkleError> {
+        if stump.roots.len() != stump.leaves.count_ones() as usize {
+            return Err(MerkleError::InvalidState);
+        }
+
+        let mut forest = Forest { nodes: BTreeMap::new(), leaf_count: stump.leaves };
+        let roots: Vec<Coord> = forest.roots().collect();
+
+        for (root, hash) in roots.into_iter().zip(&stump.roots) {
+            let empty = hash.iter().all(|byte| *byte == 0);
+            forest.nodes.insert(root, if empty { None } else { Some(hash.clone()) });
+        }
+
+        Ok(forest)
+    }
+
+    fn stump(&self) -> UtreexoStump<D> {
+        let roots = self.roots().map(|root| self.nodes[&root].clone().unwrap_or_default()).collect();
+        UtreexoStump { roots, leaves: self.leaf_count }
+    }
+
+    /// Roots, from the highest tree to the lowest.
+    fn roots(&self) -> impl Iterator<Item = Coord> {
+        let leaf_count = self.leaf_count;
+        (0..usize::BITS as usize).rev()
+            .filter(move |row| (leaf_count >> row) & 1 == 1)
+            .map(move |row| (row, (leaf_count >> row) - 1))
+    }
+
+    fn is_root(&self, (row, index): Coord) -> bool {
+        (self.leaf_count >> row) & 1 == 1 && index + 1 == self.leaf_count >> row
+    }
+
+    fn value(&self, (row, index): Coord) -> Result<&Option<Hash<D>>, MerkleError> {
+        self.nodes.get(&(row, index)).ok_or(MerkleError::MissingNode { height: row, index })
+    }
+
+    /// Position of the first node of `row` in the published numbering.
+    fn row_offset(&self, row: usize) -> usize {
+        let rows = tree_rows(self.leaf_count);
+        (2 << rows) - ((2 << rows) >> row)
+    }
+
+    fn position(&self, (row, index): Coord) -> usize {
+        self.row_offset(row) + index
+    }
+
+    /// Node at a position, failing with `MalformedProof` if it is not a node of the forest.
+    fn coord(&self, position: usize) -> Result<Coord, MerkleError> {
+        let row = (0..=tree_rows(self.leaf_count)).take_while(|row| self.row_offset(*row) <= position).last().unwrap_or(0);
+        let index = position - self.row_offset(row);
+
+        if index < self.leaf_count >> row {
+            Ok((row, index))
+        } else {
+            Err(MerkleError::MalformedProof)
+        }
+    }
+
+    /// Position of `node` once the empty nodes are taken out: whenever a node is empty, its
+    /// sibling takes the place of their parent, and the whole subtree of the sibling moves up a
+    /// row. Walks down from the root of `node`, and records the nodes met and their siblings in
+    /// `view` at their new positions.
+    fn physical(&self, node: Coord, view: &mut BTreeMap<Coord, Option<Hash<D>>>) -> Result<Coord, MerkleError> {
+        let root = self.roots()
+            .find(|(row, index)| *row >= node.0 && node.1 >> (row - node.0) == *index)
+            .ok_or(MerkleError::MalformedProof)?;
+        let (mut logical, mut physical) = (root, root);
+
+        while logical != node {
+            let toward = (logical.0 - 1, node.1 >> (logical.0 - 1 - node.0));
+            let sibling = (toward.0, toward.1 ^ 1);
+
+            // The child toward `node` only moves down when its sibling is not empty.
+            if let Some(hash) = self.value(sibling)? {
+                view.insert((physical.0 - 1, physical.1 * 2 + (sibling.1 & 1)), Some(hash.clone()));
+                physical = (physical.0 - 1, physical.1 * 2 + (toward.1 & 1));
+            }
+
+            logical = toward;
+        }
+
+        view.insert(physical, self.value(node)?.clone());
+        Ok(physical)
+    }
+
+    /// Hash of the parent of `node` and its sibling, which moves up untouched when the other one
+    /// is empty.
+    fn parent_hash(&self, (row, index): Coord) -> Result<Option<Hash<D>>, MerkleError> {
+        Ok(match (self.value((row, index & !1))?, self.value((row, index | 1))?) {
+            (Some(left), Some(right)) => Some(hash_pair::<D>(None, left, right)),
+            (Some(hash), None) | (None, Some(hash)) => Some(hash.clone()),
+            (None, None) => None,
+        })
+    }
+
+    fn add(&mut self, leaf: Hash<D>) {
+        let index = self.leaf_count;
+        let mut row = 0;
+        self.nodes.insert((0, index), Some(leaf));
+
+        // Every tree of a height whose bit is set in the index is merged into a taller one.
+        while (index >> row) & 1 == 1 {
+            let parent = self.parent_hash((row, index >> row)).expect("roots are always known");
+            row += 1;
+            self.nodes.insert((row, index >> row), parent);
+        }
+
+        self.leaf_count += 1;
+    }
+
+    /// Empties the given nodes and hashes their ancestors again, from the lowest up.
+    fn delete(&mut self, nodes: &[Coord]) -> Result<(), MerkleError> {
+        let mut pending: BTreeSet<Coord> = nodes.iter().cloned().collect();
+        nodes.iter().for_each(|node| { self.nodes.insert(*node, None); });
+
+        while let Some((row, index)) = pending.pop_first() {
+            if !self.is_root((row, index)) {
+                let parent = self.parent_hash((row, index))?;
+                self.nodes.insert((row + 1, index >> 1), parent);
+                pending.insert((row + 1, index >> 1));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Nodes whose hashes a proof of `targets` holds: the siblings of the nodes on the paths of
+    /// the targets that are not themselves on a path, in position order.
+    fn proof_nodes(&self, targets: &[Coord]) -> Vec<Coord> {
+        let mut pending: BTreeSet<Coord> = targets.iter().cloned().collect();
+        let mut nodes = Vec::new();
+
+        while let Some((row, index)) = pending.pop_first() {
+            if !self.is_root((row, index)) {
+                if !pending.remove(&(row, index ^ 1)) {
+                    nodes.push((row, index ^ 1));
+                }
+
+                pending.insert((row + 1, index >> 1));
+            }
+        }
+
+        nodes
+    }
+
+    /// Proof of the nodes `targets`, at their positions once the empty nodes are taken out.
+    fn prove(&self, targets: &[Coord]) -> Result<UtreexoProof<D>, MerkleError> {
+        let mut view = Forest::<D> { nodes: BTreeMap::new(), leaf_count: self.leaf_count };
+        let targets = targets.iter().map(|target| self.physical(*target, &mut view.nodes)).collect::<Result<Vec<_>, MerkleError>>()?;
+
+        let hashes = view.proof_nodes(&targets).into_iter()
+            .map(|node| view.value(node)?.clone().ok_or(MerkleError::MalformedProof))
+            .collect::<Result<Vec<_>, MerkleError>>()?;
+
+        Ok(UtreexoProof { targets: targets.iter().map(|target| self.position(*target)).collect(), hashes })
+    }
+
+    /// Records the nodes `proof` implies for the leaves `hashes` at its targets, after checking
+    /// that they hash up to the roots, and returns the nodes of the targets. Fails with
+    /// `RootMismatch` if a node does not match what is known of the forest.
+    fn ingest(&mut self, proof: &UtreexoProof<D>, hashes: &[Hash<D>]) -> Result<Vec<Coord>, MerkleError> {
+        let targets = proof.targets.iter().map(|target| self.coord(*target)).collect::<Result<Vec<_>, MerkleError>>()?;
+        let proof_nodes = self.proof_nodes(&targets);
+
+        if targets.len() != hashes.len() || proof_nodes.len() != proof.hashes.len() {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let mut known: BTreeMap<Coord, Hash<D>> = proof_nodes.into_iter().zip(proof.hashes.iter().cloned()).collect();
+        known.extend(targets.iter().cloned().zip(hashes.iter().cloned()));
+
+        if known.len() != targets.len() + proof.hashes.len() {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let mut pending: BTreeSet<Coord> = targets.iter().cloned().collect();
+
+        // Every node on a path has its sibling either in the proof or on another path.
+        while let Some((row, index)) = pending.pop_first() {
+            if self.is_root((row, index)) {
+                continue;
+            }
+
+            let parent = (row + 1, index >> 1);
+            let hash = hash_pair::<D>(None, &known[&(row, index & !1)], &known[&(row, index | 1)]);
+
+            if known.get(&parent).is_some_and(|known| *known != hash) {
+                return Err(MerkleError::RootMismatch);
+            }
+
+            known.insert(parent, hash);
+            pending.remove(&(row, index ^ 1));
+            pending.insert(parent);
+        }
+
+        if known.iter().any(|(node, hash)| self.nodes.get(node).is_some_and(|known| known.as_ref() != Some(hash))) {
+            return Err(MerkleError::RootMismatch);
+        }
+
+        self.nodes.extend(known.into_iter().map(|(node, hash)| (node, Some(hash))));
+        Ok(targets)
+    }
+}
+
+/// Number of rows above the leaves of the smallest perfect tree holding `leaf_count` leaves.
+fn tree_rows(leaf_count: usize) -> usize {
+    match leaf_count {
+        0 => 0,
+        _ => (usize::BITS - (leaf_count - 1).leading_zeros()) as usize,
+    }
+}
+
+// Implemented by hand so that `D` is not required to implement these traits.
+impl<D> Clone for UtreexoStump<D> where D: Digest {
+    fn clone(&self) -> Self {
+        UtreexoStump { roots: self.roots.clone(), leaves: self.leaves }
+    }
+}
+
+impl<D> PartialEq for UtreexoStump<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.roots == other.roots && self.leaves == other.leaves
+    }
+}
+
+impl<D> fmt::Debug for UtreexoStump<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UtreexoStump")
+            .field("roots", &self.roots)
+            .field("leaves", &self.leaves)
+            .finish()
+    }
+}
+
+impl<D> Clone for UtreexoProof<D> where D: Digest {
+    fn clone(&self) -> Self {
+        UtreexoProof { targets: self.targets.clone(), hashes: self.hashes.clone() }
+    }
+}
+
+impl<D> PartialEq for UtreexoProof<D> where D: Digest {
+    fn eq(&self, other: &Self) -> bool {
+        self.targets == other.targets && self.hashes == other.hashes
+    }
+}
+
+impl<D> fmt::Debug for UtreexoProof<D> where D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UtreexoProof")
+            .field("targets", &self.targets)
+            .field("hashes", &self.hashes)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use digest::generic_array::GenericArray;
+    use sha2::Sha256;
+
+    /// Leaves of the published test vectors, the SHA-256 hashes of single bytes.
+    fn leaf(value: u8) -> Hash<Sha512Trunc256> {
+        GenericArray::clone_from_slice(&Sha256::digest(&[value]))
+    }
+
+    fn leaves(values: &[u8]) -> Vec<Hash<Sha512Trunc256>> {
+        values.iter().cloned().map(leaf).collect()
+    }
+
+    fn hashes(hex: &[&str]) -> Vec<Hash<Sha512Trunc256>> {
+        hex.iter().map(|hex| GenericArray::clone_from_slice(&crate::from_hex(hex).unwrap())).collect()
+    }
+
+    #[test]
+    fn it_matches_the_published_test_vectors() {
+        let mut forest = UtreexoForest::new();
+        forest.modify(&leaves(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14]), &[]).unwrap();
+        let mut stump = forest.stump();
+        assert_eq!(stump.roots, hashes(&[
+            "b151a956139bb821d4effa34ea95c17560e0135d1e4661fc23cedc3af49dac42",
+            "9c053db406c1a077112189469a3aca0573d3481bef09fa3d2eda3304d7d44be8",
+            "55d0a0ef8f5c25a9da266b36c0c5f4b31008ece82df2512c8966bddcc27a66a0",
+            "4d7b3ef7300acf70c892d8327db8272f54434adbc61a4e130a563cb59a0d0f47",
+        ]));
+
+        // Deleting the last leaf empties the lowest root.
+        let deleted = leaves(&[0, 1, 5, 14]);
+        let proof = forest.prove(&deleted).unwrap();
+        assert_eq!(proof, UtreexoProof { targets: vec![0, 1, 5, 14], hashes: hashes(&[
+            "e52d9c508c502347344d8c07ad91cbd6068afc75ff6292f062a09ca381c89e71",
+            "9576f4ade6e9bc3a6458b506ce3e4e890df29cb14cb5d3d887672aef55647a2b",
+            "34028bbc87000c39476cdc60cf80ca32d579b3a0e2d3f80e0ad8c3739a01aa91",
+        ]) });
+
+        stump.modify(&[], &deleted, &proof).unwrap();
+        forest.modify(&[], &deleted).unwrap();
+        assert_eq!(forest.stump(), stump);
+        assert_eq!(stump.roots, hashes(&[
+            "352d0d5e172316e73b6d0d40605ad411c79b7e7c1ed0b4529c565f08057bc4a8",
+            "9c053db406c1a077112189469a3aca0573d3481bef09fa3d2eda3304d7d44be8",
+            "55d0a0ef8f5c25a9da266b36c0c5f4b31008ece82df2512c8966bddcc27a66a0",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+        ]));
+
+        let mut proof = forest.prove(&leaves(&[6])).unwrap();
+        assert_eq!(stump.verify(&proof, &leaves(&[6])), Ok(()));
+        assert_eq!(stump.verify(&proof, &leaves(&[7])), Err(MerkleError::RootMismatch));
+        proof.hashes.pop();
+        assert_eq!(stump.verify(&proof, &leaves(&[6])), Err(MerkleError::MalformedProof));
+    }
+
+    #[test]
+    fn it_moves_subtrees_up_when_their_sibling_is_deleted() {
+        let mut forest = UtreexoForest::new();
+        forest.modify(&leaves(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13]), &[]).unwrap();
+        let stump = forest.stump();
+
+        let cached = leaves(&[1, 8, 12]);
+        let mut proof = forest.prove(&cached).unwrap();
+        assert_eq!(proof.targets, vec![1, 8, 12]);
+
+        // Deleting leaves 4 to 7 moves the tree of leaves 0 to 3 up a row.
+        let deleted = leaves(&[4, 5, 6, 7]);
+        let del_proof = forest.prove(&deleted).unwrap();
+        let mut after = stump.clone();
+        after.modify(&[], &deleted, &del_proof).unwrap();
+        forest.modify(&[], &deleted).unwrap();
+        assert_eq!(after.roots[0], hashes(&["df46b17be5f66f0750a4b3efa26d4679db170a72d41eb56c3e4ff75a58c65386"])[0]);
+
+        let proven = proof.update(&stump, &cached, &[], &[], &deleted, &del_proof).unwrap();
+        assert_eq!((&proof.targets[..], &proven[..]), (&[8, 12, 17][..], &leaves(&[8, 12, 1])[..]));
+        assert_eq!(after.verify(&proof, &proven), Ok(()));
+        assert_eq!(forest.prove(&proven).unwrap(), proof);
+    }
+
+    #[test]
+    fn it_keeps_stateless_proofs_up_to_date() {
+        let mut forest = UtreexoForest::new();
+        let mut stump = UtreexoStump::new();
+        let (mut proof, mut proven) = (UtreexoProof::empty(), Vec::new());
+        let mut live: Vec<u8> = Vec::new();
+
+        for block in 0..16u8 {
+            let adds: Vec<u8> = (block * 5..block * 5 + block % 6).collect();
+            let deleted: Vec<u8> = live.iter().cloned().filter(|value| (value ^ block) % 3 == 0).collect();
+            live.retain(|value| !deleted.contains(value));
+
+            let del_proof = forest.prove(&leaves(&deleted)).unwrap();
+            let before = stump.clone();
+            stump.modify(&leaves(&adds), &leaves(&deleted), &del_proof).unwrap();
+            forest.modify(&leaves(&adds), &leaves(&deleted)).unwrap();
+            assert_eq!(forest.stump(), stump);
+
+            // Remember every other leaf added.
+            let remember: Vec<usize> = (0..adds.len()).step_by(2).collect();
+            proven = proof.update(&before, &proven, &leaves(&adds), &remember, &leaves(&deleted), &del_proof).unwrap();
+            assert_eq!(stump.verify(&proof, &proven), Ok(()));
+            assert_eq!(forest.prove(&proven).unwrap(), proof);
+            live.extend(adds);
+        }
+
+        assert!(!proven.is_empty());
+        assert_eq!(forest.modify(&[], &leaves(&[255])), Err(MerkleError::UnknownLeaf));
+    }
+}
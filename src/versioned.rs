@@ -0,0 +1,178 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use blake2::Blake2b;
+use digest::Digest;
+
+use crate::config::TreeConfig;
+use crate::error::MerkleError;
+use crate::leaf::LeafEncode;
+use crate::proof::Proof;
+use crate::snapshot::MerkleSnapshot;
+use crate::tree::MerkleTree;
+
+/// A MerkleTree keeping every version it went through, for historical state queries. Version 0
+/// is the tree it was built as, and every push or update creates the next version. Versions
+/// share the subtrees left unchanged between them, so each one only costs the nodes on the
+/// path of its change.
+pub struct VersionedMerkleTree<T, D: Digest = Blake2b> {
+    tree: MerkleTree<T, D>,
+    versions: Vec<MerkleSnapshot<T, D>>,
+}
+
+impl<T> VersionedMerkleTree<T, Blake2b> where T: LeafEncode + Clone {
+    /// Creates a VersionedMerkleTree from a vector, hashing with Blake2b and the default config.
+    pub fn from_vec(data: Vec<T>) -> Result<VersionedMerkleTree<T>, MerkleError> {
+        VersionedMerkleTree::from_vec_with_config_and_digest(data, TreeConfig::default())
+    }
+
+    /// Creates a VersionedMerkleTree from a vector, hashing with Blake2b.
+    pub fn from_vec_with_config(data: Vec<T>, config: TreeConfig) -> Result<VersionedMerkleTree<T>, MerkleError> {
+        VersionedMerkleTree::from_vec_with_config_and_digest(data, config)
+    }
+}
+
+impl<T, D> VersionedMerkleTree<T, D> where T: LeafEncode + Clone, D: Digest {
+    /// Creates a VersionedMerkleTree from a vector, hashing with the digest `D`.
+    pub fn from_vec_with_config_and_digest(data: Vec<T>, config: TreeConfig) -> Result<VersionedMerkleTree<T, D>, MerkleError> {
+        Ok(VersionedMerkleTree::new(MerkleTree::from_vec_with_config_and_digest(data, config)?))
+    }
+
+    /// Starts recording versions of `tree`, as version 0.
+    pub fn new(tree: MerkleTree<T, D>) -> VersionedMerkleTree<T, D> {
+        let versions = vec![tree.snapshot()];
+        VersionedMerkleTree { tree, versions }
+    }
+
+    /// The latest version of the tree.
+    pub fn tree(&self) -> &MerkleTree<T, D> {
+        &self.tree
+    }
+
+    /// Number of the latest version.
+    pub fn version(&self) -> usize {
+        self.versions.len() - 1
+    }
+
+    /// The tree as it was at `version`, or `None` if there is no such version yet.
+    pub fn at(&self, version: usize) -> Option<&MerkleSnapshot<T, D>> {
+        self.versions.get(version)
+    }
+
+    pub fn root_at(&self, version: usize) -> Option<&[u8]> {
+        self.at(version).map(|tree| tree.root_hash())
+    }
+
+    /// Generates an inclusion proof for the leaf at `leaf_index` of the tree as it was at `version`.
+    pub fn proof_at(&self, version: usize, leaf_index: usize) -> Result<Proof<D>, MerkleError> {
+        self.at(version)
+            .ok_or(MerkleError::UnknownVersion { version, version_count: self.versions.len() })?
+            .proof(leaf_index)
+    }
+
+    /// Appends a leaf and returns the number of the version created. No version is created when
+    /// the push fails, nor when `DuplicatePolicy::Deduplicate` drops the leaf, which leaves the
+    /// tree unchanged and returns the number of the latest version.
+    pub fn push(&mut self, data: T) -> Result<usize, MerkleError> {
+        let leaf_count = self.tree.leaf_count();
+        self.tree.push(data)?;
+
+        if self.tree.leaf_count() == leaf_count {
+            return Ok(self.version());
+        }
+
+        Ok(self.record())
+    }
+
+    /// Replaces the data of the leaf at `index` and returns the number of the version created.
+    /// No version is created when the update fails.
+    pub fn update(&mut self, index: usize, data: T) -> Result<usize, MerkleError> {
+        self.tree.update(index, data)?;
+        Ok(self.record())
+    }
+
+    fn record(&mut self) -> usize {
+        self.versions.push(self.tree.snapshot());
+        self.version()
+    }
+}
+
+impl<T, D> fmt::Debug for VersionedMerkleTree<T, D> where T: fmt::Debug, D: Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VersionedMerkleTree")
+            .field("tree", &self.tree)
+            .field("version", &(self.versions.len() - 1))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DuplicatePolicy;
+
+    fn leaves(len: usize) -> Vec<String> {
+        (0..len).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn it_answers_queries_about_every_version() {
+        let mut versioned = VersionedMerkleTree::from_vec(leaves(3)).unwrap();
+        let mut expected = vec![MerkleTree::from_vec(leaves(3)).unwrap()];
+
+        assert_eq!(versioned.push("3".to_string()), Ok(1));
+        expected.push(MerkleTree::from_vec(leaves(4)).unwrap());
+
+        assert_eq!(versioned.update(1, "updated".to_string()), Ok(2));
+        expected.push(MerkleTree::from_vec(vec!["0", "updated", "2", "3"].into_iter().map(String::from).collect()).unwrap());
+
+        assert_eq!(versioned.version(), 2);
+        assert_eq!(versioned.tree().root_hash(), expected[2].root_hash());
+
+        for (version, tree) in expected.iter().enumerate() {
+            assert_eq!(versioned.root_at(version), Some(tree.root_hash()));
+
+            for index in 0..tree.leaf_count() {
+                assert_eq!(versioned.proof_at(version, index), tree.proof(index));
+            }
+        }
+    }
+
+    #[test]
+    fn it_rejects_unknown_versions_and_failed_updates() {
+        let mut versioned = VersionedMerkleTree::from_vec(leaves(2)).unwrap();
+
+        assert_eq!(versioned.update(2, "2".to_string()), Err(MerkleError::IndexOutOfBounds { index: 2, leaf_count: 2 }));
+        assert_eq!(versioned.version(), 0);
+
+        assert_eq!(versioned.root_at(1), None);
+        assert_eq!(versioned.proof_at(1, 0), Err(MerkleError::UnknownVersion { version: 1, version_count: 1 }));
+        assert_eq!(versioned.proof_at(0, 2), Err(MerkleError::IndexOutOfBounds { index: 2, leaf_count: 2 }));
+    }
+
+    #[test]
+    fn it_keeps_old_versions_intact() {
+        let mut versioned = VersionedMerkleTree::from_vec(leaves(8)).unwrap();
+        let original = versioned.root_at(0).unwrap().to_vec();
+
+        for index in 0..8 {
+            versioned.update(index, "updated".to_string()).unwrap();
+        }
+
+        assert_eq!(versioned.root_at(0), Some(&original[..]));
+        assert_eq!(versioned.at(0).unwrap().root, MerkleTree::from_vec(leaves(8)).unwrap().root);
+        assert_eq!(versioned.version(), 8);
+    }
+
+    #[test]
+    fn it_records_no_version_for_skipped_or_rejected_leaves() {
+        let config = TreeConfig { duplicates: DuplicatePolicy::Deduplicate, ..TreeConfig::default() };
+        let mut versioned = VersionedMerkleTree::from_vec_with_config(leaves(2), config).unwrap();
+
+        assert_eq!(versioned.push("1".to_string()), Ok(0));
+        assert_eq!(versioned.push("2".to_string()), Ok(1));
+        assert_eq!(versioned.update(0, "2".to_string()), Err(MerkleError::DuplicateLeaf { index: 0, first: 2 }));
+        assert_eq!(versioned.version(), 1);
+    }
+}
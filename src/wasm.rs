@@ -0,0 +1,147 @@
+//! JavaScript bindings, generated by wasm-bindgen.
+//!
+//! Trees are hashed with Blake2b and take their config from the constructor. Hashes and proofs
+//! cross the boundary as `Uint8Array`s, proofs in the format of `Proof::to_bytes`. Proofs are
+//! checked against the config of the tree they are expected to come from, not the config they
+//! carry.
+
+use wasm_bindgen::prelude::*;
+
+use crate::config::{Padding, Prefixes, TreeConfig};
+use crate::proof::Proof;
+use crate::tree::MerkleTree;
+
+/// `MerkleTree` class of the JavaScript bindings, owning a copy of its leaves.
+#[wasm_bindgen(js_name = MerkleTree)]
+pub struct WasmMerkleTree {
+    tree: MerkleTree<Vec<u8>>,
+}
+
+#[wasm_bindgen(js_class = MerkleTree)]
+impl WasmMerkleTree {
+    /// Builds a tree with one leaf per string, from its UTF-8 bytes.
+    #[wasm_bindgen(js_name = fromStrings)]
+    pub fn from_strings(leaves: Vec<String>, config: Option<WasmConfig>) -> Result<WasmMerkleTree, JsError> {
+        let config = config.map_or_else(TreeConfig::default, |config| config.0);
+        let tree = MerkleTree::from_vec_with_config(leaves.into_iter().map(String::into_bytes).collect(), config)?;
+
+        Ok(WasmMerkleTree { tree })
+    }
+
+    /// Builds a tree with one leaf per `chunkSize` bytes of `bytes`, the last one possibly shorter.
+    #[wasm_bindgen(js_name = fromChunks)]
+    pub fn from_chunks(bytes: &[u8], chunk_size: usize, config: Option<WasmConfig>) -> Result<WasmMerkleTree, JsError> {
+        if chunk_size == 0 {
+            return Err(JsError::new("chunk size must not be zero"));
+        }
+
+        let config = config.map_or_else(TreeConfig::default, |config| config.0);
+        let tree = MerkleTree::from_vec_with_config(bytes.chunks(chunk_size).map(<[u8]>::to_vec).collect(), config)?;
+
+        Ok(WasmMerkleTree { tree })
+    }
+
+    #[wasm_bindgen(js_name = rootHash)]
+    pub fn root_hash(&self) -> Vec<u8> {
+        self.tree.root_hash().to_vec()
+    }
+
+    #[wasm_bindgen(js_name = rootHashHex)]
+    pub fn root_hash_hex(&self) -> String {
+        self.tree.root_hash_hex()
+    }
+
+    #[wasm_bindgen(getter, js_name = leafCount)]
+    pub fn leaf_count(&self) -> usize {
+        self.tree.leaf_count()
+    }
+
+    pub fn push(&mut self, leaf: &[u8]) -> Result<(), JsError> {
+        self.tree.push(leaf.to_vec())?;
+        Ok(())
+    }
+
+    /// Inclusion proof of the leaf at `index`, encoded with `Proof::to_bytes`.
+    pub fn proof(&self, index: usize) -> Result<Vec<u8>, JsError> {
+        Ok(self.tree.proof(index)?.to_bytes())
+    }
+
+    /// Whether `proof`, encoded with `Proof::to_bytes`, proves that `leaf` is in the tree
+    /// with root `rootHash` built with `config`, the default config if omitted. Proofs that
+    /// cannot be decoded or were built with another config do not verify.
+    #[wasm_bindgen(js_name = verifyProof)]
+    pub fn verify_proof(proof: &[u8], root_hash: &[u8], leaf: &[u8], config: Option<WasmConfig>) -> bool {
+        let config = config.map_or_else(TreeConfig::default, |config| config.0);
+
+        Proof::<blake2::Blake2b>::from_bytes(proof).and_then(|proof| proof.verify_with_config(root_hash, leaf, config)).is_ok()
+    }
+}
+
+/// `TreeConfig` class of the JavaScript bindings.
+#[wasm_bindgen(js_name = TreeConfig)]
+#[derive(Clone, Copy)]
+pub struct WasmConfig(TreeConfig);
+
+#[wasm_bindgen(js_class = TreeConfig)]
+impl WasmConfig {
+    /// Config with the given padding, one of `"duplicate-last"`, `"promote"` or `"empty-hash"`,
+    /// and RFC 6962 leaf and node prefixes if `prefixed` is set.
+    #[wasm_bindgen(constructor)]
+    pub fn new(padding: &str, prefixed: bool) -> Result<WasmConfig, JsError> {
+        let padding = match padding {
+            "duplicate-last" => Padding::DuplicateLast,
+            "promote" => Padding::Promote,
+            "empty-hash" => Padding::EmptyHash,
+            _ => return Err(JsError::new("unknown padding")),
+        };
+        let prefixes = if prefixed { Some(Prefixes::RFC6962) } else { None };
+
+        Ok(WasmConfig(TreeConfig { prefixes, ..TreeConfig::new(padding) }))
+    }
+}
+
+// Only the successful paths are tested, as building a `JsError` needs a JavaScript host.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_matches_the_rust_tree() {
+        let leaves: Vec<String> = (0..5).map(|i| i.to_string()).collect();
+        let config = WasmConfig::new("promote", true).ok().unwrap();
+        let mut wasm_tree = WasmMerkleTree::from_strings(leaves.clone(), Some(config)).ok().unwrap();
+        let mut tree = MerkleTree::from_vec_with_config(leaves, config.0).unwrap();
+
+        wasm_tree.push(b"5").ok().unwrap();
+        tree.push("5".to_string()).unwrap();
+
+        assert_eq!(wasm_tree.root_hash(), tree.root_hash());
+        assert_eq!(wasm_tree.root_hash_hex(), tree.root_hash_hex());
+        assert_eq!(wasm_tree.leaf_count(), 6);
+        assert_eq!(wasm_tree.proof(5).ok().unwrap(), tree.proof(5).unwrap().to_bytes());
+    }
+
+    #[test]
+    fn it_verifies_proofs() {
+        let wasm_tree = WasmMerkleTree::from_chunks(b"abcdefghij", 3, None).ok().unwrap();
+        let proof = wasm_tree.proof(1).ok().unwrap();
+
+        assert!(WasmMerkleTree::verify_proof(&proof, &wasm_tree.root_hash(), b"def", None));
+        assert!(!WasmMerkleTree::verify_proof(&proof, &wasm_tree.root_hash(), b"abc", None));
+        assert!(!WasmMerkleTree::verify_proof(&proof[1..], &wasm_tree.root_hash(), b"def", None));
+    }
+
+    #[test]
+    fn it_verifies_proofs_against_the_given_config() {
+        let config = WasmConfig::new("duplicate-last", true).ok().unwrap();
+        let wasm_tree = WasmMerkleTree::from_strings(vec!["a".to_string(), "b".to_string()], Some(config)).ok().unwrap();
+        let root = wasm_tree.root_hash();
+        assert!(WasmMerkleTree::verify_proof(&wasm_tree.proof(1).ok().unwrap(), &root, b"b", Some(config)));
+
+        // Without prefixes, the data of the root node passes for the only leaf of a tree.
+        let tree = &wasm_tree.tree;
+        let inner_node = [&[Prefixes::RFC6962.node][..], tree.node_at(0, 0).hash(), tree.node_at(0, 1).hash()].concat();
+        let forged = Proof::<blake2::Blake2b>::new(0, 1, Padding::DuplicateLast, vec![]).to_bytes();
+        assert!(!WasmMerkleTree::verify_proof(&forged, &root, &inner_node, Some(config)));
+    }
+}
@@ -0,0 +1,538 @@
+//! Compact binary encoding of proofs and suspended incremental trees, independent of serde.
+//!
+//! Every encoding starts with a version byte, a byte identifying the kind of proof and a flags
+//! byte, followed by the prefixes when present and the length of the hashes in bytes. Integers
+//! are big-endian, leaf indices and counts take 8 bytes and lists are prefixed with their length
+//! on 4 bytes.
+//!
+//! Multiproofs also have a compressed encoding, for proofs of many leaves that are close to each
+//! other. The leaf count and indices are LEB128 varints, every index but the first being stored
+//! as its distance to the previous one minus one, and the number of hashes is left out since it
+//! follows from the indices. Decoding only accepts the single encoding `to_compressed_bytes`
+//! produces for a proof: varints must be minimal, indices strictly increasing and smaller than
+//! the leaf count, and the hashes exactly the ones the indices call for.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use digest::Digest;
+use digest::generic_array::GenericArray;
+
+use crate::Hash;
+use crate::config::{DuplicatePolicy, LeafMode, Padding, Prefixes, TreeConfig};
+use crate::error::MerkleError;
+use crate::incremental::IncrementalState;
+use crate::multiproof::MultiProof;
+use crate::proof::{Proof, ProofStep, Side};
+
+/// Version 2 added the padding and leaf count of the tree to the encoding of proofs.
+const VERSION: u8 = 2;
+
+const PROOF: u8 = 0;
+const MULTI_PROOF: u8 = 1;
+const INCREMENTAL_STATE: u8 = 2;
+const COMPRESSED_MULTI_PROOF: u8 = 3;
+
+const HAS_PREFIXES: u8 = 0b01;
+const SORT_PAIRS: u8 = 0b10;
+const PREHASHED: u8 = 0b100;
+
+impl<D> Proof<D> where D: Digest {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = header::<D>(PROOF, self.prefixes, self.sort_pairs, self.leaf_mode);
+
+        bytes.push(padding_byte(self.padding));
+        bytes.extend_from_slice(&(self.leaf_count as u64).to_be_bytes());
+        bytes.extend_from_slice(&(self.leaf_index as u64).to_be_bytes());
+        bytes.extend_from_slice(&(self.siblings.len() as u32).to_be_bytes());
+
+        for step in &self.siblings {
+            bytes.push(match step.side {
+                Side::Left => 0,
+                Side::Right => 1,
+            });
+            bytes.extend_from_slice(&step.hash);
+        }
+
+        bytes
+    }
+
+    /// Decodes a proof encoded by `to_bytes`, failing with `MalformedProof` on truncated or
+    /// trailing data, or when it was encoded for a digest with another output size.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Proof<D>, MerkleError> {
+        let mut reader = Reader(bytes);
+        let (prefixes, sort_pairs, leaf_mode) = reader.header::<D>(PROOF)?;
+        let padding = reader.padding()?;
+        let leaf_count = reader.index()?;
+        let leaf_index = reader.index()?;
+
+        let siblings = (0..reader.len()?)
+            .map(|_| {
+                let side = match reader.byte()? {
+                    0 => Side::Left,
+                    1 => Side::Right,
+                    _ => return Err(MerkleError::MalformedProof),
+                };
+
+                Ok(ProofStep::new(side, reader.hash::<D>()?))
+            })
+            .collect::<Result<_, _>>()?;
+
+        reader.finish()?;
+
+        let mut proof = Proof::new(leaf_index, leaf_count, padding, siblings);
+        proof.prefixes = prefixes;
+        proof.sort_pairs = sort_pairs;
+        proof.leaf_mode = leaf_mode;
+
+        Ok(proof)
+    }
+}
+
+impl<D> MultiProof<D> where D: Digest {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = header::<D>(MULTI_PROOF, self.prefixes, self.sort_pairs, self.leaf_mode);
+
+        bytes.push(padding_byte(self.padding));
+        bytes.extend_from_slice(&(self.leaf_count as u64).to_be_bytes());
+        bytes.extend_from_slice(&(self.leaf_indices.len() as u32).to_be_bytes());
+
+        for index in &self.leaf_indices {
+            bytes.extend_from_slice(&(*index as u64).to_be_bytes());
+        }
+
+        bytes.extend_from_slice(&(self.hashes.len() as u32).to_be_bytes());
+
+        for hash in &self.hashes {
+            bytes.extend_from_slice(hash);
+        }
+
+        bytes
+    }
+
+    /// Decodes a multiproof encoded by `to_bytes`, failing with `MalformedProof` on truncated or
+    /// trailing data, or when it was encoded for a digest with another output size.
+    pub fn from_bytes(bytes: &[u8]) -> Result<MultiProof<D>, MerkleError> {
+        let mut reader = Reader(bytes);
+        let (prefixes, sort_pairs, leaf_mode) = reader.header::<D>(MULTI_PROOF)?;
+
+        let padding = reader.padding()?;
+        let leaf_count = reader.index()?;
+        let leaf_indices = (0..reader.len()?).map(|_| reader.index()).collect::<Result<_, _>>()?;
+        let hashes = (0..reader.len()?).map(|_| reader.hash::<D>()).collect::<Result<_, _>>()?;
+
+        reader.finish()?;
+
+        let mut proof = MultiProof::new(leaf_indices, leaf_count, padding, hashes);
+        proof.prefixes = prefixes;
+        proof.sort_pairs = sort_pairs;
+        proof.leaf_mode = leaf_mode;
+
+        Ok(proof)
+    }
+}
+
+impl<D> MultiProof<D> where D: Digest {
+    /// Encodes the proof in the compressed format. Several times smaller than `to_bytes` when
+    /// most of the proof is leaf indices, as for ranges of leaves.
+    pub fn to_compressed_bytes(&self) -> Vec<u8> {
+        let mut bytes = header::<D>(COMPRESSED_MULTI_PROOF, self.prefixes, self.sort_pairs, self.leaf_mode);
+
+        bytes.push(padding_byte(self.padding));
+        write_varint(&mut bytes, self.leaf_count as u64);
+        write_varint(&mut bytes, self.leaf_indices.len() as u64);
+
+        let mut next = 0;
+        for &index in &self.leaf_indices {
+            write_varint(&mut bytes, index.wrapping_sub(next) as u64);
+            next = index.wrapping_add(1);
+        }
+
+        for hash in &self.hashes {
+            bytes.extend_from_slice(hash);
+        }
+
+        bytes
+    }
+
+    /// Decodes a multiproof encoded by `to_compressed_bytes`, failing with `MalformedProof` on
+    /// truncated, trailing or non-canonical data, or when it was encoded for a digest with
+    /// another output size.
+    pub fn from_compressed_bytes(bytes: &[u8]) -> Result<MultiProof<D>, MerkleError> {
+        let mut reader = Reader(bytes);
+        let (prefixes, sort_pairs, leaf_mode) = reader.header::<D>(COMPRESSED_MULTI_PROOF)?;
+
+        let padding = reader.padding()?;
+        let leaf_count = reader.varint()?;
+        let index_count = reader.varint()?;
+
+        if index_count == 0 || index_count > leaf_count || index_count > reader.0.len() {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let mut leaf_indices = Vec::with_capacity(index_count);
+        let mut next = 0;
+
+        for _ in 0..index_count {
+            let index = reader.varint()?.checked_add(next).ok_or(MerkleError::MalformedProof)?;
+
+            if index >= leaf_count {
+                return Err(MerkleError::MalformedProof);
+            }
+
+            leaf_indices.push(index);
+            next = index + 1;
+        }
+
+        let hash_count = sibling_count(&leaf_indices, leaf_count);
+
+        if hash_count * D::output_size() != reader.0.len() {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let hashes = (0..hash_count).map(|_| reader.hash::<D>()).collect::<Result<_, _>>()?;
+
+        reader.finish()?;
+
+        let mut proof = MultiProof::new(leaf_indices, leaf_count, padding, hashes);
+        proof.prefixes = prefixes;
+        proof.sort_pairs = sort_pairs;
+        proof.leaf_mode = leaf_mode;
+
+        Ok(proof)
+    }
+}
+
+impl<D> IncrementalState<D> where D: Digest {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let config = self.config;
+        let mut bytes = header::<D>(INCREMENTAL_STATE, config.prefixes, config.sort_pairs, config.leaf_mode);
+
+        bytes.push(padding_byte(config.padding));
+        bytes.extend_from_slice(&(self.leaf_count as u64).to_be_bytes());
+        bytes.extend_from_slice(&(self.frontier.len() as u32).to_be_bytes());
+
+        for hash in &self.frontier {
+            bytes.extend_from_slice(hash);
+        }
+
+        bytes
+    }
+
+    /// Decodes a state encoded by `to_bytes`, failing with `InvalidState` on truncated or
+    /// trailing data, or when it was encoded for a digest with another output size.
+    pub fn from_bytes(bytes: &[u8]) -> Result<IncrementalState<D>, MerkleError> {
+        let decode = || {
+            let mut reader = Reader(bytes);
+            let (prefixes, sort_pairs, leaf_mode) = reader.header::<D>(INCREMENTAL_STATE)?;
+            let padding = reader.padding()?;
+            let leaf_count = reader.index()?;
+            let frontier = (0..reader.len()?).map(|_| reader.hash::<D>()).collect::<Result<_, _>>()?;
+
+            reader.finish()?;
+
+            Ok(IncrementalState::new(TreeConfig { padding, prefixes, sort_pairs, leaf_mode, duplicates: DuplicatePolicy::Allow }, leaf_count, frontier))
+        };
+
+        decode().map_err(|error| match error {
+            MerkleError::MalformedProof => MerkleError::InvalidState,
+            error => error,
+        })
+    }
+}
+
+/// Number of sibling hashes a multiproof of the leaves at `leaf_indices`, sorted and distinct, holds
+/// in a tree of `leaf_count` leaves, as `MerkleTree::multi_proof` generates them.
+fn sibling_count(leaf_indices: &[usize], leaf_count: usize) -> usize {
+    let mut level = leaf_indices.to_vec();
+    let (mut count, mut siblings) = (leaf_count, 0);
+
+    while count > 1 {
+        let mut next = Vec::with_capacity(level.len());
+        let mut known = level.into_iter().peekable();
+
+        while let Some(index) = known.next() {
+            let sibling = index ^ 1;
+
+            if known.peek() == Some(&sibling) {
+                known.next();
+            } else if sibling < count {
+                siblings += 1;
+            }
+
+            next.push(index / 2);
+        }
+
+        level = next;
+        count = count.div_ceil(2);
+    }
+
+    siblings
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    bytes.push(value as u8);
+}
+
+fn padding_byte(padding: Padding) -> u8 {
+    match padding {
+        Padding::DuplicateLast => 0,
+        Padding::Promote => 1,
+        Padding::EmptyHash => 2,
+    }
+}
+
+fn header<D: Digest>(kind: u8, prefixes: Option<Prefixes>, sort_pairs: bool, leaf_mode: LeafMode) -> Vec<u8> {
+    let mut flags = 0;
+
+    if prefixes.is_some() {
+        flags |= HAS_PREFIXES;
+    }
+
+    if sort_pairs {
+        flags |= SORT_PAIRS;
+    }
+
+    if leaf_mode == LeafMode::Prehashed {
+        flags |= PREHASHED;
+    }
+
+    let mut bytes = vec![VERSION, kind, flags];
+
+    if let Some(prefixes) = prefixes {
+        bytes.extend_from_slice(&[prefixes.leaf, prefixes.node]);
+    }
+
+    bytes.push(D::output_size() as u8);
+    bytes
+}
+
+/// Cursor over an encoded proof.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], MerkleError> {
+        if self.0.len() < len {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, MerkleError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn index(&mut self) -> Result<usize, MerkleError> {
+        let index = u64::from_be_bytes(self.take(8)?.try_into().unwrap());
+        index.try_into().map_err(|_| MerkleError::MalformedProof)
+    }
+
+    /// Length of a list. It is checked against the remaining data before anything is allocated,
+    /// as every element takes at least one byte.
+    fn len(&mut self) -> Result<usize, MerkleError> {
+        let len = u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as usize;
+
+        if len > self.0.len() {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        Ok(len)
+    }
+
+    /// A LEB128 varint, which must be encoded on as few bytes as possible and fit in a `usize`.
+    fn varint(&mut self) -> Result<usize, MerkleError> {
+        let mut value: u64 = 0;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = u64::from(byte & 0x7f);
+
+            if bits << shift >> shift != bits || (byte == 0 && shift > 0) {
+                return Err(MerkleError::MalformedProof);
+            }
+
+            value |= bits << shift;
+
+            if byte & 0x80 == 0 {
+                return value.try_into().map_err(|_| MerkleError::MalformedProof);
+            }
+        }
+
+        Err(MerkleError::MalformedProof)
+    }
+
+    fn padding(&mut self) -> Result<Padding, MerkleError> {
+        match self.byte()? {
+            0 => Ok(Padding::DuplicateLast),
+            1 => Ok(Padding::Promote),
+            2 => Ok(Padding::EmptyHash),
+            _ => Err(MerkleError::MalformedProof),
+        }
+    }
+
+    fn hash<D: Digest>(&mut self) -> Result<Hash<D>, MerkleError> {
+        Ok(GenericArray::clone_from_slice(self.take(D::output_size())?))
+    }
+
+    fn header<D: Digest>(&mut self, kind: u8) -> Result<(Option<Prefixes>, bool, LeafMode), MerkleError> {
+        let version = self.byte()?;
+
+        if version != VERSION {
+            return Err(MerkleError::UnsupportedVersion(version));
+        }
+
+        let (found, flags) = (self.byte()?, self.byte()?);
+
+        if found != kind || flags & !(HAS_PREFIXES | SORT_PAIRS | PREHASHED) != 0 {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let prefixes = if flags & HAS_PREFIXES != 0 {
+            Some(Prefixes { leaf: self.byte()?, node: self.byte()? })
+        } else {
+            None
+        };
+
+        if self.byte()? as usize != D::output_size() {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let leaf_mode = if flags & PREHASHED != 0 { LeafMode::Prehashed } else { LeafMode::Hashed };
+
+        Ok((prefixes, flags & SORT_PAIRS != 0, leaf_mode))
+    }
+
+    fn finish(self) -> Result<(), MerkleError> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(MerkleError::MalformedProof)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blake2::{Blake2b, Blake2s};
+
+    use super::*;
+    use crate::{IncrementalTree, MerkleTree};
+
+    #[test]
+    fn it_round_trips_proofs() {
+        let config = TreeConfig { prefixes: Some(Prefixes::RFC6962), ..TreeConfig::default() };
+        let merkle_tree = MerkleTree::from_vec_with_config(vec!["a", "b", "c", "d", "e"], config).unwrap();
+        let proof = merkle_tree.proof(4).unwrap();
+        let bytes = proof.to_bytes();
+
+        // Header, prefixes, hash length, padding, leaf count, index, sibling count and 3 siblings
+        // with their side.
+        assert_eq!(bytes.len(), 3 + 2 + 1 + 1 + 8 + 8 + 4 + 3 * (1 + 64));
+        assert_eq!(Proof::from_bytes(&bytes), Ok(proof));
+    }
+
+    #[test]
+    fn it_round_trips_multi_proofs() {
+        let merkle_tree = MerkleTree::from_vec_openzeppelin((0..9).map(|i| i.to_string()).collect()).unwrap();
+        let proof = merkle_tree.multi_proof(&[1, 4, 8]).unwrap();
+
+        assert_eq!(MultiProof::from_bytes(&proof.to_bytes()), Ok(proof));
+
+        let config = TreeConfig { leaf_mode: LeafMode::Prehashed, ..TreeConfig::default() };
+        let merkle_tree = MerkleTree::from_vec_with_config(vec![vec![1; 64], vec![2; 64], vec![3; 64]], config).unwrap();
+        let proof = merkle_tree.multi_proof(&[0, 2]).unwrap();
+
+        assert_eq!(MultiProof::from_bytes(&proof.to_bytes()), Ok(proof));
+    }
+
+    #[test]
+    fn it_compresses_multi_proofs_of_clustered_leaves() {
+        let merkle_tree = MerkleTree::from_vec((0..1000).map(|i| i.to_string()).collect()).unwrap();
+
+        for indices in &[(320..384).collect::<Vec<_>>(), vec![0, 1, 130, 999], vec![512]] {
+            let proof = merkle_tree.multi_proof(indices).unwrap();
+            assert_eq!(MultiProof::from_compressed_bytes(&proof.to_compressed_bytes()), Ok(proof));
+        }
+
+        let merkle_tree_with_prefixes = MerkleTree::from_vec_openzeppelin((0..9).map(|i| i.to_string()).collect()).unwrap();
+        let proof = merkle_tree_with_prefixes.multi_proof(&[2, 8]).unwrap();
+        assert_eq!(MultiProof::from_compressed_bytes(&proof.to_compressed_bytes()), Ok(proof));
+
+        // 64 consecutive leaves need 4 hashes, and their indices take 8 bytes each uncompressed.
+        let proof = merkle_tree.multi_proof(&(320..384).collect::<Vec<_>>()).unwrap();
+        assert_eq!(proof.to_bytes().len(), 3 + 1 + 1 + 8 + 4 + 64 * 8 + 4 + 4 * 64);
+        assert_eq!(proof.to_compressed_bytes().len(), 3 + 1 + 1 + 2 + 1 + 2 + 63 + 4 * 64);
+    }
+
+    #[test]
+    fn it_rejects_ambiguous_compressed_multi_proofs() {
+        let merkle_tree = MerkleTree::from_vec((0..6).map(|i| i.to_string()).collect()).unwrap();
+        let bytes = merkle_tree.multi_proof(&[1, 4]).unwrap().to_compressed_bytes();
+        let body = 3 + 1 + 1;
+
+        // Leaf count 6 and 2 indices, then the gaps 1 and 2.
+        assert_eq!(&bytes[body..body + 4], &[6, 2, 1, 2]);
+
+        let overlong = [&bytes[..body], &[0x86, 0x00], &bytes[body + 1..]].concat();
+        let past_the_end = [&bytes[..body + 3], &[5], &bytes[body + 4..]].concat();
+        let missing_hash = &bytes[..bytes.len() - 64];
+        let extra_hash = [&bytes[..], &[0; 64][..]].concat();
+        let no_indices = [&bytes[..body + 1], &[0]].concat();
+
+        for bytes in &[&overlong[..], &past_the_end, missing_hash, &extra_hash, &no_indices] {
+            assert_eq!(MultiProof::<Blake2b>::from_compressed_bytes(bytes), Err(MerkleError::MalformedProof));
+        }
+
+        assert_eq!(MultiProof::<Blake2b>::from_compressed_bytes(&merkle_tree.multi_proof(&[1, 4]).unwrap().to_bytes()), Err(MerkleError::MalformedProof));
+    }
+
+    #[test]
+    fn it_rejects_compressed_indices_that_overflow() {
+        let mut bytes = header::<Blake2b>(COMPRESSED_MULTI_PROOF, None, false, LeafMode::Hashed);
+        bytes.push(padding_byte(Padding::DuplicateLast));
+
+        // The second gap takes the index past the largest one.
+        for value in &[u64::MAX, 2, u64::MAX - 1, 1] {
+            write_varint(&mut bytes, *value);
+        }
+
+        assert_eq!(MultiProof::<Blake2b>::from_compressed_bytes(&bytes), Err(MerkleError::MalformedProof));
+    }
+
+    #[test]
+    fn it_round_trips_incremental_states() {
+        let config = TreeConfig { prefixes: Some(Prefixes::RFC6962), ..TreeConfig::new(Padding::Promote) };
+        let mut incremental = IncrementalTree::with_config(config);
+
+        for leaf in &["a", "b", "c", "d", "e"] {
+            incremental.push(leaf.as_bytes()).unwrap();
+        }
+
+        let state = incremental.suspend();
+        let bytes = state.to_bytes();
+
+        assert_eq!(IncrementalState::from_bytes(&bytes), Ok(state));
+        assert_eq!(IncrementalState::<Blake2b>::from_bytes(&bytes[..bytes.len() - 1]), Err(MerkleError::InvalidState));
+    }
+
+    #[test]
+    fn it_rejects_corrupted_encodings() {
+        let merkle_tree = MerkleTree::from_vec(vec!["a", "b", "c"]).unwrap();
+        let bytes = merkle_tree.proof(1).unwrap().to_bytes();
+
+        assert_eq!(Proof::<Blake2b>::from_bytes(&bytes[..bytes.len() - 1]), Err(MerkleError::MalformedProof));
+        assert_eq!(Proof::<Blake2b>::from_bytes(&[&bytes[..], &[0]].concat()), Err(MerkleError::MalformedProof));
+        assert_eq!(Proof::<Blake2s>::from_bytes(&bytes), Err(MerkleError::MalformedProof));
+        assert_eq!(MultiProof::<Blake2b>::from_bytes(&bytes), Err(MerkleError::MalformedProof));
+        assert_eq!(Proof::<Blake2b>::from_bytes(&[&[1], &bytes[1..]].concat()), Err(MerkleError::UnsupportedVersion(1)));
+    }
+}